@@ -1,6 +1,7 @@
-//! Adblock: downloads EasyList + uBlock filters and applies via WebKit content rules
+//! Adblock: filter lists (see `subscriptions`) applied via WebKit content rules
 use std::path::PathBuf;
 use std::fs;
+use std::sync::Mutex;
 
 pub fn filter_store_path() -> PathBuf {
     let d = crate::storage::data_dir().join("filters");
@@ -8,25 +9,8 @@ pub fn filter_store_path() -> PathBuf {
     d
 }
 
-/// URLs to fetch filter lists (JSON content blocker format for WebKit)
-/// These are pre-converted to WebKit JSON format by community projects
-const FILTER_SOURCES: &[(&str, &str)] = &[
-    (
-        "easylist",
-        "https://raw.githubusercontent.com/nicehash/webkit-content-blocker-easylist/master/easylist.json",
-    ),
-    (
-        "easyprivacy",
-        "https://raw.githubusercontent.com/nicehash/webkit-content-blocker-easylist/master/easyprivacy.json",
-    ),
-    (
-        "youtube_ads",
-        "https://raw.githubusercontent.com/AdguardTeam/FiltersRegistry/master/filters/filter_14_Annoyances/filter.txt",
-    ),
-];
-
 /// Minimal hardcoded WebKit content blocker rules for YouTube ads
-/// as fallback when download fails
+/// until a filter list has been downloaded
 pub fn builtin_youtube_rules() -> &'static str {
     r#"[
   {"trigger":{"url-filter":"googlesyndication\\.com"},"action":{"type":"block"}},
@@ -51,77 +35,363 @@ pub fn builtin_youtube_rules() -> &'static str {
 ]"#
 }
 
-/// JS still needed for DOM-level ad removal (skip button, overlay banners)
-/// Network-level blocking handles the requests, this handles the UI
-pub fn youtube_dom_cleaner_js() -> &'static str {
-    r#"(function() {
-  if (!location.hostname.includes('youtube.com')) return;
-
-  function skipAds() {
-    // Click skip button
-    const skip = document.querySelector(
-      '.ytp-skip-ad-button, .ytp-ad-skip-button, .ytp-ad-skip-button-modern'
-    );
-    if (skip) { skip.click(); return; }
-
-    // Force-end video ads
-    const video = document.querySelector('video');
-    if (video && document.querySelector('.ad-showing')) {
-      video.volume = 0;
-      video.currentTime = video.duration || 9999;
-    }
-  }
-
-  function removeOverlays() {
-    const sel = [
-      '.ytp-ad-overlay-container',
-      '.ytp-ad-text-overlay',
-      '.ytp-ad-image-overlay',
-      '#masthead-ad',
-      '.ytd-display-ad-renderer',
-      'ytd-display-ad-renderer',
-      'ytd-promoted-sparkles-web-renderer',
-      'ytd-promoted-video-renderer',
-      'ytd-search-pyv-renderer',
-      'ytd-in-feed-ad-layout-renderer',
-      '.ytd-ad-slot-renderer',
-      'ytd-ad-slot-renderer',
-      '.ytd-companion-slot-renderer',
-      '#player-ads',
-      '.ad-showing .ytp-ad-module',
-    ];
-    sel.forEach(s => document.querySelectorAll(s).forEach(el => el.remove()));
-  }
-
-  // Run immediately and on DOM changes
-  function run() { skipAds(); removeOverlays(); }
-  run();
-  setInterval(run, 300);
-  new MutationObserver(run)
-    .observe(document.documentElement, { childList: true, subtree: true });
-})();"#
+/// YouTube in filter list syntax, added to whatever the subscribed lists
+/// bring: element hiding, and scriptlets taking the ads out of the player
+/// data, pressing the skip button and fast-forwarding an ad that plays.
+pub fn builtin_rules() -> &'static str {
+    r#"youtube.com##.ytp-ad-overlay-container
+youtube.com##.ytp-ad-text-overlay
+youtube.com##.ytp-ad-image-overlay
+youtube.com###masthead-ad
+youtube.com##ytd-display-ad-renderer
+youtube.com##ytd-promoted-sparkles-web-renderer
+youtube.com##ytd-promoted-video-renderer
+youtube.com##ytd-search-pyv-renderer
+youtube.com##ytd-in-feed-ad-layout-renderer
+youtube.com##ytd-ad-slot-renderer
+youtube.com##.ytd-companion-slot-renderer
+youtube.com###player-ads
+youtube.com##.ad-showing .ytp-ad-module
+youtube.com##+js(json-prune, playerResponse.adPlacements playerResponse.playerAds playerResponse.adSlots adPlacements playerAds adSlots)
+youtube.com##+js(set-constant, ytInitialPlayerResponse.adPlacements, undefined)
+youtube.com##+js(set-constant, ytInitialPlayerResponse.playerAds, undefined)
+youtube.com##+js(set-constant, ytInitialPlayerResponse.adSlots, undefined)
+youtube.com##+js(click-element, .ytp-skip-ad-button\, .ytp-ad-skip-button\, .ytp-ad-skip-button-modern)
+youtube.com##+js(skip-media, .ad-showing video)
+"#
+}
+
+// ── Content filter ────────────────────────────────────────────────────────────
+//
+// WebKit compiles content-blocker JSON into bytecode through a
+// UserContentFilterStore kept under filters/compiled. Rule sets too big for
+// one filter are split (see `filters::split_rules`) and stored as
+// vccat-rules-<n>. The sha-256 of the JSON they were compiled from is
+// stored next to them, so unchanged rules are just loaded on the next
+// start instead of compiled again.
+
+/// What went wrong building the last content filter, for vccat:filters.
+static FILTER_PROBLEM: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn filter_problem() -> Option<String> {
+    FILTER_PROBLEM.lock().unwrap_or_else(|e| e.into_inner()).clone()
 }
 
-/// Try to download WebKit-format filter list, save to disk
-/// Returns path to saved file or None on failure
-pub fn ensure_filters_downloaded() -> Option<PathBuf> {
-    let path = filter_store_path().join("youtube_rules.json");
-
-    // Use cached version if less than 24h old
-    if let Ok(meta) = fs::metadata(&path) {
-        if let Ok(modified) = meta.modified() {
-            if let Ok(age) = modified.elapsed() {
-                if age.as_secs() < 86400 {
-                    return Some(path);
+#[cfg(target_os = "linux")]
+mod content_filter {
+    use std::cell::RefCell;
+    use std::ffi::CString;
+    use std::fs;
+    use std::ptr;
+    use gtk::glib::{self, ffi::{gpointer, GError}, gobject_ffi::GObject, translate::ToGlibPtr};
+    use gtk::gio::ffi::GAsyncResult;
+    use sha2::{Digest, Sha256};
+    use webkit2gtk::WebViewExt;
+    use webkit2gtk_sys as wk;
+    use wry::WebViewExtUnix;
+
+    const IDENTIFIER: &str = "vccat-rules";
+
+    thread_local! {
+        /// The compiled filters, once ready; owned references.
+        static FILTERS: RefCell<Option<Vec<*mut wk::WebKitUserContentFilter>>> = const { RefCell::new(None) };
+    }
+
+    struct Pending {
+        store: *mut wk::WebKitUserContentFilterStore,
+        /// The rules, one JSON array per filter.
+        parts: Vec<String>,
+        hash: String,
+        /// Loading the filters stored before, until one is missing.
+        cached: bool,
+        /// The built-in rules already stand in for rules that failed.
+        fallback: bool,
+        /// Filters ready so far, one per part.
+        filters: Vec<*mut wk::WebKitUserContentFilter>,
+        problem: Option<String>,
+        done: Box<dyn FnOnce()>,
+    }
+
+    fn hash_path() -> std::path::PathBuf {
+        super::filter_store_path().join("compiled.sha256")
+    }
+
+    fn error_text(err: *mut GError) -> String {
+        if err.is_null() { return "nieznany błąd".into(); }
+        unsafe {
+            let msg = std::ffi::CStr::from_ptr((*err).message).to_string_lossy().into_owned();
+            glib::ffi::g_error_free(err);
+            msg
+        }
+    }
+
+    /// Splits the rules into filter-sized parts; rules that aren't a JSON
+    /// array go whole, for WebKit to reject.
+    fn split(json: &str, problem: &mut Option<String>) -> Vec<String> {
+        let Ok(serde_json::Value::Array(rules)) = serde_json::from_str(json) else { return vec![json.into()] };
+        let (parts, dropped) = crate::filters::split_rules(&rules, crate::filters::MAX_RULES);
+        if dropped > 0 {
+            *problem = Some(format!("pominięto {} reguł: same wyjątki przekraczają limit WebKit ({} reguł na filtr)",
+                                    dropped, crate::filters::MAX_RULES));
+        }
+        parts.into_iter().map(|p| serde_json::Value::Array(p).to_string()).collect()
+    }
+
+    /// Puts the new filters in place, unless `failed`: then the ones from
+    /// before stay, as some filtering beats none.
+    unsafe fn finish(p: Pending, failed: bool) {
+        let Pending { store, filters, problem, done, .. } = p;
+        if !failed {
+            FILTERS.with(|f| {
+                for old in f.replace(Some(filters)).into_iter().flatten() {
+                    wk::webkit_user_content_filter_unref(old);
                 }
+            });
+        }
+        *super::FILTER_PROBLEM.lock().unwrap_or_else(|e| e.into_inner()) = problem;
+        glib::gobject_ffi::g_object_unref(store as *mut GObject);
+        done();
+    }
+
+    /// Loads or compiles the next part, or finishes once all are ready.
+    unsafe fn next(mut p: Box<Pending>) {
+        let n = p.filters.len();
+        if n == p.parts.len() {
+            if p.fallback {
+                p.problem = p.problem.map(|e| e + ", działają tylko wbudowane");
+            } else {
+                fs::write(hash_path(), &p.hash).ok();
             }
+            finish(*p, false);
+            return;
+        }
+        let id = CString::new(format!("{}-{}", IDENTIFIER, n)).unwrap_or_default();
+        let store = p.store;
+        if p.cached {
+            wk::webkit_user_content_filter_store_load(store, id.as_ptr(), ptr::null_mut(),
+                Some(loaded), Box::into_raw(p) as gpointer);
+        } else {
+            let bytes = glib::Bytes::from_owned(p.parts[n].clone().into_bytes());
+            wk::webkit_user_content_filter_store_save(store, id.as_ptr(), bytes.to_glib_none().0,
+                ptr::null_mut(), Some(saved), Box::into_raw(p) as gpointer);
+        }
+    }
+
+    unsafe extern "C" fn saved(source: *mut GObject, res: *mut GAsyncResult, data: gpointer) {
+        let mut p = Box::from_raw(data as *mut Pending);
+        let mut err = ptr::null_mut();
+        let filter = wk::webkit_user_content_filter_store_save_finish(source as *mut _, res, &mut err);
+        if !filter.is_null() {
+            p.filters.push(filter);
+            next(p);
+            return;
+        }
+        let error = error_text(err);
+        for f in p.filters.drain(..) { wk::webkit_user_content_filter_unref(f); }
+        if p.fallback {
+            p.problem = p.problem.map(|e| format!("{}, wbudowane też nie ({}); zostaje poprzedni filtr", e, error));
+            finish(*p, true);
+            return;
         }
+        // bad downloaded rules shouldn't leave pages unprotected; what is
+        // stored now is the built-in rules, not the ones hashed
+        fs::remove_file(hash_path()).ok();
+        p.problem = Some(format!("reguły nie dały się skompilować ({})", error));
+        p.parts = vec![super::builtin_youtube_rules().into()];
+        p.fallback = true;
+        next(p);
     }
 
-    // Write builtin rules as fallback immediately
-    fs::write(&path, builtin_youtube_rules()).ok()?;
+    unsafe extern "C" fn loaded(source: *mut GObject, res: *mut GAsyncResult, data: gpointer) {
+        let mut p = Box::from_raw(data as *mut Pending);
+        let mut err = ptr::null_mut();
+        let filter = wk::webkit_user_content_filter_store_load_finish(source as *mut _, res, &mut err);
+        if filter.is_null() {
+            // missing or from another WebKit version: compile again
+            error_text(err);
+            p.cached = false;
+        } else {
+            p.filters.push(filter);
+        }
+        next(p);
+    }
+
+    fn hex(json: &str) -> String {
+        Sha256::digest(json.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Loads the compiled filters for `json`, compiling them first if the
+    /// cached ones were built from other rules. Asynchronous on the GTK
+    /// main loop; `done` runs once the filters can be applied.
+    pub fn prepare(json: String, done: impl FnOnce() + 'static) {
+        let dir = super::filter_store_path().join("compiled");
+        fs::create_dir_all(&dir).ok();
+        let Ok(path) = CString::new(dir.to_string_lossy().as_bytes()) else {
+            *super::FILTER_PROBLEM.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(format!("nieprawidłowa ścieżka magazynu filtrów: {}", dir.display()));
+            done();
+            return;
+        };
+        let hash = hex(&json);
+        let cached = fs::read_to_string(hash_path()).is_ok_and(|h| h.trim() == hash);
+        // the stored filters are about to change; until they all have, they
+        // match no rules
+        if !cached { fs::remove_file(hash_path()).ok(); }
+        let mut problem = None;
+        let parts = split(&json, &mut problem);
+        unsafe {
+            let store = wk::webkit_user_content_filter_store_new(path.as_ptr());
+            next(Box::new(Pending {
+                store, parts, hash, cached, fallback: false, filters: Vec::new(), problem, done: Box::new(done),
+            }));
+        }
+    }
+
+    /// Attaches the compiled filters to a page WebView in place of any
+    /// earlier ones; does nothing until `prepare` has first finished.
+    pub fn apply(wv: &wry::WebView) {
+        let Some(manager) = wv.webview().user_content_manager() else { return };
+        FILTERS.with(|f| {
+            let filters = f.borrow();
+            let Some(filters) = filters.as_ref() else { return };
+            unsafe {
+                let manager: *mut wk::WebKitUserContentManager = manager.to_glib_none().0;
+                wk::webkit_user_content_manager_remove_all_filters(manager);
+                for filter in filters.iter() {
+                    wk::webkit_user_content_manager_add_filter(manager, *filter);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use content_filter::{apply as apply_content_filter, prepare as prepare_content_filter};
+
+// ── Scriptlets ────────────────────────────────────────────────────────────────
+//
+// The scriptlet engine's scripts as WebKit user scripts, built once per
+// engine and allowlist and shared by all page WebViews. Each WebView's
+// content manager keeps the set it was given, so the next set replaces
+// exactly those and leaves wry's own scripts alone.
+
+#[cfg(target_os = "linux")]
+mod user_scripts {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use gtk::glib::object::ObjectExt;
+    use webkit2gtk::{UserContentInjectedFrames, UserContentManagerExt, UserScript, UserScriptInjectionTime,
+                     WebViewExt};
+    use wry::WebViewExtUnix;
+
+    const KEY: &str = "vccat-scriptlets";
+
+    type Built = ((u64, Vec<String>), Rc<Vec<UserScript>>);
+
+    thread_local! {
+        static BUILT: RefCell<Option<Built>> = const { RefCell::new(None) };
+    }
+
+    fn current() -> Rc<Vec<UserScript>> {
+        let key = (crate::scriptlets::generation(), crate::allowlist::allowlisted_sites());
+        BUILT.with(|b| {
+            let mut b = b.borrow_mut();
+            if let Some((k, scripts)) = b.as_ref() {
+                if *k == key { return scripts.clone(); }
+            }
+            let scripts: Rc<Vec<UserScript>> = Rc::new(crate::scriptlets::scripts(&key.1).iter().map(|s| {
+                let allow: Vec<&str> = s.allow.iter().map(String::as_str).collect();
+                let block: Vec<&str> = s.block.iter().map(String::as_str).collect();
+                UserScript::new(&s.source, UserContentInjectedFrames::AllFrames,
+                                UserScriptInjectionTime::Start, &allow, &block)
+            }).collect());
+            *b = Some((key, scripts.clone()));
+            scripts
+        })
+    }
+
+    /// Gives a page WebView the current scriptlets in place of the ones it
+    /// had; they run from its next page load on.
+    pub fn apply(wv: &wry::WebView) {
+        let Some(manager) = wv.webview().user_content_manager() else { return };
+        let scripts = current();
+        unsafe {
+            if let Some(old) = manager.steal_data::<Rc<Vec<UserScript>>>(KEY) {
+                if Rc::ptr_eq(&old, &scripts) {
+                    manager.set_data(KEY, old);
+                    return;
+                }
+                for s in old.iter() { manager.remove_script(s); }
+            }
+            for s in scripts.iter() { manager.add_script(s); }
+            manager.set_data(KEY, scripts);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use user_scripts::apply as apply_scriptlets;
+
+// ── Element hiding ────────────────────────────────────────────────────────────
+//
+// The cosmetic engine's sheets as WebKit user stylesheets, kept the same way
+// as the scriptlets: built once per engine and allowlist, and each content
+// manager remembers the set it was given.
+
+#[cfg(target_os = "linux")]
+mod user_styles {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use gtk::glib::object::ObjectExt;
+    use webkit2gtk::{UserContentInjectedFrames, UserContentManagerExt, UserStyleLevel, UserStyleSheet,
+                     WebViewExt};
+    use wry::WebViewExtUnix;
+
+    const KEY: &str = "vccat-cosmetic";
+
+    type Built = ((u64, Vec<String>), Rc<Vec<UserStyleSheet>>);
+
+    thread_local! {
+        static BUILT: RefCell<Option<Built>> = const { RefCell::new(None) };
+    }
+
+    fn current() -> Rc<Vec<UserStyleSheet>> {
+        let key = (crate::cosmetic::generation(), crate::allowlist::allowlisted_sites());
+        BUILT.with(|b| {
+            let mut b = b.borrow_mut();
+            if let Some((k, sheets)) = b.as_ref() {
+                if *k == key { return sheets.clone(); }
+            }
+            // user level, so page styles can't show what a rule hides
+            let sheets: Rc<Vec<UserStyleSheet>> = Rc::new(crate::cosmetic::sheets(&key.1).iter().map(|s| {
+                let allow: Vec<&str> = s.allow.iter().map(String::as_str).collect();
+                let block: Vec<&str> = s.block.iter().map(String::as_str).collect();
+                UserStyleSheet::new(&s.css, UserContentInjectedFrames::AllFrames, UserStyleLevel::User,
+                                    &allow, &block)
+            }).collect());
+            *b = Some((key, sheets.clone()));
+            sheets
+        })
+    }
+
+    /// Gives a page WebView the current element-hiding sheets in place of
+    /// the ones it had.
+    pub fn apply(wv: &wry::WebView) {
+        let Some(manager) = wv.webview().user_content_manager() else { return };
+        let sheets = current();
+        unsafe {
+            if let Some(old) = manager.steal_data::<Rc<Vec<UserStyleSheet>>>(KEY) {
+                if Rc::ptr_eq(&old, &sheets) {
+                    manager.set_data(KEY, old);
+                    return;
+                }
+                for s in old.iter() { manager.remove_style_sheet(s); }
+            }
+            for s in sheets.iter() { manager.add_style_sheet(s); }
+            manager.set_data(KEY, sheets);
+        }
+    }
+}
 
-    // Try to fetch better rules in background
-    // (for now use builtin - fetching adguard/easylist WebKit JSON is complex)
-    Some(path)
-}
\ No newline at end of file
+#[cfg(target_os = "linux")]
+pub use user_styles::apply as apply_cosmetic;
@@ -0,0 +1,256 @@
+//! Address bar: decides whether toolbar input is a url, a file path or a
+//! search. Hosts are checked with real url parsing and the public-suffix
+//! list (data/public_suffix_list.dat); input that could be either a host or
+//! a search (`intranet/wiki`, `README.md`) is settled by a DNS lookup.
+use std::net::ToSocketAddrs;
+use std::sync::OnceLock;
+use publicsuffix::{List, Psl};
+use url::{Host, Url};
+
+/// Schemes typed out in full that are opened as they are.
+const SCHEMES: &[&str] = &["http", "https", "file", "about", "vccat", "data", "view-source", "mailto"];
+
+/// Host names that never reach public DNS; opened over plain http.
+pub const LOCAL_SUFFIXES: &[&str] = &["localhost", "local", "lan", "internal", "intranet", "home.arpa", "test"];
+
+/// Top-level domains that double as common file extensions, so `README.md`
+/// or `main.rs` is only opened when the name actually resolves.
+const FILE_LIKE_TLDS: &[&str] = &["md", "rs", "py", "sh", "pl", "zip", "mov", "so", "cc", "ps", "ml"];
+
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Url(String),
+    Search(String),
+    /// Opens `url` if `host` resolves, otherwise searches `query`.
+    Lookup { host: String, url: String, query: String },
+}
+
+/// The bundled list; `suffix_list_parses` keeps a broken update from
+/// silently turning every host into a search.
+const SUFFIX_LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+fn suffix_list() -> &'static List {
+    static LIST: OnceLock<List> = OnceLock::new();
+    LIST.get_or_init(|| SUFFIX_LIST.parse().unwrap_or_default())
+}
+
+/// Whether `host` is a name under a known public suffix, e.g. `example.co.uk`
+/// but neither `co.uk` itself nor `rust.borrow`.
+fn is_registrable(host: &str) -> bool {
+    suffix_list().domain(host.as_bytes()).is_some_and(|d| d.suffix().is_known())
+}
+
+/// The registrable part of `host`: `bbc.co.uk` for `www.bbc.co.uk`.
+pub fn registrable_domain(host: &str) -> Option<String> {
+    let d = suffix_list().domain(host.as_bytes()).filter(|d| d.suffix().is_known())?;
+    String::from_utf8(d.as_bytes().to_vec()).ok()
+}
+
+pub fn is_local(host: &str) -> bool {
+    LOCAL_SUFFIXES.iter().any(|s| host == *s || host.ends_with(&format!(".{}", s)))
+}
+
+fn file_target(path: &str) -> Option<Target> {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()?.join(rest),
+        None if path == "~" => dirs::home_dir()?,
+        None => path.into(),
+    };
+    Url::from_file_path(path).ok().map(|u| Target::Url(u.into()))
+}
+
+/// The host as typed, before the url parser normalizes it; `3.14` must not
+/// become the IPv4 address 3.0.0.14.
+fn typed_host(rest: &str) -> &str {
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    if host_port.starts_with('[') {
+        return host_port.split_inclusive(']').next().unwrap_or(host_port);
+    }
+    host_port.split(':').next().unwrap_or(host_port)
+}
+
+pub fn classify(input: &str) -> Target {
+    let input = input.trim();
+    if input.is_empty() { return Target::Search(String::new()); }
+
+    if input.starts_with('/') || input.starts_with("~/") || input == "~" {
+        if let Some(t) = file_target(input) { return t; }
+    }
+
+    if let Some((scheme, _)) = input.split_once(':') {
+        if SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) {
+            if let Ok(u) = Url::parse(input) {
+                return Target::Url(u.into());
+            }
+        }
+    }
+
+    if input.contains(char::is_whitespace) {
+        return Target::Search(input.into());
+    }
+
+    let Ok(url) = Url::parse(&format!("http://{}", input)) else {
+        return Target::Search(input.into());
+    };
+    // user@example.com is an e-mail address more often than a login
+    if !url.username().is_empty() && !input.contains('/') {
+        return Target::Search(input.into());
+    }
+    let has_path = input.contains(['/', '?', '#']);
+    let has_port = url.port().is_some();
+    let search = || Target::Search(input.into());
+
+    match url.host() {
+        Some(Host::Ipv6(_)) => Target::Url(url.into()),
+        Some(Host::Ipv4(_)) => {
+            // only a full dotted quad counts; `1.5` is a number
+            let typed = typed_host(input);
+            let quad = typed.split('.').count() == 4
+                && typed.split('.').all(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()));
+            if quad { Target::Url(url.into()) } else { search() }
+        }
+        Some(Host::Domain(host)) => {
+            let host = host.trim_end_matches('.');
+            if is_local(host) {
+                return Target::Url(url.into());
+            }
+            if !host.contains('.') {
+                // a lone word is a search unless it looks like a host
+                return if has_port {
+                    Target::Url(url.into())
+                } else if has_path {
+                    Target::Lookup { host: host.into(), url: url.into(), query: input.into() }
+                } else {
+                    search()
+                };
+            }
+            if !is_registrable(host) {
+                return search();
+            }
+            let mut https = url.clone();
+            https.set_scheme("https").ok();
+            let tld = host.rsplit('.').next().unwrap_or("");
+            if !has_path && !has_port && FILE_LIKE_TLDS.contains(&tld) {
+                Target::Lookup { host: host.into(), url: https.into(), query: input.into() }
+            } else {
+                Target::Url(https.into())
+            }
+        }
+        None => search(),
+    }
+}
+
+/// Resolves a `Target::Lookup` host. Blocking; run it off the UI thread.
+pub fn resolves(host: &str) -> bool {
+    (host, 80).to_socket_addrs().is_ok_and(|mut a| a.next().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Target { Target::Url(s.into()) }
+    fn search(s: &str) -> Target { Target::Search(s.into()) }
+    fn lookup(host: &str, u: &str, q: &str) -> Target {
+        Target::Lookup { host: host.into(), url: u.into(), query: q.into() }
+    }
+
+    #[test]
+    fn suffix_list_parses() {
+        let list: List = SUFFIX_LIST.parse().expect("bundled public suffix list");
+        for (name, suffix) in [("example.co.uk", "co.uk"), ("a.b.github.io", "github.io"), ("onet.pl", "pl")] {
+            let domain = list.domain(name.as_bytes()).expect(name);
+            assert!(domain.suffix().is_known(), "{}", name);
+            assert_eq!(domain.suffix().as_bytes(), suffix.as_bytes());
+        }
+    }
+
+    #[test]
+    fn explicit_schemes_pass_through() {
+        assert_eq!(classify("https://example.com/a b"), url("https://example.com/a%20b"));
+        assert_eq!(classify("HTTP://Example.com"), url("http://example.com/"));
+        assert_eq!(classify("vccat:home"), url("vccat:home"));
+        assert_eq!(classify("about:blank"), url("about:blank"));
+        assert_eq!(classify("file:///etc/hosts"), url("file:///etc/hosts"));
+        assert_eq!(classify("data:text/plain,hi"), url("data:text/plain,hi"));
+    }
+
+    #[test]
+    fn public_domains_use_https() {
+        assert_eq!(classify("example.com"), url("https://example.com/"));
+        assert_eq!(classify("docs.rs/wry"), url("https://docs.rs/wry"));
+        assert_eq!(classify("bbc.co.uk"), url("https://bbc.co.uk/"));
+        assert_eq!(classify("example.com:8443/x?y=1"), url("https://example.com:8443/x?y=1"));
+        assert_eq!(classify("  github.com  "), url("https://github.com/"));
+    }
+
+    #[test]
+    fn local_hosts_and_ports() {
+        assert_eq!(classify("localhost"), url("http://localhost/"));
+        assert_eq!(classify("localhost:3000"), url("http://localhost:3000/"));
+        assert_eq!(classify("app.localhost:8080/api"), url("http://app.localhost:8080/api"));
+        assert_eq!(classify("printer.local"), url("http://printer.local/"));
+        assert_eq!(classify("router.home.arpa"), url("http://router.home.arpa/"));
+        assert_eq!(classify("nas:5000"), url("http://nas:5000/"));
+    }
+
+    #[test]
+    fn ip_addresses() {
+        assert_eq!(classify("192.168.1.1"), url("http://192.168.1.1/"));
+        assert_eq!(classify("192.168.1.1:8080"), url("http://192.168.1.1:8080/"));
+        assert_eq!(classify("10.0.0.2/status"), url("http://10.0.0.2/status"));
+        assert_eq!(classify("[::1]:8000"), url("http://[::1]:8000/"));
+        assert_eq!(classify("[fe80::1]"), url("http://[fe80::1]/"));
+    }
+
+    #[test]
+    fn numbers_are_not_addresses() {
+        assert_eq!(classify("3.14"), search("3.14"));
+        assert_eq!(classify("1.2.3"), search("1.2.3"));
+        assert_eq!(classify("42"), search("42"));
+    }
+
+    #[test]
+    fn intranet_hosts_are_looked_up() {
+        assert_eq!(classify("intranet/wiki"), url("http://intranet/wiki"));
+        assert_eq!(classify("wiki/Start"), lookup("wiki", "http://wiki/Start", "wiki/Start"));
+        assert_eq!(classify("build-server/"), lookup("build-server", "http://build-server/", "build-server/"));
+    }
+
+    #[test]
+    fn file_names_are_looked_up_before_opening() {
+        assert_eq!(classify("README.md"), lookup("readme.md", "https://readme.md/", "README.md"));
+        assert_eq!(classify("main.rs"), lookup("main.rs", "https://main.rs/", "main.rs"));
+        // with a path it is clearly a site
+        assert_eq!(classify("docs.rs/serde"), url("https://docs.rs/serde"));
+    }
+
+    #[test]
+    fn file_paths() {
+        assert_eq!(classify("/home/me/report.html"), url("file:///home/me/report.html"));
+        assert_eq!(classify("/tmp/My Notes.txt"), url("file:///tmp/My%20Notes.txt"));
+        let home = dirs::home_dir().unwrap();
+        let expected = Url::from_file_path(home.join("notes.md")).unwrap();
+        assert_eq!(classify("~/notes.md"), url(expected.as_str()));
+    }
+
+    #[test]
+    fn searches() {
+        assert_eq!(classify("rust borrow checker"), search("rust borrow checker"));
+        assert_eq!(classify("rust"), search("rust"));
+        assert_eq!(classify("rust.borrow"), search("rust.borrow"));
+        assert_eq!(classify("co.uk"), search("co.uk"));
+        assert_eq!(classify("me@example.com"), search("me@example.com"));
+        assert_eq!(classify("what is 2+2?"), search("what is 2+2?"));
+        assert_eq!(classify("c++"), search("c++"));
+        assert_eq!(classify(""), search(""));
+    }
+
+    #[test]
+    fn unknown_schemes_are_not_urls() {
+        assert_eq!(classify("foo:bar"), search("foo:bar"));
+        assert_eq!(classify("javascript:alert(1)"), search("javascript:alert(1)"));
+    }
+}
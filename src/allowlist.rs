@@ -0,0 +1,202 @@
+//! Allowlist: sites where ad blocking is off, chosen with the toolbar
+//! shield; a site covers its subdomains. Kept in filters/allowlist.json and
+//! compiled into the content rules as ignore-previous-rules entries.
+//!
+//! All tabs share one compiled rule set, so the allowlist in force is the
+//! same for every tab, private or not. A change made from a private tab
+//! applies to all tabs until the browser closes and is never written down,
+//! just like the HTTPS-only exceptions made there: a site allowed in a
+//! private tab goes unblocked in normal tabs too, and a saved site turned
+//! back on from one is blocked everywhere until the next start.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Allowlist {
+    /// Sites blocking is off for right now.
+    sites: BTreeSet<String>,
+    /// What allowlist.json holds: `sites` without the private tabs' changes,
+    /// which last until the browser closes.
+    saved: BTreeSet<String>,
+}
+
+static ALLOWLIST: Mutex<Option<Allowlist>> = Mutex::new(None);
+
+fn allowlist_path() -> PathBuf {
+    crate::adblock::filter_store_path().join("allowlist.json")
+}
+
+/// Whether the allowlist entry `site` covers `host`: the same host or a
+/// subdomain of it.
+pub fn covers(site: &str, host: &str) -> bool {
+    host == site || host.strip_suffix(site).is_some_and(|h| h.ends_with('.'))
+}
+
+impl Allowlist {
+    fn load(path: &Path) -> Allowlist {
+        let saved: BTreeSet<String> = fs::read_to_string(path).ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Allowlist { sites: saved.clone(), saved }
+    }
+
+    fn save(&self, path: &Path) {
+        if let Ok(json) = serde_json::to_string_pretty(&self.saved) {
+            fs::write(path, json).ok();
+        }
+    }
+
+    fn covers(&self, host: &str) -> bool {
+        self.sites.iter().any(|s| covers(s, host))
+    }
+
+    /// Turns blocking off for `site`, or back on by dropping every entry
+    /// covering it; returns the entries added or removed, and whether the
+    /// saved list changed. Private tabs leave the saved list alone.
+    fn toggle(&mut self, site: &str, private: bool) -> (Vec<String>, bool) {
+        if !self.covers(site) {
+            self.sites.insert(site.to_string());
+            let saved = !private && self.saved.insert(site.to_string());
+            return (vec![site.to_string()], saved);
+        }
+        // the entry may be for a parent domain of this host
+        let changed: Vec<String> = self.sites.iter().filter(|s| covers(s, site)).cloned().collect();
+        self.sites.retain(|s| !covers(s, site));
+        let before = self.saved.len();
+        if !private { self.saved.retain(|s| !covers(s, site)); }
+        (changed, self.saved.len() != before)
+    }
+
+    fn rules(&self, rules: &str) -> String {
+        let Ok(serde_json::Value::Array(mut all)) = serde_json::from_str(rules) else { return rules.into() };
+        for site in &self.sites {
+            all.push(serde_json::json!({
+                "trigger": {"url-filter": ".*", "if-domain": [format!("*{}", site)]},
+                "action": {"type": "ignore-previous-rules"},
+            }));
+        }
+        serde_json::Value::Array(all).to_string()
+    }
+}
+
+fn with_allowlist<T>(f: impl FnOnce(&mut Allowlist) -> T) -> T {
+    let mut guard = ALLOWLIST.lock().unwrap_or_else(|e| e.into_inner());
+    f(guard.get_or_insert_with(|| Allowlist::load(&allowlist_path())))
+}
+
+/// The site a page counts as for the allowlist: its host without `www.`.
+pub fn site_of(url: &str) -> Option<String> {
+    let u = url::Url::parse(url).ok().filter(|u| matches!(u.scheme(), "http" | "https"))?;
+    let host = u.host_str()?.to_ascii_lowercase();
+    Some(host.strip_prefix("www.").unwrap_or(&host).to_string())
+}
+
+/// Whether blocking is off for the page at `url`.
+pub fn is_allowlisted(url: &str) -> bool {
+    let Some(host) = site_of(url) else { return false };
+    with_allowlist(|l| l.covers(&host))
+}
+
+/// Turns blocking off for the site of `url`, or back on. Returns the
+/// allowlist entries that changed: pages on them and their subdomains are
+/// affected.
+pub fn toggle_allowlisted(url: &str, private: bool) -> Option<Vec<String>> {
+    let site = site_of(url)?;
+    Some(with_allowlist(|l| {
+        let (changed, saved) = l.toggle(&site, private);
+        if saved { l.save(&allowlist_path()); }
+        changed
+    }))
+}
+
+/// `rules` with the allowlist appended; entries last, so they override
+/// every block and hiding rule before them on those sites.
+pub fn with_allowlist_rules(rules: &str) -> String {
+    with_allowlist(|l| l.rules(rules))
+}
+
+/// The allowlisted sites, for the scriptlets' block lists.
+pub fn allowlisted_sites() -> Vec<String> {
+    with_allowlist(|l| l.sites.iter().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let d = std::env::temp_dir().join(format!("vccat-allowlist-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&d).unwrap();
+        d.join("allowlist.json")
+    }
+
+    #[test]
+    fn sites_cover_subdomains() {
+        assert_eq!(site_of("https://WWW.Example.com/a").as_deref(), Some("example.com"));
+        assert_eq!(site_of("vccat:settings"), None);
+        assert!(covers("example.com", "example.com"));
+        assert!(covers("example.com", "shop.example.com"));
+        assert!(!covers("example.com", "badexample.com"));
+        assert!(!covers("shop.example.com", "example.com"));
+    }
+
+    #[test]
+    fn toggles_and_saves() {
+        let path = scratch("toggle");
+        fs::write(&path, r#"["example.com"]"#).unwrap();
+        let mut l = Allowlist::load(&path);
+        assert!(l.covers("news.example.com"));
+
+        // back on from a subdomain drops the parent entry
+        assert_eq!(l.toggle("news.example.com", false), (vec!["example.com".to_string()], true));
+        assert!(!l.covers("example.com"));
+        assert_eq!(l.toggle("news.example.com", false), (vec!["news.example.com".to_string()], true));
+        l.save(&path);
+        assert_eq!(Allowlist::load(&path).saved.into_iter().collect::<Vec<_>>(), ["news.example.com"]);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    // one allowlist is in force for all tabs; private tabs' changes to it
+    // last until the browser closes
+    #[test]
+    fn private_changes_last_the_session() {
+        let path = scratch("private");
+        fs::write(&path, r#"["example.com"]"#).unwrap();
+        let mut l = Allowlist::load(&path);
+
+        assert_eq!(l.toggle("other.org", true), (vec!["other.org".to_string()], false));
+        assert_eq!(l.toggle("example.com", true), (vec!["example.com".to_string()], false));
+        // what every tab gets now, normal ones included
+        assert!(l.covers("other.org") && !l.covers("example.com"));
+        assert_eq!(allowed_sites(&l.rules("[]")), ["other.org"]);
+        // nothing of it is written down
+        l.save(&path);
+        let next_start = Allowlist::load(&path);
+        assert!(next_start.covers("example.com") && !next_start.covers("other.org"));
+
+        // a normal tab's change is saved; the private one stays unsaved
+        assert_eq!(l.toggle("news.example.org", false), (vec!["news.example.org".to_string()], true));
+        assert_eq!(l.saved.iter().collect::<Vec<_>>(), ["example.com", "news.example.org"]);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    fn allowed_sites(rules: &str) -> Vec<String> {
+        let rules: Vec<serde_json::Value> = serde_json::from_str(rules).unwrap();
+        rules.iter().map(|r| r["trigger"]["if-domain"][0].as_str().unwrap().trim_start_matches('*').to_string())
+            .collect()
+    }
+
+    #[test]
+    fn rules_come_last() {
+        let mut l = Allowlist::default();
+        l.toggle("example.com", false);
+        let rules: Vec<serde_json::Value> = serde_json::from_str(&l.rules(
+            r#"[{"trigger":{"url-filter":"ads"},"action":{"type":"block"}}]"#)).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1]["action"]["type"], "ignore-previous-rules");
+        assert_eq!(rules[1]["trigger"]["if-domain"][0], "*example.com");
+        assert_eq!(l.rules("not json"), "not json");
+    }
+}
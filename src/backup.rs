@@ -0,0 +1,246 @@
+//! Profile backup: everything under `storage::data_dir()` packed into one
+//! versioned .tar.gz with a manifest, and restored from it on any machine.
+//!
+//! The WebKit cache is never in the archive, so there is no option to leave
+//! it out. WebKit keeps cookies, site storage and its cache in directories
+//! of its own: wry gives every tab a WebKit context of its own, with WebKit's
+//! default directories, and only lets a context's data directory be chosen,
+//! not its cache. One shared context could be given a data directory inside
+//! the profile, but each tab registers its own handler for the internal
+//! scheme, which a shared context refuses. Backing up site data and making
+//! the cache optional waits for the tabs to share a context.
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Archive format version written into the manifest.
+pub const FORMAT: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+/// Files describing the running instance rather than the profile.
+const SKIP_FILES: &[&str] = &["running.lock"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub format: u32,
+    pub app_version: String,
+    pub created: u64,
+    pub files: Vec<FileEntry>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn collect(dir: &Path, rel: &str, out: &mut Vec<String>) {
+    let Ok(rd) = fs::read_dir(dir) else { return };
+    let mut entries: Vec<_> = rd.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for e in entries {
+        let name = e.file_name().to_string_lossy().into_owned();
+        let path = if rel.is_empty() { name.clone() } else { format!("{}/{}", rel, name) };
+        let Ok(ft) = e.file_type() else { continue };
+        if ft.is_dir() {
+            collect(&e.path(), &path, out);
+        } else if ft.is_file() && !(rel.is_empty() && SKIP_FILES.contains(&name.as_str())) {
+            out.push(path);
+        }
+    }
+}
+
+/// Writes the profile to `dest` as a .tar.gz; the manifest is the last entry
+/// so its checksums describe exactly the bytes that were archived.
+pub fn export(dest: &Path) -> Result<Manifest, String> {
+    export_from(&crate::storage::data_dir(), dest)
+}
+
+fn export_from(root: &Path, dest: &Path) -> Result<Manifest, String> {
+    let mut files = Vec::new();
+    collect(root, "", &mut files);
+
+    let out = fs::File::create(dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+    let gz = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+    let mut tar = tar::Builder::new(gz);
+    let mut manifest = Manifest {
+        format: FORMAT,
+        app_version: env!("CARGO_PKG_VERSION").into(),
+        created: crate::storage::now(),
+        files: Vec::new(),
+    };
+    for rel in files {
+        // files may vanish while the browser runs; skip them
+        let Ok(bytes) = fs::read(root.join(&rel)) else { continue };
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(manifest.created);
+        tar.append_data(&mut header, &rel, bytes.as_slice()).map_err(|e| e.to_string())?;
+        manifest.files.push(FileEntry {
+            path: rel,
+            size: bytes.len() as u64,
+            sha256: hex(&Sha256::digest(&bytes)),
+        });
+    }
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(manifest.created);
+    tar.append_data(&mut header, MANIFEST, json.as_slice()).map_err(|e| e.to_string())?;
+    tar.into_inner().map_err(|e| e.to_string())?
+        .finish().map_err(|e| e.to_string())?
+        .flush().map_err(|e| e.to_string())?;
+    Ok(manifest)
+}
+
+/// Only plain relative paths may be extracted.
+fn safe_rel(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+        && path.components().next().is_some()
+}
+
+fn extract(archive: &Path, staging: &Path) -> Result<Manifest, String> {
+    let file = fs::File::open(archive).map_err(|e| format!("{}: {}", archive.display(), e))?;
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut manifest: Option<Manifest> = None;
+    let mut found: HashMap<String, (u64, String)> = HashMap::new();
+
+    for entry in tar.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| format!("uszkodzone archiwum: {}", e))?;
+        let path = entry.path().map_err(|e| e.to_string())?.into_owned();
+        if !entry.header().entry_type().is_file() || !safe_rel(&path) {
+            return Err(format!("niedozwolony wpis w archiwum: {}", path.display()));
+        }
+        let rel = path.to_string_lossy().replace('\\', "/");
+        if rel == MANIFEST {
+            let mut s = String::new();
+            entry.read_to_string(&mut s).map_err(|e| e.to_string())?;
+            manifest = Some(serde_json::from_str(&s).map_err(|e| format!("błędny manifest: {}", e))?);
+            continue;
+        }
+        let dest = staging.join(&path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut out = fs::File::create(&dest).map_err(|e| e.to_string())?;
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let n = entry.read(&mut buf).map_err(|e| format!("uszkodzone archiwum: {}", e))?;
+            if n == 0 { break; }
+            hasher.update(&buf[..n]);
+            out.write_all(&buf[..n]).map_err(|e| e.to_string())?;
+            size += n as u64;
+        }
+        found.insert(rel, (size, hex(&hasher.finalize())));
+    }
+
+    let manifest = manifest.ok_or("archiwum nie zawiera manifestu")?;
+    if manifest.format > FORMAT {
+        return Err(format!("archiwum w nowszym formacie ({}) niż obsługiwany ({})", manifest.format, FORMAT));
+    }
+    for f in &manifest.files {
+        match found.remove(&f.path) {
+            Some((size, sum)) if size == f.size && sum == f.sha256 => {}
+            Some(_) => return Err(format!("suma kontrolna nie zgadza się: {}", f.path)),
+            None => return Err(format!("brak pliku z manifestu: {}", f.path)),
+        }
+    }
+    if let Some(extra) = found.keys().next() {
+        return Err(format!("plik spoza manifestu: {}", extra));
+    }
+    Ok(manifest)
+}
+
+/// Replaces the profile with the contents of `archive`. The archive is fully
+/// extracted and checked against its manifest before anything is touched;
+/// the previous profile is kept next to it. Refuses to run while another
+/// instance uses the profile. Returns the manifest and the path of the
+/// previous profile.
+pub fn restore(archive: &Path) -> Result<(Manifest, PathBuf), String> {
+    if let Some(pid) = crate::storage::running_instance() {
+        return Err(format!("profil jest używany przez działającą przeglądarkę (pid {})", pid));
+    }
+    restore_into(&crate::storage::data_dir(), archive)
+}
+
+fn restore_into(target: &Path, archive: &Path) -> Result<(Manifest, PathBuf), String> {
+    let staging = target.with_file_name("vccat-browser.restore");
+    if staging.exists() { fs::remove_dir_all(&staging).map_err(|e| e.to_string())?; }
+    fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+
+    let manifest = match extract(archive, &staging) {
+        Ok(m) => m,
+        Err(e) => { fs::remove_dir_all(&staging).ok(); return Err(e); }
+    };
+    let previous = target.with_file_name(format!("vccat-browser.before-restore-{}", crate::storage::now()));
+    fs::rename(target, &previous).map_err(|e| e.to_string())?;
+    if let Err(e) = fs::rename(&staging, target) {
+        fs::rename(&previous, target).ok();
+        return Err(e.to_string());
+    }
+    Ok((manifest, previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let d = std::env::temp_dir().join(format!("vccat-backup-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&d).ok();
+        fs::create_dir_all(&d).unwrap();
+        d
+    }
+
+    #[test]
+    fn only_plain_relative_paths() {
+        assert!(safe_rel(Path::new("history.json")));
+        assert!(safe_rel(Path::new("reading/1.html")));
+        assert!(!safe_rel(Path::new("")));
+        assert!(!safe_rel(Path::new("/etc/passwd")));
+        assert!(!safe_rel(Path::new("../outside")));
+        assert!(!safe_rel(Path::new("reading/../../outside")));
+        assert!(!safe_rel(Path::new("./history.json")));
+    }
+
+    #[test]
+    fn round_trip() {
+        let base = dir("round");
+        let profile = base.join("vccat-browser");
+        fs::create_dir_all(profile.join("reading")).unwrap();
+        fs::write(profile.join("bookmarks.json"), "{}").unwrap();
+        fs::write(profile.join("reading/1.html"), "<p>x</p>").unwrap();
+        fs::write(profile.join("running.lock"), "1").unwrap();
+        let archive = base.join("kopia.tar.gz");
+        let m = export_from(&profile, &archive).unwrap();
+        let mut paths: Vec<&str> = m.files.iter().map(|f| f.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, ["bookmarks.json", "reading/1.html"]);
+
+        fs::write(profile.join("bookmarks.json"), "zmienione").unwrap();
+        let (_, previous) = restore_into(&profile, &archive).unwrap();
+        assert_eq!(fs::read_to_string(profile.join("bookmarks.json")).unwrap(), "{}");
+        assert_eq!(fs::read_to_string(profile.join("reading/1.html")).unwrap(), "<p>x</p>");
+        assert!(!profile.join("running.lock").exists());
+        assert_eq!(fs::read_to_string(previous.join("bookmarks.json")).unwrap(), "zmienione");
+
+        // a damaged archive leaves the profile alone
+        let mut bytes = fs::read(&archive).unwrap();
+        bytes.truncate(bytes.len() / 2);
+        fs::write(&archive, bytes).unwrap();
+        assert!(restore_into(&profile, &archive).is_err());
+        assert_eq!(fs::read_to_string(profile.join("bookmarks.json")).unwrap(), "{}");
+        fs::remove_dir_all(&base).ok();
+    }
+}
@@ -0,0 +1,291 @@
+//! Blocking log: which filter of which list stopped a request. WebKit's
+//! content blocker stops requests without telling the application, so pages
+//! report the resources that failed to load and those are matched against
+//! the subscribed lists again here; a blocking filter that matches (and no
+//! exception that does) means the blocker stopped the request. The matcher
+//! is global because the refresh thread builds it and the explainer thread
+//! and the event loop ask it.
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use serde::Serialize;
+use crate::filters::{NetworkFilter, Request, ResourceType};
+
+/// Requests kept per tab; the count goes on past it.
+pub const MAX_ENTRIES: usize = 500;
+
+/// Failed requests waiting for the explainer; those past it are not logged.
+const MAX_QUEUED: usize = 256;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub url: String,
+    /// The filter as written in its list.
+    pub rule: String,
+    /// Title of the list it came from.
+    pub list: String,
+}
+
+/// What the lists make of a request.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Blocked(Entry),
+    /// An exception matched: the one that did, and the block it overrides.
+    Allowed { exception: Entry, block: Option<Entry> },
+    NoMatch,
+}
+
+struct Tagged {
+    filter: NetworkFilter,
+    list: usize,
+}
+
+/// The alphanumeric run of the pattern that any url the filter matches
+/// holds whole, as one of its own runs (the longest such, lowercase). A run
+/// qualifies when neither end may run on into more letters: an end next to
+/// a literal character or `^`, or at an anchor.
+fn token_of(f: &NetworkFilter) -> Option<String> {
+    let p = f.pattern.as_bytes();
+    let mut best: Option<&[u8]> = None;
+    let mut i = 0;
+    while i < p.len() {
+        if !p[i].is_ascii_alphanumeric() { i += 1; continue; }
+        let start = i;
+        while i < p.len() && p[i].is_ascii_alphanumeric() { i += 1; }
+        let before = if start == 0 { f.host_anchor || f.start_anchor } else { p[start - 1] != b'*' };
+        let after = if i == p.len() { f.end_anchor } else { p[i] != b'*' };
+        if before && after && best.is_none_or(|b| i - start > b.len()) { best = Some(&p[start..i]); }
+    }
+    best.map(|b| String::from_utf8_lossy(b).to_ascii_lowercase())
+}
+
+/// Filters by token, so a url is only matched against those whose token it
+/// holds and the few without one.
+#[derive(Default)]
+struct Index {
+    filters: Vec<Tagged>,
+    by_token: HashMap<String, Vec<usize>>,
+    rest: Vec<usize>,
+}
+
+impl Index {
+    fn push(&mut self, filter: NetworkFilter, list: usize) {
+        let i = self.filters.len();
+        match token_of(&filter) {
+            Some(t) => self.by_token.entry(t).or_default().push(i),
+            None => self.rest.push(i),
+        }
+        self.filters.push(Tagged { filter, list });
+    }
+
+    /// Filters that may match a url with these tokens, in list order.
+    fn candidates<'a>(&'a self, tokens: &[&str]) -> impl Iterator<Item = &'a Tagged> {
+        let mut ids: Vec<usize> = tokens.iter().filter_map(|t| self.by_token.get(*t)).flatten()
+            .chain(&self.rest).copied().collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter().map(move |i| &self.filters[i])
+    }
+}
+
+#[derive(Default)]
+pub struct Matcher {
+    lists: Vec<String>,
+    blocks: Index,
+    exceptions: Index,
+}
+
+fn host_of(url: &str) -> String {
+    url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase)).unwrap_or_default()
+}
+
+impl Matcher {
+    /// Network filters of each list, by list title.
+    pub fn new(lists: Vec<(String, Vec<NetworkFilter>)>) -> Self {
+        let mut m = Matcher::default();
+        for (i, (title, filters)) in lists.into_iter().enumerate() {
+            m.lists.push(title);
+            for f in filters {
+                if f.exception { m.exceptions.push(f, i) } else { m.blocks.push(f, i) }
+            }
+        }
+        m
+    }
+
+    pub fn verdict(&self, url: &str, page_url: &str, kind: ResourceType) -> Verdict {
+        verdict_in(&[(self, None)], url, page_url, kind)
+    }
+
+    /// The verdict with the lists of `draft` standing in for this matcher's
+    /// list `title`, without copying either.
+    pub fn verdict_with(&self, draft: &Matcher, title: &str, url: &str, page_url: &str,
+                        kind: ResourceType) -> Verdict {
+        let skip = self.lists.iter().position(|l| l == title);
+        verdict_in(&[(self, skip), (draft, None)], url, page_url, kind)
+    }
+
+    /// The filter that blocked `url` on the page at `page_url`, if one did.
+    pub fn explain(&self, url: &str, page_url: &str, kind: ResourceType) -> Option<Entry> {
+        match self.verdict(url, page_url, kind) {
+            Verdict::Blocked(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Asks each matcher in turn, leaving out the list it is given by index. The
+/// first matching block wins unless a later one is `$important`, and the
+/// first matching exception overrides any but an important block.
+fn verdict_in(parts: &[(&Matcher, Option<usize>)], url: &str, page_url: &str, kind: ResourceType) -> Verdict {
+    let (host, page_host) = (host_of(url), host_of(page_url));
+    let site = |h: &str| crate::address::registrable_domain(h).unwrap_or_else(|| h.to_string());
+    let third_party = site(&host) != site(&page_host);
+    let req = Request { url, lower: url.to_ascii_lowercase(), page_host, third_party, kind };
+    let tokens: Vec<&str> = req.lower.split(|c: char| !c.is_ascii_alphanumeric()).filter(|t| !t.is_empty()).collect();
+    let mut block: Option<(Entry, bool)> = None;
+    let mut exception: Option<Entry> = None;
+    for &(m, skip) in parts {
+        let hit = |t: &&Tagged| Some(t.list) != skip && t.filter.matches(&req);
+        let entry = |t: &Tagged| Entry { url: url.into(), rule: t.filter.raw.clone(), list: m.lists[t.list].clone() };
+        if !block.as_ref().is_some_and(|b| b.1) {
+            let found = m.blocks.candidates(&tokens).filter(hit).min_by_key(|t| !t.filter.important);
+            if let Some(t) = found.filter(|t| block.is_none() || t.filter.important) {
+                block = Some((entry(t), t.filter.important));
+            }
+        }
+        if exception.is_none() {
+            exception = m.exceptions.candidates(&tokens).find(hit).map(entry);
+        }
+    }
+    match (block, exception) {
+        (Some((b, true)), _) => Verdict::Blocked(b),
+        (block, Some(e)) => Verdict::Allowed { exception: e, block: block.map(|b| b.0) },
+        (Some((b, _)), None) => Verdict::Blocked(b),
+        (None, None) => Verdict::NoMatch,
+    }
+}
+
+static MATCHER: Mutex<Option<Arc<Matcher>>> = Mutex::new(None);
+
+pub fn install(matcher: Matcher) {
+    *MATCHER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(matcher));
+}
+
+fn explain(url: &str, page_url: &str, kind: ResourceType) -> Option<Entry> {
+    let matcher = MATCHER.lock().unwrap_or_else(|e| e.into_inner()).clone()?;
+    matcher.explain(url, page_url, kind)
+}
+
+/// A request a page reported as failed.
+pub struct Failure {
+    pub tab: usize,
+    pub url: String,
+    pub page_url: String,
+    pub kind: ResourceType,
+}
+
+/// Starts the thread that matches failed requests against the lists, so a
+/// page failing many of them doesn't hold up the UI. `blocked` gets the tab,
+/// page url and log entry of each a filter stopped. Failures sent while
+/// `MAX_QUEUED` are waiting are dropped (`try_send`).
+pub fn spawn_explainer(blocked: impl Fn(usize, String, Entry) + Send + 'static) -> mpsc::SyncSender<Failure> {
+    let (tx, rx) = mpsc::sync_channel::<Failure>(MAX_QUEUED);
+    std::thread::spawn(move || {
+        for f in rx {
+            if let Some(entry) = explain(&f.url, &f.page_url, f.kind) {
+                blocked(f.tab, f.page_url, entry);
+            }
+        }
+    });
+    tx
+}
+
+/// The verdict with the list `title` as given instead of as published,
+/// for trying out rules before saving them.
+pub fn verdict_with(url: &str, page_url: &str, kind: ResourceType, title: &str,
+                    filters: Vec<NetworkFilter>) -> Verdict {
+    let draft = Matcher::new(vec![(title.to_string(), filters)]);
+    match MATCHER.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+        Some(matcher) => matcher.verdict_with(&draft, title, url, page_url, kind),
+        None => draft.verdict(url, page_url, kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::parse_list;
+    use crate::filters::ResourceType::{Image, Script};
+
+    fn matcher() -> Matcher {
+        Matcher::new(vec![
+            ("EasyList".into(), parse_list("||doubleclick.net^\n/banner/ads/*\n||adserver.com^$third-party\n").network),
+            ("Własne".into(), parse_list("@@||doubleclick.net/ok.js\n||doubleclick.net/ok.js$important\n@@/banner/ads/keep.png\n").network),
+        ])
+    }
+
+    #[test]
+    fn names_rule_and_list() {
+        let m = matcher();
+        assert_eq!(m.explain("https://ad.doubleclick.net/x.js", "https://news.org/", Script), Some(Entry {
+            url: "https://ad.doubleclick.net/x.js".into(), rule: "||doubleclick.net^".into(), list: "EasyList".into(),
+        }));
+        // an exception elsewhere is overridden by an $important block
+        assert_eq!(m.explain("https://doubleclick.net/ok.js", "https://news.org/", Script).unwrap().list, "Własne");
+        assert_eq!(m.explain("https://x.org/banner/ads/1.png", "https://x.org/", Image).unwrap().rule, "/banner/ads/*");
+    }
+
+    #[test]
+    fn verdicts_with_draft_rules() {
+        let m = matcher();
+        let keep = "https://x.org/banner/ads/keep.png";
+        assert!(matches!(m.verdict(keep, "https://x.org/", Image),
+            Verdict::Allowed { ref exception, block: Some(ref b) }
+                if exception.rule == "@@/banner/ads/keep.png" && b.rule == "/banner/ads/*"));
+        // the draft replaces the list of the same title
+        let draft = Matcher::new(vec![("Własne".into(), parse_list("||cdn.example^\n").network)]);
+        let verdict = |url: &str, page: &str| m.verdict_with(&draft, "Własne", url, page, Script);
+        assert!(matches!(verdict(keep, "https://x.org/"), Verdict::Blocked(ref e) if e.rule == "/banner/ads/*"));
+        assert!(matches!(verdict("https://cdn.example/a.js", "https://x.org/"), Verdict::Blocked(ref e) if e.list == "Własne"));
+        assert_eq!(verdict("https://news.org/", "https://news.org/"), Verdict::NoMatch);
+        // an $important block of the draft wins over an earlier one of another list
+        let draft = Matcher::new(vec![("Własne".into(), parse_list("/banner/ads/keep.png$important\n").network)]);
+        assert!(matches!(m.verdict_with(&draft, "Własne", keep, "https://x.org/", Image),
+            Verdict::Blocked(ref e) if e.rule == "/banner/ads/keep.png$important"));
+    }
+
+    #[test]
+    fn unblocked_failures_are_not_claimed() {
+        let m = matcher();
+        assert_eq!(m.explain("https://x.org/banner/ads/keep.png", "https://x.org/", Image), None);
+        assert_eq!(m.explain("https://cdn.example/a.js", "https://news.org/", Script), None);
+        // first-party requests to a $third-party filter's host
+        assert_eq!(m.explain("https://img.adserver.com/a.png", "https://www.adserver.com/", Image), None);
+        assert!(m.explain("https://img.adserver.com/a.png", "https://news.org/", Image).is_some());
+    }
+
+    #[test]
+    fn checks_resource_type() {
+        let m = Matcher::new(vec![("EasyList".into(), parse_list("||ads.example^$script\n@@||ads.example/ok.js$image\n").network)]);
+        assert!(m.explain("https://ads.example/a.js", "https://news.org/", Script).is_some());
+        // an image failing there is not the script filter's doing
+        assert_eq!(m.explain("https://ads.example/a.png", "https://news.org/", Image), None);
+        assert!(m.explain("https://ads.example/ok.js", "https://news.org/", Script).is_some());
+    }
+
+    #[test]
+    fn indexes_by_token() {
+        let tokens = |line: &str| token_of(&parse_list(line).network[0]);
+        assert_eq!(tokens("||doubleclick.net^").as_deref(), Some("doubleclick"));
+        assert_eq!(tokens("/banner/ads/*").as_deref(), Some("banner"));
+        // runs that may go on into more letters in the url don't qualify
+        assert_eq!(tokens("adserv*.com/x").as_deref(), Some("com"));
+        assert_eq!(tokens("-advert-"), Some("advert".into()));
+        assert_eq!(tokens("track*ing"), None);
+        let m = Matcher::new(vec![("L".into(), parse_list("track*ing\n||adnxs.com^\n-advert-\n").network)]);
+        let tokens = ["https", "cdn", "adnxs", "com", "x"];
+        let rules: Vec<&str> = m.blocks.candidates(&tokens).map(|t| t.filter.raw.as_str()).collect();
+        assert_eq!(rules, ["track*ing", "||adnxs.com^"]);
+        assert!(m.explain("https://x.org/img-advert-1.png", "https://x.org/", Image).is_some());
+        assert!(m.explain("https://x.org/img-adverts-1.png", "https://x.org/", Image).is_none());
+    }
+}
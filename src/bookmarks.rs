@@ -0,0 +1,412 @@
+//! Bookmarks: folders, tags and keywords, stored in bookmarks.json.
+//! Import and export use the Netscape bookmark HTML format shared by all
+//! major browsers.
+use serde::{Deserialize, Serialize};
+use crate::storage::now;
+
+/// Id of the implicit top-level folder.
+pub const ROOT: u64 = 0;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Folder {
+    pub id: u64,
+    pub name: String,
+    pub parent: u64,
+    pub created: u64,
+    pub modified: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Bookmark {
+    pub id: u64,
+    pub url: String,
+    pub title: String,
+    pub folder: u64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub keyword: Option<String>,
+    pub created: u64,
+    pub modified: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Bookmarks {
+    pub folders: Vec<Folder>,
+    pub items: Vec<Bookmark>,
+    next_id: u64,
+}
+
+pub fn load() -> Bookmarks {
+    if let Some(s) = crate::storage::read_protected("bookmarks.json") {
+        if let Ok(b) = serde_json::from_str::<Bookmarks>(&s) {
+            return b;
+        }
+    }
+    Bookmarks { next_id: 1, ..Default::default() }
+}
+
+pub fn save(bookmarks: &Bookmarks) {
+    if let Ok(s) = serde_json::to_string_pretty(bookmarks) {
+        crate::storage::write_protected("bookmarks.json", &s);
+    }
+}
+
+/// Splits a comma separated tag string, dropping empty and duplicate tags.
+pub fn parse_tags(raw: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for t in raw.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if !tags.iter().any(|x| x == t) { tags.push(t.to_string()); }
+    }
+    tags
+}
+
+impl Bookmarks {
+    fn alloc_id(&mut self) -> u64 {
+        let id = self.next_id.max(1);
+        self.next_id = id + 1;
+        id
+    }
+
+    pub fn find_by_url(&self, url: &str) -> Option<&Bookmark> {
+        self.items.iter().find(|b| b.url == url)
+    }
+
+    pub fn is_bookmarked(&self, url: &str) -> bool {
+        self.find_by_url(url).is_some()
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Bookmark> {
+        self.items.iter_mut().find(|b| b.id == id)
+    }
+
+    pub fn folder(&self, id: u64) -> Option<&Folder> {
+        self.folders.iter().find(|f| f.id == id)
+    }
+
+    pub fn add(&mut self, url: &str, title: &str, folder: u64) -> u64 {
+        let id = self.alloc_id();
+        let ts = now();
+        self.items.push(Bookmark {
+            id, url: url.into(), title: title.into(), folder,
+            tags: vec![], keyword: None, created: ts, modified: ts,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.items.retain(|b| b.id != id);
+    }
+
+    /// Bookmarks `url` into the root folder, or removes every bookmark of it.
+    /// Returns whether the url is bookmarked afterwards.
+    pub fn toggle(&mut self, url: &str, title: &str) -> bool {
+        if self.is_bookmarked(url) {
+            self.items.retain(|b| b.url != url);
+            false
+        } else {
+            self.add(url, title, ROOT);
+            true
+        }
+    }
+
+    pub fn add_folder(&mut self, name: &str, parent: u64) -> u64 {
+        let id = self.alloc_id();
+        let ts = now();
+        self.folders.push(Folder { id, name: name.into(), parent, created: ts, modified: ts });
+        id
+    }
+
+    pub fn rename_folder(&mut self, id: u64, name: &str) {
+        if let Some(f) = self.folders.iter_mut().find(|f| f.id == id) {
+            f.name = name.into();
+            f.modified = now();
+        }
+    }
+
+    /// Removes a folder together with its subfolders and their bookmarks.
+    pub fn remove_folder(&mut self, id: u64) {
+        if id == ROOT { return; }
+        let mut doomed = vec![id];
+        let mut i = 0;
+        while i < doomed.len() {
+            let parent = doomed[i];
+            doomed.extend(self.folders.iter().filter(|f| f.parent == parent).map(|f| f.id));
+            i += 1;
+        }
+        self.folders.retain(|f| !doomed.contains(&f.id));
+        self.items.retain(|b| !doomed.contains(&b.folder));
+    }
+
+    /// Bookmark bound to a toolbar keyword, e.g. `gh` for GitHub.
+    pub fn by_keyword(&self, keyword: &str) -> Option<&Bookmark> {
+        self.items.iter().find(|b| b.keyword.as_deref() == Some(keyword))
+    }
+
+    /// Resolves toolbar input starting with a bookmark keyword. Text after
+    /// the keyword replaces `%s` in the bookmarked url.
+    pub fn resolve_keyword(&self, input: &str) -> Option<String> {
+        let input = input.trim();
+        let (kw, rest) = input.split_once(' ').unwrap_or((input, ""));
+        let m = self.by_keyword(kw)?;
+        if m.url.contains("%s") {
+            Some(m.url.replace("%s", &urlencoding::encode(rest.trim())))
+        } else if rest.trim().is_empty() {
+            Some(m.url.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Folder path from the root, e.g. "Praca / Wydania".
+    pub fn folder_path(&self, id: u64) -> String {
+        let mut parts = Vec::new();
+        let mut cur = id;
+        while let Some(f) = self.folder(cur) {
+            parts.push(f.name.clone());
+            if parts.len() > 64 { break; }
+            cur = f.parent;
+        }
+        parts.reverse();
+        parts.join(" / ")
+    }
+}
+
+// ── Netscape bookmark HTML ────────────────────────────────────────────────────
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(p) = rest.find('&') {
+        out.push_str(&rest[..p]);
+        rest = &rest[p..];
+        let Some(end) = rest.find(';').filter(|&e| e <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let ent = &rest[1..end];
+        let ch = match ent {
+            "amp" => Some('&'), "lt" => Some('<'), "gt" => Some('>'),
+            "quot" => Some('"'), "apos" => Some('\''), "nbsp" => Some('\u{a0}'),
+            _ if ent.starts_with("#x") || ent.starts_with("#X") =>
+                u32::from_str_radix(&ent[2..], 16).ok().and_then(char::from_u32),
+            _ if ent.starts_with('#') => ent[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match ch {
+            Some(c) => { out.push(c); rest = &rest[end + 1..]; }
+            None => { out.push('&'); rest = &rest[1..]; }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn export_folder(b: &Bookmarks, folder: u64, depth: usize, out: &mut String) {
+    let pad = "    ".repeat(depth);
+    for f in b.folders.iter().filter(|f| f.parent == folder) {
+        out.push_str(&format!("{pad}<DT><H3 ADD_DATE=\"{}\" LAST_MODIFIED=\"{}\">{}</H3>\n",
+                              f.created, f.modified, escape(&f.name)));
+        out.push_str(&format!("{pad}<DL><p>\n"));
+        export_folder(b, f.id, depth + 1, out);
+        out.push_str(&format!("{pad}</DL><p>\n"));
+    }
+    for m in b.items.iter().filter(|m| m.folder == folder) {
+        let mut attrs = format!("HREF=\"{}\" ADD_DATE=\"{}\" LAST_MODIFIED=\"{}\"",
+                                escape(&m.url), m.created, m.modified);
+        if !m.tags.is_empty() {
+            attrs.push_str(&format!(" TAGS=\"{}\"", escape(&m.tags.join(","))));
+        }
+        if let Some(k) = &m.keyword {
+            attrs.push_str(&format!(" SHORTCUTURL=\"{}\"", escape(k)));
+        }
+        out.push_str(&format!("{pad}<DT><A {}>{}</A>\n", attrs, escape(&m.title)));
+    }
+}
+
+pub fn export_netscape(b: &Bookmarks) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+         <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+         <TITLE>Bookmarks</TITLE>\n<H1>Bookmarks</H1>\n<DL><p>\n");
+    export_folder(b, ROOT, 1, &mut out);
+    out.push_str("</DL><p>\n");
+    out
+}
+
+/// Parses `key="value"` pairs of a tag body; keys are upper-cased.
+fn parse_attrs(tag: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = tag.split_once(char::is_whitespace).map(|(_, r)| r).unwrap_or("");
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_ascii_uppercase();
+        let after = rest[eq + 1..].trim_start();
+        let (val, next) = if let Some(q) = after.strip_prefix('"') {
+            match q.find('"') { Some(e) => (&q[..e], &q[e + 1..]), None => (q, "") }
+        } else if let Some(q) = after.strip_prefix('\'') {
+            match q.find('\'') { Some(e) => (&q[..e], &q[e + 1..]), None => (q, "") }
+        } else {
+            let e = after.find(char::is_whitespace).unwrap_or(after.len());
+            (&after[..e], &after[e..])
+        };
+        attrs.push((key, unescape(val)));
+        rest = next;
+    }
+    attrs
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+}
+
+/// Imports a Netscape bookmark file below `into`, recreating its folders.
+/// Urls that are already bookmarked are skipped. Returns the number of
+/// bookmarks added.
+pub fn import_netscape(b: &mut Bookmarks, html: &str, into: u64) -> usize {
+    // closing tags are looked up in an upper-cased copy, once; ASCII case
+    // changes keep the byte offsets the same
+    let upper = html.to_ascii_uppercase();
+    let mut known: std::collections::HashSet<String> = b.items.iter().map(|m| m.url.clone()).collect();
+    let mut stack = vec![into];
+    let mut pending_folder: Option<u64> = None;
+    let mut added = 0;
+    let mut pos = 0;
+    while let Some(lt) = html[pos..].find('<') {
+        let start = pos + lt + 1;
+        let Some(gt) = html[start..].find('>') else { break };
+        let tag = &html[start..start + gt];
+        pos = start + gt + 1;
+        let name = tag.split(|c: char| c.is_whitespace()).next().unwrap_or("").to_ascii_uppercase();
+        let parent = *stack.last().unwrap_or(&into);
+        // the text up to the closing tag, which the scan then goes on from
+        let mut text_until = |close: &str| {
+            let end = upper[pos..].find(close).map_or(html.len(), |e| pos + e);
+            let text = unescape(html[pos..end].trim());
+            pos = end;
+            text
+        };
+        match name.as_str() {
+            "H3" => {
+                let folder_name = text_until("</H3");
+                let attrs = parse_attrs(tag);
+                let id = b.add_folder(&folder_name, parent);
+                if let Some(f) = b.folders.last_mut() {
+                    if let Some(t) = attr(&attrs, "ADD_DATE").and_then(|v| v.parse().ok()) { f.created = t; }
+                    if let Some(t) = attr(&attrs, "LAST_MODIFIED").and_then(|v| v.parse().ok()) { f.modified = t; }
+                }
+                pending_folder = Some(id);
+            }
+            "DL" => {
+                // a list opens the folder named just before it; the file's
+                // own top-level list simply re-enters `into`
+                stack.push(pending_folder.take().unwrap_or(parent));
+            }
+            "/DL" if stack.len() > 1 => {
+                stack.pop();
+            }
+            "A" => {
+                let title = text_until("</A");
+                let attrs = parse_attrs(tag);
+                let Some(url) = attr(&attrs, "HREF") else { continue };
+                if url.is_empty() || url.starts_with("place:") || !known.insert(url.to_string()) { continue; }
+                b.add(url, &title, parent);
+                if let Some(m) = b.items.last_mut() {
+                    if let Some(t) = attr(&attrs, "ADD_DATE").and_then(|v| v.parse().ok()) { m.created = t; }
+                    if let Some(t) = attr(&attrs, "LAST_MODIFIED").and_then(|v| v.parse().ok()) { m.modified = t; }
+                    else { m.modified = m.created; }
+                    if let Some(t) = attr(&attrs, "TAGS") { m.tags = parse_tags(t); }
+                    m.keyword = attr(&attrs, "SHORTCUTURL").filter(|k| !k.is_empty()).map(String::from);
+                }
+                added += 1;
+            }
+            _ => {}
+        }
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Bookmarks {
+        let mut b = Bookmarks::default();
+        let work = b.add_folder("Praca", ROOT);
+        let releases = b.add_folder("Wydania & <plany>", work);
+        b.add("https://example.com/", "Example", ROOT);
+        let id = b.add("https://github.com/search?q=%s", "GitHub \"szukaj\"", work);
+        let m = b.get_mut(id).unwrap();
+        m.tags = parse_tags("kod, praca");
+        m.keyword = Some("gh".into());
+        b.add("https://example.org/notes", "Notatki", releases);
+        b
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(parse_tags(" rust, ,web,rust ,Web "), ["rust", "web", "Web"]);
+        assert!(parse_tags(" , ").is_empty());
+    }
+
+    #[test]
+    fn model() {
+        let mut b = sample();
+        let releases = b.folders[1].id;
+        assert_eq!(b.folder_path(releases), "Praca / Wydania & <plany>");
+        assert_eq!(b.resolve_keyword("gh  borrow checker").as_deref(),
+                   Some("https://github.com/search?q=borrow%20checker"));
+        assert_eq!(b.resolve_keyword("xx"), None);
+
+        assert!(!b.toggle("https://example.com/", ""));
+        assert!(b.toggle("https://example.net/", "Net"));
+        assert_eq!(b.find_by_url("https://example.net/").unwrap().folder, ROOT);
+
+        // a folder goes with its subfolders and their bookmarks
+        b.remove_folder(b.folders[0].id);
+        assert!(b.folders.is_empty());
+        assert_eq!(b.items.iter().map(|m| m.url.as_str()).collect::<Vec<_>>(), ["https://example.net/"]);
+        b.remove_folder(ROOT);
+        assert_eq!(b.items.len(), 1);
+    }
+
+    #[test]
+    fn netscape_round_trip() {
+        let b = sample();
+        let html = export_netscape(&b);
+        let mut back = Bookmarks::default();
+        let into = back.add_folder("Zaimportowane", ROOT);
+        assert_eq!(import_netscape(&mut back, &html, into), 3);
+        assert_eq!(back.folders.iter().map(|f| back.folder_path(f.id)).collect::<Vec<_>>(),
+                   ["Zaimportowane", "Zaimportowane / Praca", "Zaimportowane / Praca / Wydania & <plany>"]);
+        for m in &b.items {
+            let n = back.find_by_url(&m.url).unwrap();
+            assert_eq!((&n.title, &n.tags, &n.keyword, n.created, n.modified),
+                       (&m.title, &m.tags, &m.keyword, m.created, m.modified));
+            assert_eq!(back.folder_path(n.folder).strip_prefix("Zaimportowane").unwrap().trim_start_matches(" / "),
+                       b.folder_path(m.folder));
+        }
+        // a second import adds nothing new
+        assert_eq!(import_netscape(&mut back, &html, ROOT), 0);
+    }
+
+    #[test]
+    fn imports_other_browsers_files() {
+        let html = "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n<dl><p>\n\
+            <dt><h3 add_date='10'>Pasek</h3>\n<dl><p>\n\
+            <dt><a href=\"https://a.example/\" add_date=\"20\" tags=\"x,y\">A &amp; b</a>\n\
+            <dt><a href=\"place:sort=8\">Ostatnie</a>\n\
+            </dl><p>\n<dt><A HREF=https://b.example/>B</A>\n</dl><p>\n";
+        let mut b = Bookmarks::default();
+        assert_eq!(import_netscape(&mut b, html, ROOT), 2);
+        assert_eq!((b.folders[0].name.as_str(), b.folders[0].created), ("Pasek", 10));
+        let a = b.find_by_url("https://a.example/").unwrap();
+        assert_eq!((a.title.as_str(), a.folder, a.modified), ("A & b", b.folders[0].id, 20));
+        assert_eq!(a.tags, ["x", "y"]);
+        assert_eq!(b.find_by_url("https://b.example/").unwrap().folder, ROOT);
+    }
+}
@@ -0,0 +1,294 @@
+//! Cosmetic filtering: element-hiding rules (`##`, `#@#`) from the filter
+//! lists, turned into WebKit user stylesheets with url allow and block
+//! lists. WebKit picks the sheets for a page by its real url, so hidden
+//! elements never show and the page has no say in which rules it gets.
+//! Generic rules go in a few shared sheets; a site whose exceptions touch
+//! one of them gets its own copy of just that one. The engine is global
+//! because the refresh thread builds it and the event loop reads it.
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::filters::CosmeticFilter;
+use crate::scriptlets::pattern;
+
+/// Generic selectors per shared sheet, which bounds the copy a site with
+/// exceptions to one of them gets.
+const CHUNK: usize = 500;
+
+/// A stylesheet with the pages it applies to, as WebKit URL patterns.
+#[derive(Debug, PartialEq)]
+pub struct Sheet {
+    pub allow: Vec<String>,
+    pub block: Vec<String>,
+    pub css: String,
+}
+
+/// A selector with the sites it is turned off on (`~site` in the rule).
+type Entry = (String, Vec<String>);
+
+#[derive(Default)]
+pub struct Engine {
+    /// `##sel`: every site.
+    generic: Vec<Entry>,
+    /// `site##sel`, keyed by site.
+    specific: HashMap<String, Vec<Entry>>,
+    /// `site#@#sel`, keyed by site.
+    exceptions: HashMap<String, HashSet<String>>,
+    /// `#@#sel`: turned off everywhere.
+    disabled: HashSet<String>,
+}
+
+/// The host and every parent domain of it, most specific first.
+fn suffixes(host: &str) -> Vec<&str> {
+    let host = host.trim_end_matches('.');
+    let mut out = vec![host];
+    out.extend(host.match_indices('.').map(|(i, _)| &host[i + 1..]));
+    out
+}
+
+/// One rule per selector, so a selector the page's engine rejects doesn't
+/// take others down with it.
+fn css<'a>(sels: impl IntoIterator<Item = &'a str>) -> String {
+    sels.into_iter().map(|s| format!("{}{{display:none!important}}\n", s)).collect()
+}
+
+/// Of `sites`, the subdomains of `site`, as patterns to keep its sheet off
+/// pages that have one of their own.
+fn subdomains(sites: &[&str], site: &str) -> Vec<String> {
+    sites.iter().filter(|s| s.len() > site.len() && s.ends_with(&format!(".{}", site)))
+        .map(|s| pattern(s)).collect()
+}
+
+impl Engine {
+    pub fn new(filters: &[CosmeticFilter]) -> Self {
+        let mut e = Engine::default();
+        for f in filters {
+            let sel = f.selector.clone();
+            match (f.exception, f.domains.include.is_empty()) {
+                (true, true) => { e.disabled.insert(sel); }
+                (true, false) => for d in &f.domains.include {
+                    e.exceptions.entry(d.clone()).or_default().insert(sel.clone());
+                },
+                (false, true) => e.generic.push((sel, f.domains.exclude.clone())),
+                (false, false) => for d in &f.domains.include {
+                    e.specific.entry(d.clone()).or_default().push((sel.clone(), f.domains.exclude.clone()));
+                },
+            }
+        }
+        e
+    }
+
+    /// Selectors hiding elements on pages of `host`, in list order, generic
+    /// ones first.
+    pub fn selectors(&self, host: &str) -> Vec<&str> {
+        let host = host.to_ascii_lowercase();
+        let domains = suffixes(&host);
+        let mut seen = HashSet::new();
+        self.applying(self.generic.iter(), &domains).into_iter()
+            .chain(self.specific_for(&domains))
+            .filter(|sel| seen.insert(*sel))
+            .collect()
+    }
+
+    /// Of `entries`, the selectors that apply on a site with these domains.
+    fn applying<'a>(&'a self, entries: impl Iterator<Item = &'a Entry>, domains: &[&str]) -> Vec<&'a str> {
+        let off: HashSet<&str> = domains.iter()
+            .filter_map(|d| self.exceptions.get(*d)).flatten().map(String::as_str)
+            .chain(self.disabled.iter().map(String::as_str))
+            .collect();
+        entries.filter(|(sel, unless)| !off.contains(sel.as_str())
+                && !unless.iter().any(|u| domains.contains(&u.as_str())))
+            .map(|(sel, _)| sel.as_str())
+            .collect()
+    }
+
+    /// Site rules applying on a site with these domains, parents' first.
+    fn specific_for(&self, domains: &[&str]) -> Vec<&str> {
+        self.applying(domains.iter().rev().filter_map(|d| self.specific.get(*d)).flatten(), domains)
+    }
+
+    /// The stylesheets for every page; `skip` are allowlisted sites, which
+    /// get none.
+    pub fn sheets(&self, skip: &[String]) -> Vec<Sheet> {
+        let skip: Vec<String> = skip.iter().map(|s| pattern(s)).collect();
+        let mut out = Vec::new();
+
+        // generic rules, in shared sheets kept off the sites that turn some
+        // of their selectors off; those get a copy without them
+        let mut seen = HashSet::new();
+        let generic: Vec<&Entry> = self.generic.iter()
+            .filter(|(sel, _)| !self.disabled.contains(sel) && seen.insert(sel.as_str()))
+            .collect();
+        for chunk in generic.chunks(CHUNK) {
+            let sels: HashSet<&str> = chunk.iter().map(|(sel, _)| sel.as_str()).collect();
+            let mut sites: Vec<&str> = self.exceptions.iter()
+                .filter(|(_, off)| off.iter().any(|s| sels.contains(s.as_str())))
+                .map(|(site, _)| site.as_str())
+                .chain(chunk.iter().flat_map(|(_, unless)| unless.iter().map(String::as_str)))
+                .collect::<HashSet<_>>().into_iter().collect();
+            sites.sort_unstable();
+            out.push(Sheet {
+                allow: vec!["*://*/*".into()],
+                block: sites.iter().map(|s| pattern(s)).chain(skip.iter().cloned()).collect(),
+                css: css(chunk.iter().map(|(sel, _)| sel.as_str())),
+            });
+            for site in &sites {
+                let css = css(self.applying(chunk.iter().copied(), &suffixes(site)));
+                if css.is_empty() { continue; }
+                let block = subdomains(&sites, site).into_iter().chain(skip.iter().cloned()).collect();
+                out.push(Sheet { allow: vec![pattern(site)], block, css });
+            }
+        }
+
+        // site rules: one sheet per site, a subdomain with rules or
+        // exceptions of its own getting one kept off its parent's
+        let mut sites: Vec<&str> = self.specific.keys().chain(self.exceptions.keys()).map(String::as_str)
+            .chain(self.specific.values().flatten().flat_map(|(_, unless)| unless.iter().map(String::as_str)))
+            .collect::<HashSet<_>>().into_iter().collect();
+        sites.sort_unstable();
+        for site in &sites {
+            let mut seen = HashSet::new();
+            let css = css(self.specific_for(&suffixes(site)).into_iter().filter(|sel| seen.insert(*sel)));
+            if css.is_empty() { continue; }
+            let block = subdomains(&sites, site).into_iter().chain(skip.iter().cloned()).collect();
+            out.push(Sheet { allow: vec![pattern(site)], block, css });
+        }
+        out
+    }
+}
+
+static ENGINE: Mutex<Option<Arc<Engine>>> = Mutex::new(None);
+/// Bumped by `install`, so the main loop knows to rebuild its stylesheets.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub fn install(engine: Engine) {
+    *ENGINE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(engine));
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// The stylesheets of the installed engine, none before there is one.
+pub fn sheets(skip: &[String]) -> Vec<Sheet> {
+    let engine = ENGINE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    engine.map(|e| e.sheets(skip)).unwrap_or_default()
+}
+
+/// Script counting the elements the stylesheets hide on the page at `url`,
+/// for the tab's blocking badge; None when no rule applies there. The
+/// hiding itself doesn't depend on it.
+pub fn count_js(url: &str) -> Option<String> {
+    let host = url::Url::parse(url).ok()?.host_str()?.to_string();
+    let engine = ENGINE.lock().unwrap_or_else(|e| e.into_inner()).clone()?;
+    let sels = engine.selectors(&host);
+    if sels.is_empty() { return None; }
+    Some(format!(r#"(function() {{
+    if (window.__vccatCounting) return;
+    window.__vccatCounting = true;
+    const sels = {};
+    // pages that keep changing get counted less and less often while the
+    // count stays the same, up to every 30 s, and only when the page is idle
+    let sent = -1, timer = null, delay = 1000;
+    const idle = window.requestIdleCallback || (f => f());
+    const count = () => {{
+        timer = null;
+        let n = 0;
+        for (let i = 0; i < sels.length; i += 200) {{
+            try {{ n += document.querySelectorAll(sels.slice(i, i + 200).join(',')).length; }}
+            catch (e) {{ for (const s of sels.slice(i, i + 200)) try {{ n += document.querySelectorAll(s).length; }} catch (e) {{}} }}
+        }}
+        if (n !== sent) {{ sent = n; delay = 1000; window.ipc.postMessage('hidden:' + n); }}
+        else delay = Math.min(delay * 2, 30000);
+    }};
+    const later = () => {{ if (!timer) timer = setTimeout(() => idle(count, {{ timeout: 1000 }}), delay); }};
+    const start = () => {{
+        count();
+        new MutationObserver(later).observe(document.documentElement, {{ childList: true, subtree: true }});
+    }};
+    if (document.readyState === 'loading') document.addEventListener('DOMContentLoaded', start);
+    else start();
+}})();"#, serde_json::json!(sels)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::parse_list;
+
+    fn engine(text: &str) -> Engine {
+        Engine::new(&parse_list(text).cosmetic)
+    }
+
+    #[test]
+    fn generic_and_site_rules() {
+        let e = engine("##.ad-banner\nexample.com##.sidebar-ad\nnews.example.com##.promo\n");
+        assert_eq!(e.selectors("example.org"), vec![".ad-banner"]);
+        assert_eq!(e.selectors("example.com"), vec![".ad-banner", ".sidebar-ad"]);
+        // a site rule covers its subdomains
+        assert_eq!(e.selectors("news.example.com"), vec![".ad-banner", ".sidebar-ad", ".promo"]);
+        assert_eq!(e.selectors("WWW.Example.com"), vec![".ad-banner", ".sidebar-ad"]);
+    }
+
+    #[test]
+    fn exceptions() {
+        let e = engine("##.advert\n##.sponsor\nexample.com#@#.advert\n#@#.sponsor\n\
+                        ~forum.example.com##.box\nexample.com,~shop.example.com##.promo\n");
+        assert_eq!(e.selectors("example.org"), vec![".advert", ".box"]);
+        assert_eq!(e.selectors("www.example.com"), vec![".box", ".promo"]);
+        assert_eq!(e.selectors("forum.example.com"), vec![".promo"]);
+        assert_eq!(e.selectors("shop.example.com"), vec![".box"]);
+    }
+
+    #[test]
+    fn stylesheet_rule_per_selector() {
+        let e = engine("##.a\nexample.com##div[id^=\"ad-\"]\n");
+        assert_eq!(css(e.selectors("example.com")),
+                   ".a{display:none!important}\ndiv[id^=\"ad-\"]{display:none!important}\n");
+        assert_eq!(css(engine("example.com##.a").selectors("other.com")), "");
+    }
+
+    #[test]
+    fn duplicates_once() {
+        let e = engine("##.a\nexample.com##.a\nexample.com##.b\nwww.example.com##.b\n");
+        assert_eq!(e.selectors("www.example.com"), vec![".a", ".b"]);
+    }
+
+    #[test]
+    fn sheets_follow_exceptions() {
+        let e = engine("##.ad\n##.box\nexample.com#@#.ad\nexample.com##.promo\nnews.example.com##.teaser\n\
+                        ~forum.example.com##.banner\n");
+        let sheets = e.sheets(&["blog.example.org".to_string()]);
+        let skip = "*://*.blog.example.org/*".to_string();
+        assert_eq!(sheets[0], Sheet {
+            allow: vec!["*://*/*".into()],
+            block: vec!["*://*.example.com/*".into(), "*://*.forum.example.com/*".into(), skip.clone()],
+            css: ".ad{display:none!important}\n.box{display:none!important}\n.banner{display:none!important}\n".into(),
+        });
+        // the sites taken out of the shared sheet get it without their exceptions
+        assert_eq!(sheets[1].allow, vec!["*://*.example.com/*"]);
+        assert_eq!(sheets[1].block, vec!["*://*.forum.example.com/*".to_string(), skip.clone()]);
+        assert_eq!(sheets[1].css, ".box{display:none!important}\n.banner{display:none!important}\n");
+        assert_eq!(sheets[2].allow, vec!["*://*.forum.example.com/*"]);
+        assert_eq!(sheets[2].css, ".box{display:none!important}\n");
+        // site rules, a subdomain with its own taking its parent's along
+        let site: Vec<(&str, &str)> = sheets[3..].iter().map(|s| (s.allow[0].as_str(), s.css.as_str())).collect();
+        assert_eq!(site, vec![
+            ("*://*.example.com/*", ".promo{display:none!important}\n"),
+            ("*://*.news.example.com/*", ".promo{display:none!important}\n.teaser{display:none!important}\n"),
+        ]);
+        assert_eq!(sheets[3].block, vec!["*://*.news.example.com/*".to_string(), skip]);
+    }
+
+    #[test]
+    fn generic_rules_in_chunks() {
+        let text: String = (0..CHUNK + 1).map(|i| format!("##.ad{}\n", i)).collect::<String>() + "example.com#@#.ad3\n";
+        let sheets = engine(&text).sheets(&[]);
+        assert_eq!(sheets.len(), 3);
+        assert_eq!(sheets[0].block, vec!["*://*.example.com/*"]);
+        assert_eq!(sheets[1].css.lines().count(), CHUNK - 1);
+        assert!(sheets[2].block.is_empty());
+        assert_eq!(sheets[2].css, format!(".ad{}{{display:none!important}}\n", CHUNK));
+    }
+}
@@ -0,0 +1,211 @@
+//! Local files: `file://` directories get a generated listing and plain
+//! text files a text view; everything WebKit shows by itself (html, images,
+//! pdf, media) is left to it.
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use url::Url;
+
+/// Extensions WebKit renders natively.
+const NATIVE: &[&str] = &[
+    "html", "htm", "xhtml", "xml", "svg", "pdf", "png", "jpg", "jpeg", "gif", "webp",
+    "avif", "bmp", "ico", "mp4", "webm", "ogv", "mp3", "ogg", "oga", "wav", "flac",
+];
+
+/// Bytes read to tell text from binary.
+const SNIFF_BYTES: usize = 8192;
+
+/// Text files beyond this are shown cut short.
+const MAX_TEXT_BYTES: usize = 4 * 1024 * 1024;
+
+pub fn path_of(url: &str) -> Option<PathBuf> {
+    Url::parse(url).ok().filter(|u| u.scheme() == "file")?.to_file_path().ok()
+}
+
+fn file_url(path: &Path) -> String {
+    Url::from_file_path(path).map(String::from).unwrap_or_default()
+}
+
+fn dir_url(path: &Path) -> String {
+    Url::from_directory_path(path).map(String::from).unwrap_or_default()
+}
+
+/// Whether the file looks like text: valid UTF-8 (bar a character cut at the
+/// end of the sample) with no NUL bytes.
+fn is_text(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if NATIVE.contains(&ext.as_str()) { return false; }
+    let Ok(f) = fs::File::open(path) else { return false };
+    let mut buf = Vec::with_capacity(SNIFF_BYTES);
+    if f.take(SNIFF_BYTES as u64).read_to_end(&mut buf).is_err() { return false; }
+    if buf.contains(&0) { return false; }
+    match std::str::from_utf8(&buf) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && buf.len() == SNIFF_BYTES,
+    }
+}
+
+/// Whether the url is one we render ourselves instead of WebKit.
+pub fn handles(url: &str) -> bool {
+    path_of(url).is_some_and(|p| p.is_dir() || (p.is_file() && is_text(&p)))
+}
+
+/// A local file looked at and left to WebKit, for the navigation to it to
+/// pass once.
+static LET_THROUGH: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn let_through(url: &str) {
+    *LET_THROUGH.lock().unwrap() = Some(url.to_string());
+}
+
+/// Whether a navigation to `url` should wait for `handles` to decide. Only
+/// the name is looked at, as this runs for every navigation.
+pub fn may_handle(url: &str) -> bool {
+    let Some(path) = path_of(url) else { return false };
+    let mut passed = LET_THROUGH.lock().unwrap();
+    if passed.as_deref() == Some(url) {
+        *passed = None;
+        return false;
+    }
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    !NATIVE.contains(&ext.as_str())
+}
+
+/// Page for a `file://` url we render ourselves, or None for WebKit's own.
+pub fn render(url: &str) -> Option<String> {
+    let path = path_of(url)?;
+    if path.is_dir() {
+        Some(listing_html(&path))
+    } else if path.is_file() && is_text(&path) {
+        Some(text_html(&path))
+    } else {
+        None
+    }
+}
+
+fn esc(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn listing_html(dir: &Path) -> String {
+    let mut entries: Vec<(String, bool, u64, u64, String)> = match fs::read_dir(dir) {
+        Ok(rd) => rd.flatten().map(|e| {
+            let path = e.path();
+            // follow symlinks, so a link to a folder opens as one
+            let meta = fs::metadata(&path).or_else(|_| e.metadata()).ok();
+            let is_dir = meta.as_ref().is_some_and(|m| m.is_dir());
+            let size = meta.as_ref().filter(|_| !is_dir).map_or(0, |m| m.len());
+            let mtime = meta.and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            let url = if is_dir { dir_url(&path) } else { file_url(&path) };
+            (e.file_name().to_string_lossy().into_owned(), is_dir, size, mtime, url)
+        }).collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.to_lowercase().cmp(&b.0.to_lowercase())));
+
+    let mut rows = String::new();
+    if let Some(parent) = dir.parent() {
+        rows.push_str(&format!(
+            r#"<tr class="up" data-dir="1" data-name="" data-size="-1" data-mtime="0"><td><a href="{}">..</a></td><td></td><td></td></tr>"#,
+            esc(&dir_url(parent))));
+    }
+    for (name, is_dir, size, mtime, url) in &entries {
+        let cls = if name.starts_with('.') { " class=\"hidden\"" } else { "" };
+        rows.push_str(&format!(
+            r#"<tr{cls} data-dir="{d}" data-name="{n}" data-size="{s}" data-mtime="{m}"><td><a href="{u}">{n}{slash}</a></td><td class="size"></td><td class="time"></td></tr>"#,
+            cls = cls, d = u8::from(*is_dir), n = esc(name), s = if *is_dir { -1 } else { *size as i64 },
+            m = mtime, u = esc(url), slash = if *is_dir { "/" } else { "" }));
+    }
+    let error = if fs::read_dir(dir).is_err() {
+        r#"<div class="empty">brak dostępu do katalogu</div>"#
+    } else if entries.is_empty() {
+        r#"<div class="empty">pusty katalog</div>"#
+    } else { "" };
+    let title = esc(&dir.display().to_string());
+
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>{title}</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.05em;word-break:break-all;}}
+table{{width:100%;border-collapse:collapse;}}
+th{{text-align:left;font-size:10px;color:#3a2a5e;font-weight:normal;padding:6px 10px;cursor:pointer;user-select:none;}}
+th:hover{{color:#7a5aaa;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tbody tr:hover{{background:#0c0b14;}}
+td{{padding:6px 10px;font-size:12px;}}
+a{{color:#6a4a9a;text-decoration:none;}}
+a:hover{{color:#8a6abb;}}
+tr[data-dir="1"] a{{color:#8a6abb;}}
+.hidden a{{opacity:0.5;}}
+.size,.time{{color:#3a3a5a;font-size:11px;white-space:nowrap;width:1%;}}
+.size{{text-align:right;}}
+label{{font-size:10px;color:#3a3a5a;cursor:pointer;}}
+.empty{{font-size:11px;color:#2a2a3a;margin-top:12px;}}
+body.nohidden .hidden{{display:none;}}
+</style></head><body class="nohidden">
+<h1>// {title}</h1>
+<label><input type="checkbox" onchange="document.body.classList.toggle('nohidden',!this.checked)"> ukryte pliki</label>
+<table><thead><tr><th onclick="sortBy('name')">nazwa</th><th onclick="sortBy('size')" style="text-align:right">rozmiar</th><th onclick="sortBy('mtime')">zmieniono</th></tr></thead>
+<tbody id="rows">{rows}</tbody></table>
+{error}
+<script>
+function human(n){{if(n<0)return '';const u=['B','KB','MB','GB','TB'];let i=0;
+  while(n>=1024&&i<u.length-1){{n/=1024;i++;}}return (i?n.toFixed(1):n)+' '+u[i];}}
+document.querySelectorAll('#rows tr').forEach(r=>{{
+  const m=+r.dataset.mtime;
+  r.querySelector('.size')&&(r.querySelector('.size').textContent=human(+r.dataset.size));
+  r.querySelector('.time')&&(r.querySelector('.time').textContent=m?new Date(m*1000).toLocaleString('pl-PL'):'');
+}});
+// links go through IPC: from its vccat: origin the listing can't follow file:// itself
+document.querySelectorAll('#rows a').forEach(a=>a.addEventListener('click',e=>{{
+  e.preventDefault();window.ipc.postMessage('file:'+a.getAttribute('href'));}}));
+let key='name',asc=true;
+function sortBy(k){{
+  asc=k===key?!asc:k==='name';key=k;
+  const body=document.getElementById('rows');
+  const rows=[...body.querySelectorAll('tr:not(.up)')];
+  rows.sort((a,b)=>{{
+    if(a.dataset.dir!==b.dataset.dir)return b.dataset.dir-a.dataset.dir;
+    const x=k==='name'?a.dataset.name.toLowerCase():+a.dataset[k];
+    const y=k==='name'?b.dataset.name.toLowerCase():+b.dataset[k];
+    return (x<y?-1:x>y?1:0)*(asc?1:-1);
+  }});
+  rows.forEach(r=>body.appendChild(r));
+}}
+</script>
+</body></html>"#, title = title, rows = rows, error = error)
+}
+
+fn text_html(path: &Path) -> String {
+    let mut bytes = Vec::new();
+    let read = fs::File::open(path)
+        .and_then(|f| f.take(MAX_TEXT_BYTES as u64 + 1).read_to_end(&mut bytes));
+    let cut = bytes.len() > MAX_TEXT_BYTES;
+    bytes.truncate(MAX_TEXT_BYTES);
+    let text = String::from_utf8_lossy(&bytes);
+    let note = match (&read, cut) {
+        (Err(e), _) => format!(r#"<div class="note">nie można odczytać pliku: {}</div>"#, esc(&e.to_string())),
+        (Ok(_), true) => format!(r#"<div class="note">pokazano pierwsze {} MB</div>"#, MAX_TEXT_BYTES >> 20),
+        _ => String::new(),
+    };
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let parent = path.parent().map(dir_url).unwrap_or_default();
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>{name}</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#999;font-family:'JetBrains Mono','Fira Code',monospace;padding:24px 32px;}}
+.bar{{font-size:11px;color:#3a2a5e;margin-bottom:16px;}}
+.bar a{{color:#6a4a9a;text-decoration:none;}}
+pre{{font-family:inherit;font-size:12px;line-height:1.5;white-space:pre-wrap;word-wrap:break-word;tab-size:4;}}
+.note{{font-size:11px;color:#7a4a4a;margin-bottom:12px;}}
+</style></head><body>
+<div class="bar"><a href="{parent}" onclick="event.preventDefault();window.ipc.postMessage('file:'+this.getAttribute('href'))">{dir}</a> / {name}</div>
+{note}<pre>{text}</pre>
+</body></html>"#, name = esc(&name), parent = esc(&parent),
+        dir = esc(&path.parent().map(|p| p.display().to_string()).unwrap_or_default()),
+        note = note, text = esc(&text))
+}
@@ -0,0 +1,958 @@
+//! Filter lists in Adblock Plus / uBlock Origin syntax, compiled to WebKit
+//! content-blocker JSON. Network filters (`||host^`, `|start`, `end|`,
+//! `*`, `@@` exceptions and the common `$` options) become url-filter
+//! triggers; element hiding (`##`, `#@#`) becomes `css-display-none`.
+//! Scriptlets (`##+js(...)`) are parsed for the `scriptlets` module.
+//! Whatever WebKit can't express — regular expressions, redirects,
+//! procedural selectors — is reported instead of guessed at.
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceType {
+    Script,
+    Image,
+    Stylesheet,
+    Font,
+    Media,
+    Xhr,
+    Subdocument,
+    Document,
+    Popup,
+    Ping,
+    Websocket,
+    Object,
+    Other,
+}
+
+use ResourceType::*;
+
+const TYPE_OPTIONS: &[(&str, ResourceType)] = &[
+    ("script", Script), ("image", Image), ("stylesheet", Stylesheet), ("css", Stylesheet),
+    ("font", Font), ("media", Media), ("xmlhttprequest", Xhr), ("xhr", Xhr),
+    ("subdocument", Subdocument), ("frame", Subdocument), ("document", Document), ("doc", Document),
+    ("popup", Popup), ("ping", Ping), ("beacon", Ping), ("websocket", Websocket),
+    ("object", Object), ("object-subrequest", Object), ("other", Other),
+];
+
+/// The type named like in filter options (`image`, `script`, …).
+pub fn resource_type(name: &str) -> Option<ResourceType> {
+    TYPE_OPTIONS.iter().find(|(n, _)| *n == name).map(|(_, t)| *t)
+}
+
+/// Types a filter without type options applies to: all but popups, as in
+/// Adblock Plus. WebKit has one type for frames and pages, so like
+/// uBlock's strict blocking this keeps blocked sites from opening too.
+const DEFAULT_TYPES: &[ResourceType] = &[
+    Script, Image, Stylesheet, Font, Media, Xhr, Subdocument, Ping, Websocket, Object, Other,
+];
+
+/// Options that are understood but have no WebKit counterpart.
+const UNSUPPORTED_OPTIONS: &[&str] = &[
+    "redirect", "redirect-rule", "removeparam", "queryprune", "csp", "rewrite", "replace",
+    "header", "permissions", "denyallow", "to", "method", "sitekey", "webrtc", "popunder",
+    "empty", "mp4", "cname", "inline-script", "inline-font", "strict1p", "strict3p",
+    "urltransform", "uritransform", "jsonprune", "stealth", "cookie", "network", "content",
+    "extension", "jsinject", "urlblock", "elemhide", "ehide", "generichide", "ghide",
+    "specifichide", "shide", "genericblock", "badfilter", "app", "redirect-url", "urlskip",
+];
+
+/// Selector syntax that only script-based cosmetic engines understand.
+const PROCEDURAL: &[&str] = &[
+    ":has(", ":has-text(", ":-abp-", ":xpath(", ":style(", ":matches-css", ":upward(",
+    ":remove(", ":contains(", ":min-text-length(", ":matches-path(", ":watch-attr(",
+    ":if(", ":if-not(", ":nth-ancestor(", ":others(", ":matches-attr(", ":matches-prop(",
+    ":remove-attr(", ":remove-class(", ":matches-media(",
+];
+
+/// Selectors joined into one css-display-none action, as WebKit advises.
+const SELECTORS_PER_RULE: usize = 200;
+
+/// Rules per compiled content filter. WebKit refuses bigger ones (150 000
+/// rules in current versions, 50 000 in older), so longer rule sets are
+/// split across several filters.
+pub const MAX_RULES: usize = 50_000;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Domains {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkFilter {
+    pub raw: String,
+    pub exception: bool,
+    /// Pattern without its anchors.
+    pub pattern: String,
+    /// `||`: the pattern starts at a host or subdomain boundary.
+    pub host_anchor: bool,
+    /// `|` at the start or end: the pattern starts or ends the url.
+    pub start_anchor: bool,
+    pub end_anchor: bool,
+    pub third_party: Option<bool>,
+    /// None: the default set.
+    pub types: Option<Vec<ResourceType>>,
+    pub domains: Domains,
+    pub match_case: bool,
+    /// `$important`: not overridden by exceptions.
+    pub important: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CosmeticFilter {
+    pub raw: String,
+    pub exception: bool,
+    pub domains: Domains,
+    pub selector: String,
+}
+
+/// `site##+js(name, args…)`; an exception with no name (`#@#+js()`)
+/// turns off every scriptlet on its sites.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptletFilter {
+    pub raw: String,
+    pub exception: bool,
+    pub domains: Domains,
+    /// Canonical name, aliases resolved.
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Network(NetworkFilter),
+    Cosmetic(CosmeticFilter),
+    Scriptlet(ScriptletFilter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsupported {
+    /// 1-based line in the list.
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct FilterList {
+    pub network: Vec<NetworkFilter>,
+    pub cosmetic: Vec<CosmeticFilter>,
+    pub scriptlets: Vec<ScriptletFilter>,
+    pub unsupported: Vec<Unsupported>,
+}
+
+fn parse_domains(list: &str, sep: char) -> Result<Domains, String> {
+    let mut d = Domains::default();
+    for item in list.split(sep).map(str::trim).filter(|s| !s.is_empty()) {
+        let (neg, name) = match item.strip_prefix('~') {
+            Some(n) => (true, n),
+            None => (false, item),
+        };
+        if name.ends_with(".*") || name.contains('*') {
+            return Err(format!("domena z symbolem wieloznacznym „{}”", name));
+        }
+        if !name.is_ascii() {
+            return Err(format!("domena spoza ASCII „{}”", name));
+        }
+        if name.contains(['/', ' ', '|']) {
+            return Err(format!("nieprawidłowa domena „{}”", name));
+        }
+        let name = name.to_ascii_lowercase();
+        if neg { d.exclude.push(name) } else { d.include.push(name) }
+    }
+    Ok(d)
+}
+
+/// Whether a selector is something WebKit will accept in a stylesheet;
+/// one bad selector makes it reject the whole list.
+fn plausible_selector(s: &str) -> bool {
+    if s.is_empty() || s.contains(['{', '}', '\n']) || s.starts_with(['>', '+', '~', ',']) {
+        return false;
+    }
+    let mut depth = (0i32, 0i32);
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in s.chars() {
+        if escaped { escaped = false; continue; }
+        match (quote, c) {
+            (_, '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth.0 += 1,
+            (None, ')') => depth.0 -= 1,
+            (None, '[') => depth.1 += 1,
+            (None, ']') => depth.1 -= 1,
+            _ => {}
+        }
+        if depth.0 < 0 || depth.1 < 0 { return false; }
+    }
+    quote.is_none() && depth == (0, 0) && !escaped
+}
+
+fn parse_cosmetic(line: &str, at: usize, marker: &str) -> Result<Filter, String> {
+    let selector = line[at + marker.len()..].trim();
+    match marker {
+        "#?#" | "#@?#" => return Err("proceduralny filtr kosmetyczny".into()),
+        "#$#" | "#@$#" => return Err("filtr ze stylem CSS".into()),
+        "#%#" | "#@%#" => return Err("wstrzykiwanie skryptu".into()),
+        _ => {}
+    }
+    if let Some(call) = selector.strip_prefix("+js(") {
+        return parse_scriptlet(line, at, marker, call);
+    }
+    if selector.starts_with('^') {
+        return Err("filtr HTML".into());
+    }
+    if let Some(p) = PROCEDURAL.iter().find(|p| selector.contains(*p)) {
+        return Err(format!("proceduralny selektor {}", p.trim_end_matches('(')));
+    }
+    if !plausible_selector(selector) {
+        return Err("nieprawidłowy selektor".into());
+    }
+    let domains = parse_domains(&line[..at], ',')?;
+    Ok(Filter::Cosmetic(CosmeticFilter {
+        raw: line.into(),
+        exception: marker.contains('@'),
+        domains,
+        selector: selector.into(),
+    }))
+}
+
+/// Splits scriptlet arguments at commas, as uBlock does: `\,` is a comma
+/// inside an argument, and a quoted argument may hold commas as is.
+fn scriptlet_args(call: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rest = call.trim_start();
+    while !rest.is_empty() {
+        let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\'' | '`'));
+        let closed = quote.and_then(|q| rest[1..].find(q).map(|end| (&rest[1..end + 1], &rest[end + 2..])));
+        let (arg, after) = match closed {
+            Some((arg, after)) if after.trim_start().is_empty() || after.trim_start().starts_with(',') =>
+                (arg.to_string(), after.trim_start()),
+            _ => {
+                let mut end = rest.len();
+                let bytes = rest.as_bytes();
+                for (i, &b) in bytes.iter().enumerate() {
+                    if b == b',' && (i == 0 || bytes[i - 1] != b'\\') { end = i; break; }
+                }
+                (rest[..end].trim().replace("\\,", ","), &rest[end..])
+            }
+        };
+        args.push(arg);
+        rest = after.strip_prefix(',').unwrap_or(after).trim_start();
+    }
+    args
+}
+
+fn parse_scriptlet(line: &str, at: usize, marker: &str, call: &str) -> Result<Filter, String> {
+    if marker != "##" && marker != "#@#" {
+        return Err("nieprawidłowy scriptlet".into());
+    }
+    let call = call.strip_suffix(')').ok_or("nieprawidłowy scriptlet")?;
+    let exception = marker == "#@#";
+    let domains = parse_domains(&line[..at], ',')?;
+    let mut args = scriptlet_args(call);
+    let name = if args.is_empty() { String::new() } else { args.remove(0) };
+    let name = name.strip_suffix(".js").unwrap_or(&name);
+    let name = match crate::scriptlets::canonical(name) {
+        Some(n) => n.to_string(),
+        None if exception && name.is_empty() => String::new(),
+        None => return Err(format!("nieznany scriptlet „{}”", name)),
+    };
+    if !exception && domains.include.is_empty() {
+        return Err("scriptlet bez domeny".into());
+    }
+    Ok(Filter::Scriptlet(ScriptletFilter { raw: line.into(), exception, domains, name, args }))
+}
+
+/// Finds the cosmetic marker (`##`, `#@#`, …) of a line, if it has one in
+/// a place where the part before it can be a domain list.
+fn cosmetic_marker(line: &str) -> Option<(usize, &'static str)> {
+    const MARKERS: &[&str] = &["#@?#", "#@$#", "#@%#", "#?#", "#$#", "#%#", "#@#", "##"];
+    let (at, marker) = line.match_indices('#')
+        .find_map(|(i, _)| MARKERS.iter().find(|m| line[i..].starts_with(*m)).map(|m| (i, *m)))?;
+    let domains = &line[..at];
+    if domains.contains(['/', '|', '@', '"', '!', '$', '^']) { return None; }
+    Some((at, marker))
+}
+
+fn parse_options(f: &mut NetworkFilter, options: &str) -> Result<(), String> {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for opt in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let (neg, name) = match opt.strip_prefix('~') {
+            Some(n) => (true, n),
+            None => (false, opt),
+        };
+        let (key, value) = name.split_once('=').map_or((name, None), |(k, v)| (k, Some(v)));
+        let key = key.to_ascii_lowercase();
+        if let Some((_, t)) = TYPE_OPTIONS.iter().find(|(n, _)| *n == key) {
+            if neg { exclude.push(*t) } else { include.push(*t) }
+            continue;
+        }
+        match (key.as_str(), value) {
+            ("third-party" | "3p", None) => f.third_party = Some(!neg),
+            ("first-party" | "1p", None) => f.third_party = Some(neg),
+            ("domain" | "from", Some(v)) => f.domains = parse_domains(v, '|')?,
+            ("match-case", None) => f.match_case = !neg,
+            ("important", None) => f.important = true,
+            ("all", None) => include.extend(TYPE_OPTIONS.iter().map(|(_, t)| *t)),
+            (k, _) if UNSUPPORTED_OPTIONS.contains(&k) => return Err(format!("opcja ${}", k)),
+            (k, _) => return Err(format!("nieznana opcja ${}", k)),
+        }
+    }
+    if !include.is_empty() {
+        f.types = Some(include);
+    } else if !exclude.is_empty() {
+        f.types = Some(DEFAULT_TYPES.iter().copied().filter(|t| !exclude.contains(t)).collect());
+    }
+    Ok(())
+}
+
+fn parse_network(line: &str) -> Result<Filter, String> {
+    let (exception, rest) = match line.strip_prefix("@@") {
+        Some(r) => (true, r),
+        None => (false, line),
+    };
+    // options follow the last `$`, unless that `$` belongs to a regex
+    let (mut pattern, options) = match rest.rfind('$') {
+        Some(i) if !(rest.starts_with('/') && rest[i..].contains('/'))
+            && rest[i + 1..].chars().all(|c| c.is_ascii_alphanumeric() || ",~=|._*-".contains(c)) =>
+            (&rest[..i], Some(&rest[i + 1..])),
+        _ => (rest, None),
+    };
+    if pattern.len() > 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+        return Err("wyrażenie regularne".into());
+    }
+    if !pattern.is_ascii() {
+        return Err("adres spoza ASCII".into());
+    }
+    let mut f = NetworkFilter {
+        raw: line.into(), exception, pattern: String::new(), host_anchor: false,
+        start_anchor: false, end_anchor: false, third_party: None, types: None,
+        domains: Domains::default(), match_case: false, important: false,
+    };
+    if let Some(p) = pattern.strip_prefix("||") {
+        f.host_anchor = true;
+        pattern = p;
+    } else if let Some(p) = pattern.strip_prefix('|') {
+        f.start_anchor = true;
+        pattern = p;
+    }
+    if let Some(p) = pattern.strip_suffix('|') {
+        f.end_anchor = true;
+        pattern = p;
+    }
+    if let Some(o) = options { parse_options(&mut f, o)?; }
+    f.pattern = pattern.trim_matches('*').to_string();
+    if !f.match_case { f.pattern.make_ascii_lowercase(); }
+    if pattern.starts_with('*') { f.host_anchor = false; f.start_anchor = false; }
+    if pattern.ends_with('*') { f.end_anchor = false; }
+    if f.pattern.len() < 3 && f.domains.include.is_empty() && !f.host_anchor {
+        return Err("zbyt ogólny wzorzec".into());
+    }
+    Ok(Filter::Network(f))
+}
+
+/// Parses one line of a list; Ok(None) for comments, headers and blanks.
+pub fn parse_line(line: &str) -> Result<Option<Filter>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+        return Ok(None);
+    }
+    if line.contains("$$") || line.contains("$@$") {
+        return Err("filtr HTML".into());
+    }
+    match cosmetic_marker(line) {
+        Some((at, marker)) => parse_cosmetic(line, at, marker).map(Some),
+        None => parse_network(line).map(Some),
+    }
+}
+
+pub fn parse_list(text: &str) -> FilterList {
+    let mut list = FilterList::default();
+    for (i, line) in text.lines().enumerate() {
+        match parse_line(line) {
+            Ok(Some(Filter::Network(f))) => list.network.push(f),
+            Ok(Some(Filter::Cosmetic(f))) => list.cosmetic.push(f),
+            Ok(Some(Filter::Scriptlet(f))) => list.scriptlets.push(f),
+            Ok(None) => {}
+            Err(reason) => list.unsupported.push(Unsupported {
+                line: i + 1, text: line.trim().into(), reason,
+            }),
+        }
+    }
+    list
+}
+
+// ── Matching ──────────────────────────────────────────────────────────────────
+
+fn is_separator(c: u8) -> bool {
+    !(c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'.' | b'%'))
+}
+
+/// Matches a pattern with `*` and `^` against `t`: from its start, or from
+/// anywhere in it when `floating`, and up to its end when `end`. Greedy,
+/// going back only to the last `*` (which is enough, as whatever a later
+/// `*` can reach an earlier one can too), so at most `p.len() * t.len()`
+/// steps however many `*` there are.
+fn glob(p: &[u8], t: &[u8], floating: bool, end: bool) -> bool {
+    let (mut pi, mut ti) = (0, 0);
+    // pattern position after the last `*`, and how far into `t` it reaches
+    let mut star = floating.then_some((0, 0));
+    loop {
+        if pi == p.len() {
+            if !end || ti == t.len() { return true; }
+        } else {
+            match p[pi] {
+                b'*' => {
+                    star = Some((pi + 1, ti));
+                    pi += 1;
+                    continue;
+                }
+                // `^` also matches the end of the url
+                b'^' if ti == t.len() => { pi += 1; continue; }
+                b'^' if is_separator(t[ti]) => { pi += 1; ti += 1; continue; }
+                c if t.get(ti) == Some(&c) => { pi += 1; ti += 1; continue; }
+                _ => {}
+            }
+        }
+        match star {
+            Some((sp, st)) if st < t.len() => {
+                star = Some((sp, st + 1));
+                pi = sp;
+                ti = st + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Where a `||` pattern may start: the host and each of its parent domains.
+fn host_starts(url: &str) -> Vec<usize> {
+    let Some(h) = url.find("://").map(|i| i + 3) else { return Vec::new() };
+    let end = url[h..].find(['/', ':', '?', '#']).map_or(url.len(), |e| h + e);
+    let mut starts = vec![h];
+    starts.extend(url[h..end].match_indices('.').map(|(i, _)| h + i + 1));
+    starts
+}
+
+fn on_domain(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|h| h.ends_with('.'))
+}
+
+/// A request as network filters see it, prepared once to be matched
+/// against many of them.
+pub struct Request<'a> {
+    pub url: &'a str,
+    /// The url lowercase, for filters without `$match-case`.
+    pub lower: String,
+    pub page_host: String,
+    pub third_party: bool,
+    pub kind: ResourceType,
+}
+
+impl NetworkFilter {
+    /// Whether the filter applies to the request.
+    pub fn matches(&self, req: &Request) -> bool {
+        if !self.types.as_deref().unwrap_or(DEFAULT_TYPES).contains(&req.kind) { return false; }
+        if self.third_party.is_some_and(|t| t != req.third_party) { return false; }
+        let page_host = req.page_host.as_str();
+        if !self.domains.include.is_empty() && !self.domains.include.iter().any(|d| on_domain(page_host, d)) {
+            return false;
+        }
+        if self.domains.exclude.iter().any(|d| on_domain(page_host, d)) { return false; }
+        let url = if self.match_case { req.url } else { req.lower.as_str() };
+        let (p, t) = (self.pattern.as_bytes(), url.as_bytes());
+        if self.host_anchor {
+            host_starts(url).into_iter().any(|i| glob(p, &t[i..], false, self.end_anchor))
+        } else {
+            glob(p, t, !self.start_anchor, self.end_anchor)
+        }
+    }
+}
+
+// ── WebKit compiler ───────────────────────────────────────────────────────────
+
+/// Start of every url up to a host boundary, for `||`.
+const HOST_START: &str = "^[a-z][a-z0-9.+-]*://([^/:?#]+\\.)?";
+
+/// `^`: any character but a letter, digit or one of `_-.%`.
+const SEPARATOR: &str = "[^a-zA-Z0-9_.%-]";
+
+/// The url-filter regex for a network filter, in the subset WebKit takes.
+pub fn url_filter(f: &NetworkFilter) -> String {
+    let mut re = String::new();
+    if f.host_anchor {
+        re.push_str(HOST_START);
+    } else if f.start_anchor {
+        re.push('^');
+    }
+    let last = f.pattern.len().saturating_sub(1);
+    for (i, c) in f.pattern.char_indices() {
+        match c {
+            '*' => re.push_str(".*"),
+            // a host is always followed by `/` or `:`; elsewhere the url may end
+            '^' if i == last && !f.end_anchor && (f.pattern[..i].contains('/') || !f.host_anchor) => {
+                re.push_str(&format!("({}.*)?$", SEPARATOR));
+            }
+            '^' => re.push_str(SEPARATOR),
+            '.' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '\\' | '$' | '|' => {
+                re.push('\\');
+                re.push(c);
+            }
+            _ => re.push(c),
+        }
+    }
+    if f.end_anchor { re.push('$'); }
+    if re.is_empty() { re.push_str(".*"); }
+    re
+}
+
+fn webkit_types(types: &[ResourceType]) -> Vec<&'static str> {
+    let mut out: Vec<&'static str> = Vec::new();
+    for t in types {
+        let w: &[&str] = match t {
+            Script => &["script"],
+            Image => &["image", "svg-document"],
+            Stylesheet => &["style-sheet"],
+            Font => &["font"],
+            Media => &["media"],
+            Subdocument | Document => &["document"],
+            Popup => &["popup"],
+            Ping => &["ping"],
+            Xhr | Websocket | Object | Other => &["raw"],
+        };
+        for x in w {
+            if !out.contains(x) { out.push(x); }
+        }
+    }
+    out
+}
+
+fn wildcard(domains: &[String]) -> Vec<String> {
+    domains.iter().map(|d| format!("*{}", d)).collect()
+}
+
+/// `@@||site^$document`: the whole site is exempt.
+fn site_exception(f: &NetworkFilter) -> Option<&str> {
+    let host = f.pattern.strip_suffix('^').unwrap_or(&f.pattern);
+    let only_document = f.types.as_deref() == Some(&[Document]);
+    (f.exception && only_document && f.host_anchor && !host.contains(['/', '*', '^'])).then_some(host)
+}
+
+fn network_rules(f: &NetworkFilter) -> Vec<Value> {
+    let action = if f.exception { "ignore-previous-rules" } else { "block" };
+    if let Some(host) = site_exception(f) {
+        return vec![json!({
+            "trigger": {"url-filter": ".*", "if-domain": [format!("*{}", host.to_ascii_lowercase())]},
+            "action": {"type": action},
+        })];
+    }
+    let mut trigger = json!({"url-filter": url_filter(f)});
+    if f.match_case {
+        trigger["url-filter-is-case-sensitive"] = true.into();
+    }
+    let types = f.types.as_deref().unwrap_or(DEFAULT_TYPES);
+    trigger["resource-type"] = json!(webkit_types(types));
+    if let Some(third) = f.third_party {
+        trigger["load-type"] = json!([if third { "third-party" } else { "first-party" }]);
+    }
+    let mut rules = Vec::new();
+    if !f.domains.include.is_empty() {
+        trigger["if-domain"] = json!(wildcard(&f.domains.include));
+        rules.push(json!({"trigger": trigger.clone(), "action": {"type": action}}));
+        // WebKit takes only one domain condition; exclusions undo the block
+        if !f.exception && !f.domains.exclude.is_empty() {
+            trigger["if-domain"] = json!(wildcard(&f.domains.exclude));
+            rules.push(json!({"trigger": trigger, "action": {"type": "ignore-previous-rules"}}));
+        }
+    } else {
+        if !f.domains.exclude.is_empty() {
+            trigger["unless-domain"] = json!(wildcard(&f.domains.exclude));
+        }
+        rules.push(json!({"trigger": trigger, "action": {"type": action}}));
+    }
+    rules
+}
+
+fn css_rules(selectors: &[String], condition: Option<(&str, &[String])>) -> Vec<Value> {
+    selectors.chunks(SELECTORS_PER_RULE).map(|chunk| {
+        let mut trigger = json!({"url-filter": ".*"});
+        if let Some((key, domains)) = condition {
+            trigger[key] = json!(wildcard(domains));
+        }
+        json!({"trigger": trigger, "action": {"type": "css-display-none", "selector": chunk.join(", ")}})
+    }).collect()
+}
+
+/// Element hiding rules, with `#@#` exceptions worked into the domain
+/// conditions since WebKit has no way to undo a single selector.
+fn cosmetic_rules(filters: &[CosmeticFilter]) -> Vec<Value> {
+    use std::collections::BTreeMap;
+    let mut disabled: BTreeMap<&str, Option<Vec<String>>> = BTreeMap::new();
+    for f in filters.iter().filter(|f| f.exception) {
+        let entry = disabled.entry(f.selector.as_str()).or_insert(Some(Vec::new()));
+        match (&mut *entry, f.domains.include.is_empty()) {
+            // `#@#sel` everywhere
+            (_, true) => *entry = None,
+            (Some(list), false) => list.extend(f.domains.include.iter().cloned()),
+            (None, false) => {}
+        }
+    }
+    // grouped by their domain condition, keeping list order within a group
+    let mut generic: BTreeMap<Vec<String>, Vec<String>> = BTreeMap::new();
+    let mut specific: BTreeMap<Vec<String>, Vec<String>> = BTreeMap::new();
+    for f in filters.iter().filter(|f| !f.exception) {
+        let off = disabled.get(f.selector.as_str());
+        if matches!(off, Some(None)) { continue; }
+        let off: &[String] = off.and_then(|o| o.as_deref()).unwrap_or(&[]);
+        if f.domains.include.is_empty() {
+            let mut unless: Vec<String> = f.domains.exclude.iter().chain(off).cloned().collect();
+            unless.sort();
+            unless.dedup();
+            generic.entry(unless).or_default().push(f.selector.clone());
+        } else {
+            let mut domains: Vec<String> = f.domains.include.iter()
+                .filter(|d| !off.contains(d)).cloned().collect();
+            if domains.is_empty() { continue; }
+            domains.sort();
+            specific.entry(domains).or_default().push(f.selector.clone());
+        }
+    }
+    let mut rules = Vec::new();
+    for (unless, selectors) in &generic {
+        let cond = (!unless.is_empty()).then_some(("unless-domain", unless.as_slice()));
+        rules.extend(css_rules(selectors, cond));
+    }
+    for (domains, selectors) in &specific {
+        rules.extend(css_rules(selectors, Some(("if-domain", domains.as_slice()))));
+    }
+    rules
+}
+
+/// Compiles a parsed list to WebKit content-blocker rules. Order matters
+/// to WebKit: blocking, then hiding, then exceptions, then `$important`
+/// blocks that exceptions must not undo.
+pub fn compile(list: &FilterList) -> Vec<Value> {
+    let mut rules = Vec::new();
+    for f in list.network.iter().filter(|f| !f.exception && !f.important) {
+        rules.extend(network_rules(f));
+    }
+    rules.extend(cosmetic_rules(&list.cosmetic));
+    for f in list.network.iter().filter(|f| f.exception) {
+        rules.extend(network_rules(f));
+    }
+    for f in list.network.iter().filter(|f| !f.exception && f.important) {
+        rules.extend(network_rules(f));
+    }
+    rules
+}
+
+/// Splits content-blocker rules into parts of at most `max` rules, each
+/// compiled as a filter of its own. An `ignore-previous-rules` rule only
+/// reaches rules before it in the same filter, so every part carries all
+/// of those that follow its first rule, in their places: each rule meets
+/// exactly the exceptions it met in the whole set. Also returns how many
+/// rules found no room, when the exceptions alone fill a part.
+pub fn split_rules(rules: &[Value], max: usize) -> (Vec<Vec<Value>>, usize) {
+    let ignores = |r: &Value| r["action"]["type"] == "ignore-previous-rules";
+    let mut after = vec![0; rules.len() + 1];
+    for i in (0..rules.len()).rev() {
+        after[i] = after[i + 1] + usize::from(ignores(&rules[i]));
+    }
+    let mut parts = Vec::new();
+    let mut start = 0;
+    loop {
+        // exceptions leading a part have nothing before them to undo
+        while start < rules.len() && ignores(&rules[start]) { start += 1; }
+        if start == rules.len() { return (parts, 0); }
+        let room = max.saturating_sub(after[start]);
+        if room == 0 {
+            return (parts, rules[start..].iter().filter(|r| !ignores(r)).count());
+        }
+        let (mut end, mut taken) = (start, 0);
+        while end < rules.len() && (taken < room || ignores(&rules[end])) {
+            taken += usize::from(!ignores(&rules[end]));
+            end += 1;
+        }
+        let mut part = rules[start..end].to_vec();
+        part.extend(rules[end..].iter().filter(|r| ignores(r)).cloned());
+        parts.push(part);
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines taken from EasyList, EasyPrivacy and uBlock filters.
+    const EASYLIST: &str = r#"[Adblock Plus 2.0]
+! Version: 202410181200
+! Title: EasyList
+! Expires: 4 days (update frequency)
+! *** easylist:easylist/easylist_general_block.txt ***
+-ad-banner.
+&ad_type=
+/banner/ads/*
+||doubleclick.net^
+||googlesyndication.com^$third-party
+||adnxs.com^$third-party,domain=~adnxs.net
+||amazon-adsystem.com^$third-party,script,domain=example.com|~shop.example.com
+@@||cdn.jsdelivr.net^$script,domain=cdn.jsdelivr.net
+@@||example.net^$document
+||ads.example.org/top.js|
+|http://ad.
+! *** easylist:easylist/easylist_general_hide.txt ***
+##.ad-banner
+##.advert
+##div[id^="div-gpt-ad"]
+example.com##.sidebar-ad
+example.com,example.org##.promo
+example.com#@#.advert
+~forum.example.com##.sponsored
+"#;
+
+    const UNSUPPORTED: &str = r#"youtube.com##+js(no-such-snippet, 1)
+example.com##.ad:has(> .label)
+example.com#?#.post:-abp-contains(Sponsored)
+google.*##.ads
+||example.com^$redirect=noopjs
+/^https?:\/\/[a-z]{8}\.xyz\//$script
+||tracker.example^$removeparam=utm_source
+example.com##^script:has-text(adblock)
+*$image
+"#;
+
+    fn rule_for<'a>(rules: &'a [Value], filter: &str) -> Vec<&'a Value> {
+        rules.iter().filter(|r| r["trigger"]["url-filter"] == filter).collect()
+    }
+
+    #[test]
+    fn parses_easylist_fragment() {
+        let list = parse_list(EASYLIST);
+        assert!(list.unsupported.is_empty(), "{:?}", list.unsupported);
+        assert_eq!(list.network.len(), 11);
+        assert_eq!(list.cosmetic.len(), 7);
+        let adnxs = &list.network[5];
+        assert!(adnxs.host_anchor);
+        assert_eq!(adnxs.pattern, "adnxs.com^");
+        assert_eq!(adnxs.third_party, Some(true));
+        assert_eq!(adnxs.domains.exclude, vec!["adnxs.net"]);
+        let jsdelivr = &list.network[7];
+        assert!(jsdelivr.exception);
+        assert_eq!(jsdelivr.types, Some(vec![Script]));
+    }
+
+    #[test]
+    fn reports_what_webkit_cannot_do() {
+        let list = parse_list(UNSUPPORTED);
+        let reasons: Vec<&str> = list.unsupported.iter().map(|u| u.reason.as_str()).collect();
+        assert_eq!(reasons, vec![
+            "nieznany scriptlet „no-such-snippet”", "proceduralny selektor :has", "proceduralny filtr kosmetyczny",
+            "domena z symbolem wieloznacznym „google.*”", "opcja $redirect", "wyrażenie regularne",
+            "opcja $removeparam", "filtr HTML", "zbyt ogólny wzorzec",
+        ]);
+        assert_eq!(list.unsupported[4].line, 5);
+        assert!(list.network.is_empty() && list.cosmetic.is_empty());
+    }
+
+    #[test]
+    fn scriptlets() {
+        let list = parse_list(r#"youtube.com##+js(set, ytInitialPlayerResponse.adPlacements, undefined)
+example.com,~www.example.com##+js(json-prune.js, "a.b c", d\, e)
+example.org#@#+js()
+##+js(aopr, ads)
+example.net##+js(nostif
+"#);
+        let s = &list.scriptlets;
+        assert_eq!(s.len(), 3);
+        assert_eq!((s[0].name.as_str(), &s[0].args[..]), ("set-constant", &["ytInitialPlayerResponse.adPlacements".to_string(),
+                                                                          "undefined".to_string()][..]));
+        assert_eq!(s[1].args, vec!["a.b c", "d, e"]);
+        assert_eq!(s[1].domains.exclude, vec!["www.example.com"]);
+        assert!(s[2].exception && s[2].name.is_empty());
+        let reasons: Vec<&str> = list.unsupported.iter().map(|u| u.reason.as_str()).collect();
+        assert_eq!(reasons, vec!["scriptlet bez domeny", "nieprawidłowy scriptlet"]);
+    }
+
+    #[test]
+    fn url_filters() {
+        let f = |line: &str| match parse_line(line).unwrap().unwrap() {
+            Filter::Network(n) => url_filter(&n),
+            _ => unreachable!(),
+        };
+        assert_eq!(f("||doubleclick.net^"),
+                   r"^[a-z][a-z0-9.+-]*://([^/:?#]+\.)?doubleclick\.net[^a-zA-Z0-9_.%-]");
+        assert_eq!(f("||ads.example.org/top.js|"),
+                   r"^[a-z][a-z0-9.+-]*://([^/:?#]+\.)?ads\.example\.org/top\.js$");
+        assert_eq!(f("||example.com/ads/^"),
+                   r"^[a-z][a-z0-9.+-]*://([^/:?#]+\.)?example\.com/ads/([^a-zA-Z0-9_.%-].*)?$");
+        assert_eq!(f("|http://ad."), r"^http://ad\.");
+        assert_eq!(f("/banner/ads/*"), "/banner/ads/");
+        assert_eq!(f("&ad_type="), "&ad_type=");
+        assert_eq!(f("-ad-banner."), r"-ad-banner\.");
+        assert_eq!(f("/ad.php?id=*&size="), r"/ad\.php\?id=.*&size=");
+    }
+
+    #[test]
+    fn compiles_network_filters() {
+        let rules = compile(&parse_list(EASYLIST));
+        let host = |h: &str| format!(r"{}{}[^a-zA-Z0-9_.%-]", HOST_START, h);
+
+        let gs = rule_for(&rules, &host(r"googlesyndication\.com"));
+        assert_eq!(gs.len(), 1);
+        assert_eq!(gs[0]["action"]["type"], "block");
+        assert_eq!(gs[0]["trigger"]["load-type"], json!(["third-party"]));
+        assert!(gs[0]["trigger"]["resource-type"].as_array().unwrap().contains(&json!("script")));
+        assert!(!gs[0]["trigger"]["resource-type"].as_array().unwrap().contains(&json!("popup")));
+
+        let adnxs = rule_for(&rules, &host(r"adnxs\.com"));
+        assert_eq!(adnxs[0]["trigger"]["unless-domain"], json!(["*adnxs.net"]));
+
+        // include and exclude domains: block, then undo for the exclusion
+        let amazon = rule_for(&rules, &host(r"amazon-adsystem\.com"));
+        assert_eq!(amazon.len(), 2);
+        assert_eq!(amazon[0]["trigger"]["if-domain"], json!(["*example.com"]));
+        assert_eq!(amazon[0]["trigger"]["resource-type"], json!(["script"]));
+        assert_eq!(amazon[1]["trigger"]["if-domain"], json!(["*shop.example.com"]));
+        assert_eq!(amazon[1]["action"]["type"], "ignore-previous-rules");
+
+        let site = rules.iter().find(|r| r["trigger"]["if-domain"] == json!(["*example.net"])).unwrap();
+        assert_eq!(site["trigger"]["url-filter"], ".*");
+        assert_eq!(site["action"]["type"], "ignore-previous-rules");
+    }
+
+    #[test]
+    fn exceptions_come_after_blocks() {
+        let rules = compile(&parse_list(EASYLIST));
+        let kind = |r: &Value| r["action"]["type"].as_str().unwrap().to_string();
+        let first_ignore = rules.iter().position(|r| kind(r) == "ignore-previous-rules"
+            && r["trigger"]["url-filter"].as_str().unwrap().contains("jsdelivr")).unwrap();
+        assert!(rules[..first_ignore].iter().all(|r| kind(r) != "ignore-previous-rules"
+            || r["trigger"]["if-domain"] == json!(["*shop.example.com"])));
+        assert!(rules[first_ignore..].iter().all(|r| kind(r) != "block"));
+
+        let important = parse_list("@@||ads.example^\n||ads.example^$important");
+        let rules = compile(&important);
+        assert_eq!(rules.iter().map(kind).collect::<Vec<_>>(), vec!["ignore-previous-rules", "block"]);
+    }
+
+    #[test]
+    fn compiles_element_hiding() {
+        let rules = compile(&parse_list(EASYLIST));
+        let css: Vec<&Value> = rules.iter().filter(|r| r["action"]["type"] == "css-display-none").collect();
+        let generic = css.iter().find(|r| r["trigger"].get("if-domain").is_none()
+            && r["trigger"].get("unless-domain").is_none()).unwrap();
+        assert_eq!(generic["action"]["selector"], r#".ad-banner, div[id^="div-gpt-ad"]"#);
+        // #@# turns into an unless-domain on the generic rule
+        let advert = css.iter().find(|r| r["action"]["selector"] == ".advert").unwrap();
+        assert_eq!(advert["trigger"]["unless-domain"], json!(["*example.com"]));
+        let sponsored = css.iter().find(|r| r["action"]["selector"] == ".sponsored").unwrap();
+        assert_eq!(sponsored["trigger"]["unless-domain"], json!(["*forum.example.com"]));
+        let promo = css.iter().find(|r| r["action"]["selector"] == ".promo").unwrap();
+        assert_eq!(promo["trigger"]["if-domain"], json!(["*example.com", "*example.org"]));
+    }
+
+    #[test]
+    fn batches_many_selectors() {
+        let text: String = (0..450).map(|i| format!("##.ad-{}\n", i)).collect();
+        let rules = compile(&parse_list(&text));
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[2]["action"]["selector"].as_str().unwrap().split(", ").count(), 50);
+    }
+
+    #[test]
+    fn splits_rules_with_their_exceptions() {
+        let block = |n: usize| json!({"trigger": {"url-filter": format!("b{}", n)}, "action": {"type": "block"}});
+        let allow = |n: usize| json!({"trigger": {"url-filter": format!("x{}", n)}, "action": {"type": "ignore-previous-rules"}});
+        let name = |r: &Value| r["trigger"]["url-filter"].as_str().unwrap().to_string();
+        let rules = vec![allow(0), block(0), block(1), allow(1), block(2), block(3), block(4), allow(2)];
+        let (parts, dropped) = split_rules(&rules, 4);
+        let names: Vec<Vec<String>> = parts.iter().map(|p| p.iter().map(name).collect()).collect();
+        assert_eq!(names, vec![
+            vec!["b0", "b1", "x1", "x2"],
+            vec!["b2", "b3", "b4", "x2"],
+        ]);
+        assert_eq!(dropped, 0);
+        assert!(parts.iter().all(|p| p.len() <= 4));
+        assert_eq!(split_rules(&rules, 100).0, vec![rules[1..].to_vec()]);
+        // the exceptions alone fill a part: what is left can't be placed
+        assert_eq!(split_rules(&rules, 2), (vec![], 5));
+        assert_eq!(split_rules(&[], 4), (vec![], 0));
+    }
+
+    #[test]
+    fn matching() {
+        let f = |line: &str| match parse_line(line).unwrap().unwrap() {
+            Filter::Network(n) => n,
+            _ => unreachable!(),
+        };
+        let req = |url: &'static str, page_host: &str, third_party: bool| Request {
+            url, lower: url.to_ascii_lowercase(), page_host: page_host.to_string(), third_party, kind: Script,
+        };
+        let dc = f("||doubleclick.net^");
+        assert!(dc.matches(&req("https://doubleclick.net/x", "a.com", true)));
+        assert!(dc.matches(&req("https://stats.g.doubleclick.net:443/x", "a.com", true)));
+        assert!(dc.matches(&req("https://doubleclick.net", "a.com", true)));
+        assert!(!dc.matches(&req("https://notdoubleclick.net/x", "a.com", true)));
+        assert!(!dc.matches(&req("https://doubleclick.network/x", "a.com", true)));
+        assert!(!dc.matches(&req("https://a.com/?u=doubleclick.net/", "a.com", false)));
+
+        assert!(f("/banner/ads/*").matches(&req("http://x.org/banner/ads/1.gif", "x.org", false)));
+        assert!(f("|http://ad.").matches(&req("http://ad.x.org/", "y.org", true)));
+        assert!(!f("|http://ad.").matches(&req("https://ad.x.org/", "y.org", true)));
+        assert!(f("||x.org/top.js|").matches(&req("https://x.org/top.js", "y.org", true)));
+        assert!(!f("||x.org/top.js|").matches(&req("https://x.org/top.js?v=1", "y.org", true)));
+        assert!(f("/ad.php?id=*&size=").matches(&req("https://x.org/ad.php?id=4&w=1&size=2", "x.org", false)));
+
+        let gs = f("||googlesyndication.com^$third-party,domain=example.com|~shop.example.com");
+        assert!(gs.matches(&req("https://googlesyndication.com/a", "www.example.com", true)));
+        assert!(!gs.matches(&req("https://googlesyndication.com/a", "www.example.com", false)));
+        assert!(!gs.matches(&req("https://googlesyndication.com/a", "shop.example.com", true)));
+        assert!(!gs.matches(&req("https://googlesyndication.com/a", "example.org", true)));
+        assert!(!f("||x.org/Ad$match-case").matches(&req("https://x.org/ad", "y.org", true)));
+    }
+
+    #[test]
+    fn matching_types() {
+        let f = |line: &str| match parse_line(line).unwrap().unwrap() {
+            Filter::Network(n) => n,
+            _ => unreachable!(),
+        };
+        let req = |kind| Request {
+            url: "https://x.org/ad.png", lower: "https://x.org/ad.png".into(),
+            page_host: "y.org".into(), third_party: true, kind,
+        };
+        assert!(f("/ad.png$image").matches(&req(Image)));
+        assert!(!f("/ad.png$script").matches(&req(Image)));
+        assert!(!f("/ad.png$~image").matches(&req(Image)));
+        assert!(f("/ad.png").matches(&req(Xhr)));
+        assert!(!f("/ad.png").matches(&req(Popup)));
+        assert_eq!(resource_type("xhr"), Some(Xhr));
+        assert_eq!(resource_type("nope"), None);
+    }
+
+    #[test]
+    fn wildcards_do_not_backtrack() {
+        let t = "a".repeat(5000);
+        let p = "*a".repeat(30) + "b";
+        assert!(!glob(p.as_bytes(), t.as_bytes(), true, false));
+        assert!(glob(b"a*^b", b"a//b", false, true));
+        assert!(!glob(b"x*y", b"..x..y..yx", true, true));
+        assert!(glob(b"x*y", b"..x..y..yx", true, false));
+        assert!(glob(b"x*y", b"..x..y..xy", true, true));
+        assert!(glob(b"ad^", b"/ad", true, false));
+        assert!(!glob(b"ad^", b"/adx", true, false));
+    }
+
+    #[test]
+    fn rejects_broken_selectors() {
+        assert!(parse_line("##div[id=\"x\"").is_err());
+        assert!(parse_line("##.a{color:red}").is_err());
+        assert!(parse_line("example.com##> .x").is_err());
+        assert!(parse_line(r#"##a[href*="]"]"#).is_ok());
+    }
+}
@@ -0,0 +1,198 @@
+//! HTTPS-only mode: documents from `http://` are upgraded to `https://` by
+//! the content blocker, in every frame; a page that then doesn't load gets
+//! an interstitial, and the sites the user chose to visit over HTTP anyway
+//! are remembered in https-exceptions.json (or, from private tabs, until the
+//! browser closes). The state is global because the filter compilation and
+//! the event loop both consult it.
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::Mutex;
+use url::{Host, Url};
+
+struct State {
+    enabled: bool,
+    exceptions: BTreeSet<String>,
+    /// Exceptions made in private tabs, never written down.
+    session: BTreeSet<String>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn exceptions_path() -> std::path::PathBuf {
+    crate::storage::data_dir().join("https-exceptions.json")
+}
+
+fn with_state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    let mut guard = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let state = guard.get_or_insert_with(|| State {
+        enabled: false,
+        exceptions: fs::read_to_string(exceptions_path()).ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default(),
+        session: BTreeSet::new(),
+    });
+    f(state)
+}
+
+pub fn set_enabled(enabled: bool) {
+    with_state(|s| s.enabled = enabled);
+}
+
+pub fn exceptions() -> Vec<String> {
+    with_state(|s| s.exceptions.iter().cloned().collect())
+}
+
+fn save(s: &State) {
+    if let Ok(json) = serde_json::to_string_pretty(&s.exceptions) {
+        fs::write(exceptions_path(), json).ok();
+    }
+}
+
+pub fn add_exception(host: &str) {
+    with_state(|s| if s.exceptions.insert(host.to_ascii_lowercase()) { save(s) });
+}
+
+/// An exception for as long as the browser runs, for private tabs.
+pub fn add_session_exception(host: &str) {
+    with_state(|s| s.session.insert(host.to_ascii_lowercase()));
+}
+
+pub fn remove_exception(host: &str) {
+    with_state(|s| if s.exceptions.remove(host) { save(s) });
+}
+
+/// Hosts that are never upgraded: the local network has no certificates.
+fn is_private(host: &Host<&str>) -> bool {
+    match host {
+        Host::Domain(d) => crate::address::is_local(d.trim_end_matches('.')),
+        Host::Ipv4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        Host::Ipv6(ip) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00
+            || (ip.segments()[0] & 0xffc0) == 0xfe80
+            || ip.to_ipv4_mapped().is_some_and(|v4| is_private(&Host::Ipv4(v4))),
+    }
+}
+
+/// Host of an http(s) url, lowercase, as exceptions are keyed.
+pub fn host_of(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(str::to_ascii_lowercase)
+}
+
+/// The https:// url the content blocker loads instead of `url`, when the
+/// mode is on and the site has no exception. Like the blocker, it leaves
+/// urls with a port of their own alone.
+pub fn upgrade(url: &str) -> Option<String> {
+    let mut u = Url::parse(url).ok().filter(|u| u.scheme() == "http" && u.port().is_none())?;
+    let host = u.host()?;
+    if is_private(&host) { return None; }
+    let name = host.to_string().to_ascii_lowercase();
+    if !with_state(|s| s.enabled && !s.exceptions.contains(&name) && !s.session.contains(&name)) { return None; }
+    u.set_scheme("https").ok()?;
+    Some(u.into())
+}
+
+/// Escapes a host for a content blocker url-filter.
+fn filter_escape(host: &str) -> String {
+    host.chars().fold(String::new(), |mut out, c| {
+        if ".*+?^$|()[]{}\\/".contains(c) { out.push('\\'); }
+        out.push(c);
+        out
+    })
+}
+
+/// url-filters of the documents `upgrade` leaves alone. The filters have no
+/// alternation, so each kind of local address is a rule of its own.
+fn kept_filters(exceptions: impl Iterator<Item = String>) -> Vec<String> {
+    let mut kept: Vec<String> = [
+        "^http://\\[", "^http://127\\.", "^http://10\\.", "^http://192\\.168\\.", "^http://169\\.254\\.",
+        "^http://172\\.1[6-9]\\.", "^http://172\\.2[0-9]\\.", "^http://172\\.3[01]\\.",
+    ].iter().map(|f| f.to_string()).collect();
+    for suffix in crate::address::LOCAL_SUFFIXES {
+        let s = filter_escape(suffix);
+        kept.push(format!("^http://{}\\.?[:/]", s));
+        kept.push(format!("^http://[^/:]*\\.{}\\.?[:/]", s));
+    }
+    kept.extend(exceptions.map(|h| format!("^http://{}[:/]", filter_escape(&h))));
+    kept
+}
+
+/// `rules` with the upgrade appended when the mode is on: last, so that an
+/// allowlisted site is upgraded all the same. Only documents are upgraded,
+/// for the tab and its frames alike; the exceptions then only undo the
+/// upgrade and blocking of those documents.
+pub fn with_upgrade_rules(rules: &str) -> String {
+    let Ok(serde_json::Value::Array(mut all)) = serde_json::from_str(rules) else { return rules.into() };
+    let kept = with_state(|s| s.enabled.then(|| {
+        kept_filters(s.exceptions.iter().chain(&s.session).cloned())
+    }));
+    let Some(kept) = kept else { return rules.into() };
+    all.push(serde_json::json!({
+        "trigger": {"url-filter": "^http://", "resource-type": ["document"]},
+        "action": {"type": "make-https"},
+    }));
+    for filter in kept {
+        all.push(serde_json::json!({
+            "trigger": {"url-filter": filter, "resource-type": ["document"]},
+            "action": {"type": "ignore-previous-rules"},
+        }));
+    }
+    serde_json::Value::Array(all).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn private(host: &str) -> bool {
+        is_private(&Url::parse(&format!("http://{}/", host)).unwrap().host().unwrap())
+    }
+
+    #[test]
+    fn local_network_is_private() {
+        for host in ["localhost", "printer.local", "nas.lan", "router.home.arpa", "127.0.0.1",
+                     "10.1.2.3", "192.168.1.1", "172.20.0.5", "169.254.1.1", "[::1]", "[fd00::1]",
+                     "[fe80::1]", "[::ffff:192.168.0.1]"] {
+            assert!(private(host), "{}", host);
+        }
+        for host in ["example.com", "8.8.8.8", "172.32.0.1", "[2001:db8::1]", "local.example.com"] {
+            assert!(!private(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn hosts_are_lowercase() {
+        assert_eq!(host_of("http://WWW.Example.com:8080/a").as_deref(), Some("www.example.com"));
+        assert_eq!(host_of("https://[::1]/").as_deref(), Some("[::1]"));
+        assert_eq!(host_of("vccat:settings"), None);
+        assert_eq!(host_of("nonsense"), None);
+    }
+
+    // the mode is global state, so everything that switches it is one test
+    #[test]
+    fn upgrades_and_rules() {
+        set_enabled(false);
+        assert_eq!(upgrade("http://example.com/a?b=1"), None);
+        assert_eq!(with_upgrade_rules("[]"), "[]");
+
+        set_enabled(true);
+        assert_eq!(upgrade("http://example.com/a?b=1").as_deref(), Some("https://example.com/a?b=1"));
+        assert_eq!(upgrade("http://example.com:80/").as_deref(), Some("https://example.com/"));
+        assert_eq!(upgrade("http://example.com:8080/"), None);
+        assert_eq!(upgrade("http://192.168.1.1/"), None);
+        assert_eq!(upgrade("https://example.com/"), None);
+        assert_eq!(upgrade("ftp://example.com/"), None);
+
+        add_session_exception("Plain.Example.org");
+        assert_eq!(upgrade("http://plain.example.org/"), None);
+        assert!(!exceptions().contains(&"plain.example.org".to_string()));
+
+        let rules: Vec<serde_json::Value> = serde_json::from_str(&with_upgrade_rules(
+            r#"[{"trigger":{"url-filter":"ads"},"action":{"type":"block"}}]"#)).unwrap();
+        assert_eq!(rules[0]["action"]["type"], "block");
+        assert_eq!(rules[1]["action"]["type"], "make-https");
+        assert_eq!(rules[1]["trigger"]["resource-type"][0], "document");
+        let kept: Vec<&str> = rules[2..].iter().map(|r| r["trigger"]["url-filter"].as_str().unwrap()).collect();
+        assert!(kept.contains(&"^http://plain\\.example\\.org[:/]"));
+        assert!(kept.contains(&"^http://[^/:]*\\.local\\.?[:/]"));
+        set_enabled(false);
+    }
+}
@@ -0,0 +1,243 @@
+//! Internationalized host names in the address bar. Punycode labels are
+//! shown decoded unless they fail the UTS #39 "highly restrictive"
+//! mixed-script check or look like Latin spelled in another script
+//! (Cyrillic `аррӏе`); those stay in punycode and get a warning.
+use serde::Serialize;
+use url::{Host, Url};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Script {
+    /// Digits, hyphen and other characters shared by all scripts.
+    Common,
+    /// Combining marks; they take the script of the base character.
+    Inherited,
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Georgian,
+    Devanagari,
+    Bengali,
+    Tamil,
+    Thai,
+    Ethiopic,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+    Bopomofo,
+    /// Anything this table doesn't know; never shown decoded.
+    Unknown,
+}
+
+/// Script of a character, by Unicode block, for the ranges that appear in
+/// host names. Coarser than the Unicode Scripts data, but it errs towards
+/// keeping punycode.
+fn script(c: char) -> Script {
+    use Script::*;
+    match c as u32 {
+        0x30..=0x39 | 0x2D | 0x5F | 0xB7 | 0x30FC => Common,
+        0x41..=0x5A | 0x61..=0x7A | 0xAA | 0xBA | 0xC0..=0xD6 | 0xD8..=0xF6 | 0xF8..=0x24F
+        | 0x250..=0x2AF | 0x1D00..=0x1D25 | 0x1E00..=0x1EFF | 0x2C60..=0x2C7F
+        | 0xA720..=0xA7FF | 0xAB30..=0xAB5A | 0xFF21..=0xFF3A | 0xFF41..=0xFF5A => Latin,
+        0x300..=0x36F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF
+        | 0xFE20..=0xFE2F | 0x3099..=0x309A => Inherited,
+        0x370..=0x3FF | 0x1F00..=0x1FFF => Greek,
+        0x400..=0x52F | 0x1C80..=0x1C8F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => Cyrillic,
+        0x531..=0x58F | 0xFB13..=0xFB17 => Armenian,
+        0x591..=0x5FF | 0xFB1D..=0xFB4F => Hebrew,
+        0x600..=0x6FF | 0x750..=0x77F | 0x8A0..=0x8FF | 0xFB50..=0xFDFF | 0xFE70..=0xFEFF => Arabic,
+        0x900..=0x97F => Devanagari,
+        0x980..=0x9FF => Bengali,
+        0xB80..=0xBFF => Tamil,
+        0xE00..=0xE7F => Thai,
+        0x10A0..=0x10FF | 0x1C90..=0x1CBF => Georgian,
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Hangul,
+        0x1200..=0x139F => Ethiopic,
+        0x3041..=0x3096 | 0x309D..=0x309F => Hiragana,
+        0x30A0..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => Katakana,
+        0x3100..=0x312F | 0x31A0..=0x31BF => Bopomofo,
+        0x2E80..=0x2FDF | 0x3005 | 0x3007 | 0x3021..=0x3029 | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F => Han,
+        _ => Unknown,
+    }
+}
+
+/// Cyrillic and Greek letters that pass for Latin ones in a host name.
+const LATIN_LOOKALIKES: &str = "аԁеёһіїјӏопрԛсѕуԝхъьгѵѡαικνορυχϲϳ";
+
+/// Top-level domains written in Cyrillic or Greek; under them a name in
+/// the same script is the expected thing rather than a disguise.
+const NON_LATIN_TLDS: &[&str] = &[
+    "рф", "бг", "бел", "укр", "срб", "мкд", "қаз", "мон", "рус", "москва", "дети", "сайт",
+    "онлайн", "орг", "ком", "ελ",
+];
+
+/// Whether the scripts of a label form one of the combinations UTS #39
+/// allows at the "highly restrictive" level: a single script, or Latin
+/// with Han and kana (Japanese), Han and Bopomofo (Chinese), or Han and
+/// Hangul (Korean).
+fn highly_restrictive(scripts: &[Script]) -> bool {
+    use Script::*;
+    if scripts.contains(&Unknown) { return false; }
+    let allowed: [&[Script]; 3] = [
+        &[Latin, Han, Hiragana, Katakana],
+        &[Latin, Han, Bopomofo],
+        &[Latin, Han, Hangul],
+    ];
+    scripts.len() <= 1 || allowed.iter().any(|set| scripts.iter().all(|s| set.contains(s)))
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Show,
+    /// Keep punycode, without raising an alarm (unknown script).
+    Punycode,
+    /// Keep punycode and warn.
+    Spoof,
+}
+
+fn check_label(label: &str, tld: &str) -> Verdict {
+    let mut scripts: Vec<Script> = Vec::new();
+    for c in label.chars() {
+        let s = script(c);
+        if matches!(s, Script::Common | Script::Inherited) { continue; }
+        if !scripts.contains(&s) { scripts.push(s); }
+    }
+    if scripts.contains(&Script::Unknown) { return Verdict::Punycode; }
+    if !highly_restrictive(&scripts) { return Verdict::Spoof; }
+    // whole-script confusable: every letter could be read as Latin
+    let foreign = matches!(scripts.as_slice(), [Script::Cyrillic] | [Script::Greek]);
+    if foreign && !NON_LATIN_TLDS.contains(&tld)
+        && label.chars().filter(|c| c.is_alphabetic()).all(|c| LATIN_LOOKALIKES.contains(c)) {
+        return Verdict::Spoof;
+    }
+    Verdict::Show
+}
+
+/// The host as shown, decoded where safe, and whether it is a lookalike.
+fn display_host(ascii: &str) -> (String, bool) {
+    let labels: Vec<&str> = ascii.split('.').collect();
+    let decode = |l: &str| l.strip_prefix("xn--").and_then(idna::punycode::decode_to_string);
+    let tld = labels.last().map(|t| decode(t).unwrap_or_else(|| t.to_string())).unwrap_or_default();
+    let mut spoof = false;
+    let mut shown = Vec::with_capacity(labels.len());
+    for l in &labels {
+        match decode(l) {
+            Some(u) => match check_label(&u, &tld) {
+                Verdict::Show => shown.push(u),
+                Verdict::Punycode => shown.push(l.to_string()),
+                Verdict::Spoof => { spoof = true; shown.push(l.to_string()); }
+            },
+            None => shown.push(l.to_string()),
+        }
+    }
+    // a spoofed label anywhere discredits the decoded rest too
+    if spoof { return (ascii.to_string(), true); }
+    (shown.join("."), false)
+}
+
+/// The address bar text for a url, split so the registrable domain can be
+/// emphasized: `prefix` + `domain` + `suffix` == `text`.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Display {
+    pub text: String,
+    pub prefix: String,
+    pub domain: String,
+    pub suffix: String,
+    /// The host looks like another one; shown in punycode.
+    pub spoof: bool,
+}
+
+pub fn display(url: &str) -> Display {
+    let plain = || Display { text: url.into(), suffix: url.into(), ..Default::default() };
+    let Ok(parsed) = Url::parse(url) else { return plain() };
+    let Some(Host::Domain(ascii)) = parsed.host() else {
+        // IP addresses: the whole host is what identifies the site
+        let Some(host) = parsed.host_str() else { return plain() };
+        let Some(at) = url.find(host) else { return plain() };
+        return Display {
+            text: url.into(), prefix: url[..at].into(), domain: host.into(),
+            suffix: url[at + host.len()..].into(), spoof: false,
+        };
+    };
+    let (shown, spoof) = display_host(ascii);
+    let Some(at) = url.find(ascii) else { return plain() };
+    let (before, after) = (&url[..at], &url[at + ascii.len()..]);
+
+    // registrable domain: as many trailing labels as the ascii one has
+    let n = crate::address::registrable_domain(ascii)
+        .map_or(0, |d| d.split('.').count());
+    let labels: Vec<&str> = shown.split('.').collect();
+    let split = if n == 0 { 0 } else { labels.len().saturating_sub(n) };
+    let sub = labels[..split].iter().map(|l| format!("{}.", l)).collect::<String>();
+    let domain = labels[split..].join(".");
+    Display {
+        text: format!("{}{}{}", before, shown, after),
+        prefix: format!("{}{}", before, sub),
+        domain,
+        suffix: after.into(),
+        spoof,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(h: &str) -> Display {
+        let ascii = Url::parse(&format!("https://{}/", h)).unwrap();
+        display(ascii.as_str())
+    }
+
+    #[test]
+    fn ascii_hosts_split_at_registrable_domain() {
+        let d = display("https://www.bbc.co.uk/news?x=1");
+        assert_eq!((d.prefix.as_str(), d.domain.as_str(), d.suffix.as_str()),
+                   ("https://www.", "bbc.co.uk", "/news?x=1"));
+        assert!(!d.spoof);
+        let d = display("http://192.168.1.1:8080/");
+        assert_eq!(d.domain, "192.168.1.1");
+        assert_eq!(display("vccat:home").text, "vccat:home");
+    }
+
+    #[test]
+    fn single_script_names_are_decoded() {
+        assert_eq!(host("münchen.de").text, "https://münchen.de/");
+        assert_eq!(host("яндекс.рф").text, "https://яндекс.рф/");
+        assert_eq!(host("παράδειγμα.gr").text, "https://παράδειγμα.gr/");
+        assert_eq!(host("例え.jp").domain, "例え.jp");
+    }
+
+    #[test]
+    fn allowed_cjk_mixes() {
+        // Latin with Han and kana is ordinary Japanese
+        assert!(!host("sonyの店.jp").spoof);
+        assert!(!host("中国abc.cn").spoof);
+        assert!(!host("한국abc.kr").spoof);
+    }
+
+    #[test]
+    fn mixed_scripts_stay_in_punycode() {
+        let d = host("\u{430}pple.com");
+        assert!(d.spoof);
+        assert_eq!(d.text, "https://xn--pple-43d.com/");
+        assert_eq!(d.domain, "xn--pple-43d.com");
+        // Greek omicron in a Latin name
+        assert!(host("g\u{3bf}\u{3bf}gle.com").spoof);
+        // Hangul doesn't go with kana
+        assert!(host("한국の.com").spoof);
+    }
+
+    #[test]
+    fn whole_script_lookalikes_are_flagged() {
+        assert!(host("аррӏе.com").spoof);
+        assert!(host("еха.com").spoof);
+        // the same letters under a Cyrillic TLD are just Russian
+        assert!(!host("аррӏе.рф").spoof);
+        // real Cyrillic words use letters with no Latin twin
+        assert!(!host("пример.com").spoof);
+    }
+}
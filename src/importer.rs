@@ -0,0 +1,488 @@
+//! Importer: history and bookmarks from Firefox and Chromium profiles on disk.
+//! Databases are copied to a temporary directory first, so profiles of a
+//! browser that is still running can be read despite its locks.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::bookmarks::{self, Bookmarks};
+use crate::storage::HistoryEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Browser {
+    Firefox,
+    Chromium,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub browser: Browser,
+    /// Human readable label, e.g. "Google Chrome / Default".
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedBookmark {
+    /// Folder names below the import root.
+    pub path: Vec<String>,
+    pub url: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub keyword: Option<String>,
+    pub created: u64,
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportResult {
+    pub history: Vec<HistoryEntry>,
+    pub bookmarks: Vec<ImportedBookmark>,
+}
+
+/// Seconds between 1601-01-01 (Chromium's epoch) and the unix epoch.
+const WEBKIT_EPOCH_OFFSET: u64 = 11_644_473_600;
+
+fn chromium_time(micros: i64) -> u64 {
+    (micros.max(0) as u64 / 1_000_000).saturating_sub(WEBKIT_EPOCH_OFFSET)
+}
+
+fn firefox_time(micros: i64) -> u64 {
+    micros.max(0) as u64 / 1_000_000
+}
+
+// ── Profile discovery ─────────────────────────────────────────────────────────
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut v: Vec<PathBuf> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|e| e.path()).filter(|p| p.is_dir()).collect();
+    v.sort();
+    v
+}
+
+/// Finds Firefox and Chromium-family profiles of the current user.
+pub fn discover_profiles() -> Vec<Profile> {
+    let mut out = Vec::new();
+    let Some(home) = dirs::home_dir() else { return out };
+
+    let firefox_roots = [
+        home.join(".mozilla/firefox"),
+        home.join("snap/firefox/common/.mozilla/firefox"),
+        home.join(".var/app/org.mozilla.firefox/.mozilla/firefox"),
+    ];
+    for root in firefox_roots {
+        for dir in subdirs(&root) {
+            if dir.join("places.sqlite").exists() {
+                let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                out.push(Profile { browser: Browser::Firefox, name: format!("Firefox / {}", name), path: dir });
+            }
+        }
+    }
+
+    let config = dirs::config_dir().unwrap_or_else(|| home.join(".config"));
+    let chromium_roots = [
+        ("Chromium", config.join("chromium")),
+        ("Google Chrome", config.join("google-chrome")),
+        ("Google Chrome Beta", config.join("google-chrome-beta")),
+        ("Brave", config.join("BraveSoftware/Brave-Browser")),
+        ("Microsoft Edge", config.join("microsoft-edge")),
+        ("Vivaldi", config.join("vivaldi")),
+        ("Chromium (snap)", home.join("snap/chromium/common/chromium")),
+    ];
+    for (label, root) in chromium_roots {
+        for dir in subdirs(&root) {
+            if dir.join("History").exists() || dir.join("Bookmarks").exists() {
+                let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                out.push(Profile { browser: Browser::Chromium, name: format!("{} / {}", label, name), path: dir });
+            }
+        }
+    }
+    out
+}
+
+// ── SQLite helpers ────────────────────────────────────────────────────────────
+
+/// Numbers the scratch directories of imports running side by side.
+static SCRATCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Copies `db` (with its WAL) to a scratch directory of its own and opens
+/// the copy.
+fn open_copy(db: &Path) -> Result<(rusqlite::Connection, PathBuf), String> {
+    let n = SCRATCH.fetch_add(1, Ordering::Relaxed);
+    let scratch = std::env::temp_dir().join(format!("vccat-import-{}-{}", std::process::id(), n));
+    fs::create_dir_all(&scratch).map_err(|e| e.to_string())?;
+    let name = db.file_name().ok_or("bad database path")?;
+    let copy = scratch.join(name);
+    fs::copy(db, &copy).map_err(|e| format!("{}: {}", db.display(), e))?;
+    for ext in ["-wal", "-shm"] {
+        let side = PathBuf::from(format!("{}{}", db.display(), ext));
+        if side.exists() {
+            fs::copy(&side, PathBuf::from(format!("{}{}", copy.display(), ext))).ok();
+        }
+    }
+    let conn = rusqlite::Connection::open(&copy).map_err(|e| e.to_string())?;
+    Ok((conn, scratch))
+}
+
+// ── Firefox ───────────────────────────────────────────────────────────────────
+
+fn firefox_history(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<HistoryEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT p.url, COALESCE(p.title, ''), v.visit_date
+         FROM moz_historyvisits v JOIN moz_places p ON p.id = v.place_id
+         ORDER BY v.visit_date")?;
+    let rows = stmt.query_map([], |r| Ok(HistoryEntry {
+        url: r.get(0)?,
+        title: r.get(1)?,
+        timestamp: firefox_time(r.get::<_, Option<i64>>(2)?.unwrap_or(0)),
+    }))?;
+    rows.collect()
+}
+
+fn firefox_bookmarks(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<ImportedBookmark>> {
+    struct Row { id: i64, kind: i64, parent: i64, title: String, url: Option<String>,
+                 guid: String, added: i64, modified: i64 }
+    let mut stmt = conn.prepare(
+        "SELECT b.id, b.type, b.parent, COALESCE(b.title, ''), p.url, b.guid,
+                COALESCE(b.dateAdded, 0), COALESCE(b.lastModified, 0)
+         FROM moz_bookmarks b LEFT JOIN moz_places p ON p.id = b.fk
+         ORDER BY b.parent, b.position")?;
+    let rows: Vec<Row> = stmt.query_map([], |r| Ok(Row {
+        id: r.get(0)?, kind: r.get(1)?, parent: r.get(2)?, title: r.get(3)?,
+        url: r.get(4)?, guid: r.get(5)?, added: r.get(6)?, modified: r.get(7)?,
+    }))?.collect::<rusqlite::Result<_>>()?;
+
+    let mut keywords = std::collections::HashMap::new();
+    let mut kstmt = conn.prepare(
+        "SELECT p.url, k.keyword FROM moz_keywords k JOIN moz_places p ON p.id = k.place_id")?;
+    for kv in kstmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))? {
+        let (url, kw) = kv?;
+        keywords.insert(url, kw);
+    }
+
+    let by_id: std::collections::HashMap<i64, &Row> = rows.iter().map(|r| (r.id, r)).collect();
+    let tags_root = rows.iter().find(|r| r.guid == "tags________").map(|r| r.id);
+
+    // tags are folders below the tags root holding bookmarks of the tagged url
+    let mut tags: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
+    for r in rows.iter().filter(|r| r.kind == 1) {
+        let Some(folder) = by_id.get(&r.parent) else { continue };
+        if Some(folder.parent) == tags_root {
+            if let Some(url) = &r.url {
+                tags.entry(url.clone()).or_default().push(folder.title.clone());
+            }
+        }
+    }
+
+    let root_name = |r: &Row| -> String {
+        match r.guid.as_str() {
+            "menu________"    => "Menu zakładek".into(),
+            "toolbar_____"    => "Pasek zakładek".into(),
+            "unfiled_____"    => "Inne zakładki".into(),
+            "mobile______"    => "Zakładki z telefonu".into(),
+            _ => r.title.clone(),
+        }
+    };
+
+    let mut out = Vec::new();
+    for r in rows.iter().filter(|r| r.kind == 1) {
+        let Some(url) = &r.url else { continue };
+        if url.starts_with("place:") { continue; }
+        let mut path = Vec::new();
+        let mut cur = r.parent;
+        let mut in_tags = false;
+        while let Some(f) = by_id.get(&cur) {
+            if Some(f.id) == tags_root { in_tags = true; break; }
+            if f.guid == "root________" { break; }
+            path.push(root_name(f));
+            cur = f.parent;
+            if path.len() > 64 { break; }
+        }
+        if in_tags { continue; }
+        path.reverse();
+        out.push(ImportedBookmark {
+            path,
+            url: url.clone(),
+            title: r.title.clone(),
+            tags: tags.get(url).cloned().unwrap_or_default(),
+            keyword: keywords.get(url).cloned(),
+            created: firefox_time(r.added),
+            modified: firefox_time(r.modified),
+        });
+    }
+    Ok(out)
+}
+
+// ── Chromium ──────────────────────────────────────────────────────────────────
+
+fn chromium_history(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<HistoryEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT u.url, COALESCE(u.title, ''), v.visit_time
+         FROM visits v JOIN urls u ON u.id = v.url
+         ORDER BY v.visit_time")?;
+    let rows = stmt.query_map([], |r| Ok(HistoryEntry {
+        url: r.get(0)?,
+        title: r.get(1)?,
+        timestamp: chromium_time(r.get(2)?),
+    }))?;
+    rows.collect()
+}
+
+fn chromium_node(node: &serde_json::Value, path: &mut Vec<String>, out: &mut Vec<ImportedBookmark>) {
+    let time = |k: &str| node[k].as_str().and_then(|s| s.parse::<i64>().ok()).map(chromium_time);
+    let name = node["name"].as_str().unwrap_or("").to_string();
+    match node["type"].as_str() {
+        Some("url") => {
+            let Some(url) = node["url"].as_str() else { return };
+            let created = time("date_added").unwrap_or(0);
+            out.push(ImportedBookmark {
+                path: path.clone(), url: url.into(), title: name, tags: vec![], keyword: None,
+                created, modified: time("date_modified").filter(|&t| t > 0).unwrap_or(created),
+            });
+        }
+        Some("folder") => {
+            path.push(name);
+            for child in node["children"].as_array().into_iter().flatten() {
+                chromium_node(child, path, out);
+            }
+            path.pop();
+        }
+        _ => {}
+    }
+}
+
+/// Parses a Chromium `Bookmarks` JSON file.
+pub fn chromium_bookmarks(json: &str) -> Result<Vec<ImportedBookmark>, String> {
+    let v: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let mut out = Vec::new();
+    for (key, label) in [("bookmark_bar", "Pasek zakładek"), ("other", "Inne zakładki"),
+                         ("synced", "Zakładki z telefonu")] {
+        let root = &v["roots"][key];
+        let mut path = vec![label.to_string()];
+        for child in root["children"].as_array().into_iter().flatten() {
+            chromium_node(child, &mut path, &mut out);
+        }
+    }
+    Ok(out)
+}
+
+// ── Import ────────────────────────────────────────────────────────────────────
+
+/// Reads the history and/or bookmarks of `profile`.
+pub fn read_profile(profile: &Profile, history: bool, bookmarks: bool) -> Result<ImportResult, String> {
+    let mut res = ImportResult::default();
+    match profile.browser {
+        Browser::Firefox => {
+            let (conn, scratch) = open_copy(&profile.path.join("places.sqlite"))?;
+            let r = (|| -> rusqlite::Result<()> {
+                if history { res.history = firefox_history(&conn)?; }
+                if bookmarks { res.bookmarks = firefox_bookmarks(&conn)?; }
+                Ok(())
+            })();
+            drop(conn);
+            fs::remove_dir_all(scratch).ok();
+            r.map_err(|e| e.to_string())?;
+        }
+        Browser::Chromium => {
+            if history && profile.path.join("History").exists() {
+                let (conn, scratch) = open_copy(&profile.path.join("History"))?;
+                let r = chromium_history(&conn);
+                drop(conn);
+                fs::remove_dir_all(scratch).ok();
+                res.history = r.map_err(|e| e.to_string())?;
+            }
+            if bookmarks {
+                if let Ok(json) = fs::read_to_string(profile.path.join("Bookmarks")) {
+                    res.bookmarks = chromium_bookmarks(&json)?;
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Merges imported visits into `history`, skipping visits already present
+/// (same url and timestamp). Returns the number of visits added.
+pub fn merge_history(history: &mut Vec<HistoryEntry>, imported: Vec<HistoryEntry>) -> usize {
+    let mut seen: std::collections::HashSet<(String, u64)> =
+        history.iter().map(|h| (h.url.clone(), h.timestamp)).collect();
+    let before = history.len();
+    for h in imported {
+        if h.url.is_empty() || h.url.starts_with("about:") || h.url.starts_with("vccat:") { continue; }
+        if seen.insert((h.url.clone(), h.timestamp)) {
+            history.push(h);
+        }
+    }
+    history.sort_by_key(|h| h.timestamp);
+    history.len() - before
+}
+
+/// Adds imported bookmarks below a `root_name` folder, recreating their
+/// folders. Urls that are already bookmarked are skipped. Returns the number
+/// of bookmarks added.
+pub fn merge_bookmarks(b: &mut Bookmarks, root_name: &str, imported: &[ImportedBookmark]) -> usize {
+    let find_or_add = |b: &mut Bookmarks, name: &str, parent: u64| -> u64 {
+        match b.folders.iter().find(|f| f.parent == parent && f.name == name) {
+            Some(f) => f.id,
+            None => b.add_folder(name, parent),
+        }
+    };
+    let mut known: std::collections::HashSet<String> = b.items.iter().map(|m| m.url.clone()).collect();
+    let mut added = 0;
+    for m in imported {
+        if !known.insert(m.url.clone()) { continue; }
+        let mut folder = find_or_add(b, root_name, bookmarks::ROOT);
+        for part in &m.path {
+            folder = find_or_add(b, part, folder);
+        }
+        b.add(&m.url, &m.title, folder);
+        if let Some(n) = b.items.last_mut() {
+            n.tags = m.tags.clone();
+            n.keyword = m.keyword.clone();
+            if m.created > 0 { n.created = m.created; }
+            if m.modified > 0 { n.modified = m.modified; }
+        }
+        added += 1;
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visit(url: &str, timestamp: u64) -> HistoryEntry {
+        HistoryEntry { url: url.into(), title: String::new(), timestamp }
+    }
+
+    #[test]
+    fn converts_timestamps() {
+        // 2024-01-01 00:00:00 UTC
+        assert_eq!(firefox_time(1_704_067_200_000_000), 1_704_067_200);
+        assert_eq!(chromium_time(13_348_540_800_000_000), 1_704_067_200);
+        // missing or bogus times come out as the epoch, not a wrap-around
+        assert_eq!(firefox_time(-5), 0);
+        assert_eq!(chromium_time(0), 0);
+        assert_eq!(chromium_time(1_000_000), 0);
+    }
+
+    #[test]
+    fn reads_history_databases() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE moz_places (id INTEGER, url TEXT, title TEXT);
+             CREATE TABLE moz_historyvisits (place_id INTEGER, visit_date INTEGER);
+             INSERT INTO moz_places VALUES (1, 'https://a.pl/', 'A'), (2, 'https://b.pl/', NULL);
+             INSERT INTO moz_historyvisits VALUES (2, 1704067260000000), (1, 1704067200000000);
+             CREATE TABLE urls (id INTEGER, url TEXT, title TEXT);
+             CREATE TABLE visits (url INTEGER, visit_time INTEGER);
+             INSERT INTO urls VALUES (7, 'https://c.pl/', 'C');
+             INSERT INTO visits VALUES (7, 13348540800000000);").unwrap();
+        let ff = firefox_history(&conn).unwrap();
+        assert_eq!(ff.iter().map(|h| (h.url.as_str(), h.title.as_str(), h.timestamp)).collect::<Vec<_>>(),
+                   [("https://a.pl/", "A", 1_704_067_200), ("https://b.pl/", "", 1_704_067_260)]);
+        let cr = chromium_history(&conn).unwrap();
+        assert_eq!((cr[0].url.as_str(), cr[0].timestamp), ("https://c.pl/", 1_704_067_200));
+    }
+
+    #[test]
+    fn scratch_copies_are_apart() {
+        let dir = std::env::temp_dir().join(format!("vccat-import-src-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("places.sqlite");
+        rusqlite::Connection::open(&db).unwrap().execute_batch("CREATE TABLE t (x INTEGER);").unwrap();
+        let (a, scratch_a) = open_copy(&db).unwrap();
+        let (b, scratch_b) = open_copy(&db).unwrap();
+        assert_ne!(scratch_a, scratch_b);
+        // one import finishing leaves the other's copy alone
+        drop(a);
+        fs::remove_dir_all(&scratch_a).ok();
+        assert!(b.execute_batch("SELECT x FROM t;").is_ok());
+        drop(b);
+        fs::remove_dir_all(&scratch_b).ok();
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reads_firefox_bookmarks() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE moz_places (id INTEGER, url TEXT);
+             CREATE TABLE moz_bookmarks (id INTEGER, type INTEGER, parent INTEGER, position INTEGER,
+                 title TEXT, fk INTEGER, guid TEXT, dateAdded INTEGER, lastModified INTEGER);
+             CREATE TABLE moz_keywords (place_id INTEGER, keyword TEXT);
+             INSERT INTO moz_places VALUES (1, 'https://a.pl/'), (2, 'https://b.pl/'), (3, 'place:sort=8');
+             INSERT INTO moz_bookmarks VALUES
+                 (1, 2, 0, 0, '', NULL, 'root________', 0, 0),
+                 (2, 2, 1, 0, 'menu', NULL, 'menu________', 0, 0),
+                 (3, 2, 1, 1, 'toolbar', NULL, 'toolbar_____', 0, 0),
+                 (4, 2, 1, 2, 'tags', NULL, 'tags________', 0, 0),
+                 (5, 2, 3, 0, 'Praca', NULL, 'f1', 0, 0),
+                 (6, 1, 5, 0, 'A', 1, 'b1', 1704067200000000, 1704067260000000),
+                 (7, 1, 2, 0, 'B', 2, 'b2', 1704067200000000, NULL),
+                 (8, 1, 2, 1, 'Ostatnie', 3, 'b3', 0, 0),
+                 (9, 2, 4, 0, 'rust', NULL, 't1', 0, 0),
+                 (10, 1, 9, 0, NULL, 1, 't2', 0, 0);
+             INSERT INTO moz_keywords VALUES (2, 'b');").unwrap();
+        let marks = firefox_bookmarks(&conn).unwrap();
+        let rows: Vec<(String, &str, &str)> = marks.iter()
+            .map(|m| (m.path.join(" / "), m.url.as_str(), m.title.as_str())).collect();
+        assert_eq!(rows, [("Menu zakładek".to_string(), "https://b.pl/", "B"),
+                          ("Pasek zakładek / Praca".to_string(), "https://a.pl/", "A")]);
+        assert_eq!((marks[1].tags.as_slice(), marks[1].created, marks[1].modified),
+                   (&["rust".to_string()][..], 1_704_067_200, 1_704_067_260));
+        assert_eq!(marks[0].keyword.as_deref(), Some("b"));
+        assert!(marks[0].tags.is_empty());
+    }
+
+    #[test]
+    fn reads_chromium_bookmarks() {
+        let json = r#"{"roots": {
+            "bookmark_bar": {"type": "folder", "children": [
+                {"type": "url", "name": "A", "url": "https://a.pl/", "date_added": "13348540800000000"},
+                {"type": "folder", "name": "Praca", "children": [
+                    {"type": "url", "name": "B", "url": "https://b.pl/", "date_added": "13348540800000000",
+                     "date_modified": "13348540860000000"}]}]},
+            "other": {"type": "folder", "children": [{"type": "url", "name": "C", "url": "https://c.pl/"}]},
+            "synced": {"type": "folder", "children": []}}}"#;
+        let marks = chromium_bookmarks(json).unwrap();
+        let rows: Vec<(String, &str, u64, u64)> = marks.iter()
+            .map(|m| (m.path.join(" / "), m.url.as_str(), m.created, m.modified)).collect();
+        assert_eq!(rows, [("Pasek zakładek".to_string(), "https://a.pl/", 1_704_067_200, 1_704_067_200),
+                          ("Pasek zakładek / Praca".to_string(), "https://b.pl/", 1_704_067_200, 1_704_067_260),
+                          ("Inne zakładki".to_string(), "https://c.pl/", 0, 0)]);
+        assert!(chromium_bookmarks("{").is_err());
+    }
+
+    #[test]
+    fn merges_bookmarks_without_duplicates() {
+        let imported = |path: &[&str], url: &str| ImportedBookmark {
+            path: path.iter().map(|p| p.to_string()).collect(), url: url.into(), title: url.into(),
+            tags: vec!["t".into()], keyword: Some("k".into()), created: 5, modified: 0,
+        };
+        let mut b = Bookmarks::default();
+        b.add("https://a.pl/", "A", bookmarks::ROOT);
+        let list = [imported(&["Pasek"], "https://a.pl/"), imported(&["Pasek"], "https://b.pl/"),
+                    imported(&["Pasek", "Praca"], "https://c.pl/"), imported(&["Pasek"], "https://b.pl/")];
+        assert_eq!(merge_bookmarks(&mut b, "Firefox", &list), 2);
+        assert_eq!(b.folders.iter().map(|f| b.folder_path(f.id)).collect::<Vec<_>>(),
+                   ["Firefox", "Firefox / Pasek", "Firefox / Pasek / Praca"]);
+        let c = b.find_by_url("https://c.pl/").unwrap();
+        assert_eq!(b.folder_path(c.folder), "Firefox / Pasek / Praca");
+        assert_eq!((c.tags.as_slice(), c.keyword.as_deref(), c.created), (&["t".to_string()][..], Some("k"), 5));
+        // a second import reuses the folders and adds nothing
+        assert_eq!(merge_bookmarks(&mut b, "Firefox", &list), 0);
+        assert_eq!(b.folders.len(), 3);
+    }
+
+    #[test]
+    fn merges_history_without_duplicates() {
+        let mut history = vec![visit("https://a.pl/", 10), visit("https://b.pl/", 30)];
+        let imported = vec![
+            visit("https://a.pl/", 10), visit("https://a.pl/", 20), visit("https://a.pl/", 20),
+            visit("about:blank", 5), visit("vccat:settings", 6), visit("", 7), visit("https://c.pl/", 1),
+        ];
+        assert_eq!(merge_history(&mut history, imported), 2);
+        assert_eq!(history.iter().map(|h| h.timestamp).collect::<Vec<_>>(), [1, 10, 20, 30]);
+        assert_eq!(merge_history(&mut history, vec![visit("https://c.pl/", 1)]), 0);
+    }
+}
@@ -0,0 +1,86 @@
+//! Internal pages and our views of local files are served to WebKit from the
+//! `vccat` scheme, as documents of their own `vccat://app` origin rather than
+//! html written over whatever page the tab showed. Tabs keep the short
+//! `vccat:` names and `file://` urls; the uri WebKit loads is only ever built
+//! and read back here.
+
+pub const SCHEME: &str = "vccat";
+
+/// Origin and path prefix of every internal document.
+const PREFIX: &str = "vccat://app/";
+
+/// Path of listings and text views under `PREFIX`; internal page names are
+/// encoded whole, so none of them starts like this.
+const FILE_VIEW: &str = "file/";
+
+/// Uri WebKit loads for an internal `vccat:` url or a local file we render.
+pub fn uri_of(url: &str) -> String {
+    if url.starts_with("file:") {
+        return format!("{}{}{}", PREFIX, FILE_VIEW, urlencoding::encode(url));
+    }
+    let name = url.strip_prefix("vccat:").unwrap_or(url);
+    format!("{}{}", PREFIX, urlencoding::encode(name))
+}
+
+/// The `vccat:` or `file://` url of a document WebKit shows, or None for web
+/// pages.
+pub fn url_of(uri: &str) -> Option<String> {
+    let rest = uri.strip_prefix(PREFIX)?;
+    // in-page anchors and forms may add these; names never hold them raw
+    let rest = rest.split(['#', '?']).next().unwrap_or("");
+    if let Some(file) = rest.strip_prefix(FILE_VIEW) {
+        return urlencoding::decode(file).ok().map(String::from);
+    }
+    let name = urlencoding::decode(rest).ok()?;
+    Some(format!("vccat:{}", name))
+}
+
+/// Whether the document at `uri` may drive the browser through IPC: internal
+/// pages only, and not the saved copies of web pages shown among them.
+pub fn takes_commands(uri: &str) -> bool {
+    url_of(uri).is_some_and(|u| u.starts_with("vccat:") && !u.starts_with("vccat:reading/"))
+}
+
+/// Whether the document at `uri` is local, our view of a file or a file
+/// WebKit shows itself, and so may open other local files.
+pub fn is_local(uri: &str) -> bool {
+    uri.starts_with("file:") || url_of(uri).is_some_and(|u| u.starts_with("file:"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for url in ["vccat:settings", "vccat:reading/12", "vccat:https-only/http://a.pl/x?q=1#top",
+                    "vccat:file/x", "file:///home/a%20b/c.txt", "file:///tmp/"] {
+            let uri = uri_of(url);
+            assert!(uri.starts_with("vccat://app/"));
+            let rest = &uri["vccat://app/".len()..];
+            assert!(!rest.trim_start_matches("file/").contains(['/', '?', '#']), "{}", uri);
+            assert_eq!(url_of(&uri).as_deref(), Some(url));
+        }
+        assert_eq!(url_of("vccat://app/settings#sync").as_deref(), Some("vccat:settings"));
+        assert_eq!(url_of("https://app/settings"), None);
+        assert_eq!(url_of("vccat:settings"), None);
+    }
+
+    #[test]
+    fn commands_only_from_internal_documents() {
+        assert!(takes_commands(&uri_of("vccat:settings")));
+        assert!(!takes_commands(&uri_of("vccat:reading/3")));
+        assert!(!takes_commands("https://evil.example/vccat://app/settings"));
+        assert!(!takes_commands("vccat://apps/settings"));
+        assert!(!takes_commands("about:blank"));
+        assert!(!takes_commands(&uri_of("file:///etc/")));
+    }
+
+    #[test]
+    fn local_documents() {
+        assert!(is_local(&uri_of("file:///etc/")));
+        assert!(is_local("file:///home/a/index.html"));
+        assert!(!is_local(&uri_of("vccat:settings")));
+        assert!(!is_local("https://evil.example/file:///etc/"));
+    }
+}
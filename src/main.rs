@@ -1,6 +1,27 @@
 mod storage;
 mod updater;
 mod adblock;
+mod address;
+mod allowlist;
+mod vault;
+mod backup;
+mod blocklog;
+mod bookmarks;
+mod cosmetic;
+mod files;
+mod filters;
+mod https;
+mod idn;
+mod importer;
+mod internal;
+mod omnibox;
+mod reading;
+mod scriptlets;
+mod search;
+mod settings;
+mod subscriptions;
+mod sync;
+mod tracking;
 
 use tao::{
     event::{Event, WindowEvent},
@@ -16,16 +37,41 @@ use wry::WebViewBuilderExtUnix;
 
 // ── Tab state ─────────────────────────────────────────────────────────────────
 
+/// Numbers tabs for the whole run; unlike its index, a tab's id stays put
+/// while other tabs open and close.
+static NEXT_TAB_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
 struct Tab {
+    id:        u64,
     url:       String,
     title:     String,
     favicon:   Option<String>,
     suspended: bool,
+    /// Private tabs use an ephemeral WebKit context and are never written
+    /// to history or the session file.
+    private:   bool,
+    /// OpenSearch description offered by the page, if any.
+    search_offer: Option<String>,
+    /// Requests of the current page stopped by the content blocker, the
+    /// latest `blocklog::MAX_ENTRIES` of them, and how many there were.
+    blocked: Vec<blocklog::Entry>,
+    blocked_count: usize,
+    /// Elements of the current page hidden by cosmetic filters.
+    hidden: usize,
+    /// The http url HTTPS-only mode is upgrading in this tab, and the url
+    /// shown before it, until the tab moves on.
+    upgrading: Option<(String, String)>,
 }
 
 impl Tab {
     fn new(url: &str) -> Self {
-        Tab { url: url.into(), title: String::new(), favicon: None, suspended: false }
+        Tab { id: NEXT_TAB_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+              url: url.into(), title: String::new(), favicon: None, suspended: false, private: false,
+              search_offer: None, blocked: Vec::new(), blocked_count: 0, hidden: 0, upgrading: None }
+    }
+
+    fn new_private(url: &str) -> Self {
+        Tab { private: true, ..Tab::new(url) }
     }
 }
 
@@ -34,41 +80,105 @@ impl Tab {
 #[derive(Debug, Clone)]
 enum UserEvent {
     Navigate(String),
+    /// Loads a url in a tab if it still shows the first one: tab, url it
+    /// showed when asked, url.
+    NavigateTab(usize, String, String),
+    AddressInput(String),
     GoBack, GoForward, Reload,
     NewTab,
+    NewPrivateTab,
     CloseTab(usize),
+    /// Closes the tabs with these ids, wherever they are by then.
+    CloseTabIds(Vec<u64>),
     SwitchTab(usize),
-    ShowHistory,
+    OpenTab(String),
+    ToggleBookmark,
+    /// Toolbar shield: blocking off or on for the active tab's site.
+    ToggleShield,
+    /// A new document started in a page: tab, its uri as WebKit has it.
+    CosmeticRequest(usize, String),
+    /// A resource of the page failed to load, maybe blocked: tab, type, url.
+    ResourceFailed(usize, filters::ResourceType, String),
+    /// A filter blocked a resource of the page: tab, page url, log entry.
+    ResourceBlocked(usize, String, blocklog::Entry),
+    /// Elements hidden on the page so far: tab, count.
+    ElementsHidden(usize, usize),
+    PageCommand(usize, String),
+    /// WebKit asked the vccat scheme for pages; they wait in `INTERNAL_REQUESTS`.
+    RenderInternal,
+    LocalFile(usize, String),
+    /// The main frame of a tab failed to load: tab, url, error.
+    LoadFailed(usize, String, String),
+    /// Tab and the url it was navigating to, without tracking parameters.
+    LoadStripped(usize, String),
+    CopyUrl(String),
+    /// Content-blocker JSON from the filter list refresh thread.
+    FilterRules(String),
+    ContentFilterReady,
+    ImportDone(usize, String, Result<importer::ImportResult, String>),
+    SyncTick,
+    /// A sync round finished on the worker thread.
+    SyncDone(Result<sync::Report, String>),
+    SaveForLater,
+    ArticleCaptured(usize, String),
+    ArticleReady(reading::Article, String),
+    SearchEngineOffered(usize, String),
+    AddSearchEngine,
+    SearchEngineFetched(Result<search::SearchEngine, String>),
+    OmniboxInput(String, bool),
+    OmniboxMove(i32),
+    OmniboxPick(usize),
+    OmniboxClose,
+    SettingsChanged(settings::Settings),
     PageUrlChanged(usize, String),
     PageFaviconChanged(usize, String),
     PageTitleChanged(usize, String),
     UpdateAvailable(String, String),
 }
 
-// ── URL normalizer ────────────────────────────────────────────────────────────
+// ── HTML helpers ──────────────────────────────────────────────────────────────
 
-fn normalize_url(raw: &str) -> String {
-    let raw = raw.trim();
-    if raw.is_empty() { return "vccat:home".into(); }
-    if raw.starts_with("about:") || raw.starts_with("vccat:") || raw.starts_with("data:") {
-        return raw.into();
-    }
-    if !raw.starts_with("http://") && !raw.starts_with("https://") {
-        if raw.contains('.') && !raw.contains(' ') {
-            return format!("https://{}", raw);
-        }
-        return format!("https://search.brave.com/search?q={}", urlencoding::encode(raw));
-    }
-    raw.into()
+fn esc(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+     .replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+/// The first `max` characters of `s`, not bytes: titles are often Polish.
+fn clip(s: &str, max: usize) -> &str {
+    s.char_indices().nth(max).map_or(s, |(i, _)| &s[..i])
 }
 
 // ── Page init JS ──────────────────────────────────────────────────────────────
 
 fn page_init_js(tab_idx: usize) -> String {
-    let adblock = adblock::youtube_dom_cleaner_js();
     format!(r#"(function() {{
     function ipc(m) {{ window.ipc.postMessage(m); }}
-    ipc('url:' + location.href);
+    // a new document: its counts start over, and what element hiding
+    // hides on it gets counted
+    ipc('cosmetic');
+    // the content blocker stops requests silently; failed loads are checked
+    // against the filter lists to count and log the blocked ones. Each url
+    // once, and no more than a page's worth of them
+    const failed = new Set();
+    function kindOf(t) {{
+        switch (t.tagName) {{
+            case 'IMG': return 'image';
+            case 'SCRIPT': return 'script';
+            case 'LINK': return /stylesheet/i.test(t.rel) ? 'stylesheet' : 'other';
+            case 'IFRAME': case 'FRAME': return 'subdocument';
+            case 'VIDEO': case 'AUDIO': case 'TRACK': return 'media';
+            case 'SOURCE': return t.parentElement && t.parentElement.tagName === 'PICTURE' ? 'image' : 'media';
+            case 'OBJECT': case 'EMBED': return 'object';
+            default: return 'other';
+        }}
+    }}
+    window.addEventListener('error', e => {{
+        const t = e.target;
+        const src = t && t !== window && (t.currentSrc || t.src || t.href);
+        if (!src || !/^https?:/.test(src) || failed.has(src) || failed.size >= 1000) return;
+        failed.add(src);
+        ipc('failed:' + kindOf(t) + ' ' + src);
+    }}, true);
     function sendTitle() {{ if (document.title) ipc('title:' + document.title); }}
     sendTitle();
     const titleEl = document.querySelector('title');
@@ -78,13 +188,13 @@ fn page_init_js(tab_idx: usize) -> String {
         for (const l of document.querySelectorAll('link[rel~="icon"],link[rel~="shortcut"]')) {{
             if (l.href) {{ fav = l.href; break; }}
         }}
-        if (!fav && location.origin !== 'null') fav = location.origin + '/favicon.ico';
+        if (!fav && /^https?:$/.test(location.protocol)) fav = location.origin + '/favicon.ico';
         if (fav) ipc('favicon:' + fav);
     }}
     if (document.readyState === 'loading') document.addEventListener('DOMContentLoaded', sendFavicon);
     else sendFavicon();
-    {adblock}
-}})();"#, adblock = adblock)
+    {search}
+}})();"#, search = search::discovery_js())
 }
 
 // ── Home page ─────────────────────────────────────────────────────────────────
@@ -92,10 +202,8 @@ fn page_init_js(tab_idx: usize) -> String {
 fn home_page_html(history: &[storage::HistoryEntry]) -> String {
     let recent: String = history.iter().rev().take(8).map(|h| {
         let title = if h.title.is_empty() { &h.url } else { &h.title };
-        let t = if title.len() > 45 { &title[..45] } else { title };
-        let u = if h.url.len() > 55 { &h.url[..55] } else { &h.url };
         format!(r#"<a href="{url}" class="hi"><span class="ht">{t}</span><span class="hu">{u}</span></a>"#,
-                url = h.url, t = t, u = u)
+                url = esc(&h.url), t = esc(clip(title, 45)), u = esc(clip(&h.url, 55)))
     }).collect();
 
     format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>vccat</title>
@@ -122,13 +230,34 @@ html,body{{height:100%;background:#08080f;color:#555;
 </body></html>"#, recent, env!("CARGO_PKG_VERSION"))
 }
 
+fn private_home_html() -> String {
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>vccat — prywatna</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+html,body{{height:100%;background:#0f070c;color:#5a3a4a;
+  font-family:'JetBrains Mono','Fira Code',monospace;
+  display:flex;flex-direction:column;align-items:center;justify-content:center;gap:20px;}}
+.logo{{font-size:32px;font-weight:700;letter-spacing:0.2em;color:#2a1420;
+  text-shadow:0 0 60px #4e1a34;}}
+.logo span{{color:#8a3a62;}}
+.info{{width:460px;max-width:90vw;font-size:11px;line-height:1.7;color:#6a4256;
+  border:1px solid #2a1420;background:#140a10;border-radius:8px;padding:14px 16px;}}
+.ver{{font-size:10px;color:#24121c;position:fixed;bottom:12px;right:16px;}}
+</style></head><body>
+<div class="logo">vc<span>cat</span></div>
+<div class="info">karta prywatna — odwiedzone strony nie trafiają do historii ani do zapisanej sesji,
+a ciasteczka i dane stron znikają po zamknięciu karty.</div>
+<div class="ver">v{}</div>
+</body></html>"#, env!("CARGO_PKG_VERSION"))
+}
+
 // ── History page ──────────────────────────────────────────────────────────────
 
 fn history_page_html(history: &[storage::HistoryEntry]) -> String {
     let rows: String = history.iter().rev().take(300).map(|h| {
-        let title = if h.title.is_empty() { h.url.clone() } else { h.title.clone() };
-        format!(r#"<tr><td><a href="{}">{}</a></td><td class="u">{}</td></tr>"#,
-                h.url, title, h.url)
+        let title = if h.title.is_empty() { &h.url } else { &h.title };
+        format!(r#"<tr><td><a href="{u}">{t}</a></td><td class="u">{u}</td></tr>"#,
+                u = esc(&h.url), t = esc(title))
     }).collect();
     format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Historia</title>
 <style>
@@ -147,6 +276,719 @@ a:hover{{color:#8a6abb;}}
 </body></html>"#, rows)
 }
 
+// ── Bookmarks page ────────────────────────────────────────────────────────────
+
+fn bookmarks_page_html(b: &bookmarks::Bookmarks) -> String {
+    let mut folders = vec![(bookmarks::ROOT, "Zakładki".to_string())];
+    let mut sorted: Vec<_> = b.folders.iter().map(|f| (f.id, b.folder_path(f.id))).collect();
+    sorted.sort_by_key(|f| f.1.to_lowercase());
+    folders.extend(sorted);
+
+    let options = |selected: u64| -> String {
+        folders.iter().map(|(id, name)| format!(r#"<option value="{}"{}>{}</option>"#,
+            id, if *id == selected { " selected" } else { "" }, esc(name))).collect()
+    };
+
+    let sections: String = folders.iter().map(|(fid, name)| {
+        let rows: String = b.items.iter().filter(|m| m.folder == *fid).map(|m| {
+            let title = if m.title.is_empty() { &m.url } else { &m.title };
+            let tags: String = m.tags.iter()
+                .map(|t| format!(r#"<span class="tag">{}</span>"#, esc(t))).collect();
+            let kw = m.keyword.as_deref()
+                .map(|k| format!(r#"<span class="kw">{}</span>"#, esc(k))).unwrap_or_default();
+            format!(r#"<div class="bm" data-s="{search}">
+<div class="row"><a href="{url}">{title}</a>{kw}{tags}
+<span class="act"><button onclick="edit({id})">edytuj</button><button onclick="cmd({{op:'delete',id:{id}}})">usuń</button></span></div>
+<div class="u">{url}</div>
+<form class="ed" id="ed{id}" onsubmit="save({id});return false;">
+<input name="title" value="{etitle}" placeholder="tytuł"><input name="url" value="{url}" placeholder="adres">
+<input name="tags" value="{etags}" placeholder="tagi, po przecinku"><input name="keyword" value="{ekw}" placeholder="słowo kluczowe">
+<select name="folder">{opts}</select><button>zapisz</button></form></div>"#,
+                search = esc(&format!("{} {} {}", m.title, m.url, m.tags.join(" ")).to_lowercase()),
+                url = esc(&m.url), title = esc(title), kw = kw, tags = tags, id = m.id,
+                etitle = esc(&m.title), etags = esc(&m.tags.join(", ")),
+                ekw = esc(m.keyword.as_deref().unwrap_or("")), opts = options(m.folder))
+        }).collect();
+        let del = if *fid == bookmarks::ROOT { String::new() } else {
+            format!(r#"<button onclick="rename({id})">zmień nazwę</button><button onclick="if(confirm('Usunąć folder z zawartością?'))cmd({{op:'delete-folder',id:{id}}})">usuń folder</button>"#, id = fid)
+        };
+        format!(r#"<section><h2>{}{}</h2>{}</section>"#, esc(name), del,
+                if rows.is_empty() { r#"<div class="empty">pusto</div>"#.to_string() } else { rows })
+    }).collect();
+
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Zakładki</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:11px;color:#3a2a5e;letter-spacing:0.15em;margin:22px 0 8px;display:flex;gap:10px;align-items:center;}}
+.bar{{display:flex;gap:8px;margin-bottom:8px;flex-wrap:wrap;}}
+input,select{{background:#0d0d18;border:1px solid #161625;color:#aaa;padding:5px 10px;border-radius:6px;
+  font-size:11px;font-family:inherit;outline:none;}}
+input:focus{{border-color:#3a2a5e;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+.bm{{border-bottom:1px solid #0f0e18;padding:7px 10px;}}
+.bm:hover{{background:#0c0b14;}}
+.row{{display:flex;gap:8px;align-items:center;font-size:12px;}}
+.act{{margin-left:auto;display:flex;gap:4px;visibility:hidden;}}
+.bm:hover .act{{visibility:visible;}}
+a{{color:#6a4a9a;text-decoration:none;cursor:pointer;}}
+a:hover{{color:#8a6abb;}}
+.u{{color:#1e1e2e;font-size:10px;margin-top:2px;}}
+.tag{{font-size:9px;color:#5a4a7a;border:1px solid #1e1630;border-radius:4px;padding:1px 5px;}}
+.kw{{font-size:9px;color:#8a6a3a;border:1px solid #2e2416;border-radius:4px;padding:1px 5px;}}
+.ed{{display:none;gap:6px;margin-top:6px;flex-wrap:wrap;}}
+.ed.open{{display:flex;}}
+.empty{{font-size:10px;color:#1e1e2e;padding:4px 10px;}}
+</style></head><body>
+<h1>// zakładki</h1>
+<div class="bar">
+<input id="q" placeholder="szukaj (tytuł, adres, tag)..." oninput="filter(this.value)">
+<input id="fname" placeholder="nowy folder"><select id="fparent">{parents}</select>
+<button onclick="addFolder()">+ folder</button>
+<button onclick="cmd({{op:'import'}})">importuj HTML</button>
+<button onclick="cmd({{op:'export'}})">eksportuj HTML</button>
+<button onclick="window.ipc.postMessage('go:vccat:import')">importuj z przeglądarki</button>
+</div>
+{sections}
+<script>
+function cmd(o){{window.ipc.postMessage('bm:'+JSON.stringify(o));}}
+function edit(id){{document.getElementById('ed'+id).classList.toggle('open');}}
+function save(id){{const f=document.getElementById('ed'+id);
+  cmd({{op:'edit',id:id,title:f.title.value,url:f.url.value,tags:f.tags.value,
+        keyword:f.keyword.value,folder:Number(f.folder.value)}});}}
+function addFolder(){{const n=document.getElementById('fname').value.trim();
+  if(n)cmd({{op:'add-folder',name:n,parent:Number(document.getElementById('fparent').value)}});}}
+function rename(id){{const n=prompt('Nowa nazwa folderu');
+  if(n&&n.trim())cmd({{op:'rename-folder',id:id,name:n.trim()}});}}
+function filter(q){{q=q.toLowerCase();
+  document.querySelectorAll('.bm').forEach(e=>e.style.display=e.dataset.s.includes(q)?'':'none');}}
+</script>
+</body></html>"#, parents = options(bookmarks::ROOT), sections = sections)
+}
+
+// ── Import page ───────────────────────────────────────────────────────────────
+
+fn import_page_html(profiles: &[importer::Profile]) -> String {
+    let rows: String = profiles.iter().enumerate().map(|(i, p)| {
+        format!(r#"<tr><td>{name}</td><td class="u">{path}</td>
+<td><label><input type="checkbox" id="h{i}" checked> historia</label></td>
+<td><label><input type="checkbox" id="b{i}" checked> zakładki</label></td>
+<td><button onclick="imp({i})">importuj</button></td></tr>"#,
+            name = esc(&p.name), path = esc(&p.path.display().to_string()), i = i)
+    }).collect();
+    let body = if rows.is_empty() {
+        r#"<div class="empty">nie znaleziono profili Firefoksa ani Chromium</div>"#.to_string()
+    } else {
+        format!("<table>{}</table>", rows)
+    };
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Import</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+table{{width:100%;border-collapse:collapse;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tr:hover{{background:#0c0b14;}}
+td{{padding:7px 10px;font-size:12px;white-space:nowrap;}}
+.u{{color:#1e1e2e;font-size:10px;white-space:normal;}}
+label{{font-size:11px;color:#555;cursor:pointer;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+#st{{font-size:11px;color:#6a4a9a;margin-bottom:16px;min-height:14px;}}
+.empty{{font-size:11px;color:#2a2a3a;}}
+</style></head><body>
+<h1>// import z innych przeglądarek</h1>
+<div id="st"></div>
+{body}
+<script>
+function imp(i){{
+  const h=document.getElementById('h'+i).checked,b=document.getElementById('b'+i).checked;
+  if(!h&&!b)return;
+  setStatus('importowanie...');
+  window.ipc.postMessage('imp:'+JSON.stringify({{profile:i,history:h,bookmarks:b}}));
+}}
+function setStatus(t){{document.getElementById('st').textContent=t;}}
+document.querySelectorAll('[data-ts]').forEach(e=>e.textContent=new Date(e.dataset.ts*1000).toLocaleString('pl-PL'));
+</script>
+</body></html>"#, body = body)
+}
+
+// ── Restore page ──────────────────────────────────────────────────────────────
+
+fn restore_page_html(crashed: Option<&storage::Session>) -> String {
+    let body = match crashed {
+        Some(sess) => {
+            let rows: String = sess.tabs.iter().enumerate().map(|(i, u)| {
+                let note = if i == sess.active { r#" <span class="a">(aktywna)</span>"# } else { "" };
+                format!(r#"<tr><td><label><input type="checkbox" class="t" value="{i}" checked> {u}</label>{note}</td></tr>"#,
+                        i = i, u = esc(u), note = note)
+            }).collect();
+            format!(r#"<h2>okno 1 · {n} kart</h2><table>{rows}</table>
+<div><button onclick="send('open')">przywróć zaznaczone</button>
+<button onclick="send('discard')">zacznij od nowa</button></div>"#, n = sess.tabs.len(), rows = rows)
+        }
+        None => r#"<div class="empty">brak sesji do przywrócenia</div>"#.to_string(),
+    };
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Przywracanie sesji</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:12px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#3a2a5e;margin:20px 0 8px;}}
+p{{font-size:11px;color:#444;}}
+table{{width:100%;border-collapse:collapse;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tr:hover{{background:#0c0b14;}}
+td{{padding:7px 10px;font-size:12px;}}
+label{{cursor:pointer;}}
+.a{{color:#6a4a9a;font-size:10px;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;margin-top:14px;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+.empty{{font-size:11px;color:#2a2a3a;}}
+</style></head><body>
+<h1>// poprzednia sesja nie została zamknięta poprawnie</h1>
+<p>wybierz karty do ponownego otwarcia — odznacz tę, która mogła spowodować awarię</p>
+{body}
+<script>
+function send(op){{
+  const tabs=[...document.querySelectorAll('.t')].filter(c=>c.checked).map(c=>+c.value);
+  window.ipc.postMessage('restore:'+JSON.stringify({{op:op,tabs:tabs}}));
+}}
+</script>
+</body></html>"#, body = body)
+}
+
+// ── HTTPS-only interstitial ───────────────────────────────────────────────────
+
+/// Shown at `vccat:https-only/<http url>` when a site failed to load over
+/// HTTPS; `error` is None when the page is rebuilt, e.g. from the session.
+fn https_only_page_html(url: &str, error: Option<&str>, back: &str, private: bool) -> String {
+    let host = https::host_of(url).unwrap_or_default();
+    let error = error.map(|e| format!(r#"<p class="err">{}</p>"#, esc(e))).unwrap_or_default();
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Brak HTTPS</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:64px 32px;max-width:760px;}}
+h1{{font-size:16px;color:#7a4a6a;margin-bottom:16px;letter-spacing:0.1em;}}
+p{{font-size:12px;color:#666;line-height:1.6;margin-bottom:10px;}}
+.u{{color:#8a6abb;word-break:break-all;}}
+.err{{font-size:10px;color:#3a3a5a;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:6px 12px;border-radius:6px;
+  font-size:11px;font-family:inherit;cursor:pointer;margin:14px 8px 0 0;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+button.main{{border-color:#2a1a4e;color:#8a6abb;}}
+</style></head><body>
+<h1>// ta strona nie obsługuje bezpiecznego połączenia</h1>
+<p>Tryb „tylko HTTPS” jest włączony, a <span class="u">{host}</span> nie odpowiada przez HTTPS.
+Połączenie przez HTTP nie jest szyfrowane — inni w sieci mogą podejrzeć lub zmienić to, co wysyłasz i widzisz.</p>
+<p class="u">{url}</p>
+{error}
+<button class="main" onclick="window.ipc.postMessage('go:'+{back})">wróć</button>
+<button onclick="window.ipc.postMessage('https:continue')">otwórz przez HTTP</button>
+<p class="err">{remember}</p>
+</body></html>"#, host = esc(&host), url = esc(url), error = error,
+        remember = if private { format!("wybór obowiązuje dla {} do zamknięcia przeglądarki", esc(&host)) }
+                   else { format!("wybór zostanie zapamiętany dla {} — wyjątki można usunąć w ustawieniach", esc(&host)) },
+        back = esc(&serde_json::Value::String(back.to_string()).to_string()))
+}
+
+// ── Sessions page ─────────────────────────────────────────────────────────────
+
+fn sessions_page_html(sessions: &[storage::NamedSession]) -> String {
+    let list: String = sessions.iter().map(|s| {
+        let urls: String = s.tabs.iter().map(|t| {
+            let title = if t.title.is_empty() { &t.url } else { &t.title };
+            format!(r#"<tr><td><a href="{u}">{t}</a></td><td class="u">{u}</td></tr>"#,
+                    u = esc(&t.url), t = esc(title))
+        }).collect();
+        let name = serde_json::Value::String(s.name.clone()).to_string();
+        format!(r#"<h2>{n} · {c} kart</h2>
+<div class="ops" data-name="{q}">
+<button onclick="op(this,'append')">otwórz jako nowe karty</button>
+<button onclick="op(this,'replace')">zastąp bieżące karty</button>
+<button onclick="op(this,'export-json')">eksport json</button>
+<button onclick="op(this,'export-urls')">eksport listy url</button>
+<button onclick="op(this,'delete')">usuń</button>
+</div><table>{urls}</table>"#, n = esc(&s.name), c = s.tabs.len(), q = esc(&name), urls = urls)
+    }).collect();
+    let body = if list.is_empty() {
+        r#"<div class="empty">brak zapisanych sesji</div>"#.to_string()
+    } else { list };
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Sesje</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#3a2a5e;margin:24px 0 8px;}}
+table{{width:100%;border-collapse:collapse;margin-top:8px;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tr:hover{{background:#0c0b14;}}
+td{{padding:7px 10px;font-size:12px;}}
+a{{color:#6a4a9a;text-decoration:none;}}
+a:hover{{color:#8a6abb;}}
+.u{{color:#1e1e2e;font-size:10px;}}
+input{{width:300px;background:#0c0b14;border:1px solid #161625;color:#888;padding:5px 8px;
+  border-radius:6px;font-family:inherit;font-size:11px;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+.empty{{font-size:11px;color:#2a2a3a;}}
+</style></head><body>
+<h1>// sesje</h1>
+<input id="name" placeholder="nazwa, np. release-review">
+<button onclick="save()">zapisz bieżące karty</button>
+{body}
+<script>
+function send(o){{window.ipc.postMessage('sess:'+JSON.stringify(o));}}
+function save(){{const n=document.getElementById('name').value.trim();if(n)send({{op:'save',name:n}});}}
+function op(b,o){{
+  const n=JSON.parse(b.parentNode.dataset.name);
+  if(o==='delete'&&!confirm('usunąć sesję '+n+'?'))return;
+  send({{op:o,name:n}});
+}}
+</script>
+</body></html>"#, body = body)
+}
+
+// ── Settings page ─────────────────────────────────────────────────────────────
+
+fn settings_page_html(s: &settings::Settings, status: &str) -> String {
+    let vault_label = if vault::is_enabled() { "zmień hasło profilu" } else { "zaszyfruj profil hasłem" };
+    let engines: String = s.search_engines.iter().map(|e| {
+        format!(r#"<tr class="eng"><td><input type="radio" name="def"{checked}></td>
+<td><input class="name" value="{name}"></td><td><input class="tpl" value="{tpl}"></td>
+<td><input class="kw" value="{kw}"></td><td><button onclick="this.closest('tr').remove()">usuń</button></td></tr>"#,
+            checked = if e.name == s.default_engine { " checked" } else { "" },
+            name = esc(&e.name), tpl = esc(&e.template), kw = esc(e.keyword.as_deref().unwrap_or("")))
+    }).collect();
+    let exceptions: String = https::exceptions().iter().map(|h| {
+        format!(r#"<tr><td>{h}</td><td><button onclick="send({{op:'https-remove',host:{j}}})">usuń</button></td></tr>"#,
+            h = esc(h), j = esc(&serde_json::Value::String(h.clone()).to_string()))
+    }).collect();
+    let exceptions = if exceptions.is_empty() { r#"<small>brak</small>"#.to_string() }
+        else { format!("<table>{}</table>", exceptions) };
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Ustawienia</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#3a2a5e;margin:24px 0 8px;}}
+label{{display:block;font-size:11px;margin:10px 0 4px;}}
+small{{color:#2a2a3a;font-size:10px;}}
+input{{width:420px;background:#0c0b14;border:1px solid #161625;color:#888;padding:5px 8px;
+  border-radius:6px;font-family:inherit;font-size:11px;}}
+input.n{{width:100px;}}
+input[type=checkbox]{{width:auto;vertical-align:middle;}}
+textarea{{width:420px;height:110px;background:#0c0b14;border:1px solid #161625;color:#888;padding:5px 8px;
+  border-radius:6px;font-family:inherit;font-size:11px;resize:vertical;}}
+textarea.short{{height:50px;}}
+table{{border-collapse:collapse;}}
+td{{padding:3px 4px;}}
+td input{{width:auto;}}
+input.tpl{{width:380px;}}
+input.kw{{width:60px;}}
+td button{{margin-top:0;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;margin-top:14px;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+a{{color:#6a4a9a;text-decoration:none;font-size:11px;margin-right:14px;}}
+a:hover{{color:#8a6abb;}}
+#st{{font-size:11px;color:#6a4a9a;margin-bottom:16px;min-height:14px;}}
+</style></head><body>
+<h1>// ustawienia</h1>
+<div id="st">{status}</div>
+<label>wyszukiwarki <small>{{searchTerms}} zostaje zastąpione zapytaniem · skrót: „w rust”</small></label>
+<table id="engines">{engines}</table>
+<button onclick="addEngine()">dodaj wyszukiwarkę</button>
+<label>strona startowa</label>
+<input id="home_page" value="{home_page}">
+<label>aktywne karty w tle <small>starsze są usypiane</small></label>
+<input class="n" id="suspend_threshold" type="number" value="{suspend}">
+<label>rozmiar okna</label>
+<input class="n" id="window_width" type="number" value="{w}"> × <input class="n" id="window_height" type="number" value="{h}">
+<label>sprawdzanie aktualizacji po starcie [s] <small>od następnego uruchomienia</small></label>
+<input class="n" id="update_check_delay" type="number" value="{delay}">
+<label>ważność list filtrów [h] <small>dla list bez własnego „Expires”</small></label>
+<input class="n" id="filter_max_age" type="number" value="{age}">
+<label>listy filtrów <small>subskrypcje i własne reguły</small></label>
+<a href="vccat:filters" onclick="go(event,this)">zarządzaj filtrami</a>
+<label><input id="https_only" type="checkbox"{https_only}> tylko HTTPS <small>adresy http:// są otwierane przez https://</small></label>
+<label>wyjątki HTTPS <small>strony otwierane przez HTTP</small></label>
+{exceptions}
+<label><input id="strip_tracking" type="checkbox"{strip}> usuwaj parametry śledzące z adresów</label>
+<label>parametry śledzące <small>jeden na linię · utm_* pasuje do początku · „strona: a, b” tylko dla strony</small></label>
+<textarea id="tracking_rules" spellcheck="false">{rules}</textarea>
+<label>strony bez usuwania parametrów <small>jedna na linię</small></label>
+<textarea id="tracking_bypass" class="short" spellcheck="false">{bypass}</textarea>
+<div>
+<button onclick="save()">zapisz</button>
+<button onclick="send({{op:'defaults'}})">przywróć domyślne</button>
+</div>
+<h2>profil</h2>
+<button onclick="send({{op:'passphrase'}})">{vault_label}</button>
+<h2>więcej</h2>
+<a href="vccat:sync" onclick="go(event,this)">synchronizacja</a>
+<a href="vccat:sessions" onclick="go(event,this)">sesje</a>
+<a href="vccat:import" onclick="go(event,this)">import</a>
+<a href="vccat:filters" onclick="go(event,this)">filtry</a>
+<a href="vccat:blocklog" onclick="go(event,this)">dziennik blokowania</a>
+<script>
+function send(o){{window.ipc.postMessage('set:'+JSON.stringify(o));}}
+function go(e,a){{e.preventDefault();window.ipc.postMessage('go:'+a.getAttribute('href'));}}
+function setStatus(t){{document.getElementById('st').textContent=t;}}
+function addEngine(){{
+  const t=document.getElementById('engines'),r=t.insertRow();r.className='eng';
+  r.innerHTML='<td><input type="radio" name="def"></td><td><input class="name"></td>'
+    +'<td><input class="tpl" placeholder="https://…?q={{searchTerms}}"></td><td><input class="kw"></td>'
+    +'<td><button onclick="this.closest(\'tr\').remove()">usuń</button></td>';
+}}
+function save(){{
+  const s={{search_engines:[],default_engine:''}};
+  document.querySelectorAll('input[id]').forEach(i=>s[i.id]=i.type==='number'?Number(i.value)
+    :i.type==='checkbox'?i.checked:i.value);
+  document.querySelectorAll('textarea[id]').forEach(t=>s[t.id]=t.value.split('\n').map(l=>l.trim()).filter(l=>l));
+  document.querySelectorAll('tr.eng').forEach(r=>{{
+    const v=c=>r.querySelector('.'+c).value.trim(),kw=v('kw');
+    s.search_engines.push({{name:v('name'),template:v('tpl'),keyword:kw||null}});
+    if(r.querySelector('[name=def]').checked)s.default_engine=v('name');
+  }});
+  send({{op:'save',settings:s}});
+}}
+</script>
+</body></html>"#, status = esc(status), engines = engines, home_page = esc(&s.home_page),
+        suspend = s.suspend_threshold, w = s.window_width, h = s.window_height,
+        delay = s.update_check_delay, age = s.filter_max_age, vault_label = vault_label,
+        https_only = if s.https_only { " checked" } else { "" }, exceptions = exceptions,
+        strip = if s.strip_tracking { " checked" } else { "" },
+        rules = esc(&s.tracking_rules.join("\n")), bypass = esc(&s.tracking_bypass.join("\n")))
+}
+
+// ── Filters page ──────────────────────────────────────────────────────────────
+
+/// Rows of the subscriptions table on vccat:filters.
+fn filter_lists_html() -> String {
+    let problem = adblock::filter_problem().map(|p| format!(
+        r#"<tr><td></td><td colspan="3"><small class="err">filtr WebKit: {}</small></td></tr>"#, esc(&p)));
+    problem.into_iter().chain(subscriptions::list().iter().map(|l| {
+        let state = if !l.enabled {
+            "wyłączona".to_string()
+        } else if l.state.updated == 0 {
+            "jeszcze nie pobrana".to_string()
+        } else {
+            format!(r#"{} filtrów · pobrana <span data-ts="{}"></span>"#, l.state.filters, l.state.updated)
+        };
+        let error = l.state.error.as_ref().map(|e| format!(" · <span class=\"err\">{}</span>", esc(e))).unwrap_or_default();
+        let id = esc(&serde_json::Value::String(l.id.clone()).to_string());
+        let remove = if l.id.starts_with("custom-") {
+            format!(r#"<button onclick="send({{op:'remove',id:{}}})">usuń</button>"#, id)
+        } else { String::new() };
+        format!(r#"<tr><td><input type="checkbox"{on} onchange="send({{op:'toggle',id:{id},on:this.checked}})"></td>
+<td title="{url}">{title}</td><td><small>{state}{error}</small></td><td>{remove}</td></tr>"#,
+            on = if l.enabled { " checked" } else { "" }, id = id,
+            url = esc(&l.url), title = esc(&l.title), state = state, error = error, remove = remove)
+    })).collect()
+}
+
+fn filters_page_html() -> String {
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Filtry</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#3a2a5e;margin:24px 0 8px;}}
+small{{color:#2a2a3a;font-size:10px;}}
+input,select{{background:#0c0b14;border:1px solid #161625;color:#888;padding:5px 8px;
+  border-radius:6px;font-family:inherit;font-size:11px;width:320px;}}
+input[type=checkbox]{{width:auto;vertical-align:middle;}}
+input.t{{width:160px;}}
+select{{width:auto;}}
+table{{border-collapse:collapse;}}
+td{{padding:3px 6px;font-size:11px;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+.row{{margin-top:10px;display:flex;gap:6px;align-items:center;}}
+#st{{font-size:11px;color:#6a4a9a;margin-bottom:16px;min-height:14px;}}
+.err{{color:#7a4a4a;}}
+#ed{{position:relative;width:100%;max-width:900px;height:340px;border:1px solid #161625;border-radius:6px;
+  background:#0c0b14;overflow:hidden;}}
+#ed pre,#ed textarea{{position:absolute;inset:0;margin:0;padding:8px 10px;border:0;overflow:auto;
+  font-family:inherit;font-size:11px;line-height:16px;white-space:pre;tab-size:4;}}
+#ed pre{{color:#777;pointer-events:none;}}
+#ed textarea{{background:transparent;color:transparent;caret-color:#8a6abb;resize:none;outline:none;}}
+#ed textarea::selection{{background:#2a1a4e;color:transparent;}}
+.c{{color:#2a2a3a;}}
+.n{{color:#888;}}
+.o{{color:#6a4a9a;}}
+.x{{color:#4a7a5a;}}
+.h{{color:#7a6a9a;}}
+.j{{color:#9a7acb;}}
+.bad{{text-decoration:underline wavy #7a4a4a;}}
+#sum{{font-size:10px;color:#3a3a5a;margin-top:6px;}}
+#errs{{font-size:11px;margin-top:4px;}}
+#errs div{{cursor:pointer;color:#7a4a4a;}}
+#res{{font-size:11px;margin-top:8px;word-break:break-all;}}
+#res b{{font-weight:normal;color:#8a6abb;}}
+</style></head><body>
+<h1>// filtry</h1>
+<div id="st"></div>
+<h2>listy</h2>
+<table id="lists">{lists}</table>
+<div class="row"><input id="add-url" placeholder="https://…/lista.txt"><input id="add-title" class="t" placeholder="nazwa">
+<button onclick="send({{op:'add',url:v('add-url'),title:v('add-title')}})">dodaj listę</button>
+<button onclick="send({{op:'refresh'}})">sprawdź aktualizacje</button></div>
+<h2>własne reguły <small>składnia Adblock Plus / uBlock · ! komentarz</small></h2>
+<div id="ed"><pre id="hl"></pre><textarea id="rules" spellcheck="false">{rules}</textarea></div>
+<div id="sum"></div>
+<div id="errs"></div>
+<div class="row"><button onclick="send({{op:'save',text:ta.value}})">zapisz i zastosuj</button></div>
+<h2>sprawdź adres</h2>
+<div class="row"><input id="t-url" placeholder="adres żądania"><input id="t-page" placeholder="strona (opcjonalnie)">
+<select id="t-type"><option value="script">skrypt</option><option value="image">obraz</option>
+<option value="stylesheet">styl</option><option value="font">czcionka</option><option value="media">multimedia</option>
+<option value="xmlhttprequest">xhr</option><option value="subdocument">ramka</option>
+<option value="document">strona</option><option value="other">inne</option></select>
+<button onclick="test()">sprawdź</button></div>
+<div id="res"></div>
+<script>
+const ta=document.getElementById('rules'),hl=document.getElementById('hl');
+let bad={{}},timer=null;
+function send(o){{window.ipc.postMessage('flt:'+JSON.stringify(o));}}
+function v(id){{return document.getElementById(id).value.trim();}}
+function setStatus(t){{document.getElementById('st').textContent=t;}}
+function dates(){{document.querySelectorAll('[data-ts]').forEach(e=>e.textContent=new Date(e.dataset.ts*1000).toLocaleString('pl-PL'));}}
+function setLists(h){{document.getElementById('lists').innerHTML=h;dates();}}
+function e(t){{return t.replace(/&/g,'&amp;').replace(/</g,'&lt;');}}
+function line(l){{
+  const t=l.trim();
+  if(!t)return '';
+  if(t[0]==='!'||t[0]==='[')return '<span class="c">'+e(l)+'</span>';
+  const m=t.match(/#@?[?$%]?#/);
+  if(m){{
+    const i=l.indexOf(m[0]),sel=l.slice(i+m[0].length);
+    const k=m[0].includes('@')?'x':sel.startsWith('+js(')?'j':'h';
+    return e(l.slice(0,i))+'<span class="'+k+'">'+e(m[0])+e(sel)+'</span>';
+  }}
+  const d=l.lastIndexOf('$'),k=t.startsWith('@@')?'x':'n';
+  return d>0?'<span class="'+k+'">'+e(l.slice(0,d))+'</span><span class="o">'+e(l.slice(d))+'</span>'
+    :'<span class="'+k+'">'+e(l)+'</span>';
+}}
+function paint(){{
+  hl.innerHTML=ta.value.split('\n').map((l,i)=>bad[i+1]?'<span class="bad">'+line(l)+'</span>':line(l)).join('\n')+'\n';
+  hl.scrollTop=ta.scrollTop;hl.scrollLeft=ta.scrollLeft;
+}}
+function showCheck(r){{
+  bad={{}};r.errors.forEach(x=>bad[x.line]=x.reason);
+  document.getElementById('sum').textContent='sieciowe: '+r.network+' · kosmetyczne: '+r.cosmetic
+    +' · scriptlety: '+r.scriptlets+' · błędy: '+r.errors.length;
+  const box=document.getElementById('errs');box.innerHTML='';
+  r.errors.forEach(x=>{{const d=document.createElement('div');d.textContent='linia '+x.line+': '+x.reason;
+    d.onclick=()=>jump(x.line);box.appendChild(d);}});
+  paint();
+}}
+function jump(n){{
+  const ls=ta.value.split('\n'),at=ls.slice(0,n-1).reduce((a,l)=>a+l.length+1,0);
+  ta.focus();ta.setSelectionRange(at,at+ls[n-1].length);ta.scrollTop=(n-3)*16;
+}}
+function showTest(r){{
+  const el=document.getElementById('res');
+  const rule=x=>'<b>'+e(x.rule)+'</b> <small>('+e(x.list)+')</small>';
+  el.innerHTML=r.verdict==='blocked'?'zablokowane przez '+rule(r.block)
+    :r.verdict==='allowed'?'dozwolone przez wyjątek '+rule(r.exception)+(r.block?'<br>zamiast '+rule(r.block):'')
+    :r.verdict==='invalid'?'<span class="err">nieprawidłowy adres</span>':'żadna reguła nie pasuje';
+}}
+function test(){{send({{op:'test',url:v('t-url'),page:v('t-page'),type:v('t-type'),text:ta.value}});}}
+ta.addEventListener('input',()=>{{paint();clearTimeout(timer);timer=setTimeout(()=>send({{op:'check',text:ta.value}}),300);}});
+ta.addEventListener('scroll',()=>{{hl.scrollTop=ta.scrollTop;hl.scrollLeft=ta.scrollLeft;}});
+ta.addEventListener('keydown',ev=>{{if(ev.key==='s'&&ev.ctrlKey){{ev.preventDefault();send({{op:'save',text:ta.value}});}}}});
+dates();paint();send({{op:'check',text:ta.value}});
+</script>
+</body></html>"#, lists = filter_lists_html(), rules = esc(&subscriptions::user_rules()))
+}
+
+/// The parse report for the rules being edited on vccat:filters.
+fn filter_check_json(text: &str) -> String {
+    let list = filters::parse_list(text);
+    serde_json::json!({
+        "network": list.network.len(),
+        "cosmetic": list.cosmetic.len(),
+        "scriptlets": list.scriptlets.len(),
+        "errors": list.unsupported.iter().map(|u| serde_json::json!({"line": u.line, "reason": u.reason}))
+            .collect::<Vec<_>>(),
+    }).to_string()
+}
+
+/// What the saved lists and the edited rules make of a request, for
+/// vccat:filters.
+fn filter_test_json(url: &str, page: &str, kind: &str, text: &str) -> String {
+    // typed like in the address bar: "example.com/ad.js" will do
+    let as_url = |s: &str| match address::classify(s) {
+        address::Target::Url(u) | address::Target::Lookup { url: u, .. } => Some(u),
+        address::Target::Search(_) => None,
+    };
+    let Some(url) = as_url(url) else { return r#"{"verdict":"invalid"}"#.into() };
+    let page = if page.is_empty() { url.clone() } else { as_url(page).unwrap_or_else(|| url.clone()) };
+    let kind = filters::resource_type(kind).unwrap_or(filters::ResourceType::Other);
+    let draft = filters::parse_list(text).network;
+    match blocklog::verdict_with(&url, &page, kind, subscriptions::USER_TITLE, draft) {
+        blocklog::Verdict::Blocked(b) => serde_json::json!({"verdict": "blocked", "block": b}),
+        blocklog::Verdict::Allowed { exception, block } =>
+            serde_json::json!({"verdict": "allowed", "exception": exception, "block": block}),
+        blocklog::Verdict::NoMatch => serde_json::json!({"verdict": "none"}),
+    }.to_string()
+}
+
+// ── Blocking log page ─────────────────────────────────────────────────────────
+
+fn blocklog_page_html(tabs: &[Tab]) -> String {
+    let sections: String = tabs.iter()
+        .filter(|t| !t.private && (t.blocked_count > 0 || t.hidden > 0))
+        .map(|t| {
+            let rows: String = t.blocked.iter().rev().map(|e| format!(
+                r#"<tr><td class="u">{u}</td><td class="r">{r}</td><td class="l">{l}</td></tr>"#,
+                u = esc(&e.url), r = esc(&e.rule), l = esc(&e.list))).collect();
+            let cut = if t.blocked.len() < t.blocked_count {
+                format!(r#"<div class="note">pokazano ostatnie {}</div>"#, t.blocked.len())
+            } else { String::new() };
+            format!(r#"<h2>{title}</h2><div class="sum">{url} · zablokowane żądania: {b} · ukryte elementy: {h}</div>
+{cut}<table>{rows}</table>"#,
+                title = esc(if t.title.is_empty() { &t.url } else { &t.title }), url = esc(&t.url),
+                b = t.blocked_count, h = t.hidden, cut = cut, rows = rows)
+        }).collect();
+    let body = if sections.is_empty() {
+        r#"<div class="empty">na otwartych kartach nic nie zablokowano</div>"#.to_string()
+    } else { sections };
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Dziennik blokowania</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#6a4a9a;margin:24px 0 4px;font-weight:normal;}}
+.sum{{font-size:10px;color:#3a3a5a;margin-bottom:8px;word-break:break-all;}}
+table{{width:100%;border-collapse:collapse;table-layout:fixed;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tr:hover{{background:#0c0b14;}}
+td{{padding:5px 8px;font-size:11px;word-break:break-all;vertical-align:top;}}
+.u{{width:55%;color:#777;}}
+.r{{color:#8a6abb;}}
+.l{{width:14%;color:#3a3a5a;}}
+.note,.empty{{font-size:11px;color:#2a2a3a;margin-bottom:6px;}}
+</style></head><body>
+<h1>// dziennik blokowania</h1>
+{body}
+</body></html>"#, body = body)
+}
+
+// ── Reading list page ─────────────────────────────────────────────────────────
+
+fn reading_page_html(items: &[reading::Item]) -> String {
+    let section = |read: bool| -> String {
+        let rows: String = items.iter().rev().filter(|i| i.read == read).map(|i| {
+            let title = if i.title.is_empty() { &i.url } else { &i.title };
+            let offline = if i.offline {
+                format!(r#"<button onclick="cmd('offline',{})">offline</button>"#, i.id)
+            } else { String::new() };
+            format!(r#"<tr><td><a onclick="cmd('open',{id})">{t}</a>
+<div class="u">{u}</div></td><td class="ops">{offline}
+<button onclick="cmd('toggle-read',{id})">{mark}</button>
+<button onclick="cmd('delete',{id})">usuń</button></td></tr>"#,
+                id = i.id, t = esc(title), u = esc(&i.url), offline = offline,
+                mark = if read { "nieprzeczytane" } else { "przeczytane" })
+        }).collect();
+        if rows.is_empty() { r#"<div class="empty">pusto</div>"#.to_string() }
+        else { format!("<table>{}</table>", rows) }
+    };
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Do przeczytania</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#3a2a5e;margin:24px 0 8px;}}
+table{{width:100%;border-collapse:collapse;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tr:hover{{background:#0c0b14;}}
+td{{padding:7px 10px;font-size:12px;}}
+a{{color:#6a4a9a;text-decoration:none;cursor:pointer;}}
+a:hover{{color:#8a6abb;}}
+.u{{color:#1e1e2e;font-size:10px;margin-top:2px;}}
+.ops{{white-space:nowrap;text-align:right;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+.empty{{font-size:11px;color:#2a2a3a;}}
+</style></head><body>
+<h1>// do przeczytania</h1>
+{unread}
+<h2>przeczytane</h2>
+{read}
+<script>
+function cmd(op,id){{window.ipc.postMessage('rl:'+JSON.stringify({{op:op,id:id,online:navigator.onLine}}));}}
+</script>
+</body></html>"#, unread = section(false), read = section(true))
+}
+
+// ── Sync page ─────────────────────────────────────────────────────────────────
+
+fn sync_page_html(config: &sync::SyncConfig, remote: &[sync::RemoteTabs], status: &str) -> String {
+    let dir = config.dir.as_ref().map(|d| d.display().to_string()).unwrap_or_default();
+    let devices: String = remote.iter().map(|r| {
+        let links: String = r.tabs.iter().map(|u| {
+            format!(r#"<tr><td><a href="{u}">{u}</a></td></tr>"#, u = esc(u))
+        }).collect();
+        let mins = storage::now().saturating_sub(r.updated) / 60;
+        format!("<h2>{} · {} min temu</h2><table>{}</table>", esc(&r.name), mins, links)
+    }).collect();
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8"><title>Synchronizacja</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box;}}
+body{{background:#08080f;color:#555;font-family:'JetBrains Mono','Fira Code',monospace;padding:32px;}}
+h1{{font-size:16px;color:#3a2a5e;margin-bottom:20px;letter-spacing:0.15em;}}
+h2{{font-size:12px;color:#3a2a5e;margin:20px 0 8px;}}
+table{{width:100%;border-collapse:collapse;}}
+tr{{border-bottom:1px solid #0f0e18;}}
+tr:hover{{background:#0c0b14;}}
+td{{padding:7px 10px;font-size:12px;}}
+a{{color:#6a4a9a;text-decoration:none;}}
+a:hover{{color:#8a6abb;}}
+label{{display:block;font-size:11px;margin:8px 0 4px;}}
+input{{width:420px;background:#0c0b14;border:1px solid #161625;color:#888;padding:5px 8px;
+  border-radius:6px;font-family:inherit;font-size:11px;}}
+button{{background:none;border:1px solid #161625;color:#555;padding:4px 10px;border-radius:6px;
+  font-size:10px;font-family:inherit;cursor:pointer;margin-top:10px;}}
+button:hover{{border-color:#2a1a4e;color:#7a5aaa;background:#0f0f1e;}}
+#st{{font-size:11px;color:#6a4a9a;margin-bottom:16px;min-height:14px;}}
+.note{{font-size:10px;color:#2a2a3a;margin-top:10px;}}
+</style></head><body>
+<h1>// synchronizacja</h1>
+<div id="st">{status}</div>
+<label>folder współdzielony</label>
+<input id="dir" value="{dir}"> <button onclick="send('pick')">wybierz</button>
+<label>nazwa urządzenia</label>
+<input id="name" value="{name}">
+<div>
+<button onclick="send('save')">zapisz</button>
+<button onclick="send('now')">synchronizuj teraz</button>
+<button onclick="send('off')">wyłącz</button>
+</div>
+<div class="note">urządzenie {id} · historia, zakładki, otwarte karty i ustawienia · pliki w folderze nie są szyfrowane</div>
+<h1 style="margin-top:28px">// karty z innych urządzeń</h1>
+{devices}
+<script>
+function send(op){{
+  window.ipc.postMessage('sync:'+JSON.stringify({{op:op,
+    dir:document.getElementById('dir').value,name:document.getElementById('name').value}}));
+}}
+function setStatus(t){{document.getElementById('st').textContent=t;}}
+</script>
+</body></html>"#, status = esc(status), dir = esc(&dir), name = esc(&config.device_name),
+        id = esc(&config.device_id), devices = devices)
+}
+
 // ── Sidebar HTML ──────────────────────────────────────────────────────────────
 
 fn sidebar_html() -> &'static str {
@@ -166,6 +1008,8 @@ html,body{width:52px;height:100%;background:#080810;overflow:hidden;
 .tab:hover{background:#111120;border-color:#1e1e2e;}
 .tab.active{background:#14102a;border-color:#2a1a4e;box-shadow:inset 0 0 0 1px #2a1a4e;}
 .tab.suspended{opacity:0.35;}
+.tab.private{border-color:#3a1428;background:#140a10;}
+.tab.private.active{background:#1e0c18;border-color:#6a2448;box-shadow:inset 0 0 0 1px #6a2448;}
 .tab img{width:18px;height:18px;border-radius:3px;object-fit:contain;}
 .fb{width:20px;height:20px;border-radius:5px;background:#111120;border:1px solid #1e1e2e;
   display:flex;align-items:center;justify-content:center;font-size:10px;font-weight:600;
@@ -175,6 +1019,9 @@ html,body{width:52px;height:100%;background:#080810;overflow:hidden;
   align-items:center;justify-content:center;cursor:pointer;z-index:10;}
 .tab:hover .x{display:flex;}
 .x:hover{background:#141428;color:#ccc;}
+.cnt{position:absolute;bottom:-4px;right:-4px;min-width:14px;height:12px;padding:0 3px;
+  background:#0c0b14;border:1px solid #2a1a4e;border-radius:6px;color:#6a4a9a;font-size:8px;
+  font-family:monospace;display:flex;align-items:center;justify-content:center;}
 #bottom{width:100%;display:flex;flex-direction:column;align-items:center;gap:4px;padding:6px 0;}
 .ib{width:36px;height:36px;border-radius:8px;border:1px solid #111120;background:none;
   color:#252535;font-size:14px;cursor:pointer;display:flex;align-items:center;
@@ -183,7 +1030,12 @@ html,body{width:52px;height:100%;background:#080810;overflow:hidden;
 </style></head><body>
 <div id="tabs"></div>
 <div id="bottom">
+  <button class="ib" title="Zakładki" onclick="send('bookmarks')">&#9733;</button>
   <button class="ib" title="Historia" onclick="send('history')">&#9776;</button>
+  <button class="ib" title="Sesje" onclick="send('sessions')">&#9638;</button>
+  <button class="ib" title="Do przeczytania" onclick="send('reading')">&#8675;</button>
+  <button class="ib" title="Ustawienia" onclick="send('settings')">&#9881;</button>
+  <button class="ib" title="Nowa karta prywatna" onclick="send('new-private')">&#9681;</button>
   <button class="ib" title="Nowa karta" onclick="send('new')">+</button>
 </div>
 <script>
@@ -193,11 +1045,15 @@ function render(){
   const c=document.getElementById('tabs');c.innerHTML='';
   state.tabs.forEach((t,i)=>{
     const el=document.createElement('div');
-    el.className='tab'+(i===state.active?' active':'')+(t.suspended?' suspended':'');
-    el.title=t.title||t.url||'Nowa karta';
+    el.className='tab'+(i===state.active?' active':'')+(t.suspended?' suspended':'')
+      +(t.private?' private':'');
+    el.title=(t.title||t.url||'Nowa karta')
+      +(t.blocked||t.hidden?'\nzablokowane żądania: '+t.blocked+' · ukryte elementy: '+t.hidden:'');
     if(t.favicon){const img=document.createElement('img');img.src=t.favicon;
       img.onerror=()=>img.replaceWith(makeFb(t));el.appendChild(img);}
     else el.appendChild(makeFb(t));
+    const n=t.blocked+t.hidden;
+    if(n){const c=document.createElement('div');c.className='cnt';c.textContent=n>99?'99+':n;el.appendChild(c);}
     const x=document.createElement('div');x.className='x';x.textContent='×';
     x.onclick=e=>{e.stopPropagation();send('close:'+i);};
     el.appendChild(x);
@@ -229,79 +1085,612 @@ button{background:none;border:1px solid #161625;color:#444;width:28px;height:28p
   justify-content:center;transition:all 0.1s;flex-shrink:0;}
 button:hover{background:#0f0f1e;color:#bbb;border-color:#2a1a4e;}
 button:active{transform:scale(0.92);}
-#url{flex:1;background:#0d0d18;border:1px solid #161625;color:#aaa;padding:5px 12px;
+#urlbox{flex:1;position:relative;display:flex;min-width:0;}
+#url{flex:1;min-width:0;background:#0d0d18;border:1px solid #161625;color:#aaa;padding:5px 12px;
   border-radius:7px;font-size:12px;font-family:inherit;outline:none;letter-spacing:0.02em;
   transition:border-color 0.15s;}
 #url:focus{border-color:#3a2a5e;color:#eee;}
+#urlbox.styled #url:not(:focus){color:transparent;}
+#urlview{display:none;position:absolute;left:13px;right:13px;top:0;bottom:0;align-items:center;
+  pointer-events:none;font-size:12px;letter-spacing:0.02em;white-space:pre;overflow:hidden;color:#555;}
+#urlbox.styled #url:not(:focus)+#urlview{display:flex;}
+#urlview b{font-weight:normal;color:#ccc;}
+#spoof{display:none;padding:3px 8px;border:1px solid #6a3a24;border-radius:6px;
+  font-size:10px;color:#d08050;white-space:nowrap;cursor:help;}
+#urlbox.spoof #url{border-color:#6a3a24;}
 #url::placeholder{color:#1e1e2e;}
+#star.on{color:#c9a227;border-color:#3a2e10;}
+#star[disabled],#later[disabled],#shield[disabled]{opacity:0.3;cursor:default;}
+#shield{position:relative;}
+#shield.on{color:#6a4a9a;border-color:#2a1a4e;}
+#blk{display:none;position:absolute;bottom:-5px;right:-7px;min-width:14px;height:12px;padding:0 3px;
+  background:#0a0a12;border:1px solid #2a1a4e;border-radius:6px;color:#6a4a9a;font-size:8px;
+  align-items:center;justify-content:center;}
+#se{display:none;}
 #upd{display:none;padding:3px 10px;background:#100e1e;border:1px solid #2a1a4e;
   border-radius:6px;font-size:10px;color:#7a5aaa;cursor:pointer;white-space:nowrap;}
 #upd:hover{background:#14102a;}
+#note{display:none;padding:3px 8px;border:1px solid #3a2424;border-radius:6px;font-size:10px;
+  color:#a06050;white-space:nowrap;overflow:hidden;text-overflow:ellipsis;max-width:40%;}
+#note.ok{border-color:#2a1a4e;color:#7a5aaa;}
+#priv{display:none;padding:3px 8px;border:1px solid #6a2448;border-radius:6px;
+  font-size:10px;color:#c0508a;letter-spacing:0.1em;white-space:nowrap;}
+body.private{background:#120810;border-bottom-color:#3a1428;}
+body.private #url{background:#180a14;border-color:#3a1428;}
+body.private #url:focus{border-color:#6a2448;}
+body.private #priv{display:block;}
 </style></head><body>
+<div id="priv">PRYWATNA</div>
 <button title="Wstecz"  onclick="s('back')">&#8592;</button>
 <button title="Dalej"   onclick="s('fwd')">&#8594;</button>
 <button title="Odśwież" onclick="s('reload')">&#8635;</button>
-<input id="url" type="text" placeholder="Adres lub wyszukaj..."
-  spellcheck="false"
-  onkeydown="if(event.key==='Enter'){s('nav:'+this.value);this.blur();}"
-  onfocus="this.select()"/>
+<div id="urlbox"><input id="url" type="text" placeholder="Adres lub wyszukaj..."
+  spellcheck="false" autocomplete="off"
+  onkeydown="key(event)" oninput="typed(event)"
+  onfocus="this.select()" onblur="setTimeout(()=>s('omni-close'),200)" oncopy="copied(event)"/><div id="urlview"></div></div>
+<div id="spoof" title="Nazwa tej strony używa znaków łudząco podobnych do liter innego alfabetu — może podszywać się pod inną stronę. Pokazano ją w zapisie punycode.">&#9888; podobna nazwa</div>
+<button id="star" title="Dodaj do zakładek" onclick="s('star')">&#9734;</button>
+<button id="later" title="Zapisz na później" onclick="s('later')">&#8675;</button>
+<button id="shield" title="Blokowanie reklam" onclick="s('shield')" oncontextmenu="event.preventDefault();s('blocklog')">&#9960;<span id="blk"></span></button>
+<button id="se" title="Dodaj wyszukiwarkę tej strony" onclick="s('add-search')">&#8981;+</button>
+<div id="note" onclick="this.style.display='none'"></div>
 <div id="upd" onclick="s('apply-update')"></div>
 <script>
 function s(m){window.ipc.postMessage(m);}
-function setUrl(u){const el=document.getElementById('url');if(document.activeElement!==el)el.value=u;}
+function setUrl(d){const el=document.getElementById('url');if(document.activeElement!==el)el.value=d.text;
+  const box=document.getElementById('urlbox'),v=document.getElementById('urlview');
+  v.textContent='';
+  if(d.domain){v.append(d.prefix);const b=document.createElement('b');b.textContent=d.domain;v.append(b,d.suffix);}
+  box.classList.toggle('styled',!!d.domain);box.classList.toggle('spoof',d.spoof);
+  document.getElementById('spoof').style.display=d.spoof?'block':'none';}
+function key(e){
+  const el=e.target;
+  if(e.key==='Enter'){s('nav:'+el.value);el.blur();}
+  else if(e.key==='ArrowDown'||e.key==='ArrowUp'){e.preventDefault();s('omni-move:'+(e.key==='ArrowDown'?1:-1));}
+  else if(e.key==='Escape'){s('omni-close');}
+}
+function typed(e){
+  const el=e.target;
+  // only complete while typing forward at the end of the text
+  const fwd=e.inputType==='insertText'&&el.selectionEnd===el.value.length;
+  s('omni:'+JSON.stringify({text:el.value,complete:fwd}));
+}
+function copied(e){
+  // a whole url goes out without its tracking parameters
+  const el=e.target;
+  if(el.selectionStart!==0||el.selectionEnd!==el.value.length)return;
+  e.preventDefault();s('copy:'+el.value);
+}
+function setCompletion(t,full){const el=document.getElementById('url');
+  if(document.activeElement!==el||el.value!==t)return;
+  el.value=full;el.setSelectionRange(t.length,full.length);}
+function setUrlText(t){const el=document.getElementById('url');el.value=t;el.setSelectionRange(t.length,t.length);}
+function setStar(st){const b=document.getElementById('star');
+  b.disabled=st===null;b.classList.toggle('on',st===true);
+  document.getElementById('later').disabled=st===null;
+  b.innerHTML=st===true?'&#9733;':'&#9734;';b.title=st===true?'Usuń z zakładek':'Dodaj do zakładek';}
+function setShield(st,blocked,hidden){const b=document.getElementById('shield');
+  b.disabled=st===null;b.classList.toggle('on',st===true);
+  b.title=(st===true?'Blokowanie reklam włączone — wyłącz dla tej strony'
+    :st===false?'Blokowanie reklam wyłączone na tej stronie — włącz':'Blokowanie reklam')
+    +'\nzablokowane żądania: '+blocked+' · ukryte elementy: '+hidden+'\nprawy przycisk: dziennik blokowania';
+  const n=blocked+hidden,c=document.getElementById('blk');
+  c.textContent=n>99?'99+':n;c.style.display=n&&st!==null?'flex':'none';}
+function setPrivate(p){document.body.classList.toggle('private',p);}
+function setSearchOffer(o){document.getElementById('se').style.display=o?'flex':'none';}
+function showNote(t,ok){const n=document.getElementById('note');n.textContent=t;n.title=t;
+  n.classList.toggle('ok',ok);n.style.display='block';
+  clearTimeout(n._t);n._t=setTimeout(()=>n.style.display='none',8000);}
 function showUpdate(v){const b=document.getElementById('upd');b.textContent='↑ '+v;b.style.display='block';}
 let _upd=null;
 function setPendingUpdate(v,u){_upd={v,u};showUpdate(v);}
 </script></body></html>"#
 }
 
+// ── Omnibox HTML ──────────────────────────────────────────────────────────────
+
+/// Height of one dropdown row; the popup is sized from it.
+const OMNI_ROW: i32 = 34;
+
+fn omnibox_html() -> &'static str {
+    r#"<!DOCTYPE html><html><head><meta charset="UTF-8">
+<style>
+*{margin:0;padding:0;box-sizing:border-box;}
+html,body{background:#0c0b14;overflow:hidden;font-family:'JetBrains Mono','Fira Code',monospace;
+  border:1px solid #2a1a4e;border-top:none;border-radius:0 0 8px 8px;}
+#list{padding:4px 0;}
+.row{height:34px;display:flex;align-items:center;gap:10px;padding:0 12px;cursor:pointer;}
+.row.sel,.row:hover{background:#14102a;}
+.k{width:16px;text-align:center;color:#3a2a5e;font-size:12px;flex-shrink:0;}
+.t{color:#aaa;font-size:12px;white-space:nowrap;overflow:hidden;text-overflow:ellipsis;max-width:50%;}
+.u{color:#3a3a5a;font-size:10px;white-space:nowrap;overflow:hidden;text-overflow:ellipsis;flex:1;}
+.badge{color:#6a4a9a;font-size:10px;white-space:nowrap;}
+</style></head><body>
+<div id="list"></div>
+<script>
+const icons={tab:'⇥',bookmark:'★',history:'◷',keyword:'⌕',search:'⌕'};
+const badges={tab:'przełącz na kartę',bookmark:'zakładka',history:'',keyword:'',search:''};
+function esc(s){const d=document.createElement('div');d.textContent=s;return d.innerHTML;}
+function render(items,sel){
+  document.getElementById('list').innerHTML=items.map((it,i)=>{
+    const url=it.kind==='history'||it.kind==='bookmark'||it.kind==='tab'?it.text:'';
+    return '<div class="row'+(i===sel?' sel':'')+'" onmousedown="window.ipc.postMessage(\'pick:'+i+'\')">'
+      +'<span class="k">'+icons[it.kind]+'</span><span class="t">'+esc(it.title||it.text)+'</span>'
+      +'<span class="u">'+esc(url)+'</span><span class="badge">'+badges[it.kind]+'</span></div>';
+  }).join('');
+}
+function select(sel){document.querySelectorAll('.row').forEach((r,i)=>r.classList.toggle('sel',i===sel));}
+</script></body></html>"#
+}
+
 // ── Helpers ───────────────────────────────────────────────────────────────────
 
 fn sync_sidebar(sidebar_wv: &wry::WebView, tabs: &[Tab], active: usize) {
     let items: String = tabs.iter().map(|t| {
-        format!(r#"{{"url":"{}","favicon":"{}","title":"{}","suspended":{}}}"#,
+        format!(r#"{{"url":"{}","favicon":"{}","title":"{}","suspended":{},"private":{},"blocked":{},"hidden":{}}}"#,
                 t.url.replace('"',"\\\""),
                 t.favicon.as_deref().unwrap_or("").replace('"',"\\\""),
                 t.title.replace('"',"\\\""),
-                t.suspended)
+                t.suspended,
+                t.private,
+                t.blocked_count,
+                t.hidden)
     }).collect::<Vec<_>>().join(",");
     let _ = sidebar_wv.evaluate_script(
         &format!("update({{tabs:[{}],active:{}}});", items, active)
     );
 }
 
+/// Shows `url` with its host decoded and the registrable domain emphasized;
+/// lookalike hosts stay in punycode and raise a warning badge.
 fn toolbar_set_url(toolbar_wv: &wry::WebView, url: &str) {
-    let safe = url.replace('\'', "\\'").replace('\n', "");
-    let _ = toolbar_wv.evaluate_script(&format!("setUrl('{}');", safe));
+    let d = idn::display(url);
+    let _ = toolbar_wv.evaluate_script(&format!("setUrl({});",
+        serde_json::to_string(&d).unwrap_or_default()));
+}
+
+/// `None` disables the star, e.g. on internal pages.
+fn toolbar_set_star(toolbar_wv: &wry::WebView, starred: Option<bool>) {
+    let st = match starred { Some(true) => "true", Some(false) => "false", None => "null" };
+    let _ = toolbar_wv.evaluate_script(&format!("setStar({});", st));
+}
+
+/// Star state for `url`: internal and blank pages can't be bookmarked.
+fn star_state(bookmarks: &bookmarks::Bookmarks, url: &str) -> Option<bool> {
+    if url.starts_with("vccat:") || url.starts_with("about:") || url.is_empty() { return None; }
+    Some(bookmarks.is_bookmarked(url))
+}
+
+/// Shield state for `url`: Some(true) while blocking is on, None where it
+/// doesn't apply (internal and local pages).
+fn shield_state(url: &str) -> Option<bool> {
+    allowlist::site_of(url).map(|_| !allowlist::is_allowlisted(url))
+}
+
+fn toolbar_set_shield(toolbar_wv: &wry::WebView, tab: &Tab) {
+    let st = match shield_state(&tab.url) { Some(true) => "true", Some(false) => "false", None => "null" };
+    let _ = toolbar_wv.evaluate_script(&format!("setShield({},{},{});", st, tab.blocked_count, tab.hidden));
+}
+
+fn toolbar_set_private(toolbar_wv: &wry::WebView, private: bool) {
+    let _ = toolbar_wv.evaluate_script(&format!("setPrivate({});", private));
+}
+
+/// Shows the add-search-engine button while the page offers one not yet added.
+fn toolbar_set_search_offer(toolbar_wv: &wry::WebView, tab: &Tab, settings: &settings::Settings) {
+    let offer = tab.search_offer.as_ref()
+        .is_some_and(|src| !settings.search_engines.iter().any(|e| e.source.as_ref() == Some(src)));
+    let _ = toolbar_wv.evaluate_script(&format!("setSearchOffer({});", offer));
+}
+
+/// A short message by the address bar that goes away by itself; `ok`
+/// tells success from failure.
+fn toolbar_note(toolbar_wv: &wry::WebView, text: &str, ok: bool) {
+    let _ = toolbar_wv.evaluate_script(&format!("showNote({},{});",
+        serde_json::Value::String(text.to_string()), ok));
 }
 
 fn save_session(tabs: &[Tab], active: usize) {
+    // private tabs never reach the session file; the active index is remapped
+    // onto the remaining tabs
+    let kept: Vec<usize> = (0..tabs.len()).filter(|&i| !tabs[i].private).collect();
+    let active = kept.iter().position(|&i| i >= active)
+        .unwrap_or(kept.len().saturating_sub(1));
     storage::save_session(&storage::Session {
-        tabs: tabs.iter().map(|t| t.url.clone()).collect(),
+        tabs: kept.iter().map(|&i| tabs[i].url.clone()).collect(),
         active,
     });
 }
 
+/// Urls of the tabs other devices may see; private and internal tabs stay local.
+fn synced_tabs(tabs: &[Tab]) -> Vec<String> {
+    tabs.iter().filter(|t| !t.private && !t.url.starts_with("vccat:"))
+        .map(|t| t.url.clone()).collect()
+}
+
+/// Applies a finished sync round to the profile and saves what it changed,
+/// the sync state last. Settings are applied to `synced`, a copy the
+/// caller puts in place.
+fn apply_sync(report: &mut sync::Report, state: &mut sync::SyncState, history: &mut Vec<storage::HistoryEntry>,
+              marks: &mut bookmarks::Bookmarks, synced: &mut settings::Settings) {
+    sync::apply(report, history, marks, synced);
+    if report.history_added > 0 { storage::save_history(history); }
+    if report.bookmarks_changed > 0 { bookmarks::save(marks); }
+    *state = std::mem::take(&mut report.state);
+    sync::save_state(state);
+}
+
+/// Shows an internal page or our view of a local file in the WebView. It is
+/// rendered once WebKit asks the vccat scheme for it, so reloads and history
+/// entries are rendered afresh.
+fn load_internal(wv: &wry::WebView, url: &str) {
+    let _ = wv.load_url(&internal::uri_of(url));
+}
+
+/// IPC prefixes reserved for internal vccat: pages.
+const INTERNAL_IPC: &[&str] = &["bm:", "flt:", "go:", "https:", "imp:", "restore:", "rl:", "sess:", "set:", "sync:"];
 
-fn load_html_into(wv: &wry::WebView, html: &str) {
-    let escaped = html.replace('\\', "\\\\").replace('`', "\\`").replace("${", "\\${");
-    let js = format!("document.open();document.write(\\`{}\\`);document.close();", escaped);
-    let _ = wv.evaluate_script(&js);
+#[cfg(target_os = "linux")]
+thread_local! {
+    /// vccat scheme requests waiting for the event loop: uri, whether the tab
+    /// is private, and where the rendered page goes.
+    static INTERNAL_REQUESTS: std::cell::RefCell<Vec<(String, bool, wry::RequestAsyncResponder)>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Builds the WebView of a page tab. Private tabs get their own ephemeral
+/// WebKit context, so they never share cookies or storage with normal tabs
+/// and everything they stored is dropped together with the WebView.
+#[cfg(target_os = "linux")]
+fn build_page_wv(
+    container: &gtk::Box,
+    idx: usize,
+    url: &str,
+    private: bool,
+    proxy: &tao::event_loop::EventLoopProxy<UserEvent>,
+) -> wry::Result<wry::WebView> {
+    let pu_nav = proxy.clone();
+    let pu_ipc = proxy.clone();
+    let pu_scheme = proxy.clone();
+    let init_js = page_init_js(idx);
+    let wv = WebViewBuilder::new_gtk(container)
+        .with_url(url)
+        .with_incognito(private)
+        .with_initialization_script(&init_js)
+        .with_asynchronous_custom_protocol(internal::SCHEME.into(), move |req, responder| {
+            INTERNAL_REQUESTS.with(|q| q.borrow_mut().push((req.uri().to_string(), private, responder)));
+            let _ = pu_scheme.send_event(UserEvent::RenderInternal);
+        })
+        .with_navigation_handler(move |url| {
+            // WebKit has no directory listings and downloads most text files;
+            // looking inside the file is left to the event loop
+            if files::may_handle(&url) {
+                let _ = pu_nav.send_event(UserEvent::LocalFile(idx, url));
+                return false;
+            }
+            true
+        })
+        .with_ipc_handler(move |msg: wry::http::Request<String>| {
+            let b = msg.body().to_string();
+            if b == "cosmetic" {
+                // the document's real uri, not one the page could make up
+                let _ = pu_ipc.send_event(UserEvent::CosmeticRequest(idx, msg.uri().to_string()));
+            } else if let Some((kind, u)) = b.strip_prefix("failed:").and_then(|f| f.split_once(' ')) {
+                let kind = filters::resource_type(kind).unwrap_or(filters::ResourceType::Other);
+                let _ = pu_ipc.send_event(UserEvent::ResourceFailed(idx, kind, u.to_string()));
+            } else if let Some(n) = b.strip_prefix("hidden:") {
+                if let Ok(n) = n.parse() { let _ = pu_ipc.send_event(UserEvent::ElementsHidden(idx, n)); }
+            } else if let Some(f) = b.strip_prefix("favicon:") {
+                let _ = pu_ipc.send_event(UserEvent::PageFaviconChanged(idx, f.to_string()));
+            } else if let Some(t) = b.strip_prefix("title:") {
+                let _ = pu_ipc.send_event(UserEvent::PageTitleChanged(idx, t.to_string()));
+            } else if let Some(u) = b.strip_prefix("opensearch:") {
+                let _ = pu_ipc.send_event(UserEvent::SearchEngineOffered(idx, u.to_string()));
+            } else if let Some(a) = b.strip_prefix("article:") {
+                let _ = pu_ipc.send_event(UserEvent::ArticleCaptured(idx, a.to_string()));
+            } else if let Some(u) = b.strip_prefix("file:") {
+                // only local documents may open other local files
+                if internal::is_local(&msg.uri().to_string()) {
+                    let _ = pu_ipc.send_event(UserEvent::LocalFile(idx, u.to_string()));
+                }
+            } else if INTERNAL_IPC.iter().any(|p| b.starts_with(p)) {
+                // told apart by the document WebKit shows, not by anything the page says
+                if internal::takes_commands(&msg.uri().to_string()) {
+                    let _ = pu_ipc.send_event(UserEvent::PageCommand(idx, b));
+                }
+            }
+        })
+        .build()?;
+    // the tab follows what WebKit shows: loads, redirects and history.pushState
+    {
+        use webkit2gtk::WebViewExt;
+        use wry::WebViewExtUnix;
+        let pu_uri = proxy.clone();
+        wv.webview().connect_uri_notify(move |w| {
+            let Some(uri) = w.uri() else { return };
+            let url = internal::url_of(&uri).unwrap_or_else(|| uri.to_string());
+            let _ = pu_uri.send_event(UserEvent::PageUrlChanged(idx, url));
+        });
+        // tracking parameters come off loads of the page itself, redirects
+        // included; frames and form posts keep theirs
+        let get = std::rc::Rc::new(std::cell::Cell::new(false));
+        let (get_start, pu_strip) = (get.clone(), proxy.clone());
+        wv.webview().connect_resource_load_started(move |w, resource, request| {
+            use webkit2gtk::URIRequestExt;
+            if w.main_resource().as_ref() != Some(resource) { return; }
+            get_start.set(request.http_method().is_none_or(|m| m == "GET"));
+            if !get_start.get() { return; }
+            if let Some(clean) = request.uri().and_then(|u| tracking::strip(&u)) {
+                w.stop_loading();
+                let _ = pu_strip.send_event(UserEvent::LoadStripped(idx, clean));
+            }
+        });
+        let pu_redirect = proxy.clone();
+        wv.webview().connect_load_changed(move |w, event| {
+            if event != webkit2gtk::LoadEvent::Redirected || !get.get() { return; }
+            if let Some(clean) = w.uri().and_then(|u| tracking::strip(&u)) {
+                w.stop_loading();
+                let _ = pu_redirect.send_event(UserEvent::LoadStripped(idx, clean));
+            }
+        });
+        // emitted for the main frame only; a cancelled load gave way to another
+        let pu_fail = proxy.clone();
+        wv.webview().connect_load_failed(move |_, _, uri, error| {
+            if !error.matches(webkit2gtk::NetworkError::Cancelled) {
+                let _ = pu_fail.send_event(UserEvent::LoadFailed(idx, uri.to_string(), error.message().to_string()));
+            }
+            false
+        });
+    }
+    adblock::apply_content_filter(&wv);
+    adblock::apply_scriptlets(&wv);
+    adblock::apply_cosmetic(&wv);
+    Ok(wv)
+}
+
+/// Modal GTK file chooser; `save` asks for a destination named `name`.
+#[cfg(target_os = "linux")]
+fn pick_file(parent: &gtk::ApplicationWindow, save: bool, name: &str) -> Option<std::path::PathBuf> {
+    use gtk::prelude::*;
+    let (title, action, ok) = if save {
+        ("Zapisz plik", gtk::FileChooserAction::Save, "Zapisz")
+    } else {
+        ("Otwórz plik", gtk::FileChooserAction::Open, "Otwórz")
+    };
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some(title), Some(parent), action,
+        &[("Anuluj", gtk::ResponseType::Cancel), (ok, gtk::ResponseType::Accept)],
+    );
+    if save {
+        dialog.set_current_name(name);
+        dialog.set_do_overwrite_confirmation(true);
+    }
+    let path = if dialog.run() == gtk::ResponseType::Accept { dialog.filename() } else { None };
+    dialog.close();
+    path
+}
+
+#[cfg(target_os = "linux")]
+fn pick_folder(parent: &gtk::ApplicationWindow) -> Option<std::path::PathBuf> {
+    use gtk::prelude::*;
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some("Wybierz folder"), Some(parent), gtk::FileChooserAction::SelectFolder,
+        &[("Anuluj", gtk::ResponseType::Cancel), ("Wybierz", gtk::ResponseType::Accept)],
+    );
+    let path = if dialog.run() == gtk::ResponseType::Accept { dialog.filename() } else { None };
+    dialog.close();
+    path
+}
+
+// ── Passphrase dialogs ────────────────────────────────────────────────────────
+
+/// Modal dialog with one hidden entry per label; `None` when cancelled.
+#[cfg(target_os = "linux")]
+fn passphrase_dialog(title: &str, labels: &[&str], error: Option<&str>) -> Option<Vec<String>> {
+    use gtk::prelude::*;
+    let dialog = gtk::Dialog::with_buttons(
+        Some(title), None::<&gtk::Window>, gtk::DialogFlags::MODAL,
+        &[("Anuluj", gtk::ResponseType::Cancel), ("OK", gtk::ResponseType::Accept)],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept);
+    let area = dialog.content_area();
+    area.set_spacing(6);
+    area.set_border_width(12);
+    if let Some(err) = error {
+        let l = gtk::Label::new(Some(err));
+        l.set_xalign(0.0);
+        area.pack_start(&l, false, false, 4);
+    }
+    let entries: Vec<gtk::Entry> = labels.iter().map(|text| {
+        let l = gtk::Label::new(Some(text));
+        l.set_xalign(0.0);
+        area.pack_start(&l, false, false, 0);
+        let e = gtk::Entry::new();
+        e.set_visibility(false);
+        e.set_activates_default(true);
+        area.pack_start(&e, false, false, 0);
+        e
+    }).collect();
+    dialog.show_all();
+    let ok = dialog.run() == gtk::ResponseType::Accept;
+    let values = entries.iter().map(|e| e.text().to_string()).collect();
+    dialog.close();
+    ok.then_some(values)
+}
+
+/// Asks for the profile passphrase until it is right. Returns false when the
+/// user gives up; the profile then stays locked and nothing is loaded.
+#[cfg(target_os = "linux")]
+fn unlock_vault() -> bool {
+    let mut error: Option<String> = None;
+    loop {
+        let Some(v) = passphrase_dialog("vccat — profil zaszyfrowany", &["Hasło profilu"],
+                                        error.as_deref()) else { return false };
+        match vault::unlock(&v[0]) {
+            Ok(()) => return true,
+            Err(vault::VaultError::WrongPassphrase) =>
+                error = Some("Błędne hasło — spróbuj ponownie.".into()),
+            Err(e) => error = Some(format!("Nie można odblokować profilu: {}", e)),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unlock_vault() -> bool {
+    eprint!("hasło profilu: ");
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).is_ok() && vault::unlock(line.trim_end()).is_ok()
+}
+
+/// Sets, changes or (with an empty new passphrase) removes the profile
+/// passphrase. Returns a status message, or `None` when cancelled.
+#[cfg(target_os = "linux")]
+fn change_passphrase() -> Option<String> {
+    let enabled = vault::is_enabled();
+    let labels: &[&str] = if enabled {
+        &["Obecne hasło", "Nowe hasło (puste wyłącza szyfrowanie)", "Powtórz nowe hasło"]
+    } else {
+        &["Nowe hasło", "Powtórz nowe hasło"]
+    };
+    let mut error: Option<String> = None;
+    loop {
+        let v = passphrase_dialog("vccat — hasło profilu", labels, error.as_deref())?;
+        let (current, new, repeat) = if enabled { (&v[0], &v[1], &v[2]) } else { (&v[0], &v[0], &v[1]) };
+        if enabled {
+            match vault::unlock(current) {
+                Ok(()) => {}
+                Err(vault::VaultError::WrongPassphrase) => {
+                    error = Some("Błędne obecne hasło.".into());
+                    continue;
+                }
+                Err(e) => return Some(format!("Nie można odblokować profilu: {}", e)),
+            }
+        }
+        if new != repeat {
+            error = Some("Nowe hasła się różnią.".into());
+            continue;
+        }
+        let res = if new.is_empty() {
+            if !enabled {
+                error = Some("Hasło nie może być puste.".into());
+                continue;
+            }
+            vault::disable().map(|_| "Szyfrowanie profilu wyłączone.")
+        } else {
+            vault::set_passphrase(new).map(|_| "Hasło profilu ustawione.")
+        };
+        return Some(match res {
+            Ok(msg) => msg.to_string(),
+            Err(e) => format!("Nie udało się zmienić hasła: {}", e),
+        });
+    }
+}
+
+// ── Command line ──────────────────────────────────────────────────────────────
+
+/// Handles profile maintenance commands that run without the UI.
+/// Returns the exit code when a command was given.
+fn run_cli() -> Option<i32> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let arg_after = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1));
+    if let Some(dest) = arg_after("--backup") {
+        return Some(match backup::export(std::path::Path::new(dest)) {
+            Ok(m) => { println!("zapisano kopię profilu: {} ({} plików)", dest, m.files.len()); 0 }
+            Err(e) => { eprintln!("nie udało się utworzyć kopii: {}", e); 1 }
+        });
+    }
+    if let Some(src) = arg_after("--restore") {
+        return Some(match backup::restore(std::path::Path::new(src)) {
+            Ok((m, previous)) => {
+                println!("przywrócono profil z {} (v{}, {} plików)", src, m.app_version, m.files.len());
+                println!("poprzedni profil zachowano w {}", previous.display());
+                0
+            }
+            Err(e) => { eprintln!("nie udało się przywrócić profilu: {}", e); 1 }
+        });
+    }
+    if args.iter().any(|a| a == "--passphrase") {
+        if let Some(pid) = storage::running_instance() {
+            eprintln!("profil jest używany przez działającą przeglądarkę (pid {})", pid);
+            return Some(1);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if gtk::init().is_err() { eprintln!("nie można uruchomić GTK"); return Some(1); }
+            return Some(match change_passphrase() {
+                Some(msg) => { println!("{}", msg); 0 }
+                None => 1,
+            });
+        }
+        #[cfg(not(target_os = "linux"))]
+        { eprintln!("zmiana hasła jest dostępna tylko na Linuksie"); return Some(1); }
+    }
+    if let Some(src) = arg_after("--compile-filters") {
+        let text = match std::fs::read_to_string(src) {
+            Ok(t) => t,
+            Err(e) => { eprintln!("nie można odczytać {}: {}", src, e); return Some(1); }
+        };
+        let list = filters::parse_list(&text);
+        let rules = filters::compile(&list);
+        for u in &list.unsupported {
+            eprintln!("{}:{}: {}: {}", src, u.line, u.reason, u.text);
+        }
+        eprintln!("reguł WebKit: {}, pominięto filtrów: {}", rules.len(), list.unsupported.len());
+        println!("{}", serde_json::Value::Array(rules));
+        return Some(0);
+    }
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("vccat_browser [--backup PLIK] [--restore PLIK] [--passphrase] [--compile-filters LISTA]");
+        return Some(0);
+    }
+    None
 }
 
 // ── Main ──────────────────────────────────────────────────────────────────────
 
 fn main() -> wry::Result<()> {
-    let session = storage::load_session();
-    let mut history = storage::load_history();
+    if let Some(code) = run_cli() { std::process::exit(code); }
 
     let event_loop: EventLoop<UserEvent> = EventLoopBuilder::with_user_event().build();
     let proxy = event_loop.create_proxy();
 
+    // a passphrase change cut short is finished or dropped first, unless
+    // the instance that started it is still running
+    if storage::running_instance().is_none() {
+        if let Err(e) = vault::recover() { eprintln!("nie można dokończyć zmiany hasła profilu: {}", e); }
+    }
+    // an encrypted profile must be unlocked before anything is read from it
+    if vault::is_enabled() && !unlock_vault() {
+        eprintln!("profil jest zaszyfrowany i nie został odblokowany");
+        std::process::exit(1);
+    }
+    let crashed = storage::lock_profile();
+
+    let mut session = storage::load_session();
+    // after an unclean shutdown the last tabs are offered on vccat:restore
+    // instead of being reopened, so a page that crashes on load can't keep
+    // taking the browser down
+    if crashed && session.tabs.iter().any(|u| !u.starts_with("vccat:")) {
+        storage::save_crashed_session(&session);
+        session = storage::Session { tabs: vec!["vccat:restore".into()], active: 0 };
+    }
+    let mut history = storage::load_history();
+    let mut bookmarks = bookmarks::load();
+    let mut settings = settings::load();
+    https::set_enabled(settings.https_only);
+    tracking::configure(settings.strip_tracking, &settings.tracking_rules, &settings.tracking_bypass);
+    let mut sync_config = sync::load_config();
+    let mut sync_state = sync::load_state();
+    // sync rounds run on their own thread, one at a time
+    let mut sync_running = false;
+    let ps = proxy.clone();
+    let sync_jobs = sync::spawn_worker(move |res| { let _ = ps.send_event(UserEvent::SyncDone(res)); });
+
     // background update check
     let proxy_upd = proxy.clone();
+    let update_delay = settings.update_check_delay;
     std::thread::spawn(move || {
-        std::thread::sleep(std::time::Duration::from_secs(4));
+        std::thread::sleep(std::time::Duration::from_secs(update_delay));
         if let Some(info) = updater::check_update() {
             let _ = proxy_upd.send_event(
                 UserEvent::UpdateAvailable(info.version, info.download_url)
@@ -309,15 +1698,20 @@ fn main() -> wry::Result<()> {
         }
     });
 
+    // periodic sync through the shared folder
+    let proxy_sync = proxy.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_secs(60));
+        if proxy_sync.send_event(UserEvent::SyncTick).is_err() { break; }
+    });
+
     let window = WindowBuilder::new()
         .with_title("vccat browser")
-        .with_inner_size(tao::dpi::LogicalSize::new(1360, 860))
+        .with_inner_size(tao::dpi::LogicalSize::new(settings.window_width, settings.window_height))
         .with_decorations(true)
         .build(&event_loop)
         .unwrap();
 
-    const SUSPEND_THRESHOLD: usize = 4;
-
     #[cfg(target_os = "linux")]
     {
         use gtk::prelude::*;
@@ -335,14 +1729,47 @@ fn main() -> wry::Result<()> {
         let pages_gtk = gtk::Box::new(gtk::Orientation::Vertical, 0);
         pages_gtk.set_vexpand(true);
 
+        // the omnibox dropdown floats over the pages, below the address bar
+        let overlay = gtk::Overlay::new();
+        overlay.add(&pages_gtk);
+        let omni_gtk = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        omni_gtk.set_valign(gtk::Align::Start);
+        omni_gtk.set_margin_start(96);
+        omni_gtk.set_margin_end(110);
+        overlay.add_overlay(&omni_gtk);
+
         right.pack_start(&toolbar_gtk, false, false, 0);
-        right.pack_start(&pages_gtk, true, true, 0);
+        right.pack_start(&overlay, true, true, 0);
         hbox.pack_start(&sidebar_gtk, false, false, 0);
         hbox.pack_start(&right, true, true, 0);
         root.show_all();
 
         // ── Load adblock content rules ──
-        let _filter_path = adblock::ensure_filters_downloaded();
+        // the refresh thread hands over the rules of the lists on disk first,
+        // then new rules whenever an update changed a list
+        subscriptions::set_default_max_age(settings.filter_max_age);
+        let pf = proxy.clone();
+        let filters_refresh = subscriptions::spawn_refresh(move |json| {
+            let _ = pf.send_event(UserEvent::FilterRules(json));
+        });
+        let pb = proxy.clone();
+        let explainer = blocklog::spawn_explainer(move |idx, page_url, entry| {
+            let _ = pb.send_event(UserEvent::ResourceBlocked(idx, page_url, entry));
+        });
+        // one compilation at a time; rules arriving meanwhile wait for it.
+        // The lists' rules are kept so allowlist changes can be compiled in.
+        let mut filter_compiling = false;
+        // pages loaded before the first filter was ready ran unfiltered
+        let mut filter_ready = false;
+        let mut filter_queued: Option<String> = None;
+        let mut filter_base = adblock::builtin_youtube_rules().to_string();
+        // sites to reload, subdomains too, once the rules in the works are applied; after edits
+        // on vccat:filters, every web page once the next rules are
+        let mut filter_reload: Vec<String> = Vec::new();
+        let mut filter_edited = false;
+        let mut filter_reload_all = false;
+        // tabs going on over http once their exception is compiled in
+        let mut https_continue: Vec<(usize, String)> = Vec::new();
 
         // ── Sidebar ──
         let ps = proxy.clone();
@@ -351,7 +1778,12 @@ fn main() -> wry::Result<()> {
             .with_ipc_handler(move |msg: wry::http::Request<String>| {
                 let b = msg.body().as_str();
                 if b == "new" { let _ = ps.send_event(UserEvent::NewTab); }
-                else if b == "history" { let _ = ps.send_event(UserEvent::ShowHistory); }
+                else if b == "new-private" { let _ = ps.send_event(UserEvent::NewPrivateTab); }
+                else if b == "history" { let _ = ps.send_event(UserEvent::OpenTab("vccat:history".into())); }
+                else if b == "bookmarks" { let _ = ps.send_event(UserEvent::OpenTab("vccat:bookmarks".into())); }
+                else if b == "sessions" { let _ = ps.send_event(UserEvent::OpenTab("vccat:sessions".into())); }
+                else if b == "reading" { let _ = ps.send_event(UserEvent::OpenTab("vccat:reading-list".into())); }
+                else if b == "settings" { let _ = ps.send_event(UserEvent::OpenTab("vccat:settings".into())); }
                 else if let Some(i) = b.strip_prefix("close:")
                     .and_then(|s| s.parse::<usize>().ok()) {
                     let _ = ps.send_event(UserEvent::CloseTab(i));
@@ -366,106 +1798,138 @@ fn main() -> wry::Result<()> {
         // ── Toolbar ──
         let pt = proxy.clone();
         let mut pending_update: Option<updater::UpdateInfo> = None;
+        let mut remote_tabs: Vec<sync::RemoteTabs> = Vec::new();
+        let mut sync_status = String::new();
+        // tab asked for its article by "save for later"; other article posts are ignored
+        let mut pending_capture: Option<usize> = None;
         let toolbar_wv = WebViewBuilder::new_gtk(&toolbar_gtk)
             .with_html(toolbar_html())
             .with_ipc_handler(move |msg: wry::http::Request<String>| {
                 let b = msg.body().as_str();
-                if let Some(url) = b.strip_prefix("nav:") {
-                    let _ = pt.send_event(UserEvent::Navigate(normalize_url(url)));
+                if let Some(input) = b.strip_prefix("nav:") {
+                    let _ = pt.send_event(UserEvent::AddressInput(input.to_string()));
                 } else if b == "back"    { let _ = pt.send_event(UserEvent::GoBack);    }
                 else if b == "fwd"     { let _ = pt.send_event(UserEvent::GoForward);  }
                 else if b == "reload"  { let _ = pt.send_event(UserEvent::Reload);     }
+                else if b == "star"    { let _ = pt.send_event(UserEvent::ToggleBookmark); }
+                else if b == "later"   { let _ = pt.send_event(UserEvent::SaveForLater); }
+                else if b == "shield"  { let _ = pt.send_event(UserEvent::ToggleShield); }
+                else if b == "blocklog" { let _ = pt.send_event(UserEvent::OpenTab("vccat:blocklog".into())); }
+                else if b == "add-search" { let _ = pt.send_event(UserEvent::AddSearchEngine); }
+                else if b == "omni-close" { let _ = pt.send_event(UserEvent::OmniboxClose); }
+                else if let Some(text) = b.strip_prefix("copy:") {
+                    let _ = pt.send_event(UserEvent::CopyUrl(text.to_string()));
+                }
+                else if let Some(d) = b.strip_prefix("omni-move:").and_then(|d| d.parse().ok()) {
+                    let _ = pt.send_event(UserEvent::OmniboxMove(d));
+                } else if let Some(json) = b.strip_prefix("omni:") {
+                    if let Ok(v) = serde_json::from_str::<serde_json::Value>(json) {
+                        let text = v["text"].as_str().unwrap_or("").to_string();
+                        let _ = pt.send_event(UserEvent::OmniboxInput(text, v["complete"].as_bool().unwrap_or(false)));
+                    }
+                }
             })
             .with_background_color((10, 10, 18, 255))
             .build()?;
 
-        // ── helper: build a page WebView ──
-        macro_rules! make_page_wv {
-            ($idx:expr, $url:expr, $box:expr) => {{
-                let idx = $idx;
-                let pu_nav = proxy.clone();
-                let pu_ipc = proxy.clone();
-                let pf     = proxy.clone();
-                let pt2    = proxy.clone();
-                let init_js = page_init_js(idx);
-                WebViewBuilder::new_gtk($box)
-                    .with_url($url)
-                    .with_initialization_script(&init_js)
-                    .with_navigation_handler(move |url| {
-                        let _ = pu_nav.send_event(UserEvent::PageUrlChanged(idx, url));
-                        true
-                    })
-                    .with_ipc_handler(move |msg: wry::http::Request<String>| {
-                        let b = msg.body().to_string();
-                        if let Some(u) = b.strip_prefix("url:") {
-                            let _ = pu_ipc.send_event(UserEvent::PageUrlChanged(idx, u.to_string()));
-                        } else if let Some(f) = b.strip_prefix("favicon:") {
-                            let _ = pf.send_event(UserEvent::PageFaviconChanged(idx, f.to_string()));
-                        } else if let Some(t) = b.strip_prefix("title:") {
-                            let _ = pt2.send_event(UserEvent::PageTitleChanged(idx, t.to_string()));
-                        }
-                    })
-                    .build()?
+        // ── Omnibox dropdown ──
+        let po = proxy.clone();
+        let omni_wv = WebViewBuilder::new_gtk(&omni_gtk)
+            .with_html(omnibox_html())
+            .with_ipc_handler(move |msg: wry::http::Request<String>| {
+                if let Some(i) = msg.body().strip_prefix("pick:").and_then(|i| i.parse().ok()) {
+                    let _ = po.send_event(UserEvent::OmniboxPick(i));
+                }
+            })
+            .with_background_color((12, 11, 20, 255))
+            .build()?;
+        omni_gtk.hide();
+        let mut omni = omnibox::Suggestions::default();
+        let mut omni_typed = String::new();
+        let mut omni_sel: Option<usize> = None;
+        let mut omni_index = omnibox::HistoryIndex::default();
+
+        // ── helper: render an internal vccat: page ──
+        // tabs come first: vccat:blocklog is rendered from them
+        let mut tabs: Vec<Tab> = session.tabs.iter().map(|u| Tab::new(u)).collect();
+        if tabs.is_empty() { tabs.push(Tab::new("vccat:home")); }
+
+        // one-off notes for the next render: the settings status line, and why
+        // an http page was stopped with where its back link goes
+        let mut settings_status = String::new();
+        let mut https_failures: std::collections::HashMap<String, (String, String)> = Default::default();
+
+        macro_rules! internal_html {
+            ($url:expr, $private:expr) => {{
+                match $url {
+                    "vccat:history"   => history_page_html(&history),
+                    "vccat:bookmarks" => bookmarks_page_html(&bookmarks),
+                    "vccat:import"    => import_page_html(&importer::discover_profiles()),
+                    "vccat:sync"      => sync_page_html(&sync_config, &remote_tabs, &sync_status),
+                    "vccat:restore"   => restore_page_html(storage::load_crashed_session().as_ref()),
+                    "vccat:sessions"  => sessions_page_html(&storage::load_named_sessions()),
+                    "vccat:settings"  => settings_page_html(&settings, &std::mem::take(&mut settings_status)),
+                    "vccat:reading-list" => reading_page_html(&reading::load()),
+                    "vccat:blocklog"  => blocklog_page_html(&tabs),
+                    "vccat:filters"   => filters_page_html(),
+                    u if u.starts_with("vccat:https-only/") => {
+                        let url = &u["vccat:https-only/".len()..];
+                        match https_failures.get(url) {
+                            Some((e, back)) => https_only_page_html(url, Some(e), back, $private),
+                            None => https_only_page_html(url, None, &settings.home_page, $private),
+                        }
+                    }
+                    u if u.starts_with("vccat:reading/") => u["vccat:reading/".len()..].parse().ok()
+                        .and_then(reading::offline_copy)
+                        .unwrap_or_else(|| reading_page_html(&reading::load())),
+                    _ if $private     => private_home_html(),
+                    _                 => home_page_html(&history),
+                }
+            }};
+        }
+
+        // ── helper: build the WebView of a tab, rendering internal pages ──
+        macro_rules! build_tab_wv {
+            ($box:expr, $idx:expr, $url:expr, $private:expr) => {{
+                let url: &str = $url;
+                if url.starts_with("vccat:") || files::handles(url) {
+                    build_page_wv($box, $idx, &internal::uri_of(url), $private, &proxy)
+                } else {
+                    if files::path_of(url).is_some() { files::let_through(url); }
+                    build_page_wv($box, $idx, url, $private, &proxy)
+                }
             }};
         }
 
         // ── init tabs from session ──
-        let mut tabs: Vec<Tab> = session.tabs.iter().map(|u| Tab::new(u)).collect();
-        if tabs.is_empty() { tabs.push(Tab::new("vccat:home")); }
         let mut active = session.active.min(tabs.len() - 1);
 
         // page_entries: Option<(gtk::Box, WebView)> — None = suspended
         let mut page_entries: Vec<Option<(gtk::Box, wry::WebView)>> = Vec::new();
 
-        for (i, tab) in tabs.iter_mut().enumerate() {
+        let urls: Vec<String> = tabs.iter().map(|t| t.url.clone()).collect();
+        for (i, url) in urls.iter().enumerate() {
             let b = gtk::Box::new(gtk::Orientation::Vertical, 0);
             b.set_vexpand(true);
             pages_gtk.pack_start(&b, true, true, 0);
 
             if i == active {
                 b.show_all();
-                let wv = if tab.url.starts_with("vccat:") {
-                    let html = home_page_html(&history);
-                    let idx = i;
-                    let pu_nav = proxy.clone();
-                    let pu_ipc = proxy.clone();
-                    let pf     = proxy.clone();
-                    let pt2    = proxy.clone();
-                    let init_js = page_init_js(idx);
-                    WebViewBuilder::new_gtk(&b)
-                        .with_html(&html)
-                        .with_initialization_script(&init_js)
-                        .with_navigation_handler(move |url| {
-                            let _ = pu_nav.send_event(UserEvent::PageUrlChanged(idx, url));
-                            true
-                        })
-                        .with_ipc_handler(move |msg: wry::http::Request<String>| {
-                            let b = msg.body().to_string();
-                            if let Some(u) = b.strip_prefix("url:") {
-                                let _ = pu_ipc.send_event(UserEvent::PageUrlChanged(idx, u.to_string()));
-                            } else if let Some(f) = b.strip_prefix("favicon:") {
-                                let _ = pf.send_event(UserEvent::PageFaviconChanged(idx, f.to_string()));
-                            } else if let Some(t) = b.strip_prefix("title:") {
-                                let _ = pt2.send_event(UserEvent::PageTitleChanged(idx, t.to_string()));
-                            }
-                        })
-                        .build()?
-                } else {
-                    make_page_wv!(i, &tab.url.clone(), &b)
-                };
+                let wv = build_tab_wv!(&b, i, url, false)?;
                 page_entries.push(Some((b, wv)));
-            } else if i < SUSPEND_THRESHOLD {
+            } else if i < settings.suspend_threshold {
                 b.hide();
-                let wv = make_page_wv!(i, &tab.url, &b);
+                let wv = build_tab_wv!(&b, i, url, false)?;
                 page_entries.push(Some((b, wv)));
             } else {
                 b.hide();
                 page_entries.push(None);
-                tab.suspended = true;
+                tabs[i].suspended = true;
             }
         }
 
         toolbar_set_url(&toolbar_wv, &tabs[active].url);
+        toolbar_set_star(&toolbar_wv, star_state(&bookmarks, &tabs[active].url));
         sync_sidebar(&sidebar_wv, &tabs, active);
 
         // ── Event loop ────────────────────────────────────────────────────────
@@ -474,6 +1938,25 @@ fn main() -> wry::Result<()> {
             match event {
                 Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
                     save_session(&tabs, active);
+                    if sync_config.dir.is_some() {
+                        // a last round, waited for with the window gone so
+                        // what it brings in is saved
+                        window.set_visible(false);
+                        let (reply, result) = std::sync::mpsc::channel();
+                        let job = sync::Job {
+                            config: sync_config.clone(), state: sync_state.clone(), history: history.clone(),
+                            bookmarks: bookmarks.clone(), settings: settings.clone(),
+                            open_tabs: synced_tabs(&tabs), reply: Some(reply),
+                        };
+                        if sync_jobs.send(job).is_ok() {
+                            if let Ok(Ok(mut r)) = result.recv_timeout(std::time::Duration::from_secs(15)) {
+                                let mut synced = settings.clone();
+                                apply_sync(&mut r, &mut sync_state, &mut history, &mut bookmarks, &mut synced);
+                                if r.settings_changed { settings::save(&synced); }
+                            }
+                        }
+                    }
+                    storage::unlock_profile();
                     *control_flow = ControlFlow::Exit;
                 }
 
@@ -492,32 +1975,7 @@ fn main() -> wry::Result<()> {
                                         nb.set_vexpand(true);
                                         pages_gtk.pack_start(&nb, true, true, 0);
                                         let url = tabs[i].url.clone();
-                                        let wv = {
-                                            let idx = i;
-                                            let pu_nav = proxy.clone();
-                                            let pu_ipc = proxy.clone();
-                                            let pf     = proxy.clone();
-                                            let pt2    = proxy.clone();
-                                            let init_js = page_init_js(idx);
-                                            WebViewBuilder::new_gtk(&nb)
-                                                .with_url(&url)
-                                                .with_initialization_script(&init_js)
-                                                .with_navigation_handler(move |u| {
-                                                    let _ = pu_nav.send_event(UserEvent::PageUrlChanged(idx, u));
-                                                    true
-                                                })
-                                                .with_ipc_handler(move |msg: wry::http::Request<String>| {
-                                                    let b = msg.body().to_string();
-                                                    if let Some(u) = b.strip_prefix("url:") {
-                                                        let _ = pu_ipc.send_event(UserEvent::PageUrlChanged(idx, u.to_string()));
-                                                    } else if let Some(f) = b.strip_prefix("favicon:") {
-                                                        let _ = pf.send_event(UserEvent::PageFaviconChanged(idx, f.to_string()));
-                                                    } else if let Some(t) = b.strip_prefix("title:") {
-                                                        let _ = pt2.send_event(UserEvent::PageTitleChanged(idx, t.to_string()));
-                                                    }
-                                                })
-                                                .build().unwrap()
-                                        };
+                                        let wv = build_tab_wv!(&nb, i, &url, tabs[i].private).unwrap();
                                         *slot = Some((nb, wv));
                                         tabs[i].suspended = false;
                                     }
@@ -526,25 +1984,137 @@ fn main() -> wry::Result<()> {
                         }};
                     }
 
-                    match e {
-                        UserEvent::Navigate(url) => {
-                            tabs[active].url = url.clone();
-                            tabs[active].favicon = None;
-                            if let Some(Some((_, ref wv))) = page_entries.get(active) {
-                                if url.starts_with("vccat:") {
-                                    let html = if url == "vccat:home" {
-                                        home_page_html(&history)
-                                    } else {
-                                        history_page_html(&history)
-                                    };
-                                    load_html_into(wv, &html);
+                    // helper: bring url, bookmark star and browsing mode of the
+                    // toolbar in line with the active tab
+                    macro_rules! sync_toolbar {
+                        () => {{
+                            let private = tabs[active].private;
+                            toolbar_set_url(&toolbar_wv, &tabs[active].url);
+                            toolbar_set_star(&toolbar_wv, star_state(&bookmarks, &tabs[active].url));
+                            toolbar_set_shield(&toolbar_wv, &tabs[active]);
+                            toolbar_set_private(&toolbar_wv, private);
+                            toolbar_set_search_offer(&toolbar_wv, &tabs[active], &settings);
+                            window.set_title(if private { "vccat browser — prywatna" }
+                                             else { "vccat browser" });
+                        }};
+                    }
+
+                    // helper: load url in tab i; a suspended tab just keeps
+                    // it for when it is woken
+                    macro_rules! navigate {
+                        ($i:expr, $url:expr) => {{
+                            let i = $i;
+                            let url = $url;
+                            let url = tracking::strip(&url).unwrap_or(url);
+                            tabs[i].url = url.clone();
+                            tabs[i].favicon = None;
+                            tabs[i].search_offer = None;
+                            if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                if url.starts_with("vccat:") || files::handles(&url) {
+                                    load_internal(wv, &url);
                                 } else {
+                                    if files::path_of(&url).is_some() { files::let_through(&url); }
                                     let _ = wv.load_url(&url);
                                 }
                             }
-                            toolbar_set_url(&toolbar_wv, &url);
+                            sync_toolbar!();
+                            save_session(&tabs, active);
+                            sync_sidebar(&sidebar_wv, &tabs, active);
+                        }};
+                    }
+
+                    // helper: close tab i; the last one goes back to the home page
+                    macro_rules! close_tab {
+                        ($i:expr) => {{
+                            let i = $i;
+                            if tabs.len() == 1 {
+                                if tabs[0].private {
+                                    // drop the ephemeral context along with its WebView
+                                    if let Some(Some((ref b, _))) = page_entries.first() {
+                                        pages_gtk.remove(b);
+                                    }
+                                    let nb = gtk::Box::new(gtk::Orientation::Vertical, 0);
+                                    nb.set_vexpand(true);
+                                    pages_gtk.pack_start(&nb, true, true, 0);
+                                    nb.show_all();
+                                    let wv = build_tab_wv!(&nb, 0, "vccat:home", false).unwrap();
+                                    page_entries[0] = Some((nb, wv));
+                                } else if let Some(Some((_, ref wv))) = page_entries.first() {
+                                    load_internal(wv, "vccat:home");
+                                }
+                                tabs[0] = Tab::new("vccat:home");
+                                active = 0;
+                            } else {
+                                if let Some(Some((ref b, _))) = page_entries.get(i) {
+                                    pages_gtk.remove(b);
+                                }
+                                page_entries.remove(i);
+                                tabs.remove(i);
+                                if active >= tabs.len() { active = tabs.len() - 1; }
+                                else if active > i { active -= 1; }
+
+                                // show new active, hide others
+                                for (j, entry) in page_entries.iter().enumerate() {
+                                    if let Some((ref b, _)) = entry {
+                                        if j == active { b.show_all(); } else { b.hide(); }
+                                    }
+                                }
+                                // wake if suspended
+                                wake_tab!(active);
+                            }
+                            sync_toolbar!();
                             save_session(&tabs, active);
                             sync_sidebar(&sidebar_wv, &tabs, active);
+                        }};
+                    }
+
+                    match e {
+                        UserEvent::Navigate(url) => navigate!(active, url),
+
+                        UserEvent::NavigateTab(idx, from, url) => {
+                            // the tab was closed or went elsewhere meanwhile
+                            if tabs.get(idx).map(|t| &t.url) != Some(&from) { return; }
+                            navigate!(idx, url);
+                        }
+
+                        UserEvent::AddressInput(input) => {
+                            // Enter on a "switch to tab" row switches instead of loading;
+                            // tabs may have been closed or moved since, so go by url
+                            let chosen = omni_sel.and_then(|i| omni.items.get(i))
+                                .filter(|s| s.kind == omnibox::Kind::Tab && s.text == input)
+                                .and_then(|s| s.tab.filter(|&t| tabs.get(t).is_some_and(|t| t.url == s.text))
+                                    .or_else(|| tabs.iter().position(|t| t.url == s.text)));
+                            omni = omnibox::Suggestions::default();
+                            omni_sel = None;
+                            omni_gtk.hide();
+                            if let Some(t) = chosen {
+                                let _ = proxy.send_event(UserEvent::SwitchTab(t));
+                                return;
+                            }
+                            if input.trim().is_empty() {
+                                let _ = proxy.send_event(UserEvent::Navigate(settings.home_page.clone()));
+                                return;
+                            }
+                            if let Some(url) = bookmarks.resolve_keyword(&input)
+                                .or_else(|| search::resolve_keyword(&settings.search_engines, &input)) {
+                                let _ = proxy.send_event(UserEvent::Navigate(url));
+                                return;
+                            }
+                            match address::classify(&input) {
+                                address::Target::Url(url) => { let _ = proxy.send_event(UserEvent::Navigate(url)); }
+                                address::Target::Search(q) => { let _ = proxy.send_event(UserEvent::Navigate(settings.search(&q))); }
+                                address::Target::Lookup { host, url, query } => {
+                                    // DNS can take seconds; the tab changes once it answers,
+                                    // unless it was closed or went elsewhere meanwhile
+                                    let fallback = settings.search(&query);
+                                    let from = tabs[active].url.clone();
+                                    let pl = proxy.clone();
+                                    std::thread::spawn(move || {
+                                        let url = if address::resolves(&host) { url } else { fallback };
+                                        let _ = pl.send_event(UserEvent::NavigateTab(active, from, url));
+                                    });
+                                }
+                            }
                         }
 
                         UserEvent::GoBack => {
@@ -563,14 +2133,20 @@ fn main() -> wry::Result<()> {
                             }
                         }
 
-                        UserEvent::NewTab => {
+                        UserEvent::NewTab | UserEvent::NewPrivateTab | UserEvent::OpenTab(_) => {
+                            let private = matches!(e, UserEvent::NewPrivateTab);
+                            let url = match e {
+                                UserEvent::OpenTab(ref u) => u.clone(),
+                                _ => settings.home_page.clone(),
+                            };
                             let idx = tabs.len();
-                            tabs.push(Tab::new("vccat:home"));
+                            tabs.push(if private { Tab::new_private(&url) } else { Tab::new(&url) });
 
-                            // suspend oldest background tabs if over threshold
-                            if idx >= SUSPEND_THRESHOLD {
+                            // suspend oldest background tabs if over threshold; private
+                            // tabs stay alive since their data only lives in the WebView
+                            if idx >= settings.suspend_threshold {
                                 for i in 0..idx {
-                                    if i != active && !tabs[i].suspended {
+                                    if i != active && !tabs[i].suspended && !tabs[i].private {
                                         if let Some(ref mut slot) = page_entries.get_mut(i) {
                                             if let Some((ref b, _)) = slot {
                                                 b.hide();
@@ -592,67 +2168,23 @@ fn main() -> wry::Result<()> {
                             pages_gtk.pack_start(&nb, true, true, 0);
                             nb.show_all();
 
-                            let home_html = home_page_html(&history);
-                            let wv = {
-                                let pu_nav = proxy.clone();
-                                let pu_ipc = proxy.clone();
-                                let pf     = proxy.clone();
-                                let pt2    = proxy.clone();
-                                let init_js = page_init_js(idx);
-                                WebViewBuilder::new_gtk(&nb)
-                                    .with_html(&home_html)
-                                    .with_initialization_script(&init_js)
-                                    .with_navigation_handler(move |u| {
-                                        let _ = pu_nav.send_event(UserEvent::PageUrlChanged(idx, u));
-                                        true
-                                    })
-                                    .with_ipc_handler(move |msg: wry::http::Request<String>| {
-                                        let b = msg.body().to_string();
-                                        if let Some(u) = b.strip_prefix("url:") {
-                                            let _ = pu_ipc.send_event(UserEvent::PageUrlChanged(idx, u.to_string()));
-                                        } else if let Some(f) = b.strip_prefix("favicon:") {
-                                            let _ = pf.send_event(UserEvent::PageFaviconChanged(idx, f.to_string()));
-                                        } else if let Some(t) = b.strip_prefix("title:") {
-                                            let _ = pt2.send_event(UserEvent::PageTitleChanged(idx, t.to_string()));
-                                        }
-                                    })
-                                    .build().unwrap()
-                            };
+                            let wv = build_tab_wv!(&nb, idx, &url, private).unwrap();
                             page_entries.push(Some((nb, wv)));
                             active = idx;
-                            toolbar_set_url(&toolbar_wv, "vccat:home");
+                            sync_toolbar!();
                             save_session(&tabs, active);
                             sync_sidebar(&sidebar_wv, &tabs, active);
                         }
 
                         UserEvent::CloseTab(i) => {
-                            if tabs.len() == 1 {
-                                tabs[0] = Tab::new("vccat:home");
-                                if let Some(Some((_, ref wv))) = page_entries.get(0) {
-                                    load_html_into(wv, &home_page_html(&history));
-                                }
-                                active = 0;
-                            } else {
-                                if let Some(Some((ref b, _))) = page_entries.get(i) {
-                                    pages_gtk.remove(b);
-                                }
-                                page_entries.remove(i);
-                                tabs.remove(i);
-                                if active >= tabs.len() { active = tabs.len() - 1; }
-                                else if active > i { active -= 1; }
+                            // the tab may be gone since the event was sent
+                            if i < tabs.len() { close_tab!(i); }
+                        }
 
-                                // show new active, hide others
-                                for (j, entry) in page_entries.iter().enumerate() {
-                                    if let Some((ref b, _)) = entry {
-                                        if j == active { b.show_all(); } else { b.hide(); }
-                                    }
-                                }
-                                // wake if suspended
-                                wake_tab!(active);
+                        UserEvent::CloseTabIds(ids) => {
+                            for id in ids {
+                                if let Some(i) = tabs.iter().position(|t| t.id == id) { close_tab!(i); }
                             }
-                            toolbar_set_url(&toolbar_wv, &tabs[active].url);
-                            save_session(&tabs, active);
-                            sync_sidebar(&sidebar_wv, &tabs, active);
                         }
 
                         UserEvent::SwitchTab(i) => {
@@ -665,46 +2197,703 @@ fn main() -> wry::Result<()> {
                             if let Some(Some((ref b, _))) = page_entries.get(active) {
                                 b.show_all();
                             }
-                            toolbar_set_url(&toolbar_wv, &tabs[active].url);
+                            sync_toolbar!();
                             save_session(&tabs, active);
                             sync_sidebar(&sidebar_wv, &tabs, active);
                         }
 
-                        UserEvent::ShowHistory => {
-                            let idx = tabs.len();
-                            tabs.push(Tab { url: "vccat:history".into(), title: "Historia".into(),
-                                favicon: None, suspended: false });
-                            if let Some(Some((ref b, _))) = page_entries.get(active) { b.hide(); }
-                            let nb = gtk::Box::new(gtk::Orientation::Vertical, 0);
-                            nb.set_vexpand(true);
-                            pages_gtk.pack_start(&nb, true, true, 0);
-                            nb.show_all();
-                            let hist_html = history_page_html(&history);
-                            let ph = proxy.clone();
-                            let wv = WebViewBuilder::new_gtk(&nb)
-                                .with_html(&hist_html)
-                                .with_navigation_handler(move |url| {
-                                    if url.starts_with("http") {
-                                        let _ = ph.send_event(UserEvent::Navigate(url));
-                                        return false;
-                                    }
-                                    true
-                                })
-                                .build().unwrap();
-                            page_entries.push(Some((nb, wv)));
-                            active = idx;
-                            toolbar_set_url(&toolbar_wv, "vccat:history");
+                        UserEvent::ToggleBookmark => {
+                            let tab = &tabs[active];
+                            if star_state(&bookmarks, &tab.url).is_some() {
+                                let title = if tab.title.is_empty() { &tab.url } else { &tab.title };
+                                bookmarks.toggle(&tab.url, title);
+                                bookmarks::save(&bookmarks);
+                                sync_toolbar!();
+                            }
+                        }
+
+                        UserEvent::LoadFailed(idx, failed, error) => {
+                            // only a page HTTPS-only mode upgraded gets the interstitial
+                            let Some((http, back)) = tabs.get(idx).and_then(|t| t.upgrading.clone()) else { return };
+                            if failed != http && https::upgrade(&http).as_deref() != Some(failed.as_str()) { return; }
+                            let Some(Some((_, ref wv))) = page_entries.get(idx) else { return };
+                            // going back to the same site would only upgrade it again
+                            let same = https::host_of(&back) == https::host_of(&http);
+                            let back = if same || !back.starts_with("http") { settings.home_page.clone() } else { back };
+                            https_failures.insert(http.clone(), (error, back));
+                            load_internal(wv, &format!("vccat:https-only/{}", http));
+                        }
+
+                        UserEvent::FilterRules(json) => {
+                            // downloads show up in the list states
+                            for (i, tab) in tabs.iter().enumerate() {
+                                if tab.url != "vccat:filters" { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                    let _ = wv.evaluate_script(&format!("setLists({});",
+                                        serde_json::Value::String(filter_lists_html())));
+                                }
+                            }
+                            if std::mem::take(&mut filter_edited) { filter_reload_all = true; }
+                            if filter_compiling {
+                                filter_queued = Some(json);
+                            } else {
+                                filter_compiling = true;
+                                filter_base = json;
+                                let pf = proxy.clone();
+                                let rules = https::with_upgrade_rules(&allowlist::with_allowlist_rules(&filter_base));
+                                adblock::prepare_content_filter(rules, move || {
+                                    let _ = pf.send_event(UserEvent::ContentFilterReady);
+                                });
+                            }
+                        }
+
+                        UserEvent::ContentFilterReady => {
+                            // tabs built before the filter was ready; later ones get it in build_page_wv
+                            for (_, wv) in page_entries.iter().flatten() {
+                                adblock::apply_content_filter(wv);
+                                adblock::apply_scriptlets(wv);
+                                adblock::apply_cosmetic(wv);
+                            }
+                            filter_compiling = false;
+                            if !std::mem::replace(&mut filter_ready, true) { filter_reload_all = true; }
+                            // a compile that failed shows in the list state
+                            for (i, tab) in tabs.iter().enumerate() {
+                                if tab.url != "vccat:filters" { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                    let _ = wv.evaluate_script(&format!("setLists({});",
+                                        serde_json::Value::String(filter_lists_html())));
+                                }
+                            }
+                            if let Some(json) = filter_queued.take() {
+                                let _ = proxy.send_event(UserEvent::FilterRules(json));
+                                return;
+                            }
+                            for (i, tab) in tabs.iter().enumerate() {
+                                let site = allowlist::site_of(&tab.url);
+                                let web = tab.url.starts_with("http://") || tab.url.starts_with("https://");
+                                let reload = site.is_some_and(|s| filter_reload.iter().any(|r| allowlist::covers(r, &s)))
+                                    || (filter_reload_all && web);
+                                if !reload { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                    let _ = wv.evaluate_script("location.reload()");
+                                }
+                            }
+                            filter_reload.clear();
+                            filter_reload_all = false;
+                            for (i, url) in std::mem::take(&mut https_continue) {
+                                if tabs.get(i).map(|t| t.url.as_str()) != Some(&format!("vccat:https-only/{}", url)) { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) { let _ = wv.load_url(&url); }
+                            }
+                        }
+
+                        UserEvent::ResourceFailed(idx, kind, url) => {
+                            let Some(tab) = tabs.get(idx) else { return };
+                            if allowlist::is_allowlisted(&tab.url) { return; }
+                            let failure = blocklog::Failure { tab: idx, url, page_url: tab.url.clone(), kind };
+                            let _ = explainer.try_send(failure);
+                        }
+
+                        UserEvent::ResourceBlocked(idx, page_url, entry) => {
+                            // the tab went on to another page meanwhile
+                            let Some(tab) = tabs.get_mut(idx).filter(|t| t.url == page_url) else { return };
+                            if tab.blocked.len() >= blocklog::MAX_ENTRIES { tab.blocked.remove(0); }
+                            tab.blocked.push(entry);
+                            tab.blocked_count += 1;
+                            if idx == active { toolbar_set_shield(&toolbar_wv, &tabs[idx]); }
+                            sync_sidebar(&sidebar_wv, &tabs, active);
+                        }
+
+                        UserEvent::ElementsHidden(idx, n) => {
+                            let Some(tab) = tabs.get_mut(idx) else { return };
+                            tab.hidden = n;
+                            if idx == active { toolbar_set_shield(&toolbar_wv, &tabs[idx]); }
                             sync_sidebar(&sidebar_wv, &tabs, active);
                         }
 
+                        UserEvent::ToggleShield => {
+                            let url = tabs[active].url.clone();
+                            let Some(changed) = allowlist::toggle_allowlisted(&url, tabs[active].private) else { return };
+                            sync_toolbar!();
+                            filter_reload.extend(changed);
+                            // the current rules again, now with the changed allowlist
+                            let json = filter_queued.take().unwrap_or_else(|| filter_base.clone());
+                            let _ = proxy.send_event(UserEvent::FilterRules(json));
+                        }
+
+                        UserEvent::CosmeticRequest(idx, url) => {
+                            // a new document: its counts start over
+                            if let Some(tab) = tabs.get_mut(idx) {
+                                tab.blocked.clear();
+                                tab.blocked_count = 0;
+                                tab.hidden = 0;
+                                if idx == active { toolbar_set_shield(&toolbar_wv, &tabs[idx]); }
+                                sync_sidebar(&sidebar_wv, &tabs, active);
+                            }
+                            if allowlist::is_allowlisted(&url) { return; }
+                            if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                if let Some(js) = cosmetic::count_js(&url) {
+                                    let _ = wv.evaluate_script(&js);
+                                }
+                            }
+                        }
+
+                        UserEvent::LoadStripped(idx, url) => {
+                            if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                let _ = wv.load_url(&url);
+                            }
+                        }
+
+                        UserEvent::CopyUrl(text) => {
+                            let text = tracking::strip(text.trim()).unwrap_or(text);
+                            gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD).set_text(&text);
+                        }
+
+                        UserEvent::LocalFile(idx, url) => {
+                            // only a page already showing local files may open others
+                            if idx != active || !tabs[idx].url.starts_with("file://") { return; }
+                            let _ = proxy.send_event(UserEvent::Navigate(url));
+                        }
+
+                        UserEvent::RenderInternal => {
+                            for (uri, private, responder) in INTERNAL_REQUESTS.with(|q| q.take()) {
+                                let html = match internal::url_of(&uri) {
+                                    Some(url) if url.starts_with("vccat:") => internal_html!(url.as_str(), private),
+                                    Some(url) => files::render(&url).unwrap_or_default(),
+                                    None => String::new(),
+                                };
+                                let res = wry::http::Response::builder()
+                                    .header(wry::http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+                                    .body(html.into_bytes());
+                                if let Ok(res) = res { responder.respond(res); }
+                            }
+                        }
+
+                        UserEvent::PageCommand(idx, cmd) => {
+                            // the IPC handler only lets internal documents through; the tab
+                            // may have moved on since
+                            if idx >= tabs.len() || !tabs[idx].url.starts_with("vccat:") { return; }
+                            if let Some(url) = cmd.strip_prefix("go:") {
+                                if idx == active { let _ = proxy.send_event(UserEvent::Navigate(url.to_string())); }
+                            } else if let Some(json) = cmd.strip_prefix("imp:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let profiles = importer::discover_profiles();
+                                let Some(profile) = v["profile"].as_u64()
+                                    .and_then(|i| profiles.get(i as usize)).cloned() else { return };
+                                let (h, b) = (v["history"].as_bool().unwrap_or(false),
+                                              v["bookmarks"].as_bool().unwrap_or(false));
+                                let pi = proxy.clone();
+                                std::thread::spawn(move || {
+                                    let res = importer::read_profile(&profile, h, b);
+                                    let _ = pi.send_event(UserEvent::ImportDone(idx, profile.name, res));
+                                });
+                            } else if let Some(json) = cmd.strip_prefix("restore:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                if v["op"] == "open" {
+                                    let crashed = storage::load_crashed_session().unwrap_or_default();
+                                    for i in v["tabs"].as_array().into_iter().flatten().filter_map(|i| i.as_u64()) {
+                                        if let Some(url) = crashed.tabs.get(i as usize) {
+                                            let _ = proxy.send_event(UserEvent::OpenTab(url.clone()));
+                                        }
+                                    }
+                                } else if v["op"] != "discard" {
+                                    return;
+                                }
+                                storage::discard_crashed_session();
+                                // queued after the tabs above; by id, as they move the others
+                                let _ = proxy.send_event(UserEvent::CloseTabIds(vec![tabs[idx].id]));
+                            } else if let Some(json) = cmd.strip_prefix("sess:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let name = v["name"].as_str().unwrap_or("").trim().to_string();
+                                let mut saved = storage::load_named_sessions();
+                                let pos = saved.iter().position(|s| s.name == name);
+                                match (v["op"].as_str().unwrap_or(""), pos) {
+                                    ("save", _) if !name.is_empty() => {
+                                        let snap = storage::NamedSession::snapshot(&name,
+                                            tabs.iter().map(|t| (t.url.as_str(), t.title.as_str(), t.private)));
+                                        storage::put_named_session(&mut saved, snap);
+                                        storage::save_named_sessions(&saved);
+                                    }
+                                    ("delete", Some(i)) => {
+                                        saved.remove(i);
+                                        storage::save_named_sessions(&saved);
+                                    }
+                                    (op @ ("append" | "replace"), Some(i)) => {
+                                        if saved[i].tabs.is_empty() { return; }
+                                        for t in &saved[i].tabs {
+                                            let _ = proxy.send_event(UserEvent::OpenTab(t.url.clone()));
+                                        }
+                                        if op == "replace" {
+                                            // closed once the new tabs are open, by id: indices
+                                            // shift as tabs come and go meanwhile
+                                            let old = tabs.iter().filter(|t| !t.private).map(|t| t.id).collect();
+                                            let _ = proxy.send_event(UserEvent::CloseTabIds(old));
+                                        }
+                                        return;
+                                    }
+                                    (op @ ("export-json" | "export-urls"), Some(i)) => {
+                                        let json = op == "export-json";
+                                        let file = format!("{}.{}", name, if json { "json" } else { "txt" });
+                                        if let Some(path) = pick_file(window.gtk_window(), true, &file) {
+                                            let out = if json {
+                                                serde_json::to_string_pretty(&saved[i]).unwrap_or_default()
+                                            } else {
+                                                saved[i].url_list()
+                                            };
+                                            std::fs::write(path, out).ok();
+                                        }
+                                        return;
+                                    }
+                                    _ => return,
+                                }
+                                if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                    load_internal(wv, &tabs[idx].url);
+                                }
+                            } else if let Some(json) = cmd.strip_prefix("rl:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let id = v["id"].as_u64().unwrap_or(0);
+                                let mut items = reading::load();
+                                let Some(pos) = items.iter().position(|i| i.id == id) else { return };
+                                match v["op"].as_str().unwrap_or("") {
+                                    op @ ("open" | "offline") => {
+                                        let item = &mut items[pos];
+                                        item.read = true;
+                                        // without network the offline copy stands in for the page
+                                        let online = op == "open" && v["online"].as_bool().unwrap_or(true);
+                                        let url = if online || !item.offline { item.url.clone() }
+                                                  else { format!("vccat:reading/{}", item.id) };
+                                        reading::save(&items);
+                                        if idx == active { let _ = proxy.send_event(UserEvent::Navigate(url)); }
+                                        return;
+                                    }
+                                    "toggle-read" => items[pos].read = !items[pos].read,
+                                    "delete" => reading::remove(&mut items, id),
+                                    _ => return,
+                                }
+                                reading::save(&items);
+                                if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                    load_internal(wv, &tabs[idx].url);
+                                }
+                            } else if cmd == "https:continue" {
+                                // the url comes from the tab, not the message
+                                let Some(url) = tabs[idx].url.strip_prefix("vccat:https-only/").map(String::from) else { return };
+                                let Some(host) = https::host_of(&url) else { return };
+                                // private tabs leave nothing behind on disk
+                                if tabs[idx].private { https::add_session_exception(&host); } else { https::add_exception(&host); }
+                                // the page opens once the blocker stops upgrading it
+                                https_continue.push((idx, url));
+                                let json = filter_queued.take().unwrap_or_else(|| filter_base.clone());
+                                let _ = proxy.send_event(UserEvent::FilterRules(json));
+                            } else if let Some(json) = cmd.strip_prefix("set:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let status = match v["op"].as_str().unwrap_or("") {
+                                    "save" => match serde_json::from_value::<settings::Settings>(v["settings"].clone()) {
+                                        Ok(mut new) => match new.validate() {
+                                            Ok(()) => {
+                                                // the form doesn't carry where discovered engines came from
+                                                for e in &mut new.search_engines {
+                                                    e.source = settings.search_engines.iter()
+                                                        .find(|o| o.template == e.template)
+                                                        .and_then(|o| o.source.clone());
+                                                }
+                                                let _ = proxy.send_event(UserEvent::SettingsChanged(new));
+                                                return;
+                                            }
+                                            Err(e) => e,
+                                        },
+                                        Err(_) => "nieprawidłowa wartość liczbowa".to_string(),
+                                    },
+                                    "defaults" => {
+                                        let _ = proxy.send_event(UserEvent::SettingsChanged(settings::Settings::default()));
+                                        return;
+                                    }
+                                    "https-remove" => {
+                                        https::remove_exception(v["host"].as_str().unwrap_or(""));
+                                        let json = filter_queued.take().unwrap_or_else(|| filter_base.clone());
+                                        let _ = proxy.send_event(UserEvent::FilterRules(json));
+                                        if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                            settings_status = "usunięto wyjątek".into();
+                                            load_internal(wv, &tabs[idx].url);
+                                        }
+                                        return;
+                                    }
+                                    "passphrase" => match change_passphrase() {
+                                        Some(msg) => msg,
+                                        None => return,
+                                    },
+                                    _ => return,
+                                };
+                                if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                    let _ = wv.evaluate_script(&format!("setStatus({});",
+                                        serde_json::Value::String(status)));
+                                }
+                            } else if let Some(json) = cmd.strip_prefix("flt:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let Some(Some((_, ref wv))) = page_entries.get(idx) else { return };
+                                let text = |k: &str| v[k].as_str().unwrap_or("").to_string();
+                                let status = match v["op"].as_str().unwrap_or("") {
+                                    "check" => {
+                                        let _ = wv.evaluate_script(&format!("showCheck({});", filter_check_json(&text("text"))));
+                                        return;
+                                    }
+                                    "test" => {
+                                        let r = filter_test_json(&text("url"), &text("page"), &text("type"), &text("text"));
+                                        let _ = wv.evaluate_script(&format!("showTest({});", r));
+                                        return;
+                                    }
+                                    "save" => match subscriptions::save_user_rules(&text("text")) {
+                                        Ok(()) => {
+                                            filter_edited = true;
+                                            filters_refresh.send(subscriptions::Refresh::Apply).ok();
+                                            "zapisano · po skompilowaniu reguł otwarte strony zostaną przeładowane".to_string()
+                                        }
+                                        Err(e) => format!("nie udało się zapisać reguł: {}", e),
+                                    },
+                                    "toggle" => {
+                                        subscriptions::set_enabled(&text("id"), v["on"].as_bool().unwrap_or(false));
+                                        filter_edited = true;
+                                        filters_refresh.send(subscriptions::Refresh::Apply).ok();
+                                        "zmieniono listy · reguły są kompilowane".to_string()
+                                    }
+                                    "add" => match subscriptions::add(&text("url"), &text("title")) {
+                                        Ok(()) => {
+                                            filters_refresh.send(subscriptions::Refresh::Apply).ok();
+                                            "dodano listę · jest pobierana w tle".to_string()
+                                        }
+                                        Err(e) => e,
+                                    },
+                                    "remove" => {
+                                        subscriptions::remove(&text("id"));
+                                        filter_edited = true;
+                                        filters_refresh.send(subscriptions::Refresh::Apply).ok();
+                                        "usunięto listę".to_string()
+                                    }
+                                    "refresh" => {
+                                        filters_refresh.send(subscriptions::Refresh::Check).ok();
+                                        "listy filtrów są sprawdzane w tle".to_string()
+                                    }
+                                    _ => return,
+                                };
+                                let _ = wv.evaluate_script(&format!("setLists({});setStatus({});",
+                                    serde_json::Value::String(filter_lists_html()), serde_json::Value::String(status)));
+                            } else if let Some(json) = cmd.strip_prefix("sync:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let text = |k: &str| v[k].as_str().unwrap_or("").trim().to_string();
+                                match v["op"].as_str().unwrap_or("") {
+                                    "pick" => match pick_folder(window.gtk_window()) {
+                                        Some(dir) => sync_config.dir = Some(dir),
+                                        None => return,
+                                    },
+                                    "save" | "now" => {
+                                        sync_config.dir = Some(text("dir")).filter(|d| !d.is_empty()).map(Into::into);
+                                        if !text("name").is_empty() { sync_config.device_name = text("name"); }
+                                    }
+                                    "off" => {
+                                        sync_config.dir = None;
+                                        sync_status.clear();
+                                        remote_tabs.clear();
+                                    }
+                                    _ => return,
+                                }
+                                sync::save_config(&sync_config);
+                                if v["op"] == "now" {
+                                    let _ = proxy.send_event(UserEvent::SyncTick);
+                                }
+                                if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                    load_internal(wv, &tabs[idx].url);
+                                }
+                            } else if let Some(json) = cmd.strip_prefix("bm:") {
+                                let Ok(v) = serde_json::from_str::<serde_json::Value>(json) else { return };
+                                let id = v["id"].as_u64().unwrap_or(0);
+                                let text = |k: &str| v[k].as_str().unwrap_or("").trim().to_string();
+                                match v["op"].as_str().unwrap_or("") {
+                                    "delete" => bookmarks.remove(id),
+                                    "edit" => if let Some(m) = bookmarks.get_mut(id) {
+                                        m.title = text("title");
+                                        if !text("url").is_empty() { m.url = text("url"); }
+                                        m.tags = bookmarks::parse_tags(&text("tags"));
+                                        m.keyword = Some(text("keyword")).filter(|k| !k.is_empty());
+                                        m.folder = v["folder"].as_u64().unwrap_or(m.folder);
+                                        m.modified = storage::now();
+                                    },
+                                    "add-folder" => {
+                                        let parent = v["parent"].as_u64().unwrap_or(bookmarks::ROOT);
+                                        bookmarks.add_folder(&text("name"), parent);
+                                    }
+                                    "rename-folder" => bookmarks.rename_folder(id, &text("name")),
+                                    "delete-folder" => bookmarks.remove_folder(id),
+                                    "import" => {
+                                        if let Some(path) = pick_file(window.gtk_window(), false, "") {
+                                            if let Ok(html) = std::fs::read_to_string(&path) {
+                                                bookmarks::import_netscape(&mut bookmarks, &html, bookmarks::ROOT);
+                                            }
+                                        }
+                                    }
+                                    "export" => {
+                                        if let Some(path) = pick_file(window.gtk_window(), true, "bookmarks.html") {
+                                            std::fs::write(path, bookmarks::export_netscape(&bookmarks)).ok();
+                                        }
+                                        return;
+                                    }
+                                    _ => return,
+                                }
+                                bookmarks::save(&bookmarks);
+                                if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                    load_internal(wv, &tabs[idx].url);
+                                }
+                                sync_toolbar!();
+                            }
+                        }
+
+                        UserEvent::SaveForLater => {
+                            if star_state(&bookmarks, &tabs[active].url).is_none() { return; }
+                            if let Some(Some((_, ref wv))) = page_entries.get(active) {
+                                pending_capture = Some(active);
+                                let _ = wv.evaluate_script(reading::capture_js());
+                            }
+                        }
+
+                        UserEvent::ArticleCaptured(idx, json) => {
+                            if pending_capture.take() != Some(idx) { return; }
+                            let Ok(mut article) = serde_json::from_str::<reading::Article>(&json) else { return };
+                            // saved under the tab's url, not whatever the page claims
+                            let Some(tab) = tabs.get(idx) else { return };
+                            article.url = tab.url.clone();
+                            let pa = proxy.clone();
+                            std::thread::spawn(move || {
+                                let html = reading::build_offline(&article);
+                                let _ = pa.send_event(UserEvent::ArticleReady(article, html));
+                            });
+                        }
+
+                        UserEvent::ArticleReady(article, html) => {
+                            let mut items = reading::load();
+                            reading::add(&mut items, &article, &html);
+                            reading::save(&items);
+                            for (i, tab) in tabs.iter().enumerate() {
+                                if tab.url != "vccat:reading-list" { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                    load_internal(wv, &tab.url);
+                                }
+                            }
+                        }
+
+                        UserEvent::OmniboxInput(text, complete) => {
+                            let now = storage::now();
+                            omni_index.update(&history, now);
+                            let sources = omnibox::Sources {
+                                history: &omni_index,
+                                bookmarks: &bookmarks,
+                                tabs: tabs.iter().enumerate().filter(|(i, _)| *i != active)
+                                    .map(|(i, t)| (i, t.url.as_str(), t.title.as_str())).collect(),
+                                engines: &settings.search_engines,
+                                default_engine: &settings.default_engine,
+                            };
+                            omni = omnibox::suggest(&text, &sources);
+                            omni_typed = text.clone();
+                            omni_sel = None;
+                            if omni.items.is_empty() {
+                                omni_gtk.hide();
+                                return;
+                            }
+                            omni_gtk.set_size_request(-1, omni.items.len() as i32 * OMNI_ROW + 10);
+                            omni_gtk.show_all();
+                            let _ = omni_wv.evaluate_script(&format!("render({},-1);",
+                                serde_json::to_string(&omni.items).unwrap_or_default()));
+                            if let (true, Some(full)) = (complete, &omni.completion) {
+                                let _ = toolbar_wv.evaluate_script(&format!("setCompletion({},{});",
+                                    serde_json::Value::String(text), serde_json::Value::String(full.clone())));
+                            }
+                        }
+
+                        UserEvent::OmniboxMove(d) => {
+                            let n = omni.items.len() as i32;
+                            if n == 0 { return; }
+                            // -1 stands for the text as typed
+                            let cur = omni_sel.map_or(-1, |i| i as i32);
+                            let next = (cur + 1 + d).rem_euclid(n + 1) - 1;
+                            omni_sel = usize::try_from(next).ok();
+                            let _ = omni_wv.evaluate_script(&format!("select({});", next));
+                            let text = omni_sel.map_or(omni_typed.clone(), |i| omni.items[i].text.clone());
+                            let _ = toolbar_wv.evaluate_script(&format!("setUrlText({});",
+                                serde_json::Value::String(text)));
+                        }
+
+                        UserEvent::OmniboxPick(i) => {
+                            let Some(item) = omni.items.get(i).cloned() else { return };
+                            if item.kind == omnibox::Kind::Keyword && item.text.ends_with(' ') {
+                                // keyword alone: leave it in the bar for the query
+                                let _ = toolbar_wv.evaluate_script(&format!(
+                                    "setUrlText({});document.getElementById('url').focus();",
+                                    serde_json::Value::String(item.text)));
+                                return;
+                            }
+                            omni_sel = Some(i);
+                            let _ = proxy.send_event(match item.kind {
+                                omnibox::Kind::Search => UserEvent::Navigate(settings.search(&item.text)),
+                                _ => UserEvent::AddressInput(item.text),
+                            });
+                        }
+
+                        UserEvent::OmniboxClose => {
+                            omni_gtk.hide();
+                        }
+
+                        UserEvent::SearchEngineOffered(idx, src) => {
+                            if idx >= tabs.len() || tabs[idx].private { return; }
+                            tabs[idx].search_offer = Some(src);
+                            if idx == active { sync_toolbar!(); }
+                        }
+
+                        UserEvent::AddSearchEngine => {
+                            let Some(src) = tabs[active].search_offer.clone() else { return };
+                            let pf = proxy.clone();
+                            std::thread::spawn(move || {
+                                let _ = pf.send_event(UserEvent::SearchEngineFetched(search::fetch_opensearch(&src)));
+                            });
+                        }
+
+                        UserEvent::SearchEngineFetched(res) => {
+                            let mut engine = match res {
+                                Ok(e) => e,
+                                Err(err) => {
+                                    toolbar_note(&toolbar_wv, &format!("nie dodano wyszukiwarki: {}", err), false);
+                                    return;
+                                }
+                            };
+                            let base = engine.name.clone();
+                            let mut n = 2;
+                            while settings.search_engines.iter().any(|e| e.name == engine.name) {
+                                engine.name = format!("{} ({})", base, n);
+                                n += 1;
+                            }
+                            let engine_name = engine.name.clone();
+                            let mut new = settings.clone();
+                            new.search_engines.push(engine);
+                            match new.validate() {
+                                Ok(()) => {
+                                    toolbar_note(&toolbar_wv, &format!("dodano wyszukiwarkę {}", engine_name), true);
+                                    let _ = proxy.send_event(UserEvent::SettingsChanged(new));
+                                }
+                                Err(err) => toolbar_note(&toolbar_wv, &format!("nie dodano wyszukiwarki: {}", err), false),
+                            }
+                        }
+
+                        UserEvent::SettingsChanged(new) => {
+                            // search, home page and the suspend limit are read where
+                            // they are used; filter age and update delay apply at the
+                            // next start
+                            if (new.window_width, new.window_height) != (settings.window_width, settings.window_height) {
+                                window.set_inner_size(tao::dpi::LogicalSize::new(new.window_width, new.window_height));
+                            }
+                            if new.https_only != settings.https_only {
+                                https::set_enabled(new.https_only);
+                                let json = filter_queued.take().unwrap_or_else(|| filter_base.clone());
+                                let _ = proxy.send_event(UserEvent::FilterRules(json));
+                            }
+                            subscriptions::set_default_max_age(new.filter_max_age);
+                            tracking::configure(new.strip_tracking, &new.tracking_rules, &new.tracking_bypass);
+                            settings = new;
+                            settings::save(&settings);
+                            sync_toolbar!();
+                            for (i, tab) in tabs.iter().enumerate() {
+                                if tab.url != "vccat:settings" { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                    settings_status = "zapisano".into();
+                                    load_internal(wv, &tab.url);
+                                }
+                            }
+                        }
+
+                        UserEvent::SyncTick => {
+                            if sync_config.dir.is_none() || sync_running { return; }
+                            let job = sync::Job {
+                                config: sync_config.clone(), state: sync_state.clone(), history: history.clone(),
+                                bookmarks: bookmarks.clone(), settings: settings.clone(),
+                                open_tabs: synced_tabs(&tabs), reply: None,
+                            };
+                            sync_running = sync_jobs.send(job).is_ok();
+                        }
+
+                        UserEvent::SyncDone(res) => {
+                            sync_running = false;
+                            let before: Vec<_> = remote_tabs.iter().map(|r| (&r.device, &r.tabs)).collect();
+                            let mut devices_changed = false;
+                            match res {
+                                Ok(mut r) => {
+                                    let mut synced = settings.clone();
+                                    apply_sync(&mut r, &mut sync_state, &mut history, &mut bookmarks, &mut synced);
+                                    if r.bookmarks_changed > 0 { sync_toolbar!(); }
+                                    if r.settings_changed {
+                                        let _ = proxy.send_event(UserEvent::SettingsChanged(synced));
+                                    }
+                                    // turned off while the round ran: what it brought in is
+                                    // kept, but there is nothing to show
+                                    if sync_config.dir.is_none() { return; }
+                                    sync_status = format!("zsynchronizowano: wysłano {} zmian, przyjęto {} wizyt i {} zakładek",
+                                                          r.published, r.history_added, r.bookmarks_changed);
+                                    devices_changed = before != r.remote_tabs.iter()
+                                        .map(|r| (&r.device, &r.tabs)).collect::<Vec<_>>();
+                                    remote_tabs = r.remote_tabs;
+                                }
+                                Err(err) => sync_status = format!("błąd synchronizacji — {}", err),
+                            }
+                            // open sync pages get the new status; the device list is
+                            // only re-rendered when it changed, to keep form input
+                            for (i, tab) in tabs.iter().enumerate() {
+                                if tab.url != "vccat:sync" { continue; }
+                                if let Some(Some((_, ref wv))) = page_entries.get(i) {
+                                    if devices_changed {
+                                        load_internal(wv, &tab.url);
+                                    } else {
+                                        let _ = wv.evaluate_script(&format!("setStatus({});",
+                                            serde_json::Value::String(sync_status.clone())));
+                                    }
+                                }
+                            }
+                        }
+
+                        UserEvent::ImportDone(idx, name, res) => {
+                            let status = match res {
+                                Ok(r) => {
+                                    let visits = importer::merge_history(&mut history, r.history);
+                                    let dropped = storage::trim_history(&mut history);
+                                    let marks = importer::merge_bookmarks(&mut bookmarks, &name, &r.bookmarks);
+                                    if visits > 0 { storage::save_history(&history); }
+                                    if marks > 0 { bookmarks::save(&bookmarks); }
+                                    let mut status = format!("{}: dodano {} wizyt i {} zakładek", name, visits, marks);
+                                    if dropped > 0 {
+                                        status += &format!(" · pominięto {} najstarszych wizyt (historia mieści {})",
+                                                           dropped, storage::HISTORY_LIMIT);
+                                    }
+                                    status
+                                }
+                                Err(err) => format!("{}: błąd importu — {}", name, err),
+                            };
+                            if let Some(Some((_, ref wv))) = page_entries.get(idx) {
+                                let _ = wv.evaluate_script(&format!("setStatus({});",
+                                    serde_json::Value::String(status)));
+                            }
+                        }
+
                         UserEvent::PageUrlChanged(idx, url) => {
                             // filter out data: URLs (home page internal)
                             if url.starts_with("data:") { return; }
+                            // pushState can put tracking parameters back; keep them out of history
+                            let url = tracking::strip(&url).unwrap_or(url);
                             if idx < tabs.len() {
+                                let tab = &mut tabs[idx];
+                                if https::upgrade(&url).is_some() {
+                                    tab.upgrading = Some((url.clone(), tab.url.clone()));
+                                } else if tab.upgrading.as_ref().and_then(|u| https::upgrade(&u.0)).as_deref() != Some(url.as_str()) {
+                                    tab.upgrading = None;
+                                }
                                 tabs[idx].url = url.clone();
-                                storage::append_history(&mut history, &url, &tabs[idx].title);
+                                tabs[idx].search_offer = None;
+                                if !tabs[idx].private {
+                                    storage::append_history(&mut history, &url, &tabs[idx].title);
+                                }
                             }
-                            if idx == active { toolbar_set_url(&toolbar_wv, &url); }
+                            if idx == active { sync_toolbar!(); }
                             save_session(&tabs, active);
                             sync_sidebar(&sidebar_wv, &tabs, active);
                         }
@@ -716,7 +2905,7 @@ fn main() -> wry::Result<()> {
 
                         UserEvent::PageTitleChanged(idx, title) => {
                             if idx < tabs.len() {
-                                if idx == active {
+                                if idx == active && !tabs[idx].private {
                                     storage::append_history(&mut history, &tabs[idx].url, &title);
                                 }
                                 tabs[idx].title = title;
@@ -746,6 +2935,7 @@ fn main() -> wry::Result<()> {
         event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Wait;
             if let Event::WindowEvent { event: WindowEvent::CloseRequested, .. } = event {
+                storage::unlock_profile();
                 *control_flow = ControlFlow::Exit;
             }
         });
@@ -0,0 +1,311 @@
+//! Omnibox: suggestions for the address bar from history (ranked by
+//! frecency), bookmarks, open tabs and search engine keywords, plus the
+//! inline completion of what is being typed. History is folded per url and
+//! lowercased once into a `HistoryIndex`, not on every keystroke.
+use std::collections::HashMap;
+use serde::Serialize;
+use crate::bookmarks::Bookmarks;
+use crate::search::SearchEngine;
+use crate::storage::HistoryEntry;
+
+const MAX_SUGGESTIONS: usize = 8;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Tab,
+    Bookmark,
+    History,
+    Keyword,
+    Search,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Suggestion {
+    pub kind: Kind,
+    pub title: String,
+    /// Text put into the address bar when the row is chosen.
+    pub text: String,
+    /// Index of the open tab for `Kind::Tab`.
+    pub tab: Option<usize>,
+    #[serde(skip)]
+    score: f64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Suggestions {
+    pub items: Vec<Suggestion>,
+    /// Full address bar text after inline completion, if any.
+    pub completion: Option<String>,
+}
+
+/// Url without scheme and `www.`, the form people type.
+fn bare(url: &str) -> &str {
+    let u = url.split_once("://").map_or(url, |(_, rest)| rest);
+    u.strip_prefix("www.").unwrap_or(u)
+}
+
+/// Visit weight by age, in the spirit of Firefox's frecency buckets.
+fn recency_weight(age_secs: u64) -> f64 {
+    match age_secs / 86_400 {
+        0..=3 => 100.0,
+        4..=13 => 70.0,
+        14..=30 => 50.0,
+        31..=90 => 30.0,
+        _ => 10.0,
+    }
+}
+
+fn matches(terms: &[String], haystacks: &[&str]) -> bool {
+    terms.iter().all(|t| haystacks.iter().any(|h| h.to_lowercase().contains(t.as_str())))
+}
+
+/// A visited url with its visits summed up.
+struct Visited {
+    url: String,
+    title: String,
+    frecency: f64,
+    /// Url and title, lowercased, for matching.
+    lower: String,
+    /// `bare` url, lowercased, for prefixes.
+    bare: String,
+}
+
+/// History folded per url with its frecency, ready to match typed text
+/// against.
+#[derive(Default)]
+pub struct HistoryIndex {
+    /// Length, oldest and newest visit of the history it was built from,
+    /// and the hour: frecency moves with time.
+    key: (usize, u64, u64, u64),
+    visited: Vec<Visited>,
+    by_url: HashMap<String, usize>,
+}
+
+impl HistoryIndex {
+    /// Builds the index again if the history or the hour changed since.
+    pub fn update(&mut self, history: &[HistoryEntry], now: u64) {
+        let key = (history.len(), history.first().map_or(0, |h| h.timestamp),
+                   history.last().map_or(0, |h| h.timestamp), now / 3600);
+        if key == self.key { return; }
+        let mut index = HistoryIndex { key, ..Default::default() };
+        for h in history {
+            let i = *index.by_url.entry(h.url.clone()).or_insert_with(|| {
+                index.visited.push(Visited { url: h.url.clone(), title: String::new(), frecency: 0.0,
+                                             lower: String::new(), bare: bare(&h.url).to_lowercase() });
+                index.visited.len() - 1
+            });
+            let v = &mut index.visited[i];
+            v.frecency += recency_weight(now.saturating_sub(h.timestamp));
+            if !h.title.is_empty() { v.title = h.title.clone(); }
+        }
+        for v in &mut index.visited {
+            v.lower = format!("{}\n{}", v.url, v.title).to_lowercase();
+        }
+        *self = index;
+    }
+
+    fn frecency(&self, url: &str) -> f64 {
+        self.by_url.get(url).map_or(0.0, |&i| self.visited[i].frecency)
+    }
+}
+
+pub struct Sources<'a> {
+    pub history: &'a HistoryIndex,
+    pub bookmarks: &'a Bookmarks,
+    /// (index, url, title) of tabs other than the active one.
+    pub tabs: Vec<(usize, &'a str, &'a str)>,
+    pub engines: &'a [SearchEngine],
+    pub default_engine: &'a str,
+}
+
+pub fn suggest(input: &str, src: &Sources) -> Suggestions {
+    let query = input.trim();
+    if query.is_empty() { return Suggestions::default(); }
+    let lower = query.to_lowercase();
+    let terms: Vec<String> = lower.split_whitespace().map(String::from).collect();
+    let mut out: Vec<Suggestion> = Vec::new();
+
+    // keywords: `w` offers Wikipedia, `w rust` searches it
+    let (first, rest) = query.split_once(' ').map_or((query, ""), |(f, r)| (f, r.trim()));
+    for e in src.engines {
+        let Some(kw) = &e.keyword else { continue };
+        if kw == first && !rest.is_empty() {
+            out.push(Suggestion { kind: Kind::Keyword, title: format!("Szukaj w {}: {}", e.name, rest),
+                                  text: query.into(), tab: None, score: 1e6 });
+        } else if rest.is_empty() && kw.starts_with(first) {
+            out.push(Suggestion { kind: Kind::Keyword, title: format!("{} — {}", kw, e.name),
+                                  text: format!("{} ", kw), tab: None, score: 50.0 });
+        }
+    }
+
+    for (i, url, title) in &src.tabs {
+        if matches(&terms, &[url, title]) {
+            out.push(Suggestion { kind: Kind::Tab, title: title.to_string(), text: url.to_string(),
+                                  tab: Some(*i), score: 5000.0 });
+        }
+    }
+
+    for b in &src.bookmarks.items {
+        let tags = b.tags.join(" ");
+        if !matches(&terms, &[&b.url, &b.title, &tags]) { continue; }
+        out.push(Suggestion { kind: Kind::Bookmark, title: b.title.clone(), text: b.url.clone(),
+                              tab: None, score: 1000.0 + src.history.frecency(&b.url) });
+    }
+
+    for v in &src.history.visited {
+        if !terms.iter().all(|t| v.lower.contains(t.as_str())) { continue; }
+        let prefix = if v.bare.starts_with(&lower) { 2.0 } else { 1.0 };
+        out.push(Suggestion { kind: Kind::History, title: v.title.clone(), text: v.url.clone(),
+                              tab: None, score: v.frecency * prefix });
+    }
+
+    out.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.text.cmp(&b.text)));
+    // one row per url; the tab or bookmark row wins over history
+    let mut seen = std::collections::HashSet::new();
+    out.retain(|s| s.kind == Kind::Keyword || seen.insert(s.text.clone()));
+    out.truncate(MAX_SUGGESTIONS - 1);
+
+    if !out.iter().any(|s| s.kind == Kind::Keyword && s.score >= 1e6) {
+        out.push(Suggestion { kind: Kind::Search, title: format!("Szukaj w {}: {}", src.default_engine, query),
+                              text: query.into(), tab: None, score: 0.0 });
+    }
+
+    Suggestions { completion: complete(query, src.history, src.bookmarks), items: out }
+}
+
+/// Completes typed text to the best-known site: `git` → `github.com/`, or the
+/// whole url once the typing goes past the host.
+fn complete(query: &str, history: &HistoryIndex, bookmarks: &Bookmarks) -> Option<String> {
+    if query.contains(char::is_whitespace) { return None; }
+    let lower = query.to_lowercase();
+    let mut best: Option<(f64, String)> = None;
+    let candidates = history.visited.iter().map(|v| (v.url.as_str(), v.bare.starts_with(&lower), v.frecency))
+        .chain(bookmarks.items.iter().map(|b| (b.url.as_str(), bare(&b.url).to_lowercase().starts_with(&lower), 1000.0)));
+    for (url, prefixed, score) in candidates {
+        if !prefixed { continue; }
+        let b = bare(url);
+        let host_end = b.find('/').map_or(b.len(), |i| i + 1);
+        let text = if lower.len() < host_end { &b[..host_end] } else { b };
+        if best.as_ref().is_none_or(|(s, _)| score > *s) {
+            best = Some((score, text.to_string()));
+        }
+    }
+    // keep the user's own casing for the typed part
+    let (_, text) = best?;
+    let tail = text.get(query.len()..).filter(|t| !t.is_empty())?;
+    Some(format!("{}{}", query, tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86_400;
+    const NOW: u64 = 1000 * DAY;
+
+    fn visit(url: &str, title: &str, days_ago: u64) -> HistoryEntry {
+        HistoryEntry { url: url.into(), title: title.into(), timestamp: NOW - days_ago * DAY }
+    }
+
+    fn engines() -> Vec<SearchEngine> {
+        vec![SearchEngine { name: "Wikipedia".into(), template: "https://w.example/?q={searchTerms}".into(),
+                            keyword: Some("w".into()), source: None }]
+    }
+
+    fn index(history: &[HistoryEntry]) -> HistoryIndex {
+        let mut index = HistoryIndex::default();
+        index.update(history, NOW);
+        index
+    }
+
+    #[test]
+    fn frecency_sums_visits_by_age() {
+        let i = index(&[visit("https://a.example/", "", 1), visit("https://a.example/", "A", 2),
+                        visit("https://b.example/", "B", 200), visit("https://c.example/", "C", 20)]);
+        assert_eq!(i.frecency("https://a.example/"), 200.0);
+        assert_eq!(i.frecency("https://b.example/"), 10.0);
+        assert_eq!(i.frecency("https://c.example/"), 50.0);
+        assert_eq!(i.frecency("https://d.example/"), 0.0);
+        // the last non-empty title is kept
+        assert_eq!(i.visited[i.by_url["https://a.example/"]].title, "A");
+    }
+
+    #[test]
+    fn index_rebuilds_only_on_change() {
+        let mut history = vec![visit("https://a.example/", "A", 1)];
+        let mut i = index(&history);
+        i.visited[0].frecency = -1.0;
+        i.update(&history, NOW + 60);
+        assert_eq!(i.visited[0].frecency, -1.0);
+        history.push(visit("https://b.example/", "B", 0));
+        i.update(&history, NOW + 60);
+        assert_eq!((i.visited.len(), i.frecency("https://a.example/")), (2, 100.0));
+        // an hour later the ages are counted again
+        i.visited[0].frecency = -1.0;
+        i.update(&history, NOW + 3600);
+        assert_eq!(i.frecency("https://a.example/"), 100.0);
+    }
+
+    #[test]
+    fn suggestions_rank_tabs_bookmarks_history() {
+        let history = index(&[visit("https://rust-lang.org/learn", "Learn Rust", 1),
+                              visit("https://docs.rs/serde", "serde - Rust", 1),
+                              visit("https://docs.rs/serde", "serde - Rust", 2),
+                              visit("https://old.example/rust", "Old rust", 300),
+                              visit("https://python.org/", "Python", 0)]);
+        let mut bookmarks = Bookmarks::default();
+        let id = bookmarks.add("https://doc.rust-lang.org/book/", "The Book", crate::bookmarks::ROOT);
+        bookmarks.get_mut(id).unwrap().tags = vec!["rust".into()];
+        let engines = engines();
+        let src = Sources { history: &history, bookmarks: &bookmarks,
+                            tabs: vec![(3, "https://docs.rs/serde", "serde - Rust")],
+                            engines: &engines, default_engine: "DuckDuckGo" };
+        let s = suggest("Rust", &src);
+        let rows: Vec<(Kind, &str)> = s.items.iter().map(|s| (s.kind.clone(), s.text.as_str())).collect();
+        assert_eq!(rows, vec![
+            (Kind::Tab, "https://docs.rs/serde"),
+            (Kind::Bookmark, "https://doc.rust-lang.org/book/"),
+            (Kind::History, "https://rust-lang.org/learn"),
+            (Kind::History, "https://old.example/rust"),
+            (Kind::Search, "Rust"),
+        ]);
+        assert_eq!(s.items[0].tab, Some(3));
+        // every word has to match somewhere
+        let s = suggest("serde python", &src);
+        assert_eq!(s.items.len(), 1);
+        assert_eq!(s.items[0].kind, Kind::Search);
+        assert!(suggest("  ", &src).items.is_empty());
+    }
+
+    #[test]
+    fn keyword_rows() {
+        let history = HistoryIndex::default();
+        let bookmarks = Bookmarks::default();
+        let engines = engines();
+        let src = Sources { history: &history, bookmarks: &bookmarks, tabs: vec![],
+                            engines: &engines, default_engine: "DuckDuckGo" };
+        let s = suggest("w", &src);
+        assert_eq!((s.items[0].kind.clone(), s.items[0].text.as_str()), (Kind::Keyword, "w "));
+        assert_eq!(s.items.last().unwrap().kind, Kind::Search);
+        // with a query the keyword search replaces the default one
+        let s = suggest("w borrow checker", &src);
+        assert_eq!(s.items.len(), 1);
+        assert_eq!(s.items[0].title, "Szukaj w Wikipedia: borrow checker");
+    }
+
+    #[test]
+    fn completes_host_then_url() {
+        let history = index(&[visit("https://www.github.com/rust-lang/rust", "", 1),
+                              visit("https://gitlab.com/", "", 100)]);
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.add("https://docs.rs/", "docs", crate::bookmarks::ROOT);
+        assert_eq!(complete("git", &history, &bookmarks).as_deref(), Some("github.com/"));
+        assert_eq!(complete("GitL", &history, &bookmarks).as_deref(), Some("GitLab.com/"));
+        assert_eq!(complete("github.com/r", &history, &bookmarks).as_deref(), Some("github.com/rust-lang/rust"));
+        assert_eq!(complete("do", &history, &bookmarks).as_deref(), Some("docs.rs/"));
+        assert_eq!(complete("docs.rs/", &history, &bookmarks), None);
+        assert_eq!(complete("git hub", &history, &bookmarks), None);
+        assert_eq!(complete("x", &history, &bookmarks), None);
+    }
+}
@@ -0,0 +1,355 @@
+//! Reading list: articles saved for later, each with an offline copy.
+//! The page extracts the article itself (see `capture_js`); its markup is
+//! cleaned again here, as the page may have sent anything, and images are
+//! downloaded and inlined, so the copy needs no network at all.
+use std::fs;
+use std::path::PathBuf;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Limits for inlined images; larger or further ones keep their url.
+const MAX_IMAGES: usize = 40;
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Tags kept in offline copies; other tags go, but their text stays.
+const KEEP_TAGS: &[&str] = &[
+    "p", "br", "hr", "h1", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "li", "dl", "dt", "dd",
+    "blockquote", "pre", "code", "em", "strong", "b", "i", "u", "s", "sub", "sup", "small", "mark",
+    "a", "img", "figure", "figcaption", "table", "thead", "tbody", "tfoot", "tr", "td", "th",
+    "caption", "span", "div", "section", "article", "abbr", "cite", "q", "time", "del", "ins",
+    "kbd", "samp", "var",
+];
+
+/// Tags dropped along with everything inside them.
+const DROP_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "iframe", "object", "embed", "svg", "math", "form",
+    "button", "select", "textarea", "canvas", "video", "audio", "head", "title",
+];
+
+/// Of `DROP_TAGS`, those whose content is raw text up to the end tag.
+const RAW_TEXT: &[&str] = &["script", "style", "noscript", "textarea", "title"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Item {
+    pub id: u64,
+    pub url: String,
+    pub title: String,
+    pub added: u64,
+    pub read: bool,
+    /// Whether reading/<id>.html holds an offline copy.
+    pub offline: bool,
+}
+
+/// Article as extracted by `capture_js`; image sources in `html` are
+/// replaced with `vccat-img:<n>` placeholders indexing `images`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Article {
+    /// The tab's url, filled in by the browser rather than the page.
+    #[serde(default)]
+    pub url: String,
+    pub title: String,
+    pub html: String,
+    pub images: Vec<String>,
+}
+
+pub fn reading_dir() -> PathBuf {
+    let d = crate::storage::data_dir().join("reading");
+    fs::create_dir_all(&d).ok();
+    d
+}
+
+pub fn load() -> Vec<Item> {
+    crate::storage::read_protected("reading-list.json")
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(items: &[Item]) {
+    if let Ok(s) = serde_json::to_string_pretty(items) {
+        crate::storage::write_protected("reading-list.json", &s);
+    }
+}
+
+pub fn offline_copy(id: u64) -> Option<String> {
+    crate::storage::read_protected(&format!("reading/{}.html", id))
+}
+
+pub fn remove(items: &mut Vec<Item>, id: u64) {
+    items.retain(|i| i.id != id);
+    fs::remove_file(reading_dir().join(format!("{}.html", id))).ok();
+}
+
+fn fetch_data_uri(client: &reqwest::blocking::Client, url: &str) -> Option<String> {
+    if url.starts_with("data:") { return Some(url.to_string()); }
+    let resp = client.get(url).send().ok()?.error_for_status().ok()?;
+    let mime = resp.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|m| m.starts_with("image/"))?
+        .to_string();
+    if resp.content_length().is_some_and(|l| l as usize > MAX_IMAGE_BYTES) { return None; }
+    let bytes = resp.bytes().ok()?;
+    if bytes.len() > MAX_IMAGE_BYTES { return None; }
+    Some(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(&bytes)))
+}
+
+/// A start or end tag as the sanitizer reads it.
+struct Tag<'a> {
+    name: String,
+    end: bool,
+    closed: bool,
+    attrs: Vec<(String, &'a str)>,
+}
+
+/// Reads the tag at the start of `s` (which begins with `<`), returning it
+/// and the length it took; None when `<` doesn't start a tag.
+fn read_tag(s: &str) -> Option<(Tag<'_>, usize)> {
+    let b = s.as_bytes();
+    let mut i = 1;
+    let end = b.get(i) == Some(&b'/');
+    if end { i += 1; }
+    if !b.get(i).is_some_and(u8::is_ascii_alphabetic) { return None; }
+    let start = i;
+    while b.get(i).is_some_and(|c| c.is_ascii_alphanumeric()) { i += 1; }
+    let name = s[start..i].to_ascii_lowercase();
+    let mut tag = Tag { name, end, closed: false, attrs: Vec::new() };
+    loop {
+        while b.get(i).is_some_and(|c| c.is_ascii_whitespace() || *c == b'/') {
+            tag.closed = b[i] == b'/';
+            i += 1;
+        }
+        match b.get(i) {
+            None => return Some((tag, i)),
+            Some(b'>') => return Some((tag, i + 1)),
+            _ => {}
+        }
+        tag.closed = false;
+        let start = i;
+        while b.get(i).is_some_and(|c| !c.is_ascii_whitespace() && !b"=>/".contains(c)) { i += 1; }
+        if i == start { i += 1; continue; }
+        let name = s[start..i].to_ascii_lowercase();
+        while b.get(i).is_some_and(u8::is_ascii_whitespace) { i += 1; }
+        let mut value = "";
+        if b.get(i) == Some(&b'=') {
+            i += 1;
+            while b.get(i).is_some_and(u8::is_ascii_whitespace) { i += 1; }
+            match b.get(i) {
+                Some(&q) if q == b'"' || q == b'\'' => {
+                    let len = s[i + 1..].find(q as char).unwrap_or(s.len() - i - 1);
+                    value = &s[i + 1..i + 1 + len];
+                    i = (i + 2 + len).min(s.len());
+                }
+                _ => {
+                    let start = i;
+                    while b.get(i).is_some_and(|c| !c.is_ascii_whitespace() && *c != b'>') { i += 1; }
+                    value = &s[start..i];
+                }
+            }
+        }
+        tag.attrs.push((name, value));
+    }
+}
+
+/// Whether a link or image url may stay: web and mail links, in-page
+/// anchors and inline images. Entity-encoded schemes fail the test too.
+fn safe_url(url: &str, image: bool) -> bool {
+    let lower = url.trim_start().to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+        || if image { lower.starts_with("data:image/") } else { lower.starts_with("mailto:") || lower.starts_with('#') }
+}
+
+/// Cleans article markup down to `KEEP_TAGS` and a few attributes: link
+/// targets, image sources and alt text, table spans. Image sources that
+/// are `vccat-img:<n>` placeholders are replaced by `image(n)`, and only
+/// there, so the same text elsewhere in the article stays as it is.
+pub fn sanitize(html: &str, image: impl Fn(usize) -> Option<String>) -> String {
+    let attr = |s: &str| s.replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut out = String::with_capacity(html.len());
+    // a dropped element being skipped, with how deep it nests in itself
+    let mut dropping: Option<(String, usize)> = None;
+    let mut rest = html;
+    while !rest.is_empty() {
+        let lt = rest.find('<').unwrap_or(rest.len());
+        if dropping.is_none() { out.push_str(&rest[..lt].replace('>', "&gt;")); }
+        rest = &rest[lt..];
+        if rest.is_empty() { break; }
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |i| &comment[i + 3..]);
+            continue;
+        }
+        let Some((tag, len)) = read_tag(rest) else {
+            if dropping.is_none() { out.push_str("&lt;"); }
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[len..];
+        if let Some((name, depth)) = &mut dropping {
+            if tag.name == *name && !tag.closed {
+                if !tag.end { *depth += 1; } else { *depth -= 1; }
+                if *depth == 0 { dropping = None; }
+            }
+            continue;
+        }
+        if DROP_TAGS.contains(&tag.name.as_str()) {
+            if tag.end || tag.closed { continue; }
+            if RAW_TEXT.contains(&tag.name.as_str()) {
+                let close = format!("</{}", tag.name);
+                let at = rest.to_ascii_lowercase().find(&close);
+                rest = at.map_or("", |i| &rest[i..]);
+                rest = rest.find('>').map_or("", |i| &rest[i + 1..]);
+            } else {
+                dropping = Some((tag.name, 1));
+            }
+            continue;
+        }
+        if !KEEP_TAGS.contains(&tag.name.as_str()) { continue; }
+        if tag.end {
+            out.push_str(&format!("</{}>", tag.name));
+            continue;
+        }
+        out.push('<');
+        out.push_str(&tag.name);
+        for (name, value) in &tag.attrs {
+            let value = match (tag.name.as_str(), name.as_str()) {
+                ("a", "href") if safe_url(value, false) => value.to_string(),
+                ("img", "src") => match value.strip_prefix("vccat-img:").and_then(|n| n.parse().ok()) {
+                    Some(n) => match image(n) { Some(src) if safe_url(&src, true) => src, _ => continue },
+                    None if safe_url(value, true) => value.to_string(),
+                    None => continue,
+                },
+                ("img", "alt") | ("td" | "th", "colspan" | "rowspan") => value.to_string(),
+                _ => continue,
+            };
+            out.push_str(&format!(" {}=\"{}\"", name, attr(&value)));
+        }
+        out.push('>');
+    }
+    out
+}
+
+/// Builds the standalone offline page, downloading the article's images.
+/// Blocking; run it off the UI thread.
+pub fn build_offline(article: &Article) -> String {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(15))
+        .user_agent("vccat-browser")
+        .build();
+    let images: Vec<String> = article.images.iter().enumerate().map(|(n, src)| {
+        let inlined = match &client {
+            Ok(c) if n < MAX_IMAGES => fetch_data_uri(c, src),
+            _ => None,
+        };
+        inlined.unwrap_or_else(|| src.clone())
+    }).collect();
+    let html = sanitize(&article.html, |n| images.get(n).cloned());
+    let esc = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;");
+    // the markup comes from the page, so scripts stay off in the copy
+    format!(r#"<!DOCTYPE html><html><head><meta charset="UTF-8">
+<meta http-equiv="Content-Security-Policy" content="script-src 'none'; object-src 'none'">
+<title>{title}</title>
+<style>
+body{{background:#08080f;color:#aaa;font-family:Georgia,serif;font-size:18px;line-height:1.6;
+  max-width:720px;margin:0 auto;padding:40px 24px;}}
+h1{{color:#ccc;font-size:28px;line-height:1.25;margin-bottom:6px;}}
+.src{{font-family:'JetBrains Mono','Fira Code',monospace;font-size:11px;margin-bottom:32px;}}
+a{{color:#8a6abb;}}
+img,video,figure{{max-width:100%;height:auto;}}
+pre{{overflow-x:auto;background:#0c0b14;padding:10px;font-size:13px;}}
+</style></head><body>
+<h1>{title}</h1>
+<div class="src"><a href="{url}">{url}</a> · kopia offline</div>
+{html}
+</body></html>"#, title = esc(&article.title), url = esc(&article.url), html = html)
+}
+
+/// Adds an article to the list and writes its offline copy.
+/// Saving a url again replaces its earlier entry.
+pub fn add(items: &mut Vec<Item>, article: &Article, offline_html: &str) -> u64 {
+    let old: Vec<u64> = items.iter().filter(|i| i.url == article.url).map(|i| i.id).collect();
+    for id in old {
+        remove(items, id);
+    }
+    let id = items.iter().map(|i| i.id).max().unwrap_or(0) + 1;
+    // written through the vault, by name, into the directory made here
+    reading_dir();
+    let offline = crate::storage::write_protected(&format!("reading/{}.html", id), offline_html);
+    items.push(Item {
+        id, url: article.url.clone(), title: article.title.clone(),
+        added: crate::storage::now(), read: false, offline,
+    });
+    id
+}
+
+/// Script run in the page to extract the article: the biggest block of
+/// paragraph text, cleaned of scripts, chrome and attributes. Posts
+/// `article:<json>` back through IPC.
+pub fn capture_js() -> &'static str {
+    r#"(function(){
+  const score=el=>[...el.querySelectorAll('p')].reduce((n,p)=>n+p.textContent.trim().length,0);
+  let best=document.querySelector('article')||document.querySelector('main');
+  if(!best||score(best)<200){
+    let top=0;
+    document.querySelectorAll('div,section,article,main,td').forEach(el=>{
+      const direct=[...el.children].filter(c=>c.tagName==='P').reduce((n,p)=>n+p.textContent.length,0);
+      if(direct>top){top=direct;best=el;}
+    });
+  }
+  best=(best||document.body).cloneNode(true);
+  best.querySelectorAll('script,style,noscript,iframe,object,embed,form,button,input,nav,aside,header,footer,svg,canvas,[role=navigation],[aria-hidden=true]')
+    .forEach(e=>e.remove());
+  const keep={A:['href'],IMG:['src','alt'],TD:['colspan','rowspan'],TH:['colspan','rowspan']};
+  const images=[];
+  best.querySelectorAll('*').forEach(el=>{
+    if(el.tagName==='IMG'){
+      const src=el.currentSrc||el.src;
+      if(src){el.setAttribute('src','vccat-img:'+images.length);images.push(src);}
+    }
+    if(el.tagName==='A'&&el.href)el.setAttribute('href',el.href);
+    const ok=keep[el.tagName]||[];
+    [...el.attributes].forEach(a=>{if(!ok.includes(a.name))el.removeAttribute(a.name);});
+  });
+  window.ipc.postMessage('article:'+JSON.stringify({title:document.title,
+    html:best.innerHTML,images:images}));
+})();"#
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(html: &str) -> String {
+        sanitize(html, |n| ["data:image/png;base64,AAAA", "javascript:alert(1)"].get(n).map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn keeps_article_markup() {
+        assert_eq!(clean(r#"<h2 class="x" id="t">Tytuł</h2><p style="color:red">a &amp; b<br>c</p>"#),
+                   "<h2>Tytuł</h2><p>a &amp; b<br>c</p>");
+        assert_eq!(clean(r#"<table><tr><td colspan="2" onclick="x()">1</td></tr></table>"#),
+                   r#"<table><tr><td colspan="2">1</td></tr></table>"#);
+        // unknown tags go, their text stays
+        assert_eq!(clean("<custom-el><font>tekst</font></custom-el>"), "tekst");
+        assert_eq!(clean("1 < 2 > 0"), "1 &lt; 2 &gt; 0");
+    }
+
+    #[test]
+    fn drops_active_content() {
+        assert_eq!(clean("a<script>if (x < y) document.write('<p>')</script>b"), "ab");
+        assert_eq!(clean("a<STYLE>p{}</STYLE>b<!-- <p>ukryte</p> -->c"), "abc");
+        assert_eq!(clean("a<svg><svg><p>x</p></svg><p>y</p></svg>b"), "ab");
+        assert_eq!(clean(r#"<iframe src="https://e.example/"></iframe><p>ok</p>"#), "<p>ok</p>");
+        assert_eq!(clean(r#"<img src=x onerror=alert(1)>"#), "<img>");
+        assert_eq!(clean(r#"<a href="javascript:alert(1)">x</a>"#), "<a>x</a>");
+        assert_eq!(clean(r#"<a href="java&#115;cript:alert(1)">x</a>"#), "<a>x</a>");
+        assert_eq!(clean(r#"<a href='https://e.example/?q="x"'>x</a>"#),
+                   r#"<a href="https://e.example/?q=&quot;x&quot;">x</a>"#);
+    }
+
+    #[test]
+    fn placeholders_only_in_image_sources() {
+        assert_eq!(clean(r#"<p>vccat-img:0</p><img src="vccat-img:0" alt="vccat-img:1">"#),
+                   r#"<p>vccat-img:0</p><img src="data:image/png;base64,AAAA" alt="vccat-img:1">"#);
+        // unsafe or missing images leave the tag without a source
+        assert_eq!(clean(r#"<img src="vccat-img:1"><img src="vccat-img:12">"#), "<img><img>");
+        assert_eq!(clean(r#"<img src="https://e.example/a.png">"#), r#"<img src="https://e.example/a.png">"#);
+    }
+}
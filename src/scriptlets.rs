@@ -0,0 +1,363 @@
+//! Scriptlets: small named scripts from the filter lists (`site##+js(name,
+//! args…)`) that change what a page's own scripts see or do — a constant
+//! set, a property that throws when read, ad fields pruned from JSON. They
+//! have to run before the page's scripts, so each site gets one WebKit user
+//! script injected at document start into every frame, built here from the
+//! rules that apply to it. The engine is global because the refresh thread
+//! builds it and the main loop turns it into user scripts.
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::filters::ScriptletFilter;
+
+/// Helpers shared by the snippets: `chain` walks or traps a dotted property
+/// path, objects that don't exist yet included; `onDom` runs a callback
+/// once the document is parsed and again as it changes, at most every
+/// 100 ms, with one observer for all snippets of the page.
+const PRELUDE: &str = r#"const noop = () => {};
+const values = {
+  'undefined': undefined, 'null': null, 'true': true, 'false': false, '': '',
+  'emptyStr': '', 'noopFunc': noop, 'trueFunc': () => true, 'falseFunc': () => false,
+  'emptyArr': [], 'emptyObj': {}, '[]': [], '{}': {},
+};
+const value = s => s in values ? values[s]
+  : /^-?\d+$/.test(s) && Math.abs(+s) <= 32767 ? +s : undefined;
+const chain = (path, trap) => {
+  const trapAt = (owner, props) => {
+    const prop = props[0];
+    if (props.length === 1) { trap(owner, prop); return; }
+    let inner = owner[prop];
+    if (inner instanceof Object) { trapAt(inner, props.slice(1)); return; }
+    const desc = Object.getOwnPropertyDescriptor(owner, prop);
+    if (desc && !desc.configurable) return;
+    // another snippet may be waiting on the same object already
+    const prev = desc && desc.set ? desc : null;
+    Object.defineProperty(owner, prop, {
+      configurable: true,
+      get() { return prev ? prev.get.call(this) : inner; },
+      set(v) {
+        if (prev) { prev.set.call(this, v); v = prev.get.call(this); } else inner = v;
+        if (v instanceof Object) trapAt(v, props.slice(1));
+      },
+    });
+  };
+  trapAt(window, path.split('.'));
+};
+const callbacks = [];
+let pending = false;
+const runAll = () => { pending = false; for (const f of callbacks) try { f(); } catch (e) {} };
+const onDom = f => {
+  if (callbacks.push(f) > 1) return;
+  const start = () => {
+    runAll();
+    new MutationObserver(() => { if (!pending) { pending = true; setTimeout(runAll, 100); } })
+      .observe(document.documentElement, { childList: true, subtree: true, attributes: true,
+        attributeFilter: ['class'] });
+  };
+  if (document.readyState === 'loading') document.addEventListener('DOMContentLoaded', start);
+  else start();
+};
+"#;
+
+/// The snippets by canonical name, with the aliases uBlock lists use, as
+/// JS function expressions taking the rule's arguments.
+const LIBRARY: &[(&str, &[&str], &str)] = &[
+    ("set-constant", &["set"], r#"(path, v) => {
+  const val = value(v);
+  chain(path, (owner, prop) => {
+    const desc = Object.getOwnPropertyDescriptor(owner, prop);
+    if (desc && !desc.configurable) return;
+    Object.defineProperty(owner, prop, { configurable: false, get: () => val, set: noop });
+  });
+}"#),
+    ("abort-on-property-read", &["aopr"], r#"path => {
+  chain(path, (owner, prop) => {
+    Object.defineProperty(owner, prop, { configurable: false,
+      get() { throw new ReferenceError(prop); }, set: noop });
+  });
+}"#),
+    ("abort-on-property-write", &["aopw"], r#"path => {
+  chain(path, (owner, prop) => {
+    let v = owner[prop];
+    Object.defineProperty(owner, prop, { configurable: false,
+      get: () => v, set() { throw new ReferenceError(prop); } });
+  });
+}"#),
+    ("json-prune", &[], r#"(prune, need) => {
+  const paths = (prune || '').split(/\s+/).filter(Boolean).map(p => p.split('.'));
+  const needs = (need || '').split(/\s+/).filter(Boolean).map(p => p.split('.'));
+  const has = (o, p) => p.every(k => (o = o instanceof Object ? o[k] : undefined) !== undefined);
+  const strip = o => {
+    if (!(o instanceof Object) || !needs.every(p => has(o, p))) return o;
+    for (const p of paths) {
+      let owner = o;
+      for (const k of p.slice(0, -1)) owner = owner instanceof Object ? owner[k] : undefined;
+      if (owner instanceof Object) delete owner[p[p.length - 1]];
+    }
+    return o;
+  };
+  const parse = JSON.parse;
+  JSON.parse = function() { return strip(parse.apply(this, arguments)); };
+  const json = Response.prototype.json;
+  Response.prototype.json = function() { return json.apply(this, arguments).then(strip); };
+}"#),
+    ("abort-current-inline-script", &["acis", "abort-current-script", "acs"], r#"(path, needle) => {
+  const re = needle ? new RegExp(needle.replace(/[.*+?^${}()|[\]\\]/g, '\\$&')) : null;
+  chain(path, (owner, prop) => {
+    let v = owner[prop];
+    const check = () => {
+      const s = document.currentScript;
+      if (s && (!re || re.test(s.textContent) || re.test(s.src))) throw new ReferenceError(prop);
+    };
+    Object.defineProperty(owner, prop, { configurable: true,
+      get() { check(); return v; }, set(x) { check(); v = x; } });
+  });
+}"#),
+    ("no-setTimeout-if", &["nostif", "prevent-setTimeout"], r#"(needle, delay) => {
+  const st = window.setTimeout;
+  window.setTimeout = function(f, ms) {
+    const src = String(f);
+    const hit = (!needle || src.includes(needle)) && (!delay || String(ms) === delay);
+    return hit ? st.call(this, noop, ms) : st.apply(this, arguments);
+  };
+}"#),
+    ("no-setInterval-if", &["nosiif", "prevent-setInterval"], r#"(needle, delay) => {
+  const si = window.setInterval;
+  window.setInterval = function(f, ms) {
+    const src = String(f);
+    const hit = (!needle || src.includes(needle)) && (!delay || String(ms) === delay);
+    return hit ? si.call(this, noop, ms) : si.apply(this, arguments);
+  };
+}"#),
+    ("remove-class", &["rc"], r#"(names, selector) => {
+  const list = (names || '').split(/[\s|]+/).filter(Boolean);
+  const sel = selector || list.map(n => '.' + CSS.escape(n)).join(',');
+  if (list.length) onDom(() => document.querySelectorAll(sel).forEach(el => el.classList.remove(...list)));
+}"#),
+    ("remove-attr", &["ra"], r#"(names, selector) => {
+  const list = (names || '').split(/[\s|]+/).filter(Boolean);
+  const sel = selector || list.map(n => '[' + CSS.escape(n) + ']').join(',');
+  if (list.length) onDom(() => document.querySelectorAll(sel)
+    .forEach(el => list.forEach(n => el.removeAttribute(n))));
+}"#),
+    ("click-element", &[], r#"selector => {
+  onDom(() => document.querySelectorAll(selector).forEach(el => el.click()));
+}"#),
+    ("skip-media", &[], r#"selector => {
+  // muted while it matches, then as it was
+  const muted = new Set();
+  onDom(() => {
+    for (const m of muted) if (!m.matches(selector)) { m.muted = false; muted.delete(m); }
+    document.querySelectorAll(selector).forEach(m => {
+      if (!(m instanceof HTMLMediaElement)) return;
+      if (!m.muted) { m.muted = true; muted.add(m); }
+      if (isFinite(m.duration) && m.currentTime < m.duration) m.currentTime = m.duration;
+    });
+  });
+}"#),
+];
+
+/// The canonical name of a snippet, or None when there is no such snippet.
+pub fn canonical(name: &str) -> Option<&'static str> {
+    LIBRARY.iter().find(|(n, aliases, _)| *n == name || aliases.contains(&name)).map(|(n, _, _)| *n)
+}
+
+fn source(name: &str) -> &'static str {
+    LIBRARY.iter().find(|(n, _, _)| *n == name).map(|(_, _, js)| *js).unwrap_or("noop")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Call {
+    name: String,
+    args: Vec<String>,
+    /// Sites it is turned off on (`~site` in the rule).
+    unless: Vec<String>,
+}
+
+/// What exceptions name a call by: `name, arg, …`; empty for all of them.
+fn key(name: &str, args: &[String]) -> String {
+    if name.is_empty() { return String::new(); }
+    std::iter::once(name).chain(args.iter().map(String::as_str)).collect::<Vec<_>>().join(", ")
+}
+
+impl Call {
+    fn key(&self) -> String {
+        key(&self.name, &self.args)
+    }
+}
+
+fn key_of(f: &ScriptletFilter) -> String {
+    key(&f.name, &f.args)
+}
+
+/// The host and every parent domain of it, most specific first.
+fn suffixes(host: &str) -> Vec<&str> {
+    let host = host.trim_end_matches('.');
+    let mut out = vec![host];
+    out.extend(host.match_indices('.').map(|(i, _)| &host[i + 1..]));
+    out
+}
+
+#[derive(Default)]
+pub struct Engine {
+    /// `site##+js(…)`, keyed by site.
+    specific: HashMap<String, Vec<Call>>,
+    /// `site#@#+js(…)` by site; an empty key turns off all of them.
+    exceptions: HashMap<String, HashSet<String>>,
+    /// `#@#+js(…)`: turned off everywhere.
+    disabled: HashSet<String>,
+}
+
+/// A user script to add: the sites it runs on and those it must not,
+/// as WebKit URL patterns, and its source.
+#[derive(Debug, PartialEq)]
+pub struct Script {
+    pub allow: Vec<String>,
+    pub block: Vec<String>,
+    pub source: String,
+}
+
+/// WebKit URL pattern matching a site and its subdomains.
+pub fn pattern(site: &str) -> String {
+    format!("*://*.{}/*", site)
+}
+
+impl Engine {
+    pub fn new(filters: &[ScriptletFilter]) -> Self {
+        let mut e = Engine::default();
+        for f in filters {
+            match (f.exception, f.domains.include.is_empty()) {
+                (true, true) => { e.disabled.insert(key_of(f)); }
+                (true, false) => for d in &f.domains.include {
+                    e.exceptions.entry(d.clone()).or_default().insert(key_of(f));
+                },
+                (false, _) => for d in &f.domains.include {
+                    e.specific.entry(d.clone()).or_default().push(Call {
+                        name: f.name.clone(), args: f.args.clone(), unless: f.domains.exclude.clone(),
+                    });
+                },
+            }
+        }
+        e
+    }
+
+    /// Scriptlets running on pages of `host`, parent domains' first.
+    fn calls(&self, host: &str) -> Vec<&Call> {
+        let domains = suffixes(host);
+        let off: HashSet<&str> = domains.iter()
+            .filter_map(|d| self.exceptions.get(*d)).flatten().map(String::as_str)
+            .chain(self.disabled.iter().map(String::as_str))
+            .collect();
+        if off.contains("") { return Vec::new(); }
+        let mut seen = HashSet::new();
+        domains.iter().rev().filter_map(|d| self.specific.get(*d)).flatten()
+            .filter(|c| !c.unless.iter().any(|u| domains.contains(&u.as_str())))
+            .filter(|c| { let k = c.key(); !off.contains(k.as_str()) && seen.insert(k) })
+            .collect()
+    }
+
+    /// One script per site with rules. A subdomain with rules or exceptions
+    /// of its own gets a script of its own, blocked from its parent's, so
+    /// every page runs exactly one; `skip` are allowlisted sites.
+    pub fn scripts(&self, skip: &[String]) -> Vec<Script> {
+        let mut sites: Vec<&str> = self.specific.keys().chain(self.exceptions.keys()).map(String::as_str)
+            .chain(self.specific.values().flatten().flat_map(|c| c.unless.iter().map(String::as_str)))
+            .collect::<HashSet<_>>().into_iter().collect();
+        sites.sort_unstable();
+        sites.iter().filter_map(|site| {
+            let calls = self.calls(site);
+            if calls.is_empty() { return None; }
+            let block = sites.iter().filter(|s| s.len() > site.len() && s.ends_with(&format!(".{}", site)))
+                .map(|s| pattern(s))
+                .chain(skip.iter().map(|s| pattern(s)))
+                .collect();
+            Some(Script { allow: vec![pattern(site)], block, source: script_source(&calls) })
+        }).collect()
+    }
+}
+
+fn script_source(calls: &[&Call]) -> String {
+    let mut names: Vec<&str> = calls.iter().map(|c| c.name.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+    let snippets: String = names.iter()
+        .map(|n| format!("  {}: {},\n", serde_json::Value::String(n.to_string()), source(n)))
+        .collect();
+    let list: Vec<serde_json::Value> = calls.iter().map(|c| serde_json::json!([c.name, c.args])).collect();
+    format!("(function() {{\n'use strict';\n{}const snippets = {{\n{}}};\n\
+             for (const [name, args] of {}) try {{ snippets[name](...args); }} catch (e) {{}}\n}})();",
+            PRELUDE, snippets, serde_json::Value::Array(list))
+}
+
+static ENGINE: Mutex<Option<Arc<Engine>>> = Mutex::new(None);
+/// Bumped by `install`, so the main loop knows to rebuild its scripts.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub fn install(engine: Engine) {
+    *ENGINE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(engine));
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// The scripts of the installed engine, none before there is one.
+pub fn scripts(skip: &[String]) -> Vec<Script> {
+    let engine = ENGINE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    engine.map(|e| e.scripts(skip)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::parse_list;
+
+    fn engine(text: &str) -> Engine {
+        let list = parse_list(text);
+        assert!(list.unsupported.is_empty(), "{:?}", list.unsupported);
+        Engine::new(&list.scriptlets)
+    }
+
+    fn names(e: &Engine, host: &str) -> Vec<String> {
+        e.calls(host).iter().map(|c| c.key()).collect()
+    }
+
+    #[test]
+    fn rules_per_site() {
+        let e = engine("example.com##+js(set, ads, false)\nnews.example.com##+js(aopr, adblock)\n\
+                        example.com,~shop.example.com##+js(nostif, ad)\n\
+                        forum.example.com#@#+js(set-constant, ads, false)\nplain.example.com#@#+js()\n");
+        assert_eq!(names(&e, "example.com"), vec!["set-constant, ads, false", "no-setTimeout-if, ad"]);
+        assert_eq!(names(&e, "news.example.com"),
+                   vec!["set-constant, ads, false", "no-setTimeout-if, ad", "abort-on-property-read, adblock"]);
+        assert_eq!(names(&e, "shop.example.com"), vec!["set-constant, ads, false"]);
+        assert_eq!(names(&e, "forum.example.com"), vec!["no-setTimeout-if, ad"]);
+        assert!(names(&e, "plain.example.com").is_empty());
+        assert!(names(&e, "example.org").is_empty());
+    }
+
+    #[test]
+    fn one_script_per_page() {
+        let e = engine("example.com##+js(set, ads, false)\nnews.example.com##+js(aopr, adblock)\n\
+                        plain.example.com#@#+js()\n");
+        let scripts = e.scripts(&["blog.example.com".to_string()]);
+        let allows: Vec<&str> = scripts.iter().map(|s| s.allow[0].as_str()).collect();
+        assert_eq!(allows, vec!["*://*.example.com/*", "*://*.news.example.com/*"]);
+        assert_eq!(scripts[0].block, vec!["*://*.news.example.com/*", "*://*.plain.example.com/*",
+                                          "*://*.blog.example.com/*"]);
+        assert!(scripts[1].source.contains(r#"["abort-on-property-read",["adblock"]]"#));
+        assert!(scripts[1].source.contains(r#"["set-constant",["ads","false"]]"#));
+        assert!(!scripts[0].source.contains("\"json-prune\":"));
+    }
+
+    #[test]
+    fn every_snippet_is_named() {
+        for (name, aliases, js) in LIBRARY {
+            assert_eq!(canonical(name), Some(*name));
+            assert!(aliases.iter().all(|a| canonical(a) == Some(*name)));
+            assert!(js.contains("=>"), "{}", name);
+        }
+        assert_eq!(canonical("no-such"), None);
+    }
+}
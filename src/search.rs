@@ -0,0 +1,210 @@
+//! Search engines: url templates with `{searchTerms}`, optional toolbar
+//! keywords, and OpenSearch descriptions offered by visited pages.
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SearchEngine {
+    pub name: String,
+    /// Result page url; `{searchTerms}` is replaced with the query.
+    pub template: String,
+    /// Toolbar shortcut, e.g. `w` in `w rust borrow checker`.
+    #[serde(default)]
+    pub keyword: Option<String>,
+    /// OpenSearch description the engine was added from.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl SearchEngine {
+    pub fn new(name: &str, template: &str, keyword: &str) -> Self {
+        SearchEngine {
+            name: name.into(), template: template.into(),
+            keyword: Some(keyword.to_string()).filter(|k| !k.is_empty()), source: None,
+        }
+    }
+
+    pub fn url_for(&self, query: &str) -> String {
+        self.template.replace("{searchTerms}", &urlencoding::encode(query))
+    }
+}
+
+pub fn default_engines() -> Vec<SearchEngine> {
+    vec![
+        SearchEngine::new("Brave", "https://search.brave.com/search?q={searchTerms}", "b"),
+        SearchEngine::new("DuckDuckGo", "https://duckduckgo.com/?q={searchTerms}", "d"),
+        SearchEngine::new("Wikipedia", "https://pl.wikipedia.org/w/index.php?search={searchTerms}", "w"),
+        SearchEngine::new("GitHub", "https://github.com/search?q={searchTerms}", "gh"),
+        SearchEngine::new("docs.rs", "https://docs.rs/releases/search?query={searchTerms}", "rs"),
+    ]
+}
+
+/// Routes `keyword query` input to the engine with that keyword.
+pub fn resolve_keyword(engines: &[SearchEngine], input: &str) -> Option<String> {
+    let (kw, query) = input.trim().split_once(' ')?;
+    let query = query.trim();
+    if query.is_empty() { return None; }
+    engines.iter().find(|e| e.keyword.as_deref() == Some(kw)).map(|e| e.url_for(query))
+}
+
+/// Checks templates and keywords; the error names the first bad engine.
+pub fn validate(engines: &[SearchEngine]) -> Result<(), String> {
+    for (i, e) in engines.iter().enumerate() {
+        if e.name.trim().is_empty() {
+            return Err("wyszukiwarka musi mieć nazwę".into());
+        }
+        if !(e.template.starts_with("https://") || e.template.starts_with("http://"))
+            || !e.template.contains("{searchTerms}") {
+            return Err(format!("{}: adres musi zaczynać się od http(s):// i zawierać {{searchTerms}}", e.name));
+        }
+        if let Some(k) = &e.keyword {
+            if k.is_empty() || k.contains(char::is_whitespace) {
+                return Err(format!("{}: skrót nie może zawierać spacji", e.name));
+            }
+            if engines[..i].iter().any(|o| o.keyword.as_ref() == Some(k)) {
+                return Err(format!("{}: skrót „{}” jest już zajęty", e.name, k));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Script part run on every page: reports an OpenSearch description.
+pub fn discovery_js() -> &'static str {
+    r#"(function() {
+    function sendSearch() {
+        const l = document.querySelector('link[rel="search"][type="application/opensearchdescription+xml"]');
+        if (l && l.href) window.ipc.postMessage('opensearch:' + l.href);
+    }
+    if (document.readyState === 'loading') document.addEventListener('DOMContentLoaded', sendSearch);
+    else sendSearch();
+})();"#
+}
+
+fn tag_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = xml.find(&format!("<{}", tag))?;
+    let start = open + xml[open..].find('>')? + 1;
+    let end = start + xml[start..].find(&format!("</{}", tag))?;
+    Some(xml[start..end].trim())
+}
+
+fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    for q in ['"', '\''] {
+        let key = format!("{}={}", name, q);
+        if let Some(i) = tag.find(&key) {
+            let rest = &tag[i + key.len()..];
+            return rest.find(q).map(|e| &rest[..e]);
+        }
+    }
+    None
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<")
+     .replace("&gt;", ">").replace("&amp;", "&")
+}
+
+/// Value of an OpenSearch template parameter other than `{searchTerms}`
+/// for the first result page; `offsets` are the Url element's
+/// `indexOffset` and `pageOffset`. Unknown parameters are left empty.
+fn param_value(name: &str, offsets: (&str, &str)) -> &'static str {
+    match name {
+        "startIndex" if offsets.0 == "0" => "0",
+        "startIndex" => "1",
+        "startPage" if offsets.1 == "0" => "0",
+        "startPage" => "1",
+        "count" => "20",
+        "language" => "*",
+        "inputEncoding" | "outputEncoding" => "UTF-8",
+        _ => "",
+    }
+}
+
+/// Reads an OpenSearch description: the short name and the text/html
+/// result template. Parameters other than `{searchTerms}` get their
+/// first-page values, or none when optional (`{x?}`) or unknown.
+pub fn parse_opensearch(xml: &str) -> Result<SearchEngine, String> {
+    let name = tag_text(xml, "ShortName").ok_or("brak ShortName")?;
+    let (template, offsets) = xml.match_indices("<Url").filter_map(|(i, _)| {
+        let tag = &xml[i..i + xml[i..].find('>')?];
+        let html = attr(tag, "type").is_none_or(|t| t == "text/html");
+        let offsets = (attr(tag, "indexOffset").unwrap_or("1"), attr(tag, "pageOffset").unwrap_or("1"));
+        if html { attr(tag, "template").map(|t| (t, offsets)) } else { None }
+    }).next().ok_or("brak adresu text/html")?;
+    let template = unescape_xml(template);
+    let mut clean = String::new();
+    let mut rest = template.as_str();
+    while let Some(s) = rest.find('{') {
+        let Some(e) = rest[s..].find('}') else { break };
+        let param = &rest[s + 1..s + e];
+        clean.push_str(&rest[..s]);
+        match param {
+            "searchTerms" => clean.push_str("{searchTerms}"),
+            p if p.ends_with('?') => {}
+            p => clean.push_str(param_value(p, offsets)),
+        }
+        rest = &rest[s + e + 1..];
+    }
+    clean.push_str(rest);
+    if !clean.contains("{searchTerms}") {
+        return Err("adres nie zawiera {searchTerms}".into());
+    }
+    Ok(SearchEngine {
+        name: unescape_xml(name), template: clean, keyword: None, source: None,
+    })
+}
+
+/// Downloads and parses an OpenSearch description. Blocking.
+pub fn fetch_opensearch(url: &str) -> Result<SearchEngine, String> {
+    let xml = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .user_agent("vccat-browser")
+        .build().map_err(|e| e.to_string())?
+        .get(url).send().and_then(|r| r.error_for_status())
+        .and_then(|r| r.text()).map_err(|e| e.to_string())?;
+    let mut engine = parse_opensearch(&xml)?;
+    engine.source = Some(url.to_string());
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_opensearch() {
+        let xml = r#"<?xml version="1.0"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <ShortName>Wiki &amp; co</ShortName>
+  <Url type="application/x-suggestions+json" template="https://w.example/suggest?q={searchTerms}"/>
+  <Url type="text/html" indexOffset="0"
+       template="https://w.example/s?q={searchTerms}&amp;from={startIndex}&amp;n={count}&amp;ie={inputEncoding}&amp;p={startPage?}&amp;x={ns:custom}"/>
+</OpenSearchDescription>"#;
+        let e = parse_opensearch(xml).unwrap();
+        assert_eq!(e.name, "Wiki & co");
+        assert_eq!(e.template, "https://w.example/s?q={searchTerms}&from=0&n=20&ie=UTF-8&p=&x=");
+        assert_eq!(e.url_for("a b&c"), "https://w.example/s?q=a%20b%26c&from=0&n=20&ie=UTF-8&p=&x=");
+        assert!(parse_opensearch("<OpenSearchDescription><Url template='https://x/'/></OpenSearchDescription>").is_err());
+        assert!(parse_opensearch("<ShortName>X</ShortName><Url type='text/html' template='https://x/?q=1'/>").is_err());
+    }
+
+    #[test]
+    fn keywords() {
+        let engines = default_engines();
+        assert_eq!(resolve_keyword(&engines, "w  rust borrow ").as_deref(),
+                   Some("https://pl.wikipedia.org/w/index.php?search=rust%20borrow"));
+        assert_eq!(resolve_keyword(&engines, "w"), None);
+        assert_eq!(resolve_keyword(&engines, "w   "), None);
+        assert_eq!(resolve_keyword(&engines, "rust borrow"), None);
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate(&default_engines()).is_ok());
+        let bad = |e: SearchEngine| validate(&[SearchEngine::new("A", "https://a/?q={searchTerms}", "a"), e]).unwrap_err();
+        assert_eq!(bad(SearchEngine::new(" ", "https://b/?q={searchTerms}", "")), "wyszukiwarka musi mieć nazwę");
+        assert!(bad(SearchEngine::new("B", "ftp://b/?q={searchTerms}", "")).starts_with("B: adres"));
+        assert!(bad(SearchEngine::new("B", "https://b/?q=%s", "")).starts_with("B: adres"));
+        assert_eq!(bad(SearchEngine::new("B", "https://b/?q={searchTerms}", "a")), "B: skrót „a” jest już zajęty");
+        assert_eq!(bad(SearchEngine::new("B", "https://b/?q={searchTerms}", "x y")), "B: skrót nie może zawierać spacji");
+    }
+}
@@ -0,0 +1,156 @@
+//! Settings: user preferences persisted in settings.json. Missing fields
+//! take their defaults; a file that fails validation is ignored as a whole.
+use serde::{Deserialize, Serialize};
+use crate::search::{self, SearchEngine};
+use crate::tracking;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub search_engines: Vec<SearchEngine>,
+    /// Name of the engine used for address bar input that isn't a url.
+    pub default_engine: String,
+    /// Page opened in new tabs and for empty address bar input.
+    pub home_page: String,
+    /// Background tabs beyond this many are suspended.
+    pub suspend_threshold: usize,
+    pub window_width: u32,
+    pub window_height: u32,
+    /// Seconds after startup before checking for updates.
+    pub update_check_delay: u64,
+    /// Hours a downloaded filter list is used before it is fetched again.
+    pub filter_max_age: u64,
+    /// Upgrade http:// navigations to https://.
+    pub https_only: bool,
+    /// Remove tracking parameters from urls.
+    pub strip_tracking: bool,
+    /// Tracking parameter rules, see `tracking`.
+    pub tracking_rules: Vec<String>,
+    /// Sites whose urls are left alone.
+    pub tracking_bypass: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            search_engines: search::default_engines(),
+            default_engine: "Brave".into(),
+            home_page: "vccat:home".into(),
+            suspend_threshold: 4,
+            window_width: 1360,
+            window_height: 860,
+            update_check_delay: 4,
+            filter_max_age: 24,
+            https_only: false,
+            strip_tracking: true,
+            tracking_rules: tracking::default_rules(),
+            tracking_bypass: Vec::new(),
+        }
+    }
+}
+
+/// Settings tied to one machine; sync leaves them alone.
+pub const LOCAL_ONLY: &[&str] = &["window_width", "window_height"];
+
+impl Settings {
+    /// Checks every field; the error names the first bad one.
+    pub fn validate(&self) -> Result<(), String> {
+        let web = |u: &str| u.starts_with("https://") || u.starts_with("http://");
+        search::validate(&self.search_engines)?;
+        if !self.search_engines.iter().any(|e| e.name == self.default_engine) {
+            return Err("domyślna wyszukiwarka nie istnieje".into());
+        }
+        if !(web(&self.home_page) || self.home_page.starts_with("vccat:")
+             || self.home_page.starts_with("about:")) {
+            return Err("strona startowa musi być adresem http(s)://, vccat: lub about:".into());
+        }
+        if !(1..=64).contains(&self.suspend_threshold) {
+            return Err("limit aktywnych kart musi mieścić się w 1–64".into());
+        }
+        if !(400..=10_000).contains(&self.window_width) || !(300..=10_000).contains(&self.window_height) {
+            return Err("rozmiar okna musi mieścić się w 400×300 – 10000×10000".into());
+        }
+        if self.update_check_delay > 3600 {
+            return Err("opóźnienie sprawdzania aktualizacji może wynosić najwyżej 3600 s".into());
+        }
+        if !(1..=720).contains(&self.filter_max_age) {
+            return Err("ważność list filtrów musi mieścić się w 1–720 h".into());
+        }
+        tracking::validate(&self.tracking_rules, &self.tracking_bypass)?;
+        Ok(())
+    }
+
+    pub fn search(&self, query: &str) -> String {
+        match self.search_engines.iter().find(|e| e.name == self.default_engine) {
+            Some(e) => e.url_for(query),
+            None => search::default_engines()[0].url_for(query),
+        }
+    }
+}
+
+pub fn load() -> Settings {
+    crate::storage::read_protected("settings.json").map(|s| parse(&s)).unwrap_or_default()
+}
+
+/// Settings from the file's text; defaults when it is unreadable or invalid.
+fn parse(text: &str) -> Settings {
+    let Ok(mut raw) = serde_json::from_str::<serde_json::Value>(text) else {
+        return Settings::default();
+    };
+    // files from before search engines had a single `search_url` with %s
+    if let Some(url) = raw.get("search_url").and_then(|u| u.as_str()) {
+        if raw.get("search_engines").is_none() {
+            let template = url.replace("%s", "{searchTerms}");
+            let mut engines = search::default_engines();
+            let default = match engines.iter().find(|e| e.template == template) {
+                Some(e) => e.name.clone(),
+                None => {
+                    engines.insert(0, SearchEngine::new("Własna", &template, ""));
+                    "Własna".into()
+                }
+            };
+            raw["search_engines"] = serde_json::to_value(engines).unwrap_or_default();
+            raw["default_engine"] = default.into();
+        }
+    }
+    serde_json::from_value::<Settings>(raw).ok()
+        .filter(|s| s.validate().is_ok())
+        .unwrap_or_default()
+}
+
+pub fn save(settings: &Settings) {
+    if let Ok(s) = serde_json::to_string_pretty(settings) {
+        crate::storage::write_protected("settings.json", &s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_search_url() {
+        // a known engine becomes the default
+        let s = parse(r#"{"search_url": "https://duckduckgo.com/?q=%s", "home_page": "https://start.example/"}"#);
+        assert_eq!(s.default_engine, "DuckDuckGo");
+        assert_eq!(s.search_engines, search::default_engines());
+        assert_eq!(s.home_page, "https://start.example/");
+        // any other one is added in front
+        let s = parse(r#"{"search_url": "https://s.example/find?q=%s"}"#);
+        assert_eq!(s.default_engine, "Własna");
+        assert_eq!(s.search_engines[0].template, "https://s.example/find?q={searchTerms}");
+        assert_eq!(s.search("a b"), "https://s.example/find?q=a%20b");
+        // engines saved since then win over a leftover search_url
+        let s = parse(r#"{"search_url": "https://s.example/?q=%s", "search_engines": [
+            {"name": "X", "template": "https://x.example/?q={searchTerms}"}], "default_engine": "X"}"#);
+        assert_eq!(s.search_engines.len(), 1);
+        assert_eq!(s.default_engine, "X");
+    }
+
+    #[test]
+    fn invalid_files_fall_back_to_defaults() {
+        assert_eq!(parse("{"), Settings::default());
+        assert_eq!(parse(r#"{"search_url": "https://s.example/?q=none"}"#), Settings::default());
+        assert_eq!(parse(r#"{"filter_max_age": 0}"#), Settings::default());
+    }
+}
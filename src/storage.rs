@@ -1,5 +1,7 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use serde::{Deserialize, Serialize};
 
 pub fn data_dir() -> PathBuf {
@@ -9,6 +11,39 @@ pub fn data_dir() -> PathBuf {
     d
 }
 
+/// Reads a file that may be sealed by the vault; `name` is relative to the
+/// data directory. A file that can't be opened is moved aside, so the
+/// fallback written later never replaces it.
+pub fn read_protected(name: &str) -> Option<String> {
+    read_protected_in(&data_dir(), name)
+}
+
+fn read_protected_in(dir: &Path, name: &str) -> Option<String> {
+    let path = dir.join(name);
+    let data = fs::read(&path).ok()?;
+    match crate::vault::open(&data) {
+        Ok(plain) => String::from_utf8(plain).ok(),
+        Err(crate::vault::VaultError::Corrupt(_)) => {
+            fs::rename(&path, dir.join(format!("{}.unreadable", name))).ok();
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Writes a file through the vault; nothing is written while it is locked.
+/// The data goes to a temporary file first, so a kill mid-write leaves the
+/// previous version in place. Returns whether the file was written.
+pub fn write_protected(name: &str, contents: &str) -> bool {
+    write_protected_in(&data_dir(), name, contents)
+}
+
+fn write_protected_in(dir: &Path, name: &str, contents: &str) -> bool {
+    let Ok(data) = crate::vault::seal_in(dir, contents.as_bytes()) else { return false };
+    let tmp = dir.join(format!("{}.part", name));
+    fs::write(&tmp, data).is_ok() && fs::rename(&tmp, dir.join(name)).is_ok()
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Session {
     pub tabs: Vec<String>,
@@ -17,22 +52,96 @@ pub struct Session {
 
 pub fn save_session(session: &Session) {
     if let Ok(s) = serde_json::to_string_pretty(session) {
-        fs::write(data_dir().join("session.json"), s).ok();
+        write_protected("session.json", &s);
     }
 }
 
 pub fn load_session() -> Session {
-    let path = data_dir().join("session.json");
-    if path.exists() {
-        if let Ok(s) = fs::read_to_string(&path) {
-            if let Ok(sess) = serde_json::from_str::<Session>(&s) {
-                if !sess.tabs.is_empty() { return sess; }
-            }
+    if let Some(s) = read_protected("session.json") {
+        if let Ok(sess) = serde_json::from_str::<Session>(&s) {
+            if !sess.tabs.is_empty() { return sess; }
         }
     }
     Session { tabs: vec!["vccat:home".into()], active: 0 }
 }
 
+/// Session left behind by a run that didn't exit cleanly, kept until the
+/// user picks what to restore from it.
+pub fn load_crashed_session() -> Option<Session> {
+    serde_json::from_str::<Session>(&read_protected("session-crashed.json")?).ok()
+}
+
+pub fn save_crashed_session(session: &Session) {
+    if let Ok(s) = serde_json::to_string_pretty(session) {
+        write_protected("session-crashed.json", &s);
+    }
+}
+
+pub fn discard_crashed_session() {
+    fs::remove_file(data_dir().join("session-crashed.json")).ok();
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedTab {
+    pub url: String,
+    pub title: String,
+}
+
+/// Set of tabs saved under a name, e.g. "release-review".
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NamedSession {
+    pub name: String,
+    pub saved: u64,
+    pub tabs: Vec<SavedTab>,
+}
+
+impl NamedSession {
+    /// The open tabs as `(url, title, private)`; private tabs and the
+    /// sessions page itself are left out.
+    pub fn snapshot<'a>(name: &str, tabs: impl IntoIterator<Item = (&'a str, &'a str, bool)>) -> NamedSession {
+        NamedSession {
+            name: name.to_string(),
+            saved: now(),
+            tabs: tabs.into_iter().filter(|(url, _, private)| !private && *url != "vccat:sessions")
+                .map(|(url, title, _)| SavedTab { url: url.to_string(), title: title.to_string() })
+                .collect(),
+        }
+    }
+
+    /// One url per line, for the url list export.
+    pub fn url_list(&self) -> String {
+        self.tabs.iter().map(|t| format!("{}\n", t.url)).collect()
+    }
+}
+
+/// Adds `session`, replacing the one saved under the same name.
+pub fn put_named_session(sessions: &mut Vec<NamedSession>, session: NamedSession) {
+    match sessions.iter_mut().find(|s| s.name == session.name) {
+        Some(s) => *s = session,
+        None => sessions.push(session),
+    }
+}
+
+pub fn load_named_sessions() -> Vec<NamedSession> {
+    load_named_sessions_in(&data_dir())
+}
+
+fn load_named_sessions_in(dir: &Path) -> Vec<NamedSession> {
+    read_protected_in(dir, "sessions.json")
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_named_sessions(sessions: &[NamedSession]) {
+    save_named_sessions_in(&data_dir(), sessions)
+}
+
+fn save_named_sessions_in(dir: &Path, sessions: &[NamedSession]) {
+    if let Ok(s) = serde_json::to_string_pretty(sessions) {
+        write_protected_in(dir, "sessions.json", &s);
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct HistoryEntry {
     pub url: String,
@@ -40,37 +149,244 @@ pub struct HistoryEntry {
     pub timestamp: u64,
 }
 
+/// Visits since history.json was last written, a JSON object per line (see
+/// `vault::seal_line_in`). A navigation only appends its line; the log is
+/// folded into history.json on start-up and every `HISTORY_LOG_LIMIT` visits.
+const HISTORY_LOG: &str = "history.log";
+const HISTORY_LOG_LIMIT: usize = 500;
+static HISTORY_LOG_LINES: AtomicUsize = AtomicUsize::new(0);
+
 pub fn load_history() -> Vec<HistoryEntry> {
-    let path = data_dir().join("history.json");
-    if path.exists() {
-        if let Ok(s) = fs::read_to_string(&path) {
-            if let Ok(h) = serde_json::from_str::<Vec<HistoryEntry>>(&s) {
-                return h;
-            }
-        }
+    load_history_in(&data_dir())
+}
+
+fn load_history_in(dir: &Path) -> Vec<HistoryEntry> {
+    let mut history: Vec<HistoryEntry> = read_protected_in(dir, "history.json")
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    let logged: Vec<HistoryEntry> = fs::read_to_string(dir.join(HISTORY_LOG)).unwrap_or_default()
+        .lines()
+        .filter_map(|l| serde_json::from_str(&crate::vault::open_line(l).ok()?).ok())
+        .collect();
+    if let Some(from) = logged.first().map(|h| h.timestamp) {
+        // a run that stopped between writing history.json and removing the
+        // log has its last visits in both
+        let seen: std::collections::HashSet<(String, u64)> = history.iter().rev()
+            .take_while(|h| h.timestamp >= from).map(|h| (h.url.clone(), h.timestamp)).collect();
+        history.extend(logged.into_iter().filter(|h| !seen.contains(&(h.url.clone(), h.timestamp))));
+        save_history_in(dir, &history);
     }
-    vec![]
+    history
 }
 
+/// Number of most recent visits kept in history.json.
+pub const HISTORY_LIMIT: usize = 100_000;
+
+/// Writes the whole history, leaving out all but the latest `HISTORY_LIMIT`
+/// visits, and empties the log.
 pub fn save_history(history: &[HistoryEntry]) {
-    let slice = if history.len() > 5000 { &history[history.len()-5000..] } else { history };
-    if let Ok(s) = serde_json::to_string_pretty(slice) {
-        fs::write(data_dir().join("history.json"), s).ok();
+    save_history_in(&data_dir(), history)
+}
+
+fn save_history_in(dir: &Path, history: &[HistoryEntry]) {
+    let slice = if history.len() > HISTORY_LIMIT { &history[history.len()-HISTORY_LIMIT..] } else { history };
+    if let Ok(s) = serde_json::to_string(slice) {
+        if write_protected_in(dir, "history.json", &s) {
+            fs::remove_file(dir.join(HISTORY_LOG)).ok();
+            HISTORY_LOG_LINES.store(0, Ordering::Relaxed);
+        }
     }
 }
 
+/// Drops the oldest visits past `HISTORY_LIMIT`, as saving would, and
+/// returns how many, for imports and sync to tell the user.
+pub fn trim_history(history: &mut Vec<HistoryEntry>) -> usize {
+    let over = history.len().saturating_sub(HISTORY_LIMIT);
+    history.drain(..over);
+    over
+}
+
+fn append_history_log(dir: &Path, entry: &HistoryEntry) -> bool {
+    let Ok(json) = serde_json::to_string(entry) else { return false };
+    let Ok(line) = crate::vault::seal_line_in(dir, &json) else { return false };
+    fs::OpenOptions::new().create(true).append(true).open(dir.join(HISTORY_LOG))
+        .and_then(|mut f| f.write_all(format!("{}\n", line).as_bytes()))
+        .is_ok()
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0)
+}
+
 pub fn append_history(history: &mut Vec<HistoryEntry>, url: &str, title: &str) {
+    append_history_in(&data_dir(), history, url, title)
+}
+
+fn append_history_in(dir: &Path, history: &mut Vec<HistoryEntry>, url: &str, title: &str) {
     if url == "about:blank" || url.starts_with("vccat:") || url.is_empty() { return; }
-    let ts = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs()).unwrap_or(0);
+    let ts = now();
     if let Some(last) = history.last() { if last.url == url { return; } }
-    history.push(HistoryEntry { url: url.into(), title: title.into(), timestamp: ts });
-    save_history(history);
+    let entry = HistoryEntry { url: url.into(), title: title.into(), timestamp: ts };
+    let logged = append_history_log(dir, &entry);
+    history.push(entry);
+    if !logged || HISTORY_LOG_LINES.fetch_add(1, Ordering::Relaxed) + 1 >= HISTORY_LOG_LIMIT {
+        save_history_in(dir, history);
+    }
 }
 
-pub fn webview_data_dir() -> PathBuf {
-    let d = data_dir().join("webview-data");
-    fs::create_dir_all(&d).ok();
-    d
-}
\ No newline at end of file
+// ── Profile lock ──────────────────────────────────────────────────────────────
+
+/// Marker holding the pid and start time of the instance running on the
+/// profile in `dir`.
+fn lock_path(dir: &Path) -> PathBuf {
+    dir.join("running.lock")
+}
+
+/// When the process started, in clock ticks since boot. With the pid it
+/// tells the instance that wrote a lock from a process that got its pid
+/// after the instance died.
+fn start_time(pid: u32) -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // the command name in parentheses may hold spaces; starttime is
+        // the 22nd field, the 20th after it
+        stat.rsplit_once(')')?.1.split_whitespace().nth(19)?.parse().ok()
+    }
+    #[cfg(not(target_os = "linux"))]
+    { let _ = pid; None }
+}
+
+/// Pid and start time in the lock; locks from before start times were
+/// written have only the pid.
+fn read_lock(dir: &Path) -> Option<(u32, Option<u64>)> {
+    let s = fs::read_to_string(lock_path(dir)).ok()?;
+    let mut parts = s.split_whitespace();
+    let pid = parts.next()?.parse().ok()?;
+    Some((pid, parts.next().and_then(|t| t.parse().ok())))
+}
+
+fn alive(pid: u32, start: Option<u64>) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        match start {
+            Some(t) => start_time(pid) == Some(t),
+            None => std::path::Path::new(&format!("/proc/{}", pid)).exists(),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    { let _ = (pid, start); true }
+}
+
+/// Pid of another live instance using this profile, if any.
+pub fn running_instance() -> Option<u32> {
+    running_instance_in(&data_dir())
+}
+
+fn running_instance_in(dir: &Path) -> Option<u32> {
+    let (pid, start) = read_lock(dir)?;
+    if pid != std::process::id() && alive(pid, start) { Some(pid) } else { None }
+}
+
+/// Marks the profile as in use. Returns true when the previous run left its
+/// lock behind, i.e. it was killed or crashed instead of exiting cleanly.
+pub fn lock_profile() -> bool {
+    lock_profile_in(&data_dir())
+}
+
+fn lock_profile_in(dir: &Path) -> bool {
+    let stale = read_lock(dir).is_some_and(|(pid, start)| pid != std::process::id() && !alive(pid, start));
+    let pid = std::process::id();
+    let start = start_time(pid).map(|t| format!(" {}", t)).unwrap_or_default();
+    fs::write(lock_path(dir), format!("{}{}", pid, start)).ok();
+    stale
+}
+
+pub fn unlock_profile() {
+    unlock_profile_in(&data_dir())
+}
+
+fn unlock_profile_in(dir: &Path) {
+    fs::remove_file(lock_path(dir)).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let d = std::env::temp_dir().join(format!("vccat-storage-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&d).ok();
+        fs::create_dir_all(&d).unwrap();
+        d
+    }
+
+    #[test]
+    fn history_log_is_folded_in() {
+        let dir = scratch("history");
+        let (json, log) = (dir.join("history.json"), dir.join(HISTORY_LOG));
+        let mut history = Vec::new();
+        append_history_in(&dir, &mut history, "https://a.pl/", "A");
+        append_history_in(&dir, &mut history, "https://a.pl/", "A");
+        append_history_in(&dir, &mut history, "vccat:settings", "");
+        append_history_in(&dir, &mut history, "https://b.pl/", "B");
+        assert_eq!(history.len(), 2);
+        assert!(!json.exists());
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 2);
+
+        let loaded = load_history_in(&dir);
+        assert_eq!(loaded.iter().map(|h| h.url.as_str()).collect::<Vec<_>>(), ["https://a.pl/", "https://b.pl/"]);
+        assert!(json.exists() && !log.exists());
+        // a log left next to the history.json it was already folded into
+        assert!(append_history_log(&dir, &history[1]));
+        assert_eq!(load_history_in(&dir).len(), 2);
+
+        let mut long: Vec<HistoryEntry> = (0..HISTORY_LIMIT as u64 + 3)
+            .map(|i| HistoryEntry { url: format!("https://a.pl/{}", i), title: String::new(), timestamp: i })
+            .collect();
+        assert_eq!(trim_history(&mut long), 3);
+        assert_eq!(long[0].timestamp, 3);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn named_sessions_save_and_restore() {
+        let dir = scratch("sessions");
+        assert!(load_named_sessions_in(&dir).is_empty());
+        let tabs = [("https://a.pl/", "A", false), ("https://tajne.pl/", "", true),
+                    ("vccat:sessions", "Sesje", false), ("vccat:history", "Historia", false)];
+        let mut sessions = Vec::new();
+        put_named_session(&mut sessions, NamedSession::snapshot("release-review", tabs));
+        put_named_session(&mut sessions, NamedSession::snapshot("incident-123", tabs[..1].iter().copied()));
+        assert_eq!(sessions[0].url_list(), "https://a.pl/\nvccat:history\n");
+        // saving under a taken name replaces that session
+        put_named_session(&mut sessions, NamedSession::snapshot("release-review", tabs[3..].iter().copied()));
+        save_named_sessions_in(&dir, &sessions);
+
+        let loaded = load_named_sessions_in(&dir);
+        assert_eq!(loaded.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["release-review", "incident-123"]);
+        assert_eq!(loaded[0].tabs.iter().map(|t| (t.url.as_str(), t.title.as_str())).collect::<Vec<_>>(),
+                   [("vccat:history", "Historia")]);
+        assert_eq!(loaded[1].url_list(), "https://a.pl/\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reused_pids_are_not_instances() {
+        let dir = scratch("lock");
+        let start = start_time(std::process::id());
+        assert!(start.is_some());
+        // pid 1 runs, but is not the process that wrote this lock
+        fs::write(lock_path(&dir), format!("1 {}", start.unwrap() + 1)).unwrap();
+        assert_eq!(running_instance_in(&dir), None);
+        assert!(lock_profile_in(&dir));
+        assert_eq!(read_lock(&dir), Some((std::process::id(), start)));
+        unlock_profile_in(&dir);
+        assert!(!lock_profile_in(&dir));
+        unlock_profile_in(&dir);
+        assert!(!lock_path(&dir).exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
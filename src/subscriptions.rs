@@ -0,0 +1,612 @@
+//! Filter list subscriptions: lists in Adblock Plus syntax fetched from
+//! their urls into filters/lists and refreshed by a background thread when
+//! their `! Expires:` period (or the filter list setting, for lists without
+//! one) runs out, and not before the server's cache headers say the copy
+//! has gone stale. Requests are conditional on the ETag and Last-Modified
+//! of the copy we have. A download that fails or doesn't look like a filter
+//! list leaves that copy in place; the copy before it is kept as
+//! `<id>.prev.txt` and takes over if the current one becomes unreadable.
+//! The user's own rules, written on vccat:filters, are kept in
+//! filters/user.txt and published with the lists.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::filters;
+
+/// How often the refresh thread looks for expired lists.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// A list whose last download failed is tried again after this long.
+const RETRY_SECS: u64 = 3600;
+
+/// Lists with at least this many filters may not shrink below half of it
+/// in one update; such a download is taken to be cut short.
+const SHRINK_GUARD: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct FetchState {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// When the list last changed here, unix seconds; 0 before the first download.
+    pub updated: u64,
+    /// When the server was last asked.
+    pub checked: u64,
+    /// The list's own `! Expires:`, in hours.
+    pub expires_hours: Option<u64>,
+    /// How long the server said its copy stays fresh, from Cache-Control
+    /// max-age or Expires, in seconds.
+    pub max_age: Option<u64>,
+    /// Filters in the current copy.
+    pub filters: usize,
+    /// Why the last download was rejected, if it was.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Subscription {
+    /// File name under filters/lists.
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub enabled: bool,
+    #[serde(default)]
+    pub state: FetchState,
+}
+
+impl Subscription {
+    fn new(id: &str, title: &str, url: &str, enabled: bool) -> Self {
+        Subscription { id: id.into(), title: title.into(), url: url.into(), enabled, state: FetchState::default() }
+    }
+
+    /// Whether the list should be asked for again at `now`.
+    pub fn is_due(&self, now: u64, default_hours: u64) -> bool {
+        if self.state.updated == 0 { return true; }
+        let period = self.state.expires_hours.unwrap_or(default_hours).clamp(1, 720) * 3600;
+        let period = period.max(self.state.max_age.unwrap_or(0).min(720 * 3600));
+        let period = if self.state.error.is_some() { period.min(RETRY_SECS) } else { period };
+        now.saturating_sub(self.state.checked) >= period
+    }
+}
+
+pub fn defaults() -> Vec<Subscription> {
+    vec![
+        Subscription::new("easylist", "EasyList", "https://easylist.to/easylist/easylist.txt", true),
+        Subscription::new("easyprivacy", "EasyPrivacy", "https://easylist.to/easylist/easyprivacy.txt", true),
+        Subscription::new("ublock", "uBlock filters",
+            "https://ublockorigin.github.io/uAssets/filters/filters.txt", true),
+        Subscription::new("adguard-annoyances", "AdGuard Annoyances",
+            "https://raw.githubusercontent.com/AdguardTeam/FiltersRegistry/master/filters/filter_14_Annoyances/filter.txt",
+            false),
+    ]
+}
+
+// ── Registry ──────────────────────────────────────────────────────────────────
+
+/// Held while subscriptions.json is read and written back, so the refresh
+/// thread doesn't undo changes made from the UI in between.
+static REGISTRY: Mutex<()> = Mutex::new(());
+
+fn registry_path(dir: &Path) -> PathBuf {
+    dir.join("subscriptions.json")
+}
+
+fn list_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join("lists").join(format!("{}.txt", id))
+}
+
+fn prev_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join("lists").join(format!("{}.prev.txt", id))
+}
+
+fn load_in(dir: &Path) -> Vec<Subscription> {
+    fs::read_to_string(registry_path(dir)).ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_else(defaults)
+}
+
+fn save_in(dir: &Path, subs: &[Subscription]) {
+    if let Ok(json) = serde_json::to_string_pretty(subs) {
+        fs::write(registry_path(dir), json).ok();
+    }
+}
+
+/// Applies `f` to the stored subscriptions and saves them.
+fn update_in<T>(dir: &Path, f: impl FnOnce(&mut Vec<Subscription>) -> T) -> T {
+    let _guard = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut subs = load_in(dir);
+    let out = f(&mut subs);
+    save_in(dir, &subs);
+    out
+}
+
+pub fn list() -> Vec<Subscription> {
+    load_in(&crate::adblock::filter_store_path())
+}
+
+pub fn set_enabled(id: &str, enabled: bool) {
+    update_in(&crate::adblock::filter_store_path(), |subs| {
+        if let Some(s) = subs.iter_mut().find(|s| s.id == id) { s.enabled = enabled; }
+    });
+}
+
+/// Subscribes to the list at `url`; it is downloaded on the next refresh.
+pub fn add(url: &str, title: &str) -> Result<(), String> {
+    add_in(&crate::adblock::filter_store_path(), url, title)
+}
+
+fn add_in(dir: &Path, url: &str, title: &str) -> Result<(), String> {
+    let parsed = url::Url::parse(url.trim()).map_err(|_| "nieprawidłowy adres listy".to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("lista musi być pod adresem http(s)".into());
+    }
+    let title = match title.trim() {
+        "" => parsed.path_segments().and_then(|mut p| p.next_back()).filter(|n| !n.is_empty())
+            .or(parsed.host_str()).unwrap_or("lista").to_string(),
+        t => t.to_string(),
+    };
+    update_in(dir, |subs| {
+        if subs.iter().any(|s| s.url == parsed.as_str()) {
+            return Err("ta lista jest już subskrybowana".to_string());
+        }
+        let n = (1..).find(|n| !subs.iter().any(|s| s.id == format!("custom-{}", n))).unwrap_or(1);
+        subs.push(Subscription::new(&format!("custom-{}", n), &title, parsed.as_str(), true));
+        Ok(())
+    })
+}
+
+/// Drops a list added by the user, with its copies; the default lists can
+/// only be turned off.
+pub fn remove(id: &str) {
+    let dir = crate::adblock::filter_store_path();
+    if !id.starts_with("custom-") { return; }
+    update_in(&dir, |subs| subs.retain(|s| s.id != id));
+    fs::remove_file(list_path(&dir, id)).ok();
+    fs::remove_file(prev_path(&dir, id)).ok();
+}
+
+// ── User rules ────────────────────────────────────────────────────────────────
+
+/// The title the user's rules go by in the blocking log.
+pub const USER_TITLE: &str = "Własne reguły";
+
+fn user_path(dir: &Path) -> PathBuf {
+    dir.join("user.txt")
+}
+
+pub fn user_rules() -> String {
+    fs::read_to_string(user_path(&crate::adblock::filter_store_path())).unwrap_or_default()
+}
+
+/// Saves the rules as written, lines the parser rejects included, so they
+/// can still be fixed; those lines are skipped when publishing.
+pub fn save_user_rules(text: &str) -> Result<(), String> {
+    fs::write(user_path(&crate::adblock::filter_store_path()), text).map_err(|e| e.to_string())
+}
+
+// ── Downloads ─────────────────────────────────────────────────────────────────
+
+/// Hours from a `! Expires: 4 days (update frequency)` header line.
+fn parse_expires(text: &str) -> Option<u64> {
+    let header = text.lines().take_while(|l| l.starts_with('!') || l.starts_with('[') || l.trim().is_empty());
+    let value = header.filter_map(|l| l.trim_start_matches('!').trim().strip_prefix("Expires:")).next()?;
+    let mut words = value.split_whitespace();
+    let n: u64 = words.next()?.parse().ok()?;
+    match words.next()? {
+        u if u.starts_with("day") => Some(n * 24),
+        u if u.starts_with("hour") => Some(n),
+        _ => None,
+    }
+}
+
+/// Unix seconds of an HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`).
+fn http_date(s: &str) -> Option<u64> {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts[..] else { return None };
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter().position(|m| *m == month)? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+    let hms: Vec<i64> = time.split(':').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+    let [h, m, sec] = hms[..] else { return None };
+    // days since the epoch of a civil date, after Howard Hinnant
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146_097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719_468;
+    u64::try_from(days * 86_400 + h * 3600 + m * 60 + sec).ok()
+}
+
+/// Seconds the response stays fresh by its cache headers: Cache-Control
+/// max-age, else Expires counted from Date (or `now`). None when the
+/// server asks not to cache or says nothing.
+fn max_age(headers: &reqwest::header::HeaderMap, now: u64) -> Option<u64> {
+    use reqwest::header::{CACHE_CONTROL, DATE, EXPIRES};
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(cc) = header(CACHE_CONTROL) {
+        let directives: Vec<&str> = cc.split(',').map(str::trim).collect();
+        if directives.iter().any(|d| d.eq_ignore_ascii_case("no-cache") || d.eq_ignore_ascii_case("no-store")) {
+            return None;
+        }
+        if let Some(n) = directives.iter().find_map(|d| d.strip_prefix("max-age=")) {
+            return n.trim_matches('"').parse().ok();
+        }
+    }
+    let expires = http_date(header(EXPIRES)?)?;
+    let date = header(DATE).and_then(http_date).unwrap_or(now);
+    Some(expires.saturating_sub(date))
+}
+
+/// Checks that a download is a filter list and not an error page or a
+/// truncated copy; returns its number of filters.
+fn check_list(text: &str, previous: Option<usize>) -> Result<usize, String> {
+    let start = text.trim_start();
+    if start.starts_with('<') {
+        return Err("serwer zwrócił stronę HTML zamiast listy".into());
+    }
+    let parsed = filters::parse_list(text);
+    let n = parsed.network.len() + parsed.cosmetic.len() + parsed.scriptlets.len();
+    if n == 0 {
+        return Err("lista nie zawiera filtrów".into());
+    }
+    if parsed.unsupported.len() > n && !start.starts_with("[Adblock") {
+        return Err("większość linii nie jest filtrami".into());
+    }
+    if let Some(p) = previous.filter(|p| *p >= SHRINK_GUARD && n < p / 2) {
+        return Err(format!("lista skurczyła się z {} do {} filtrów", p, n));
+    }
+    Ok(n)
+}
+
+/// Makes `text` the current copy, keeping the one it replaces as `.prev`.
+fn install(dir: &Path, id: &str, text: &str) -> Result<(), String> {
+    let path = list_path(dir, id);
+    fs::create_dir_all(dir.join("lists")).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension("txt.new");
+    fs::write(&tmp, text).map_err(|e| e.to_string())?;
+    if path.exists() {
+        fs::rename(&path, prev_path(dir, id)).map_err(|e| e.to_string())?;
+    }
+    fs::rename(&tmp, &path).map_err(|e| e.to_string())
+}
+
+/// The current copy of a list, or the previous one when the current copy
+/// is missing or damaged; the previous copy then becomes current again.
+fn load_list(dir: &Path, id: &str) -> Option<String> {
+    let path = list_path(dir, id);
+    if let Some(text) = fs::read_to_string(&path).ok().filter(|t| check_list(t, None).is_ok()) {
+        return Some(text);
+    }
+    let prev = fs::read_to_string(prev_path(dir, id)).ok().filter(|t| check_list(t, None).is_ok())?;
+    fs::write(&path, &prev).ok();
+    Some(prev)
+}
+
+fn client() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .user_agent("vccat-browser")
+        .build()
+        .unwrap_or_default()
+}
+
+/// Asks the server for a newer copy of one list. Ok(true) when a new copy
+/// was installed; the state records the outcome either way.
+fn fetch(client: &reqwest::blocking::Client, dir: &Path, sub: &mut Subscription, now: u64) -> Result<bool, String> {
+    use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+    sub.state.checked = now;
+    let have = list_path(dir, &sub.id).exists();
+    let mut req = client.get(&sub.url);
+    if have {
+        if let Some(e) = &sub.state.etag { req = req.header(IF_NONE_MATCH, e); }
+        if let Some(m) = &sub.state.last_modified { req = req.header(IF_MODIFIED_SINCE, m); }
+    }
+    let resp = req.send().map_err(|e| e.to_string())?;
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED && have {
+        sub.state.max_age = max_age(resp.headers(), now);
+        return Ok(false);
+    }
+    if !resp.status().is_success() {
+        return Err(format!("HTTP {}", resp.status().as_u16()));
+    }
+    let header = |name| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+    let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+    let fresh = max_age(resp.headers(), now);
+    let text = resp.text().map_err(|e| e.to_string())?;
+    let n = check_list(&text, have.then_some(sub.state.filters))?;
+    install(dir, &sub.id, &text)?;
+    sub.state = FetchState {
+        etag, last_modified, updated: now, checked: now,
+        expires_hours: parse_expires(&text), max_age: fresh, filters: n, error: None,
+    };
+    Ok(true)
+}
+
+/// Fetches the enabled lists that are due, or all of them with `force`.
+/// Returns whether any list changed.
+fn refresh_in(dir: &Path, client: &reqwest::blocking::Client, force: bool, default_hours: u64, now: u64) -> bool {
+    let subs = update_in(dir, |s| s.clone());
+    let mut changed = false;
+    for mut sub in subs.into_iter().filter(|s| s.enabled) {
+        if !force && !sub.is_due(now, default_hours) { continue; }
+        match fetch(client, dir, &mut sub, now) {
+            Ok(c) => { changed |= c; sub.state.error = None; }
+            Err(e) => sub.state.error = Some(e),
+        }
+        update_in(dir, |all| {
+            if let Some(s) = all.iter_mut().find(|s| s.id == sub.id) { s.state = sub.state.clone(); }
+        });
+    }
+    changed
+}
+
+/// Every enabled list with a good copy, by title; empty while there is
+/// nothing downloaded yet.
+fn load_lists(dir: &Path) -> Vec<(String, filters::FilterList)> {
+    load_in(dir).into_iter().filter(|s| s.enabled)
+        .filter_map(|s| Some((s.title, filters::parse_list(&load_list(dir, &s.id)?))))
+        .collect()
+}
+
+/// The user's rules, when there are any.
+fn load_user(dir: &Path) -> Option<(String, filters::FilterList)> {
+    let list = filters::parse_list(&fs::read_to_string(user_path(dir)).ok()?);
+    let empty = list.network.is_empty() && list.cosmetic.is_empty() && list.scriptlets.is_empty();
+    (!empty).then(|| (USER_TITLE.to_string(), list))
+}
+
+/// Hands the lists on disk and the user's rules over: element hiding goes
+/// to the cosmetic engine, scriptlets to theirs, network filters to the
+/// blocking log and, compiled for WebKit as one list so exceptions in one
+/// apply to blocks from another, to `rules`. The built-in rules stand in
+/// while no list has been downloaded.
+fn publish(dir: &Path, rules: &dyn Fn(String)) {
+    let mut lists = load_lists(dir);
+    let downloaded = !lists.is_empty();
+    lists.extend(load_user(dir));
+    let builtin = filters::parse_list(crate::adblock::builtin_rules());
+    let (mut cosmetic, mut scriptlets) = (builtin.cosmetic, builtin.scriptlets);
+    let mut network = filters::FilterList::default();
+    let mut tagged = Vec::new();
+    for (title, mut list) in lists {
+        cosmetic.append(&mut list.cosmetic);
+        scriptlets.append(&mut list.scriptlets);
+        network.network.extend(list.network.iter().cloned());
+        tagged.push((title, list.network));
+    }
+    let json = if downloaded {
+        serde_json::Value::Array(filters::compile(&network)).to_string()
+    } else if network.network.is_empty() {
+        crate::adblock::builtin_youtube_rules().into()
+    } else {
+        // the user's rules after the built-in ones, so their exceptions count
+        let mut all: Vec<serde_json::Value> =
+            serde_json::from_str(crate::adblock::builtin_youtube_rules()).unwrap_or_default();
+        all.extend(filters::compile(&network));
+        serde_json::Value::Array(all).to_string()
+    };
+    crate::cosmetic::install(crate::cosmetic::Engine::new(&cosmetic));
+    crate::scriptlets::install(crate::scriptlets::Engine::new(&scriptlets));
+    crate::blocklog::install(crate::blocklog::Matcher::new(tagged));
+    rules(json);
+}
+
+// ── Refresh thread ────────────────────────────────────────────────────────────
+
+/// The filter list setting, for lists without `! Expires:`.
+static DEFAULT_MAX_AGE: AtomicU64 = AtomicU64::new(24);
+
+pub fn set_default_max_age(hours: u64) {
+    DEFAULT_MAX_AGE.store(hours, Ordering::Relaxed);
+}
+
+/// What the refresh thread is asked to do.
+pub enum Refresh {
+    /// Ask for every enabled list now.
+    Check,
+    /// Subscriptions or the user's rules changed: fetch what is due (a list
+    /// just turned on is) and publish again.
+    Apply,
+}
+
+/// Starts the refresh thread. The lists are published (see `publish`) on
+/// that thread: first those already on disk, then again after every update
+/// that changed a list and on `Refresh::Apply`.
+pub fn spawn_refresh(rules: impl Fn(String) + Send + 'static) -> mpsc::Sender<Refresh> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let dir = crate::adblock::filter_store_path();
+        let client = client();
+        publish(&dir, &rules);
+        let (mut force, mut republish) = (false, false);
+        loop {
+            let hours = DEFAULT_MAX_AGE.load(Ordering::Relaxed);
+            if refresh_in(&dir, &client, force, hours, crate::storage::now()) || republish {
+                publish(&dir, &rules);
+            }
+            (force, republish) = match rx.recv_timeout(CHECK_INTERVAL) {
+                Ok(Refresh::Check) => (true, false),
+                Ok(Refresh::Apply) => (false, true),
+                Err(mpsc::RecvTimeoutError::Timeout) => (false, false),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    const LIST: &str = "[Adblock Plus 2.0]\n! Title: Test\n! Expires: 2 days\n||ads.example^\n##.banner\n";
+
+    /// A local stand-in for a list server: answers each request with the
+    /// next canned response and records the request headers.
+    struct Server {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    fn serve(responses: Vec<String>) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/list.txt", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        std::thread::spawn(move || {
+            for (stream, resp) in listener.incoming().zip(responses) {
+                let Ok(mut stream) = stream else { return };
+                let mut head = String::new();
+                let mut reader = BufReader::new(&stream);
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" { break; }
+                    head.push_str(&line.to_ascii_lowercase());
+                }
+                log.lock().unwrap().push(head);
+                stream.write_all(resp.as_bytes()).ok();
+            }
+        });
+        Server { url, requests }
+    }
+
+    fn ok(body: &str, etag: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            etag, body.len(), body)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let d = std::env::temp_dir().join(format!("vccat-subs-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&d).ok();
+        fs::create_dir_all(&d).unwrap();
+        d
+    }
+
+    fn subscribe(dir: &Path, url: &str) {
+        save_in(dir, &[Subscription::new("test", "Test", url, true)]);
+    }
+
+    #[test]
+    fn downloads_and_honours_etag() {
+        let dir = temp_dir("etag");
+        let server = serve(vec![
+            ok(LIST, "\"v1\""),
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".into(),
+        ]);
+        subscribe(&dir, &server.url);
+        assert!(refresh_in(&dir, &client(), false, 24, 1000));
+        let s = &load_in(&dir)[0];
+        assert_eq!((s.state.filters, s.state.expires_hours), (2, Some(48)));
+        assert_eq!(s.state.etag.as_deref(), Some("\"v1\""));
+
+        // not due yet: nothing is asked
+        assert!(!refresh_in(&dir, &client(), false, 24, 1000 + 3600));
+        // forced: conditional request, answered with 304
+        assert!(!refresh_in(&dir, &client(), true, 24, 1000 + 7200));
+        let requests = server.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert_eq!(load_in(&dir)[0].state.checked, 1000 + 7200);
+        let lists = load_lists(&dir);
+        assert_eq!((lists[0].1.network.len(), lists[0].1.cosmetic.len()), (1, 1));
+    }
+
+    #[test]
+    fn bad_downloads_keep_the_good_copy() {
+        let dir = temp_dir("bad");
+        let big: String = (0..200).map(|i| format!("||ad{}.example^\n", i)).collect();
+        let server = serve(vec![
+            ok(&big, "\"v1\""),
+            ok("<!DOCTYPE html><html>rate limited</html>", "\"v2\""),
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".into(),
+            ok("||ad1.example^\n", "\"v3\""),
+        ]);
+        subscribe(&dir, &server.url);
+        assert!(refresh_in(&dir, &client(), true, 24, 1));
+        for _ in 0..3 {
+            assert!(!refresh_in(&dir, &client(), true, 24, 2));
+        }
+        let s = &load_in(&dir)[0];
+        assert_eq!(s.state.filters, 200);
+        assert_eq!(s.state.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(s.state.error.as_deref(), Some("lista skurczyła się z 200 do 1 filtrów"));
+        assert_eq!(fs::read_to_string(list_path(&dir, "test")).unwrap(), big);
+        // a failed download is retried within the hour
+        assert!(s.is_due(2 + RETRY_SECS, 24));
+    }
+
+    #[test]
+    fn damaged_copy_rolls_back() {
+        let dir = temp_dir("rollback");
+        install(&dir, "test", LIST).unwrap();
+        install(&dir, "test", "||other.example^\n").unwrap();
+        assert_eq!(fs::read_to_string(prev_path(&dir, "test")).unwrap(), LIST);
+        fs::write(list_path(&dir, "test"), "").unwrap();
+        assert_eq!(load_list(&dir, "test").as_deref(), Some(LIST));
+        assert_eq!(fs::read_to_string(list_path(&dir, "test")).unwrap(), LIST);
+    }
+
+    #[test]
+    fn added_lists_and_user_rules() {
+        let dir = temp_dir("user");
+        save_in(&dir, &[]);
+        add_in(&dir, "https://example.org/lists/extra.txt", "").unwrap();
+        assert!(add_in(&dir, "https://example.org/lists/extra.txt", "x").is_err());
+        assert!(add_in(&dir, "ftp://example.org/a.txt", "").is_err());
+        add_in(&dir, "https://example.org/b.txt", "Druga").unwrap();
+        let subs = load_in(&dir);
+        let ids: Vec<(&str, &str)> = subs.iter().map(|s| (s.id.as_str(), s.title.as_str())).collect();
+        assert_eq!(ids, vec![("custom-1", "extra.txt"), ("custom-2", "Druga")]);
+
+        // nothing downloaded: the user's rules join the built-in ones
+        fs::write(user_path(&dir), "||tracker.test^\nbad rule$redirect=x\n").unwrap();
+        let json = std::cell::RefCell::new(String::new());
+        publish(&dir, &|j| *json.borrow_mut() = j);
+        let rules: Vec<serde_json::Value> = serde_json::from_str(&json.borrow()).unwrap();
+        let builtin: Vec<serde_json::Value> =
+            serde_json::from_str(crate::adblock::builtin_youtube_rules()).unwrap();
+        assert_eq!(rules.len(), builtin.len() + 1);
+        assert!(rules.last().unwrap()["trigger"]["url-filter"].as_str().unwrap().contains("tracker"));
+    }
+
+    #[test]
+    fn expiry() {
+        assert_eq!(parse_expires("[Adblock Plus 2.0]\n! Expires: 4 days (update frequency)\n||a^"), Some(96));
+        assert_eq!(parse_expires("! Title: x\n! Expires: 12 hours\n"), Some(12));
+        assert_eq!(parse_expires("||a^\n! Expires: 1 day"), None);
+        let mut s = Subscription::new("a", "A", "http://x/", true);
+        assert!(s.is_due(0, 24));
+        s.state = FetchState { updated: 10, checked: 10, expires_hours: Some(96), ..Default::default() };
+        assert!(!s.is_due(10 + 24 * 3600, 24));
+        assert!(s.is_due(10 + 96 * 3600, 24));
+        // the server keeps its copy fresh for longer: not asked before then
+        s.state.max_age = Some(7 * 24 * 3600);
+        assert!(!s.is_due(10 + 96 * 3600, 24));
+        assert!(s.is_due(10 + 7 * 24 * 3600, 24));
+    }
+
+    #[test]
+    fn cache_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue, CACHE_CONTROL, DATE, EXPIRES};
+        let headers = |pairs: &[(reqwest::header::HeaderName, &str)]| {
+            let mut h = HeaderMap::new();
+            for (k, v) in pairs { h.insert(k, HeaderValue::from_str(v).unwrap()); }
+            h
+        };
+        assert_eq!(http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+        assert_eq!(http_date("Thu, 29 Feb 2024 00:00:00 GMT"), Some(1_709_164_800));
+        assert_eq!(http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(max_age(&headers(&[(CACHE_CONTROL, "public, max-age=86400")]), 0), Some(86_400));
+        assert_eq!(max_age(&headers(&[(CACHE_CONTROL, "no-cache, max-age=86400")]), 0), None);
+        assert_eq!(max_age(&headers(&[(DATE, "Sun, 06 Nov 1994 08:49:37 GMT"),
+                                      (EXPIRES, "Mon, 07 Nov 1994 08:49:37 GMT")]), 0), Some(86_400));
+        assert_eq!(max_age(&headers(&[(EXPIRES, "0")]), 0), None);
+        assert_eq!(max_age(&HeaderMap::new(), 0), None);
+    }
+}
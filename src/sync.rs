@@ -0,0 +1,612 @@
+//! Sync: profile data shared through a plain directory (a mounted share, a
+//! Syncthing folder, ...). Every device appends its own changes to
+//! `<device>.jsonl` and never touches other devices' logs, so instances can
+//! write at the same time without locking. Logs are merged last-writer-wins
+//! per record, ordered by a Lamport clock with the device id as tie-break,
+//! which gives every device the same result whatever order it reads them in.
+//! Rounds run on a worker thread (see `spawn_worker`); the event loop
+//! applies what they bring in.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use crate::bookmarks::{self, Bookmarks};
+use crate::settings::{self, Settings};
+use crate::storage::HistoryEntry;
+
+/// Own log is compacted once it holds this many superseded changes.
+const COMPACT_AFTER: usize = 2000;
+
+/// A log lock older than this was left by a writer that died.
+const LOCK_STALE: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncConfig {
+    /// Shared directory; sync is off while unset.
+    pub dir: Option<PathBuf>,
+    pub device_id: String,
+    pub device_name: String,
+}
+
+/// What this device last published or applied, per record.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SyncState {
+    pub clock: u64,
+    pub known: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Change {
+    /// `kind/id`, e.g. `bookmark/https://example.com/`.
+    pub key: String,
+    /// `None` marks a deletion.
+    pub value: Option<Value>,
+    pub clock: u64,
+    pub device: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoteTabs {
+    pub device: String,
+    pub name: String,
+    pub tabs: Vec<String>,
+    pub updated: u64,
+}
+
+/// Records other devices won, not yet applied to the profile.
+#[derive(Clone, Debug, Default)]
+pub struct Incoming {
+    pub history: Vec<HistoryEntry>,
+    /// Bookmark url and its record, `None` for a deletion.
+    pub bookmarks: Vec<(String, Option<Value>)>,
+    /// Settings by field name.
+    pub settings: serde_json::Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub published: usize,
+    pub history_added: usize,
+    pub bookmarks_changed: usize,
+    pub settings_changed: bool,
+    pub remote_tabs: Vec<RemoteTabs>,
+    pub incoming: Incoming,
+    /// The state after the round, to be saved once `incoming` is applied:
+    /// records it lists as known are not brought in again.
+    pub state: SyncState,
+}
+
+fn new_device_id() -> String {
+    let seed = format!("{:?}{}{:?}", std::time::SystemTime::now(), std::process::id(),
+                       dirs::home_dir());
+    Sha256::digest(seed.as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn load_config() -> SyncConfig {
+    let path = crate::storage::data_dir().join("sync.json");
+    if let Ok(s) = fs::read_to_string(&path) {
+        if let Ok(c) = serde_json::from_str::<SyncConfig>(&s) { return c; }
+    }
+    let c = SyncConfig { dir: None, device_id: new_device_id(), device_name: "vccat".into() };
+    save_config(&c);
+    c
+}
+
+pub fn save_config(c: &SyncConfig) {
+    if let Ok(s) = serde_json::to_string_pretty(c) {
+        fs::write(crate::storage::data_dir().join("sync.json"), s).ok();
+    }
+}
+
+pub fn load_state() -> SyncState {
+    crate::storage::read_protected("sync-state.json")
+        .and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+}
+
+pub fn save_state(state: &SyncState) {
+    if let Ok(s) = serde_json::to_string(state) {
+        crate::storage::write_protected("sync-state.json", &s);
+    }
+}
+
+fn digest(v: &Option<Value>) -> String {
+    let s = v.as_ref().map(|v| v.to_string()).unwrap_or_default();
+    Sha256::digest(s.as_bytes()).iter().take(12).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// True when `a` wins over `b`.
+fn newer(a: &Change, b: &Change) -> bool {
+    (a.clock, &a.device) > (b.clock, &b.device)
+}
+
+/// Reads one log; a line that doesn't parse (e.g. still being written by
+/// its device or by the file syncer) is skipped.
+fn read_log(path: &Path) -> Vec<Change> {
+    fs::read_to_string(path).unwrap_or_default().lines()
+        .filter_map(|l| serde_json::from_str(l).ok()).collect()
+}
+
+fn log_path(shared: &Path, device: &str) -> PathBuf {
+    shared.join(format!("{}.jsonl", device))
+}
+
+/// Merges every device log in `shared` into the winning change per key.
+pub fn merge_logs(shared: &Path) -> BTreeMap<String, Change> {
+    let mut merged: BTreeMap<String, Change> = BTreeMap::new();
+    let mut logs: Vec<PathBuf> = fs::read_dir(shared).into_iter().flatten().flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|x| x == "jsonl"))
+        .collect();
+    logs.sort();
+    for path in logs {
+        for c in read_log(&path) {
+            match merged.get(&c.key) {
+                Some(cur) if !newer(&c, cur) => {}
+                _ => { merged.insert(c.key.clone(), c); }
+            }
+        }
+    }
+    merged
+}
+
+/// Held while a device's log is written. Compaction rewrites the log, so a
+/// line appended meanwhile by another writer under the same device id (the
+/// profile copied to a second machine, say) would be lost without it.
+struct LogLock(PathBuf);
+
+impl LogLock {
+    fn take(shared: &Path, device: &str) -> std::io::Result<LogLock> {
+        let path = shared.join(format!(".{}.lock", device));
+        for _ in 0..100 {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(LogLock(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path).and_then(|m| m.modified())
+                        .map_or(true, |t| t.elapsed().unwrap_or_default() > LOCK_STALE);
+                    if stale { fs::remove_file(&path).ok(); } else { std::thread::sleep(Duration::from_millis(100)); }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(std::io::Error::other("dziennik urządzenia jest zajęty przez inny proces"))
+    }
+}
+
+impl Drop for LogLock {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+fn append(shared: &Path, device: &str, changes: &[Change]) -> std::io::Result<()> {
+    if changes.is_empty() { return Ok(()); }
+    let mut buf = String::new();
+    for c in changes {
+        buf.push_str(&serde_json::to_string(c).map_err(std::io::Error::other)?);
+        buf.push('\n');
+    }
+    // one append per sync: readers see whole lines or nothing new
+    let mut f = fs::OpenOptions::new().create(true).append(true).open(log_path(shared, device))?;
+    f.write_all(buf.as_bytes())
+}
+
+/// Rewrites the own log keeping only the latest change per key.
+fn compact(shared: &Path, device: &str) -> std::io::Result<()> {
+    let path = log_path(shared, device);
+    let all = read_log(&path);
+    let mut latest: BTreeMap<String, Change> = BTreeMap::new();
+    for c in &all {
+        match latest.get(&c.key) {
+            Some(cur) if !newer(c, cur) => {}
+            _ => { latest.insert(c.key.clone(), c.clone()); }
+        }
+    }
+    if all.len() - latest.len() < COMPACT_AFTER { return Ok(()); }
+    let tmp = shared.join(format!(".{}.jsonl.tmp", device));
+    let mut out = String::new();
+    for c in latest.values() {
+        out.push_str(&serde_json::to_string(c).map_err(std::io::Error::other)?);
+        out.push('\n');
+    }
+    fs::write(&tmp, out)?;
+    fs::rename(tmp, path)
+}
+
+// ── Local records ─────────────────────────────────────────────────────────────
+
+fn history_records(history: &[HistoryEntry], out: &mut BTreeMap<String, Value>) {
+    for h in history {
+        out.insert(format!("history/{}|{}", h.timestamp, h.url),
+                   json!({"url": h.url, "title": h.title, "timestamp": h.timestamp}));
+    }
+}
+
+fn bookmark_records(b: &Bookmarks, out: &mut BTreeMap<String, Value>) {
+    for m in &b.items {
+        let mut path = Vec::new();
+        let mut cur = m.folder;
+        while let Some(f) = b.folder(cur) {
+            path.push(f.name.clone());
+            if path.len() > 64 { break; }
+            cur = f.parent;
+        }
+        path.reverse();
+        out.insert(format!("bookmark/{}", m.url), json!({
+            "url": m.url, "title": m.title, "path": path, "tags": m.tags,
+            "keyword": m.keyword, "created": m.created, "modified": m.modified,
+        }));
+    }
+}
+
+fn setting_records(s: &Settings, out: &mut BTreeMap<String, Value>) {
+    if let Ok(Value::Object(fields)) = serde_json::to_value(s) {
+        for (name, v) in fields {
+            if settings::LOCAL_ONLY.contains(&name.as_str()) { continue; }
+            out.insert(format!("setting/{}", name), v);
+        }
+    }
+}
+
+fn apply_bookmark(b: &mut Bookmarks, url: &str, value: &Option<Value>) {
+    let Some(v) = value else {
+        b.items.retain(|m| m.url != url);
+        return;
+    };
+    let mut folder = bookmarks::ROOT;
+    for part in v["path"].as_array().into_iter().flatten().filter_map(|p| p.as_str()) {
+        folder = match b.folders.iter().find(|f| f.parent == folder && f.name == part) {
+            Some(f) => f.id,
+            None => b.add_folder(part, folder),
+        };
+    }
+    let id = match b.find_by_url(url) {
+        Some(m) => m.id,
+        None => b.add(url, "", folder),
+    };
+    if let Some(m) = b.get_mut(id) {
+        m.title = v["title"].as_str().unwrap_or("").into();
+        m.folder = folder;
+        m.tags = v["tags"].as_array().into_iter().flatten()
+            .filter_map(|t| t.as_str().map(String::from)).collect();
+        m.keyword = v["keyword"].as_str().map(String::from);
+        m.created = v["created"].as_u64().unwrap_or(m.created);
+        m.modified = v["modified"].as_u64().unwrap_or(m.modified);
+    }
+}
+
+/// One sync round against `shared`: publishes local changes made since the
+/// last round, and collects the records other devices won in
+/// `Report::incoming` (see `apply`).
+pub fn sync(
+    shared: &Path,
+    config: &SyncConfig,
+    state: &mut SyncState,
+    history: &[HistoryEntry],
+    bookmarks: &Bookmarks,
+    settings: &Settings,
+    open_tabs: &[String],
+) -> std::io::Result<Report> {
+    fs::create_dir_all(shared)?;
+    let me = config.device_id.as_str();
+    let mut merged = merge_logs(shared);
+    state.clock = merged.values().map(|c| c.clock).fold(state.clock, u64::max);
+
+    // ── publish ──
+    let mut local = BTreeMap::new();
+    history_records(history, &mut local);
+    bookmark_records(bookmarks, &mut local);
+    setting_records(settings, &mut local);
+    local.insert(format!("tabs/{}", me), json!({
+        "name": config.device_name, "tabs": open_tabs, "updated": crate::storage::now(),
+    }));
+
+    let mut changes = Vec::new();
+    let mut publish = |key: &str, value: Option<Value>, state: &mut SyncState| {
+        let d = digest(&value);
+        if state.known.get(key) == Some(&d) { return; }
+        // the open tabs record changes its timestamp every round
+        if key.starts_with("tabs/") {
+            let strip = |v: &Option<Value>| v.as_ref().map(|v| v["tabs"].clone());
+            if merged.get(key).map(|c| strip(&c.value)) == Some(strip(&value)) { return; }
+        }
+        state.clock += 1;
+        state.known.insert(key.to_string(), d);
+        changes.push(Change { key: key.into(), value, clock: state.clock, device: me.into() });
+    };
+    for (key, value) in &local {
+        publish(key, Some(value.clone()), state);
+    }
+    // bookmarks known before but gone now were deleted here
+    let gone: Vec<String> = state.known.keys()
+        .filter(|k| k.starts_with("bookmark/") && !local.contains_key(*k)).cloned().collect();
+    for key in gone {
+        if merged.get(&key).is_some_and(|c| c.value.is_none()) {
+            state.known.insert(key, digest(&None));
+            continue;
+        }
+        publish(&key, None, state);
+    }
+    {
+        let _lock = LogLock::take(shared, me)?;
+        append(shared, me, &changes)?;
+        compact(shared, me).ok();
+    }
+    for c in &changes {
+        merged.insert(c.key.clone(), c.clone());
+    }
+
+    // ── apply ──
+    let mut report = Report { published: changes.len(), ..Default::default() };
+    let mut incoming = Incoming::default();
+    for (key, c) in &merged {
+        if let Some(device) = key.strip_prefix("tabs/") {
+            if device != me {
+                if let Some(v) = &c.value {
+                    report.remote_tabs.push(RemoteTabs {
+                        device: device.into(),
+                        name: v["name"].as_str().unwrap_or(device).into(),
+                        tabs: v["tabs"].as_array().into_iter().flatten()
+                            .filter_map(|t| t.as_str().map(String::from)).collect(),
+                        updated: v["updated"].as_u64().unwrap_or(0),
+                    });
+                }
+            }
+            continue;
+        }
+        let d = digest(&c.value);
+        if c.device == me || state.known.get(key) == Some(&d) { continue; }
+        if key.starts_with("history/") {
+            if let Some(v) = &c.value {
+                incoming.history.push(HistoryEntry {
+                    url: v["url"].as_str().unwrap_or("").into(),
+                    title: v["title"].as_str().unwrap_or("").into(),
+                    timestamp: v["timestamp"].as_u64().unwrap_or(0),
+                });
+            }
+        } else if let Some(url) = key.strip_prefix("bookmark/") {
+            incoming.bookmarks.push((url.to_string(), c.value.clone()));
+        } else if let (Some(name), Some(v)) = (key.strip_prefix("setting/"), &c.value) {
+            incoming.settings.insert(name.to_string(), v.clone());
+        }
+        state.known.insert(key.clone(), d);
+    }
+    report.incoming = incoming;
+    report.state = state.clone();
+    Ok(report)
+}
+
+/// Applies what other devices won to the profile and counts it in the
+/// report. The caller saves what changed, then `report.state`.
+pub fn apply(report: &mut Report, history: &mut Vec<HistoryEntry>, bookmarks: &mut Bookmarks, settings: &mut Settings) {
+    let incoming = std::mem::take(&mut report.incoming);
+    for (url, value) in &incoming.bookmarks {
+        apply_bookmark(bookmarks, url, value);
+    }
+    report.bookmarks_changed = incoming.bookmarks.len();
+    report.history_added = crate::importer::merge_history(history, incoming.history);
+    crate::storage::trim_history(history);
+    if incoming.settings.is_empty() { return; }
+    let mut fields = serde_json::to_value(&*settings).unwrap_or(Value::Null);
+    for (name, v) in incoming.settings {
+        fields[name] = v;
+    }
+    // a remote value this build can't use leaves the local settings alone
+    if let Ok(remote) = serde_json::from_value::<Settings>(fields) {
+        if remote != *settings && remote.validate().is_ok() {
+            *settings = remote;
+            report.settings_changed = true;
+        }
+    }
+}
+
+/// A sync round asked of the worker, with a copy of the profile as it is.
+pub struct Job {
+    pub config: SyncConfig,
+    pub state: SyncState,
+    pub history: Vec<HistoryEntry>,
+    pub bookmarks: Bookmarks,
+    pub settings: Settings,
+    pub open_tabs: Vec<String>,
+    /// Where the result goes instead of the worker's callback, for a round
+    /// whose result is waited for.
+    pub reply: Option<mpsc::Sender<Result<Report, String>>>,
+}
+
+/// Starts the thread sync rounds run on, so a slow or unreachable shared
+/// folder doesn't hold up the UI. Results go to `done` unless the job
+/// brings its own reply channel.
+pub fn spawn_worker(done: impl Fn(Result<Report, String>) + Send + 'static) -> mpsc::Sender<Job> {
+    let (tx, rx) = mpsc::channel::<Job>();
+    std::thread::spawn(move || {
+        for job in rx {
+            let mut state = job.state;
+            let res = match job.config.dir.as_ref() {
+                Some(dir) => sync(dir, &job.config, &mut state, &job.history, &job.bookmarks,
+                                  &job.settings, &job.open_tabs)
+                    .map_err(|e| format!("{}: {}", dir.display(), e)),
+                None => Err("synchronizacja jest wyłączona".into()),
+            };
+            match job.reply {
+                Some(reply) => { reply.send(res).ok(); }
+                None => done(res),
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let d = std::env::temp_dir().join(format!("vccat-sync-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&d).ok();
+        fs::create_dir_all(&d).unwrap();
+        d
+    }
+
+    struct Device {
+        config: SyncConfig,
+        state: SyncState,
+        history: Vec<HistoryEntry>,
+        bookmarks: Bookmarks,
+        settings: Settings,
+        tabs: Vec<String>,
+    }
+
+    impl Device {
+        fn new(id: &str, shared: &Path) -> Self {
+            Device {
+                config: SyncConfig { dir: Some(shared.into()), device_id: id.into(), device_name: id.into() },
+                state: SyncState::default(),
+                history: vec![],
+                bookmarks: Bookmarks::default(),
+                settings: Settings::default(),
+                tabs: vec![],
+            }
+        }
+
+        fn sync(&mut self) -> Report {
+            let shared = self.config.dir.clone().unwrap();
+            let mut r = sync(&shared, &self.config, &mut self.state, &self.history,
+                             &self.bookmarks, &self.settings, &self.tabs).unwrap();
+            apply(&mut r, &mut self.history, &mut self.bookmarks, &mut self.settings);
+            r
+        }
+    }
+
+    fn visit(url: &str, ts: u64) -> HistoryEntry {
+        HistoryEntry { url: url.into(), title: url.into(), timestamp: ts }
+    }
+
+    #[test]
+    fn history_and_bookmarks_reach_the_other_device() {
+        let shared = temp_dir("basic");
+        let mut a = Device::new("a", &shared);
+        let mut b = Device::new("b", &shared);
+        a.history.push(visit("https://a.example/", 10));
+        a.bookmarks.add("https://rust-lang.org/", "Rust", bookmarks::ROOT);
+        b.history.push(visit("https://b.example/", 20));
+        a.sync();
+        b.sync();
+        a.sync();
+        assert_eq!(a.history.len(), 2);
+        assert_eq!(b.history.len(), 2);
+        assert!(b.bookmarks.is_bookmarked("https://rust-lang.org/"));
+    }
+
+    #[test]
+    fn deletions_propagate() {
+        let shared = temp_dir("delete");
+        let mut a = Device::new("a", &shared);
+        let mut b = Device::new("b", &shared);
+        a.bookmarks.add("https://x.example/", "X", bookmarks::ROOT);
+        a.sync();
+        b.sync();
+        assert!(b.bookmarks.is_bookmarked("https://x.example/"));
+        b.bookmarks.toggle("https://x.example/", "X");
+        b.sync();
+        a.sync();
+        assert!(!a.bookmarks.is_bookmarked("https://x.example/"));
+        // and nothing resurrects it on later rounds
+        a.sync();
+        b.sync();
+        assert!(!b.bookmarks.is_bookmarked("https://x.example/"));
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let shared = temp_dir("conflict");
+        let mut a = Device::new("a", &shared);
+        let mut b = Device::new("b", &shared);
+        a.bookmarks.add("https://c.example/", "first", bookmarks::ROOT);
+        a.sync();
+        b.sync();
+        // both edit the same bookmark before seeing each other's change
+        let id = a.bookmarks.find_by_url("https://c.example/").unwrap().id;
+        a.bookmarks.get_mut(id).unwrap().title = "from a".into();
+        let id = b.bookmarks.find_by_url("https://c.example/").unwrap().id;
+        b.bookmarks.get_mut(id).unwrap().title = "from b".into();
+        a.sync();
+        b.sync();
+        a.sync();
+        let ta = &a.bookmarks.find_by_url("https://c.example/").unwrap().title;
+        let tb = &b.bookmarks.find_by_url("https://c.example/").unwrap().title;
+        assert_eq!(ta, tb);
+    }
+
+    #[test]
+    fn merge_ignores_log_order_and_torn_lines() {
+        let shared = temp_dir("torn");
+        let mut a = Device::new("a", &shared);
+        a.bookmarks.add("https://t.example/", "T", bookmarks::ROOT);
+        a.sync();
+        // a half-written line as seen while another instance appends
+        let mut f = fs::OpenOptions::new().append(true).open(log_path(&shared, "b")).or_else(|_| {
+            fs::File::create(log_path(&shared, "b"))
+        }).unwrap();
+        f.write_all(b"{\"key\":\"bookmark/https://t.example/\",\"val").unwrap();
+        let merged = merge_logs(&shared);
+        assert_eq!(merged["bookmark/https://t.example/"].device, "a");
+    }
+
+    #[test]
+    fn compaction_keeps_lines_appended_meanwhile() {
+        let shared = temp_dir("lock");
+        let change = |key: String, clock: u64| Change { key, value: Some(Value::Bool(true)), clock, device: "a".into() };
+        // one writer keeps rewriting a single key, so its log is compacted
+        // often, while the other adds keys of its own under the same id
+        let rewriter = {
+            let shared = shared.clone();
+            std::thread::spawn(move || for round in 0..20u64 {
+                let batch: Vec<_> = (0..COMPACT_AFTER as u64 / 4).map(|i| change("same".into(), round * 10_000 + i)).collect();
+                let _lock = LogLock::take(&shared, "a").unwrap();
+                append(&shared, "a", &batch).unwrap();
+                compact(&shared, "a").unwrap();
+            })
+        };
+        for i in 0..200 {
+            let _lock = LogLock::take(&shared, "a").unwrap();
+            append(&shared, "a", &[change(format!("key/{}", i), i)]).unwrap();
+            compact(&shared, "a").unwrap();
+        }
+        rewriter.join().unwrap();
+        let merged = merge_logs(&shared);
+        assert_eq!(merged.len(), 201);
+        assert!(!shared.join(".a.lock").exists());
+    }
+
+    #[test]
+    fn settings_sync_except_local_ones() {
+        let shared = temp_dir("settings");
+        let mut a = Device::new("a", &shared);
+        let mut b = Device::new("b", &shared);
+        a.sync();
+        b.sync();
+        a.settings.home_page = "https://start.example/".into();
+        a.settings.window_width = 800;
+        a.sync();
+        let report = b.sync();
+        assert!(report.settings_changed);
+        assert_eq!(b.settings.home_page, "https://start.example/");
+        assert_eq!(b.settings.window_width, Settings::default().window_width);
+    }
+
+    #[test]
+    fn open_tabs_are_listed_for_other_devices() {
+        let shared = temp_dir("tabs");
+        let mut a = Device::new("a", &shared);
+        let mut b = Device::new("b", &shared);
+        a.tabs = vec!["https://one.example/".into()];
+        a.sync();
+        let report = b.sync();
+        assert_eq!(report.remote_tabs.len(), 1);
+        assert_eq!(report.remote_tabs[0].tabs, a.tabs);
+    }
+}
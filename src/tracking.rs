@@ -0,0 +1,171 @@
+//! Tracking parameters: query parameters such as `utm_source` or `fbclid`
+//! are removed from urls of pages as they load (the page's own GET loads,
+//! not its frames or form posts) and before urls are stored or copied. Rules
+//! are lines of parameter names (`utm_*` matches a prefix), optionally
+//! limited to one site: `youtube.com: si, pp`. Sites that break without
+//! their parameters go on the bypass list. The state is global because the
+//! WebView load handlers consult it from every tab.
+use std::sync::Mutex;
+use url::Url;
+
+pub fn default_rules() -> Vec<String> {
+    [
+        "utm_*", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "mc_eid", "mc_cid",
+        "yclid", "twclid", "igshid", "_hsenc", "_hsmi", "mkt_tok", "oly_enc_id", "oly_anon_id",
+        "vero_id", "__s", "ttclid",
+        "youtube.com: si, pp",
+        "youtu.be: si",
+        "open.spotify.com: si",
+        "x.com: s, t",
+        "twitter.com: s, t",
+        "amazon.com: pd_rd_*, pf_rd_*, ref_, content-id",
+        "allegro.pl: bi_*, reco_id, sid",
+    ].iter().map(|s| s.to_string()).collect()
+}
+
+struct Rule {
+    /// Site the rule is limited to, matching subdomains too.
+    site: Option<String>,
+    param: String,
+}
+
+impl Rule {
+    fn matches(&self, host: &str, key: &str) -> bool {
+        let site_ok = self.site.as_ref().is_none_or(|s| on_site(host, s));
+        let key_ok = match self.param.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == self.param,
+        };
+        site_ok && key_ok
+    }
+}
+
+fn on_site(host: &str, site: &str) -> bool {
+    host == site || host.strip_suffix(site).is_some_and(|h| h.ends_with('.'))
+}
+
+/// Parses rule lines; the error names the first bad one.
+fn parse_rules(lines: &[String]) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let (site, params) = match line.split_once(':') {
+            Some((site, params)) => (Some(site.trim().to_ascii_lowercase()), params),
+            None => (None, line),
+        };
+        if site.as_ref().is_some_and(|s| s.is_empty() || s.contains(['/', ' '])) {
+            return Err(format!("„{}”: nieprawidłowa nazwa strony", line));
+        }
+        for p in params.split(',').map(str::trim) {
+            let bad = p.is_empty() || p == "*" || p.contains(['=', '&', ' '])
+                || p.find('*').is_some_and(|i| i != p.len() - 1);
+            if bad {
+                return Err(format!("„{}”: nieprawidłowy parametr „{}”", line, p));
+            }
+            rules.push(Rule { site: site.clone(), param: p.to_string() });
+        }
+    }
+    Ok(rules)
+}
+
+/// Checks rule lines and bypass hosts, for settings validation.
+pub fn validate(rules: &[String], bypass: &[String]) -> Result<(), String> {
+    parse_rules(rules)?;
+    if let Some(b) = bypass.iter().find(|b| b.trim().is_empty() || b.contains(['/', ' ', ':'])) {
+        return Err(format!("„{}”: wyjątek musi być samą nazwą strony", b));
+    }
+    Ok(())
+}
+
+struct State {
+    enabled: bool,
+    rules: Vec<Rule>,
+    bypass: Vec<String>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State { enabled: false, rules: Vec::new(), bypass: Vec::new() });
+
+pub fn configure(enabled: bool, rules: &[String], bypass: &[String]) {
+    let mut s = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    s.enabled = enabled;
+    s.rules = parse_rules(rules).unwrap_or_default();
+    s.bypass = bypass.iter().map(|b| b.trim().to_ascii_lowercase()).collect();
+}
+
+fn strip_with(url: &str, rules: &[Rule], bypass: &[String]) -> Option<String> {
+    let mut u = Url::parse(url).ok().filter(|u| matches!(u.scheme(), "http" | "https"))?;
+    let host = u.host_str()?.to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    if bypass.iter().any(|b| on_site(host, b.strip_prefix("www.").unwrap_or(b))) { return None; }
+    let query = u.query()?;
+    // kept pairs stay exactly as they were written
+    let kept: Vec<&str> = query.split('&').filter(|pair| {
+        let raw = pair.split('=').next().unwrap_or("");
+        let key = urlencoding::decode(raw).map(|k| k.into_owned()).unwrap_or_else(|_| raw.into());
+        !rules.iter().any(|r| r.matches(host, &key))
+    }).collect();
+    if kept.len() == query.split('&').count() { return None; }
+    let kept = kept.join("&");
+    u.set_query(Some(&kept).filter(|q| !q.is_empty()).map(|q| q.as_str()));
+    Some(u.into())
+}
+
+/// The url without its tracking parameters, or None when nothing was removed.
+pub fn strip(url: &str) -> Option<String> {
+    let s = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if !s.enabled { return None; }
+    strip_with(url, &s.rules, &s.bypass)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(url: &str) -> Option<String> {
+        strip_with(url, &parse_rules(&default_rules()).unwrap(), &["example.org".into()])
+    }
+
+    #[test]
+    fn removes_common_trackers() {
+        assert_eq!(strip("https://a.com/p?utm_source=x&utm_medium=y&id=5").as_deref(),
+                   Some("https://a.com/p?id=5"));
+        assert_eq!(strip("https://a.com/?fbclid=abc").as_deref(), Some("https://a.com/"));
+        assert_eq!(strip("https://a.com/?gclid=1#top").as_deref(), Some("https://a.com/#top"));
+    }
+
+    #[test]
+    fn keeps_other_parameters_as_written() {
+        assert_eq!(strip("https://a.com/s?q=a%20b&utm_id=1&x=%C3%B3").as_deref(),
+                   Some("https://a.com/s?q=a%20b&x=%C3%B3"));
+        assert_eq!(strip("https://a.com/s?q=utm_source"), None);
+        assert_eq!(strip("https://a.com/s?q=1"), None);
+        assert_eq!(strip("https://a.com/"), None);
+    }
+
+    #[test]
+    fn site_rules_apply_to_their_site_only() {
+        assert_eq!(strip("https://www.youtube.com/watch?v=abc&si=xyz").as_deref(),
+                   Some("https://www.youtube.com/watch?v=abc"));
+        assert_eq!(strip("https://m.youtube.com/watch?v=abc&si=xyz").as_deref(),
+                   Some("https://m.youtube.com/watch?v=abc"));
+        assert_eq!(strip("https://other.com/?si=xyz"), None);
+    }
+
+    #[test]
+    fn bypass_and_other_schemes() {
+        assert_eq!(strip("https://www.example.org/?utm_source=x"), None);
+        assert_eq!(strip("https://shop.example.org/?utm_source=x"), None);
+        assert_eq!(strip("vccat:home"), None);
+        assert_eq!(strip("file:///tmp/a?utm_source=x"), None);
+    }
+
+    #[test]
+    fn rule_syntax() {
+        assert!(validate(&["utm_*".into(), "a.com: x, y*".into(), "# comment".into()], &[]).is_ok());
+        assert!(validate(&["*".into()], &[]).is_err());
+        assert!(validate(&["u*m".into()], &[]).is_err());
+        assert!(validate(&[": x".into()], &[]).is_err());
+        assert!(validate(&[], &["a.com/b".into()]).is_err());
+    }
+}
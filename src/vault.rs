@@ -0,0 +1,450 @@
+//! Vault: optional at-rest encryption of profile files. Files are sealed with
+//! XChaCha20-Poly1305 under a key derived from the user's passphrase through
+//! Argon2id. vault.json holds the salt, the KDF parameters and a sealed check
+//! value that tells a wrong passphrase apart from a damaged file.
+//!
+//! Sealed: history, sessions, bookmarks, the reading list and its offline
+//! copies, settings and the sync state (which holds other devices' tabs).
+//! Left plain: sync.json (folder and device name only), what is written to
+//! the sync folder (the other devices read it without this passphrase),
+//! filter lists, the allowlist and HTTPS exceptions, and WebKit's own
+//! cookies, cache and site storage.
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, rand_core::RngCore};
+use chacha20poly1305::XChaCha20Poly1305;
+
+/// Files written through `storage` that get sealed while the vault is on.
+pub const PROTECTED: &[&str] = &[
+    "history.json", "session.json", "session-crashed.json", "sessions.json", "bookmarks.json",
+    "reading-list.json", "settings.json", "sync-state.json",
+];
+
+/// Directories all of whose files are sealed.
+pub const PROTECTED_DIRS: &[&str] = &["reading"];
+
+/// Files appended to a line at a time, each line sealed on its own.
+pub const PROTECTED_LOGS: &[&str] = &["history.log"];
+
+/// Extension of files staged by a rewrite, and the file that marks one as
+/// complete (see `rewrite_protected`).
+const STAGED: &str = "rekey";
+const REWRITE_MARK: &str = "vault-rewrite.json";
+
+const MAGIC: &[u8] = b"VCCATENC1";
+const NONCE_LEN: usize = 24;
+const CHECK_PLAIN: &[u8] = b"vccat-vault-check";
+
+static KEY: Mutex<Option<[u8; 32]>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum VaultError {
+    WrongPassphrase,
+    Locked,
+    Corrupt(String),
+}
+
+impl std::fmt::Display for VaultError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VaultError::WrongPassphrase => write!(f, "błędne hasło"),
+            VaultError::Locked => write!(f, "profil jest zablokowany"),
+            VaultError::Corrupt(e) => write!(f, "uszkodzone dane: {}", e),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    check: String,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) { return None; }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn read_vault(dir: &Path) -> Result<Option<VaultFile>, VaultError> {
+    match fs::read_to_string(dir.join("vault.json")) {
+        Ok(s) => serde_json::from_str(&s).map(Some).map_err(|e| VaultError::Corrupt(e.to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Names of the protected files present in `dir`, relative to it.
+fn protected_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = PROTECTED.iter().filter(|n| dir.join(n).is_file()).map(|n| n.to_string()).collect();
+    for d in PROTECTED_DIRS {
+        for e in fs::read_dir(dir.join(d)).into_iter().flatten().flatten() {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let ext = Path::new(&name).extension().and_then(|x| x.to_str()).unwrap_or("");
+            if e.path().is_file() && !["part", STAGED, "unreadable"].contains(&ext) {
+                names.push(format!("{}/{}", d, name));
+            }
+        }
+    }
+    names
+}
+
+/// Whether the profile in `dir` is encrypted: it has a vault.json, or
+/// sealed files without one. The latter can't be unlocked, and so are
+/// never written over with plain data.
+fn enabled_in(dir: &Path) -> bool {
+    let sealed = |name: &&str| fs::File::open(dir.join(name)).and_then(|f| {
+        let mut head = Vec::new();
+        f.take(2 * MAGIC.len() as u64).read_to_end(&mut head).map(|_| head)
+    }).is_ok_and(|head| is_sealed(&head) || head.starts_with(hex(MAGIC).as_bytes()));
+    dir.join("vault.json").exists() || PROTECTED.iter().chain(PROTECTED_LOGS).any(sealed)
+}
+
+/// Whether the profile is encrypted.
+pub fn is_enabled() -> bool {
+    enabled_in(&crate::storage::data_dir())
+}
+
+pub fn is_unlocked() -> bool {
+    KEY.lock().map(|k| k.is_some()).unwrap_or(false)
+}
+
+fn derive(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32)
+    -> Result<[u8; 32], VaultError>
+{
+    let params = argon2::Params::new(m_cost, t_cost, p_cost, Some(32))
+        .map_err(|e| VaultError::Corrupt(e.to_string()))?;
+    let argon = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    let mut key = [0u8; 32];
+    argon.hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| VaultError::Corrupt(e.to_string()))?;
+    Ok(key)
+}
+
+fn seal_with(key: &[u8; 32], plain: &[u8]) -> Vec<u8> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain).expect("in-memory encryption cannot fail");
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    out
+}
+
+fn open_with(key: &[u8; 32], data: &[u8]) -> Option<Vec<u8>> {
+    let body = data.strip_prefix(MAGIC)?;
+    if body.len() < NONCE_LEN { return None; }
+    let (nonce, sealed) = body.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), sealed).ok()
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// The key `passphrase` gives for the vault in `dir`.
+fn key_in(dir: &Path, passphrase: &str) -> Result<[u8; 32], VaultError> {
+    let v = read_vault(dir)?.ok_or(VaultError::Corrupt("brak vault.json".into()))?;
+    let salt = unhex(&v.salt).ok_or(VaultError::Corrupt("salt".into()))?;
+    let check = unhex(&v.check).ok_or(VaultError::Corrupt("check".into()))?;
+    let key = derive(passphrase, &salt, v.m_cost, v.t_cost, v.p_cost)?;
+    if open_with(&key, &check).as_deref() != Some(CHECK_PLAIN) {
+        return Err(VaultError::WrongPassphrase);
+    }
+    Ok(key)
+}
+
+/// Derives the key from `passphrase` and keeps it for this process.
+pub fn unlock(passphrase: &str) -> Result<(), VaultError> {
+    let key = key_in(&crate::storage::data_dir(), passphrase)?;
+    *KEY.lock().unwrap() = Some(key);
+    Ok(())
+}
+
+/// Seals `plain` when the vault is on for the profile in `dir`. Refuses to
+/// write while it is locked, so a locked profile is never overwritten with
+/// plain data.
+pub fn seal_in(dir: &Path, plain: &[u8]) -> Result<Vec<u8>, VaultError> {
+    if !enabled_in(dir) { return Ok(plain.to_vec()); }
+    match *KEY.lock().unwrap() {
+        Some(ref key) => Ok(seal_with(key, plain)),
+        None => Err(VaultError::Locked),
+    }
+}
+
+fn open_in(key: Option<&[u8; 32]>, data: &[u8]) -> Result<Vec<u8>, VaultError> {
+    if !is_sealed(data) { return Ok(data.to_vec()); }
+    match key {
+        Some(key) => open_with(key, data)
+            .ok_or_else(|| VaultError::Corrupt("nie można odszyfrować pliku".into())),
+        None => Err(VaultError::Locked),
+    }
+}
+
+/// Opens data written by `seal_in`; plain data from before encryption was
+/// turned on passes through unchanged.
+pub fn open(data: &[u8]) -> Result<Vec<u8>, VaultError> {
+    open_in(KEY.lock().unwrap().as_ref(), data)
+}
+
+/// Seals one line of a log file, as hex so the log stays line based.
+pub fn seal_line_in(dir: &Path, plain: &str) -> Result<String, VaultError> {
+    if !enabled_in(dir) { return Ok(plain.to_string()); }
+    seal_in(dir, plain.as_bytes()).map(|d| hex(&d))
+}
+
+fn open_line_in(key: Option<&[u8; 32]>, line: &str) -> Result<String, VaultError> {
+    match unhex(line).filter(|d| is_sealed(d)) {
+        Some(data) => String::from_utf8(open_in(key, &data)?).map_err(|e| VaultError::Corrupt(e.to_string())),
+        None => Ok(line.to_string()),
+    }
+}
+
+/// Opens a line written by `seal_line_in`; plain lines pass through.
+pub fn open_line(line: &str) -> Result<String, VaultError> {
+    open_line_in(KEY.lock().unwrap().as_ref(), line)
+}
+
+/// Argon2id memory (KiB), iterations and lanes for new vaults.
+#[cfg(not(test))]
+const KDF_PARAMS: (u32, u32, u32) = (64 * 1024, 3, 1);
+#[cfg(test)]
+const KDF_PARAMS: (u32, u32, u32) = (64, 1, 1);
+
+fn io_err(e: std::io::Error) -> VaultError {
+    VaultError::Corrupt(e.to_string())
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> Result<(), VaultError> {
+    let mut f = fs::File::create(path).map_err(io_err)?;
+    f.write_all(data).and_then(|_| f.sync_all()).map_err(io_err)
+}
+
+/// A rewrite whose files are all staged.
+#[derive(Serialize, Deserialize)]
+struct Rewrite {
+    /// Files staged as `<name>.rekey`, vault.json among them when it changes.
+    files: Vec<String>,
+    /// Whether vault.json goes away, i.e. encryption is turned off.
+    remove_vault: bool,
+}
+
+/// Re-seals every protected file in `dir` from `old_key` to `new_key` (None
+/// = plain text) and swaps in the new vault.json. Everything is staged as
+/// .rekey files first; once they are all on disk a mark says so and they
+/// are renamed into place. A rewrite cut short before the mark is dropped by
+/// `recover`, one cut short after it is finished, so the files never end up
+/// sealed under different keys.
+fn rewrite_protected(dir: &Path, old_key: Option<&[u8; 32]>, new_key: Option<&[u8; 32]>,
+                     vault_json: Option<String>) -> Result<(), VaultError> {
+    let staged = |name: &str| dir.join(format!("{}.{}", name, STAGED));
+    let mut files = Vec::new();
+    for name in protected_files(dir) {
+        let Ok(data) = fs::read(dir.join(&name)) else { continue };
+        let plain = open_in(old_key, &data)?;
+        let out = match new_key { Some(k) => seal_with(k, &plain), None => plain };
+        write_synced(&staged(&name), &out)?;
+        files.push(name);
+    }
+    for name in PROTECTED_LOGS {
+        let Ok(text) = fs::read_to_string(dir.join(name)) else { continue };
+        let mut out = String::new();
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            let plain = open_line_in(old_key, line)?;
+            out.push_str(&match new_key { Some(k) => hex(&seal_with(k, plain.as_bytes())), None => plain });
+            out.push('\n');
+        }
+        write_synced(&staged(name), out.as_bytes())?;
+        files.push(name.to_string());
+    }
+    if let Some(json) = &vault_json {
+        write_synced(&staged("vault.json"), json.as_bytes())?;
+        files.push("vault.json".into());
+    }
+    let mark = Rewrite { files, remove_vault: vault_json.is_none() };
+    let mark = serde_json::to_vec(&mark).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+    write_synced(&dir.join(REWRITE_MARK), &mark)?;
+    finish_rewrite(dir)
+}
+
+/// Moves the staged files of a marked rewrite into place. Files already
+/// moved by an earlier attempt are skipped.
+fn finish_rewrite(dir: &Path) -> Result<(), VaultError> {
+    let mark = fs::read(dir.join(REWRITE_MARK)).map_err(io_err)?;
+    let mark: Rewrite = serde_json::from_slice(&mark).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+    for name in &mark.files {
+        let tmp = dir.join(format!("{}.{}", name, STAGED));
+        if tmp.exists() { fs::rename(&tmp, dir.join(name)).map_err(io_err)?; }
+    }
+    if mark.remove_vault && dir.join("vault.json").exists() {
+        fs::remove_file(dir.join("vault.json")).map_err(io_err)?;
+    }
+    fs::remove_file(dir.join(REWRITE_MARK)).map_err(io_err)
+}
+
+fn recover_in(dir: &Path) -> Result<(), VaultError> {
+    if dir.join(REWRITE_MARK).exists() { return finish_rewrite(dir); }
+    let mut leftovers: Vec<std::path::PathBuf> = fs::read_dir(dir).into_iter().flatten().flatten().map(|e| e.path()).collect();
+    for d in PROTECTED_DIRS {
+        leftovers.extend(fs::read_dir(dir.join(d)).into_iter().flatten().flatten().map(|e| e.path()));
+    }
+    for p in leftovers.iter().filter(|p| p.extension().is_some_and(|x| x == STAGED)) {
+        fs::remove_file(p).map_err(io_err)?;
+    }
+    Ok(())
+}
+
+/// Finishes or drops a passphrase change that a crash interrupted. Run
+/// before anything is read from the profile.
+pub fn recover() -> Result<(), VaultError> {
+    recover_in(&crate::storage::data_dir())
+}
+
+/// A fresh salt and the key `passphrase` gives with it, with the vault.json
+/// that records them.
+fn new_vault(passphrase: &str) -> Result<([u8; 32], String), VaultError> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let (m_cost, t_cost, p_cost) = KDF_PARAMS;
+    let key = derive(passphrase, &salt, m_cost, t_cost, p_cost)?;
+    let v = VaultFile {
+        version: 1, salt: hex(&salt), m_cost, t_cost, p_cost,
+        check: hex(&seal_with(&key, CHECK_PLAIN)),
+    };
+    let json = serde_json::to_string_pretty(&v).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+    Ok((key, json))
+}
+
+/// Turns encryption on, or changes the passphrase of an unlocked vault.
+/// The protected files are re-sealed under the new key.
+pub fn set_passphrase(passphrase: &str) -> Result<(), VaultError> {
+    if is_enabled() && !is_unlocked() { return Err(VaultError::Locked); }
+    let (key, json) = new_vault(passphrase)?;
+    let old = *KEY.lock().unwrap();
+    rewrite_protected(&crate::storage::data_dir(), old.as_ref(), Some(&key), Some(json))?;
+    *KEY.lock().unwrap() = Some(key);
+    Ok(())
+}
+
+/// Turns encryption off, writing the protected files back as plain text.
+pub fn disable() -> Result<(), VaultError> {
+    if !is_enabled() { return Ok(()); }
+    if !is_unlocked() { return Err(VaultError::Locked); }
+    let old = *KEY.lock().unwrap();
+    rewrite_protected(&crate::storage::data_dir(), old.as_ref(), None, None)?;
+    *KEY.lock().unwrap() = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> std::path::PathBuf {
+        let d = std::env::temp_dir().join(format!("vccat-vault-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&d).ok();
+        fs::create_dir_all(d.join("reading")).unwrap();
+        fs::write(d.join("history.json"), "[]").unwrap();
+        fs::write(d.join("bookmarks.json"), r#"{"items":[]}"#).unwrap();
+        fs::write(d.join("reading/1.html"), "<p>artykuł</p>").unwrap();
+        fs::write(d.join("history.log"), "{\"url\":\"https://a.pl/\"}\n").unwrap();
+        d
+    }
+
+    fn read(dir: &Path, name: &str, key: Option<&[u8; 32]>) -> Result<String, VaultError> {
+        open_in(key, &fs::read(dir.join(name)).unwrap()).map(|p| String::from_utf8(p).unwrap())
+    }
+
+    #[test]
+    fn seals_and_opens() {
+        let key = [7u8; 32];
+        let sealed = seal_with(&key, b"tajne");
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(5).any(|w| w == b"tajne"));
+        assert_eq!(open_with(&key, &sealed).as_deref(), Some(&b"tajne"[..]));
+        assert_eq!(open_with(&[8u8; 32], &sealed), None);
+        // a flipped bit is caught, not decrypted to garbage
+        let mut bad = sealed.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(open_with(&key, &bad), None);
+        assert!(matches!(open_in(None, &sealed), Err(VaultError::Locked)));
+        assert_eq!(open_in(None, b"plain").unwrap(), b"plain");
+        let line = hex(&seal_with(&key, b"{}"));
+        assert_eq!(open_line_in(Some(&key), &line).unwrap(), "{}");
+        assert_eq!(open_line_in(None, "{}").unwrap(), "{}");
+    }
+
+    #[test]
+    fn rekeys_every_protected_file() {
+        let dir = profile("rekey");
+        assert!(!enabled_in(&dir));
+        let (k1, json) = new_vault("pierwsze").unwrap();
+        rewrite_protected(&dir, None, Some(&k1), Some(json)).unwrap();
+        assert!(enabled_in(&dir));
+        assert_eq!(key_in(&dir, "pierwsze").unwrap(), k1);
+        assert!(matches!(key_in(&dir, "drugie"), Err(VaultError::WrongPassphrase)));
+        for name in ["history.json", "bookmarks.json", "reading/1.html"] {
+            assert!(is_sealed(&fs::read(dir.join(name)).unwrap()), "{}", name);
+        }
+        assert_eq!(read(&dir, "reading/1.html", Some(&k1)).unwrap(), "<p>artykuł</p>");
+
+        let (k2, json) = new_vault("drugie").unwrap();
+        rewrite_protected(&dir, Some(&k1), Some(&k2), Some(json)).unwrap();
+        assert!(matches!(key_in(&dir, "pierwsze"), Err(VaultError::WrongPassphrase)));
+        assert_eq!(key_in(&dir, "drugie").unwrap(), k2);
+        assert!(matches!(read(&dir, "bookmarks.json", Some(&k1)), Err(VaultError::Corrupt(_))));
+        assert_eq!(read(&dir, "bookmarks.json", Some(&k2)).unwrap(), r#"{"items":[]}"#);
+        let log = fs::read_to_string(dir.join("history.log")).unwrap();
+        assert_eq!(open_line_in(Some(&k2), log.trim_end()).unwrap(), r#"{"url":"https://a.pl/"}"#);
+
+        rewrite_protected(&dir, Some(&k2), None, None).unwrap();
+        assert!(!dir.join("vault.json").exists() && !enabled_in(&dir));
+        assert_eq!(fs::read_to_string(dir.join("history.json")).unwrap(), "[]");
+        assert_eq!(fs::read_to_string(dir.join("history.log")).unwrap(), "{\"url\":\"https://a.pl/\"}\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn fails_closed_without_vault_json() {
+        let dir = profile("closed");
+        let (key, json) = new_vault("hasło").unwrap();
+        rewrite_protected(&dir, None, Some(&key), Some(json)).unwrap();
+        fs::remove_file(dir.join("vault.json")).unwrap();
+        assert!(enabled_in(&dir));
+        assert!(matches!(key_in(&dir, "hasło"), Err(VaultError::Corrupt(_))));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn interrupted_rewrites() {
+        let dir = profile("recover");
+        let (key, json) = new_vault("hasło").unwrap();
+        // staged but not marked: dropped, the profile stays plain
+        fs::write(dir.join("history.json.rekey"), seal_with(&key, b"[]")).unwrap();
+        fs::write(dir.join("reading/1.html.rekey"), seal_with(&key, b"x")).unwrap();
+        recover_in(&dir).unwrap();
+        assert!(!dir.join("history.json.rekey").exists() && !dir.join("reading/1.html.rekey").exists());
+        assert!(!enabled_in(&dir));
+        // marked, with one file already moved: finished
+        fs::write(dir.join("history.json"), seal_with(&key, b"[]")).unwrap();
+        fs::write(dir.join("bookmarks.json.rekey"), seal_with(&key, b"{}")).unwrap();
+        fs::write(dir.join("vault.json.rekey"), json).unwrap();
+        let mark = Rewrite { files: vec!["history.json".into(), "bookmarks.json".into(), "vault.json".into()], remove_vault: false };
+        fs::write(dir.join(REWRITE_MARK), serde_json::to_vec(&mark).unwrap()).unwrap();
+        recover_in(&dir).unwrap();
+        assert!(!dir.join(REWRITE_MARK).exists());
+        assert_eq!(key_in(&dir, "hasło").unwrap(), key);
+        assert_eq!(read(&dir, "bookmarks.json", Some(&key)).unwrap(), "{}");
+        fs::remove_dir_all(&dir).ok();
+    }
+}